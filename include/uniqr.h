@@ -0,0 +1,136 @@
+#ifndef UNIQR_H
+#define UNIQR_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit by hand. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * Result code returned by every `uniqr_*` function
+ */
+typedef enum UniqrStatus {
+  UNIQR_STATUS_OK = 0,
+  /**
+   * A required pointer argument was null
+   */
+  UNIQR_STATUS_NULL_POINTER = 1,
+  /**
+   * Invalid options, or a path that isn't valid UTF-8
+   */
+  UNIQR_STATUS_INVALID_ARGUMENT = 2,
+  /**
+   * Reading input, writing output or temporary storage failed
+   */
+  UNIQR_STATUS_IO = 3,
+  /**
+   * A key was not valid UTF-8 where UTF-8 was required
+   */
+  UNIQR_STATUS_UTF8 = 4,
+  /**
+   * An internal error (Rust panic) was caught at the boundary
+   */
+  UNIQR_STATUS_PANIC = 5,
+} UniqrStatus;
+
+/**
+ * Deduplication strategy, the values of `UniqrOptions::mode`
+ */
+typedef enum UniqrMode {
+  UNIQR_MODE_KEEP_FIRST = 0,
+  UNIQR_MODE_KEEP_LAST = 1,
+  UNIQR_MODE_REMOVE_ALL = 2,
+} UniqrMode;
+
+/**
+ * C-compatible subset of `DeduplicationOptions`; start from `uniqr_options_default()`
+ */
+typedef struct UniqrOptions {
+  /**
+   * A `UniqrMode`. Plain `u32` rather than the enum, so a value from C
+   * outside it is an error instead of undefined behavior
+   */
+  uint32_t mode;
+  bool ignore_case;
+  bool count;
+  bool show_removed;
+  /**
+   * 1-indexed whitespace-separated key column, or 0 for the whole record
+   */
+  uint32_t column;
+  /**
+   * Byte that ends each record (`'\n'` by default, `'\0'` for NUL-separated input)
+   */
+  uint8_t separator;
+} UniqrOptions;
+
+/**
+ * Deduplication statistics
+ */
+typedef struct UniqrStats {
+  uint64_t lines_read;
+  uint64_t lines_written;
+  uint64_t lines_removed;
+  uint64_t unique_lines;
+} UniqrStats;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Default options: keep first, whole-line keys, newline-separated records
+ */
+struct UniqrOptions uniqr_options_default(void);
+
+/**
+ * Deduplicate `input_len` bytes at `input` into a newly allocated buffer.
+ *
+ * On success `*output` and `*output_len` describe the result, which must be
+ * released with `uniqr_free_buffer`. `stats` may be null.
+ *
+ * # Safety
+ *
+ * `input` must point to `input_len` readable bytes (or may be null if
+ * `input_len` is 0). `options`, `output` and `output_len` must be valid
+ * pointers; `stats` must be null or valid.
+ */
+enum UniqrStatus uniqr_dedup_buffer(const uint8_t *input,
+                                    size_t input_len,
+                                    const struct UniqrOptions *options,
+                                    uint8_t **output,
+                                    size_t *output_len,
+                                    struct UniqrStats *stats);
+
+/**
+ * Free a buffer returned by `uniqr_dedup_buffer`
+ *
+ * # Safety
+ *
+ * `buffer` and `len` must come from a single successful `uniqr_dedup_buffer`
+ * call, and the buffer must not be freed twice. Null is ignored.
+ */
+void uniqr_free_buffer(uint8_t *buffer, size_t len);
+
+/**
+ * Deduplicate the file at `input_path` into `output_path` (UTF-8 paths).
+ *
+ * The output is written atomically with `write_atomically`, so it may be
+ * the input file itself. `stats` may be null.
+ *
+ * # Safety
+ *
+ * `input_path` and `output_path` must be valid NUL-terminated strings and
+ * `options` a valid pointer; `stats` must be null or valid.
+ */
+enum UniqrStatus uniqr_dedup_file(const char *input_path,
+                                  const char *output_path,
+                                  const struct UniqrOptions *options,
+                                  struct UniqrStats *stats);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* UNIQR_H */
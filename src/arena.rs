@@ -0,0 +1,129 @@
+//! Compact in-memory storage of input records for the two-pass algorithms
+use crate::error::Result;
+use crate::hash::{KeyMap, key_map};
+use crate::{DeduplicationOptions, Origin, record_key};
+use std::borrow::Cow;
+use std::hash::BuildHasher;
+
+/// Records stored back to back in one buffer, with where each came from.
+///
+/// Costs the record bytes plus one offset per record; origins are stored
+/// once per run of consecutive records from the same input.
+pub(crate) struct RecordArena {
+    data: Vec<u8>,
+    /// End of each record in `data`
+    ends: Vec<usize>,
+    /// Index and origin of the first record of each run whose origins
+    /// follow on from one another
+    runs: Vec<(usize, Origin)>,
+}
+
+impl RecordArena {
+    pub(crate) fn new() -> Self {
+        Self {
+            data: Vec::new(),
+            ends: Vec::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, record: &[u8], origin: Origin) {
+        let idx = self.ends.len();
+        let follows = self.runs.last().is_some_and(|&(first, start)| {
+            let offset = idx - first;
+            start.source == origin.source
+                && start.line + offset == origin.line
+                && start.number + offset == origin.number
+        });
+        if !follows {
+            self.runs.push((idx, origin));
+        }
+        self.data.extend_from_slice(record);
+        self.ends.push(self.data.len());
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    /// The record at `idx`, including its terminator
+    pub(crate) fn record(&self, idx: usize) -> &[u8] {
+        let start = if idx == 0 { 0 } else { self.ends[idx - 1] };
+        &self.data[start..self.ends[idx]]
+    }
+
+    pub(crate) fn origin(&self, idx: usize) -> Origin {
+        let run = self.runs.partition_point(|&(first, _)| first <= idx) - 1;
+        let (first, start) = self.runs[run];
+        let offset = idx - first;
+        Origin {
+            source: start.source,
+            line: start.line + offset,
+            number: start.number + offset,
+        }
+    }
+
+    /// Every record with its origin, in input order
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&[u8], Origin)> {
+        (0..self.len()).map(|idx| (self.record(idx), self.origin(idx)))
+    }
+}
+
+/// Numbers each distinct key of the records in a `RecordArena` without
+/// storing key bytes: only the key's hash and one record with that key are
+/// kept, and the key is recomputed from the record when hashes collide.
+pub(crate) struct RecordKeyIndex {
+    /// Newest entry for each key hash
+    heads: KeyMap<u64, usize>,
+    /// Record with each entry's key, and the previous entry with the same hash
+    entries: Vec<(usize, Option<usize>)>,
+}
+
+impl RecordKeyIndex {
+    pub(crate) fn new(options: &DeduplicationOptions) -> Result<Self> {
+        Ok(Self {
+            heads: key_map(options.hasher)?,
+            entries: Vec::new(),
+        })
+    }
+
+    /// Number of distinct keys
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Entry number of `key`, the key of record `idx`; a new key gets the
+    /// next number, `len()` before the call. Record `idx` need not be in
+    /// `records` yet, but must be added before the next lookup.
+    pub(crate) fn entry(
+        &mut self,
+        key: &[u8],
+        idx: usize,
+        records: &RecordArena,
+        options: &DeduplicationOptions,
+    ) -> Result<usize> {
+        let hash = self.heads.hasher().hash_one(key);
+        let mut candidate = self.heads.get(&hash).copied();
+        while let Some(entry) = candidate {
+            let (record, previous) = self.entries[entry];
+            if record_key(records.record(record), options)? == key {
+                return Ok(entry);
+            }
+            candidate = previous;
+        }
+        let entry = self.entries.len();
+        let previous = self.heads.insert(hash, entry);
+        self.entries.push((idx, previous));
+        Ok(entry)
+    }
+
+    /// The key of `entry`
+    pub(crate) fn key<'a>(
+        &self,
+        entry: usize,
+        records: &'a RecordArena,
+        options: &DeduplicationOptions,
+    ) -> Result<Cow<'a, [u8]>> {
+        record_key(records.record(self.entries[entry].0), options)
+    }
+}
@@ -0,0 +1,213 @@
+//! Deduplication over tokio's `AsyncRead`/`AsyncWrite` (requires 'async' feature)
+use crate::error::{Error, Result};
+use crate::{
+    Decision, DeduplicatingWriter, DeduplicationMode, DeduplicationOptions, DeduplicationStats,
+    Deduplicator, RecordSeparator,
+};
+use std::io::{Seek, SeekFrom};
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+/// Async counterpart of `deduplicate`.
+///
+/// `KeepFirst` with a byte or byte-string separator streams: kept records
+/// are written as soon as they are read. Other modes and options (two-pass
+/// modes, counts, headers and footers, paragraphs, disk-backed storage) spool
+/// the input to a temporary file and deduplicate it on tokio's blocking
+/// thread pool, so they must run inside a tokio runtime.
+pub async fn deduplicate_async<R, W>(
+    mut input: R,
+    output: &mut W,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let streaming = options.mode == DeduplicationMode::KeepFirst
+        && !options.count
+        && !options.adjacent
+        && options.group.is_none()
+        && options.sort.is_none()
+        && options.max_line_length.is_none()
+        && options.exclude_keys.is_none()
+        && !options.ensure_trailing_newline
+        && !options.use_disk
+        && options.memory_limit.is_none()
+        && matches!(
+            options.record_separator,
+            RecordSeparator::Byte(_) | RecordSeparator::Bytes(_)
+        )
+        && options.column_name.is_none()
+        && !options.csv.as_ref().is_some_and(|csv| csv.has_header)
+        && options.header_lines == 0
+        && options.footer_lines == 0;
+
+    if !streaming {
+        let spool = blocking(tempfile::tempfile).await?;
+        let mut spool = tokio::fs::File::from_std(spool);
+        tokio::io::copy(&mut input, &mut spool).await?;
+        spool.flush().await?;
+        let mut spool = spool.into_std().await;
+
+        let options = options.clone();
+        let (stats, result) = blocking(move || -> Result<_> {
+            spool.seek(SeekFrom::Start(0))?;
+            let mut result = std::io::BufWriter::new(tempfile::tempfile()?);
+            let stats = crate::deduplicate_seekable(spool, &mut result, &options)?;
+            let mut result = result.into_inner().map_err(|e| e.into_error())?;
+            result.seek(SeekFrom::Start(0))?;
+            Ok((stats, result))
+        })
+        .await?;
+
+        tokio::io::copy(&mut tokio::fs::File::from_std(result), output).await?;
+        output.flush().await?;
+        return Ok(stats);
+    }
+
+    let mut writer = DeduplicatingWriter::new(Vec::new(), options.clone())?;
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        let read = input.read(&mut chunk).await?;
+        if read == 0 {
+            break;
+        }
+        std::io::Write::write_all(&mut writer, &chunk[..read])?;
+        let pending = writer.get_mut();
+        if !pending.is_empty() {
+            output.write_all(pending).await?;
+            pending.clear();
+        }
+    }
+
+    let (rest, stats) = writer.finish_with_stats()?;
+    output.write_all(&rest).await?;
+    output.flush().await?;
+    Ok(stats)
+}
+
+/// Runs `f` on tokio's blocking thread pool, resuming its panic if it panics
+async fn blocking<T, E>(f: impl FnOnce() -> std::result::Result<T, E> + Send + 'static) -> Result<T>
+where
+    T: Send + 'static,
+    E: Into<Error> + Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result.map_err(Into::into),
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => Err(Error::Io(std::io::Error::other(e))),
+    }
+}
+
+/// A kept record yielded by a `DedupStream`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DedupLine {
+    /// The record as read, with its terminator unless it ended the input
+    /// without one
+    pub record: Vec<u8>,
+    /// 1-based position of the record in the input
+    pub line: usize,
+}
+
+/// Deduplicate `input` as a `Stream` of the kept records, for consumers that
+/// apply their own backpressure: input is only read when the next record is
+/// polled for.
+///
+/// Only `KeepFirst` with a byte or byte-string separator is supported, with
+/// the options a `Deduplicator` takes.
+pub fn dedup_stream<R: AsyncRead + Unpin>(
+    input: R,
+    options: &DeduplicationOptions,
+) -> Result<DedupStream<R>> {
+    let terminator = match &options.record_separator {
+        RecordSeparator::Byte(byte) => vec![*byte],
+        RecordSeparator::Bytes(bytes) => bytes.clone(),
+        RecordSeparator::Paragraph | RecordSeparator::QuotedLine => {
+            return Err(Error::InvalidArgument(
+                "dedup_stream only supports byte and byte-string separators".to_string(),
+            ));
+        }
+    };
+    Ok(DedupStream {
+        input: BufReader::new(input),
+        dedup: Deduplicator::new(options.clone())?,
+        terminator,
+        record: Vec::new(),
+        line: 0,
+        done: false,
+    })
+}
+
+/// The `Stream` returned by `dedup_stream`
+pub struct DedupStream<R> {
+    input: BufReader<R>,
+    dedup: Deduplicator,
+    terminator: Vec<u8>,
+    /// The part of the next record read so far
+    record: Vec<u8>,
+    line: usize,
+    done: bool,
+}
+
+impl<R> DedupStream<R> {
+    /// Statistics for the records read so far
+    pub fn stats(&self) -> &DeduplicationStats {
+        self.dedup.stats()
+    }
+
+    /// The final statistics, with the key reports the options ask for
+    pub fn finish(self) -> DeduplicationStats {
+        self.dedup.finish()
+    }
+}
+
+impl<R: AsyncRead + Unpin> futures_core::Stream for DedupStream<R> {
+    type Item = Result<DedupLine>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let last = this.terminator[this.terminator.len() - 1];
+        while !this.done {
+            let available = match ready!(Pin::new(&mut this.input).poll_fill_buf(cx)) {
+                Ok(available) => available,
+                Err(e) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(e.into())));
+                }
+            };
+            if available.is_empty() {
+                this.done = true;
+                if this.record.is_empty() {
+                    break;
+                }
+            } else {
+                let end = available.iter().position(|&byte| byte == last);
+                let used = end.map_or(available.len(), |end| end + 1);
+                this.record.extend_from_slice(&available[..used]);
+                Pin::new(&mut this.input).consume(used);
+                if end.is_none() || !this.record.ends_with(&this.terminator) {
+                    continue;
+                }
+            }
+
+            this.line += 1;
+            let record = std::mem::take(&mut this.record);
+            match this.dedup.push_line(&record) {
+                Ok(Decision::Keep) => {
+                    return Poll::Ready(Some(Ok(DedupLine {
+                        record,
+                        line: this.line,
+                    })));
+                }
+                Ok(Decision::Remove) => {}
+                Err(e) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(e)));
+                }
+            }
+        }
+        Poll::Ready(None)
+    }
+}
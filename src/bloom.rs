@@ -0,0 +1,49 @@
+//! Fixed-size Bloom filter for approximate deduplication
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// A Bloom filter sized up front for an expected number of keys and a target
+/// false-positive rate. Memory use never grows after construction.
+pub(crate) struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    /// Size the filter for `expected` keys at `false_positive_rate`
+    pub(crate) fn new(expected: usize, false_positive_rate: f64) -> Self {
+        let n = expected.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-n * false_positive_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / n) * ln2).round().clamp(1.0, 32.0) as u32;
+        Self {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+        }
+    }
+
+    /// Add `key`, returning `true` if it was (probably) already present
+    pub(crate) fn check_and_insert(&mut self, key: &[u8]) -> bool {
+        // Double hashing: bit i is h1 + i * h2
+        let h1 = Self::hash(key, 0);
+        let h2 = Self::hash(key, 1) | 1;
+        let mut present = true;
+        for i in 0..u64::from(self.num_hashes) {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits;
+            let (word, mask) = ((bit / 64) as usize, 1u64 << (bit % 64));
+            present &= self.bits[word] & mask != 0;
+            self.bits[word] |= mask;
+        }
+        present
+    }
+
+    fn hash(key: &[u8], seed: u8) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        seed.hash(&mut hasher);
+        key.hash(&mut hasher);
+        hasher.finish()
+    }
+}
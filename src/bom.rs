@@ -0,0 +1,21 @@
+//! Byte order mark detection
+use std::io::{self, BufRead};
+
+/// The UTF-8 byte order mark. A UTF-16 one is only recognized when the
+/// input is decoded (`--input-encoding`), which turns it into this one;
+/// stripping its two bytes from undecoded input would misalign the rest.
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// The byte order mark `data` starts with, if any
+pub(crate) fn detect(data: &[u8]) -> Option<&'static [u8]> {
+    data.starts_with(UTF8_BOM).then_some(UTF8_BOM)
+}
+
+/// Consume the byte order mark at the start of `input`, if any, and return it
+pub(crate) fn strip<R: BufRead>(input: &mut R) -> io::Result<Option<&'static [u8]>> {
+    let bom = detect(input.fill_buf()?);
+    if let Some(bom) = bom {
+        input.consume(bom.len());
+    }
+    Ok(bom)
+}
@@ -0,0 +1,367 @@
+//! Builder for `DeduplicationOptions` that checks option combinations up front
+use crate::error::{Error, Result};
+use crate::{
+    ApproximateOptions, CaseMode, CountFormat, CsvOptions, DeduplicationMode, DeduplicationOptions,
+    DiskOptions, EmptyLinePolicy, FuzzyOptions, GroupSeparator, HashAlgorithm, KeyExtractor,
+    KeyType, LineLengthPolicy, NonUtf8Case, NormalizationForm, Normalizer, ProgressCallback,
+    PunctuationMode, RecordCallback, RecordFilter, RecordSeparator, SimilarityOptions, SortOrder,
+    SourceAnnotation, TrimMode, Utf8Policy, WindowMode,
+};
+
+impl DeduplicationOptions {
+    /// Start building options from the defaults
+    ///
+    /// ```
+    /// use uniqr::{DeduplicationMode, DeduplicationOptions};
+    ///
+    /// let options = DeduplicationOptions::builder()
+    ///     .mode(DeduplicationMode::KeepLast)
+    ///     .ignore_case(true)
+    ///     .column(2)
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(options.column, Some(2));
+    ///
+    /// assert!(DeduplicationOptions::builder().column(0).build().is_err());
+    /// ```
+    pub fn builder() -> DeduplicationOptionsBuilder {
+        DeduplicationOptionsBuilder::default()
+    }
+}
+
+/// Builder for `DeduplicationOptions`; see `DeduplicationOptions::builder`
+#[derive(Debug, Clone, Default)]
+pub struct DeduplicationOptionsBuilder {
+    options: DeduplicationOptions,
+}
+
+impl DeduplicationOptionsBuilder {
+    pub fn mode(mut self, mode: DeduplicationMode) -> Self {
+        self.options.mode = mode;
+        self
+    }
+
+    pub fn ignore_case(mut self, ignore_case: bool) -> Self {
+        self.options.ignore_case = ignore_case;
+        self
+    }
+
+    pub fn case_mode(mut self, case_mode: CaseMode) -> Self {
+        self.options.case_mode = case_mode;
+        self
+    }
+
+    pub fn non_utf8_case(mut self, non_utf8_case: NonUtf8Case) -> Self {
+        self.options.non_utf8_case = non_utf8_case;
+        self
+    }
+
+    pub fn utf8_policy(mut self, utf8_policy: Utf8Policy) -> Self {
+        self.options.utf8_policy = utf8_policy;
+        self
+    }
+
+    pub fn empty_lines(mut self, empty_lines: EmptyLinePolicy) -> Self {
+        self.options.empty_lines = empty_lines;
+        self
+    }
+
+    /// Write the records `filter` picks through without deduplicating them
+    pub fn passthrough(mut self, filter: RecordFilter) -> Self {
+        self.options.passthrough = Some(filter);
+        self
+    }
+
+    pub fn count(mut self, count: bool) -> Self {
+        self.options.count = count;
+        self
+    }
+
+    pub fn count_format(mut self, count_format: CountFormat) -> Self {
+        self.options.count_format = count_format;
+        self
+    }
+
+    pub fn show_removed(mut self, show_removed: bool) -> Self {
+        self.options.show_removed = show_removed;
+        self
+    }
+
+    /// Write the kept records in `order`; the count orders also turn on
+    /// `count`
+    pub fn sort(mut self, order: SortOrder) -> Self {
+        self.options.sort = Some(order);
+        self.options.count |= order.by_count();
+        self
+    }
+
+    /// Write every record grouped by key; see `DeduplicationOptions::group`
+    pub fn group(mut self, separator: GroupSeparator) -> Self {
+        self.options.group = Some(separator);
+        self
+    }
+
+    /// Key on this 1-indexed whitespace-separated column
+    pub fn column(mut self, column: usize) -> Self {
+        self.options.column = Some(column);
+        self
+    }
+
+    pub fn column_name(mut self, name: impl Into<String>) -> Self {
+        self.options.column_name = Some(name.into());
+        self
+    }
+
+    /// Copy the first `lines` records to the output without deduplicating them
+    pub fn header_lines(mut self, lines: usize) -> Self {
+        self.options.header_lines = lines;
+        self
+    }
+
+    /// Copy the last `lines` records to the output without deduplicating them
+    pub fn footer_lines(mut self, lines: usize) -> Self {
+        self.options.footer_lines = lines;
+        self
+    }
+
+    pub fn record_separator(mut self, separator: RecordSeparator) -> Self {
+        self.options.record_separator = separator;
+        self
+    }
+
+    /// Handle lines longer than `max` bytes by `policy`
+    pub fn max_line_length(mut self, max: usize, policy: LineLengthPolicy) -> Self {
+        self.options.max_line_length = Some(max);
+        self.options.line_length_policy = policy;
+        self
+    }
+
+    pub fn csv(mut self, csv: CsvOptions) -> Self {
+        self.options.csv = Some(csv);
+        self
+    }
+
+    pub fn json_pointer(mut self, pointer: impl Into<String>) -> Self {
+        self.options.json_pointer = Some(pointer.into());
+        self
+    }
+
+    /// Compute keys with `extractor` instead of the built-in key options
+    pub fn key_extractor<K: KeyExtractor + 'static>(mut self, extractor: K) -> Self {
+        self.options = self.options.with_key_extractor(extractor);
+        self
+    }
+
+    /// Append a step to the key-normalization pipeline
+    pub fn normalizer<N: Normalizer + 'static>(mut self, normalizer: N) -> Self {
+        self.options
+            .normalizers
+            .push(std::sync::Arc::new(normalizer));
+        self
+    }
+
+    pub fn skip_fields(mut self, fields: usize) -> Self {
+        self.options.skip_fields = fields;
+        self
+    }
+
+    pub fn skip_chars(mut self, chars: usize) -> Self {
+        self.options.skip_chars = chars;
+        self
+    }
+
+    pub fn compare_width(mut self, width: usize) -> Self {
+        self.options.compare_width = Some(width);
+        self
+    }
+
+    pub fn trim(mut self, trim: TrimMode) -> Self {
+        self.options.trim = trim;
+        self
+    }
+
+    pub fn punctuation(mut self, punctuation: PunctuationMode) -> Self {
+        self.options.punctuation = punctuation;
+        self
+    }
+
+    pub fn normalization(mut self, form: NormalizationForm) -> Self {
+        self.options.normalization = Some(form);
+        self
+    }
+
+    /// Compare keys without their accents and other combining marks
+    pub fn ignore_diacritics(mut self, ignore: bool) -> Self {
+        self.options.ignore_diacritics = ignore;
+        self
+    }
+
+    pub fn key_type(mut self, key_type: KeyType) -> Self {
+        self.options.key_type = key_type;
+        self
+    }
+
+    /// Drop records with any of these keys, e.g. from `load_reference_keys`
+    pub fn exclude_keys(mut self, keys: std::collections::HashSet<Vec<u8>>) -> Self {
+        self.options.exclude_keys = Some(std::sync::Arc::new(keys));
+        self
+    }
+
+    pub fn annotate_source(mut self, annotation: SourceAnnotation) -> Self {
+        self.options.annotate_source = Some(annotation);
+        self
+    }
+
+    pub fn approximate(mut self, approximate: ApproximateOptions) -> Self {
+        self.options.approximate = Some(approximate);
+        self
+    }
+
+    /// Treat records within `fuzzy.max_distance` edits of a kept record as duplicates
+    pub fn fuzzy(mut self, fuzzy: FuzzyOptions) -> Self {
+        self.options.fuzzy = Some(fuzzy);
+        self
+    }
+
+    /// Only treat records as duplicates of the recent ones in `window`
+    pub fn window(mut self, window: WindowMode) -> Self {
+        self.options.window = Some(window);
+        self
+    }
+
+    /// Shingle size and signature scheme for `DeduplicationMode::Similar`
+    pub fn similarity(mut self, similarity: SimilarityOptions) -> Self {
+        self.options.similarity = similarity;
+        self
+    }
+
+    pub fn hasher(mut self, hasher: HashAlgorithm) -> Self {
+        self.options.hasher = hasher;
+        self
+    }
+
+    /// Report the `limit` most repeated keys in the statistics
+    pub fn top_duplicates(mut self, limit: usize) -> Self {
+        self.options.top_duplicates = Some(limit);
+        self
+    }
+
+    /// Collect every key's occurrence count in the statistics
+    pub fn collect_key_counts(mut self, collect: bool) -> Self {
+        self.options.collect_key_counts = collect;
+        self
+    }
+
+    /// Collect a histogram of how often keys occur in the statistics
+    pub fn collect_histogram(mut self, collect: bool) -> Self {
+        self.options.collect_histogram = collect;
+        self
+    }
+
+    pub fn collect_removed_records(mut self, collect: bool) -> Self {
+        self.options.collect_removed_records = collect;
+        self
+    }
+
+    pub fn use_disk(mut self, use_disk: bool) -> Self {
+        self.options.use_disk = use_disk;
+        self
+    }
+
+    pub fn disk(mut self, disk: DiskOptions) -> Self {
+        self.options.disk = disk;
+        self
+    }
+
+    /// Persist the seen keys at `path` across runs; implies `use_disk`
+    pub fn state_db(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.options.state_db = Some(path.into());
+        self.options.use_disk = true;
+        self
+    }
+
+    /// Record a checkpoint in the state database every `records` records
+    pub fn checkpoint_every(mut self, records: usize) -> Self {
+        self.options.checkpoint_every = Some(records);
+        self
+    }
+
+    /// Resume the run interrupted after the state database's checkpoint
+    pub fn resume(mut self) -> Self {
+        self.options.resume = true;
+        self
+    }
+
+    /// Move the in-memory keep-first keys to disk once they take about `bytes`
+    pub fn memory_limit(mut self, bytes: usize) -> Self {
+        self.options.memory_limit = Some(bytes);
+        self
+    }
+
+    pub fn line_numbers(mut self, line_numbers: bool) -> Self {
+        self.options.line_numbers = line_numbers;
+        self
+    }
+
+    /// Write the key of each kept record instead of the record
+    pub fn print_key(mut self, print_key: bool) -> Self {
+        self.options.print_key = print_key;
+        self
+    }
+
+    /// Pass each record written to the main output, with its location, to `callback`
+    pub fn record_callback<C: RecordCallback + 'static>(mut self, callback: C) -> Self {
+        self.options = self.options.with_record_callback(callback);
+        self
+    }
+
+    /// Report progress to `callback` while inputs are read
+    pub fn progress_callback<P: ProgressCallback + 'static>(mut self, callback: P) -> Self {
+        self.options = self.options.with_progress_callback(callback);
+        self
+    }
+
+    /// Stop reading with `Error::Cancelled` once `cancel` is set
+    pub fn cancel(mut self, cancel: std::sync::Arc<std::sync::atomic::AtomicBool>) -> Self {
+        self.options.cancel = Some(cancel);
+        self
+    }
+
+    /// Check the options for unsupported combinations and return them
+    pub fn build(self) -> Result<DeduplicationOptions> {
+        let options = self.options;
+
+        let key_sources = [
+            options.column.is_some(),
+            options.column_name.is_some(),
+            options.csv.is_some(),
+            options.json_pointer.is_some(),
+            options.key_extractor.is_some(),
+        ];
+        if key_sources.iter().filter(|&&set| set).count() > 1 {
+            return invalid(
+                "Only one of column, column_name, csv, json_pointer and key_extractor can be set",
+            );
+        }
+        if options.use_disk && !cfg!(feature = "disk-backed") {
+            return invalid("use_disk requires the 'disk-backed' feature");
+        }
+        if options.memory_limit.is_some() && !cfg!(feature = "disk-backed") {
+            return invalid("memory_limit requires the 'disk-backed' feature");
+        }
+        if options.csv.is_some() && !cfg!(feature = "csv") {
+            return invalid("csv requires the 'csv' feature");
+        }
+        if options.json_pointer.is_some() && !cfg!(feature = "json") {
+            return invalid("json_pointer requires the 'json' feature");
+        }
+        crate::hash::key_map::<(), ()>(options.hasher)?;
+        crate::validate_options(&options)?;
+
+        Ok(options)
+    }
+}
+
+fn invalid<T>(message: &str) -> Result<T> {
+    Err(Error::InvalidArgument(message.to_string()))
+}
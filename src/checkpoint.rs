@@ -0,0 +1,231 @@
+//! Checkpoints of disk-backed runs with a state database, so an interrupted
+//! run can resume where it left off instead of starting over.
+//!
+//! Every `checkpoint_every` records, the output is flushed and the position
+//! of the run is stored in the state database along with the keys seen so
+//! far. Keys stored by a checkpointed run also record when they were first
+//! seen, so a resumed run can tell the keys of records it will read again
+//! (stored after the checkpoint, before the interruption) from the keys it
+//! had really seen.
+use crate::disk::{DiskStore, open_store};
+use crate::error::{Error, Result};
+use crate::{DeduplicationOptions, DeduplicationStats};
+
+/// Metadata name of the number of the latest checkpointed run
+const RUN: &str = "run";
+/// Metadata name of the latest checkpoint
+const CHECKPOINT: &str = "checkpoint";
+
+/// Length of a stored key's value that records when it was first seen:
+/// count, run and record number, all little-endian u64
+const VALUE_LEN: usize = 24;
+
+/// How far an interrupted run had got at its last checkpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Records read before the checkpoint, across all inputs
+    pub records_read: u64,
+    /// Input bytes consumed by those records (see `RecordReader::bytes_read`)
+    pub input_offset: u64,
+    /// Bytes written to the main output by then. The output of a resumed run
+    /// continues from this offset, so cut the output back to it first.
+    pub output_offset: u64,
+    /// Records deduplicated before the checkpoint (not counting those
+    /// skipped for `exclude_keys`), and how many were written and removed
+    pub lines_read: u64,
+    pub lines_written: u64,
+    pub lines_removed: u64,
+    /// The run the checkpoint belongs to
+    run: u64,
+    /// Whether the terminator of the last record written was held back
+    pub(crate) terminator_held: bool,
+}
+
+impl Checkpoint {
+    fn encode(&self) -> Vec<u8> {
+        let fields = [
+            self.run,
+            self.records_read,
+            self.input_offset,
+            self.output_offset,
+            self.lines_read,
+            self.lines_written,
+            self.lines_removed,
+            self.terminator_held as u64,
+        ];
+        fields
+            .iter()
+            .flat_map(|field| field.to_le_bytes())
+            .collect()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        let fields: Vec<u64> = bytes
+            .chunks_exact(8)
+            .map(|field| u64::from_le_bytes(field.try_into().unwrap_or_default()))
+            .collect();
+        let &[
+            run,
+            records_read,
+            input_offset,
+            output_offset,
+            lines_read,
+            lines_written,
+            lines_removed,
+            terminator_held,
+        ] = &fields[..]
+        else {
+            return Err(Error::InvalidArgument(
+                "The state database has a corrupt checkpoint".to_string(),
+            ));
+        };
+        Ok(Self {
+            records_read,
+            input_offset,
+            output_offset,
+            lines_read,
+            lines_written,
+            lines_removed,
+            run,
+            terminator_held: terminator_held != 0,
+        })
+    }
+}
+
+/// The checkpoint of the interrupted run in `options.state_db`, if any.
+///
+/// A run that finishes removes its checkpoint. Use this before resuming to
+/// find where the output should be cut back to.
+pub fn read_checkpoint(options: &DeduplicationOptions) -> Result<Option<Checkpoint>> {
+    let Some(path) = options.state_db.as_deref() else {
+        return Err(Error::InvalidArgument(
+            "Checkpoints require a state database".to_string(),
+        ));
+    };
+    let mut db = open_store(&options.disk, Some(path), options.hasher)?;
+    load(db.as_mut())
+}
+
+fn load(db: &mut dyn DiskStore) -> Result<Option<Checkpoint>> {
+    db.metadata(CHECKPOINT)?
+        .map(|bytes| Checkpoint::decode(&bytes))
+        .transpose()
+}
+
+/// Checkpoint bookkeeping of a run with `checkpoint_every` or `resume`
+pub(crate) struct Checkpointer {
+    run: u64,
+    every: Option<usize>,
+    resumed: Option<Checkpoint>,
+}
+
+impl Checkpointer {
+    /// Resume the interrupted run in `db`, or start a new checkpointed run.
+    /// Returns `None` if `options` asks for neither.
+    pub(crate) fn start(
+        db: &mut dyn DiskStore,
+        options: &DeduplicationOptions,
+    ) -> Result<Option<Self>> {
+        let every = options.checkpoint_every;
+        if options.resume {
+            let checkpoint = load(db)?.ok_or_else(|| {
+                Error::InvalidArgument(
+                    "The state database has no checkpoint to resume from".to_string(),
+                )
+            })?;
+            log::info!(
+                "Resuming from the checkpoint after {} records",
+                checkpoint.records_read
+            );
+            return Ok(Some(Self {
+                run: checkpoint.run,
+                every,
+                resumed: Some(checkpoint),
+            }));
+        }
+        if every.is_none() {
+            return Ok(None);
+        }
+        // Number the run before it stores any keys
+        let run = match db.metadata(RUN)? {
+            Some(bytes) => u64::from_le_bytes(bytes.try_into().unwrap_or_default()) + 1,
+            None => 1,
+        };
+        db.set_metadata(RUN, Some(&run.to_le_bytes()))?;
+        db.set_metadata(CHECKPOINT, None)?;
+        db.flush()?;
+        Ok(Some(Self {
+            run,
+            every,
+            resumed: None,
+        }))
+    }
+
+    /// The checkpoint this run resumes from
+    pub(crate) fn resumed(&self) -> Option<&Checkpoint> {
+        self.resumed.as_ref()
+    }
+
+    /// Whether `value`, stored for a key, was stored by the interrupted run
+    /// when it first saw the key at or after record `record` (1-based), the
+    /// one being read. That record comes after the checkpoint, so the key
+    /// hasn't really been seen yet. Every input is read the same way each
+    /// time, so a key first seen earlier has been stored again since.
+    pub(crate) fn stored_ahead(&self, value: &[u8], record: usize) -> bool {
+        let field = |idx: usize| u64::from_le_bytes(value[idx * 8..][..8].try_into().unwrap());
+        self.resumed.is_some()
+            && value.len() == VALUE_LEN
+            && field(1) == self.run
+            && field(2) >= record as u64
+    }
+
+    /// The value to store for a key first seen in record `record` (1-based)
+    pub(crate) fn first_seen(&self, record: usize) -> [u8; VALUE_LEN] {
+        let mut value = [0; VALUE_LEN];
+        value[..8].copy_from_slice(&1u64.to_le_bytes());
+        value[8..16].copy_from_slice(&self.run.to_le_bytes());
+        value[16..].copy_from_slice(&(record as u64).to_le_bytes());
+        value
+    }
+
+    /// Whether a checkpoint is due after `records_read` records
+    pub(crate) fn due(&self, records_read: usize) -> bool {
+        self.every
+            .is_some_and(|every| records_read.is_multiple_of(every))
+    }
+
+    /// Store a checkpoint after `records_read` records (`input_offset`
+    /// bytes) and `output_offset` bytes of output, with statistics `stats`,
+    /// along with the keys in `db`. The output must have been flushed.
+    pub(crate) fn save(
+        &self,
+        db: &mut dyn DiskStore,
+        records_read: usize,
+        input_offset: u64,
+        output_offset: u64,
+        terminator_held: bool,
+        stats: &DeduplicationStats,
+    ) -> Result<()> {
+        let checkpoint = Checkpoint {
+            records_read: records_read as u64,
+            input_offset,
+            output_offset,
+            lines_read: stats.lines_read as u64,
+            lines_written: stats.lines_written as u64,
+            lines_removed: stats.lines_removed as u64,
+            run: self.run,
+            terminator_held,
+        };
+        log::debug!("Checkpoint after {} records", records_read);
+        db.set_metadata(CHECKPOINT, Some(&checkpoint.encode()))?;
+        // sled makes writes durable in order, so the checkpoint is only ever
+        // found with the keys stored before it
+        db.flush()
+    }
+
+    /// Remove the checkpoint once the run has finished
+    pub(crate) fn finish(self, db: &mut dyn DiskStore) -> Result<()> {
+        db.set_metadata(CHECKPOINT, None)?;
+        db.flush()
+    }
+}
@@ -0,0 +1,186 @@
+//! Transparent decompression of inputs and compression of outputs
+use crate::error::{Error, Result};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// Compression format of an input or output stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Plain, uncompressed data
+    None,
+    /// gzip (`.gz`), requires the `gzip` feature
+    Gzip,
+    /// Zstandard (`.zst`), requires the `compress-zstd` feature
+    Zstd,
+    /// xz (`.xz`), requires the `compress-xz` feature
+    Xz,
+}
+
+impl Compression {
+    /// Guess the format from a file extension
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            Some("xz") => Compression::Xz,
+            _ => Compression::None,
+        }
+    }
+
+    /// Cargo feature needed to read or write this format
+    pub fn feature(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Zstd => Some("compress-zstd"),
+            Compression::Xz => Some("compress-xz"),
+        }
+    }
+}
+
+/// A reader that decompresses its input on the fly
+pub enum CompressedReader<R: Read> {
+    Plain(R),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::read::MultiGzDecoder<R>),
+    #[cfg(feature = "compress-zstd")]
+    Zstd(zstd::stream::read::Decoder<'static, io::BufReader<R>>),
+    #[cfg(feature = "compress-xz")]
+    Xz(xz2::read::XzDecoder<R>),
+}
+
+impl<R: Read> CompressedReader<R> {
+    /// Wrap `inner`, decompressing it as `compression`.
+    ///
+    /// Returns an error if support for the format wasn't compiled in.
+    pub fn new(inner: R, compression: Compression) -> Result<Self> {
+        match compression {
+            Compression::None => Ok(CompressedReader::Plain(inner)),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Ok(CompressedReader::Gzip(flate2::read::MultiGzDecoder::new(
+                inner,
+            ))),
+            #[cfg(feature = "compress-zstd")]
+            Compression::Zstd => Ok(CompressedReader::Zstd(zstd::stream::read::Decoder::new(
+                inner,
+            )?)),
+            #[cfg(feature = "compress-xz")]
+            Compression::Xz => Ok(CompressedReader::Xz(
+                xz2::read::XzDecoder::new_multi_decoder(inner),
+            )),
+            #[allow(unreachable_patterns)]
+            other => Err(Error::InvalidArgument(format!(
+                "{:?} compression requires the '{}' feature",
+                other,
+                other.feature().unwrap_or_default()
+            ))),
+        }
+    }
+
+    /// The underlying reader, if the input isn't compressed
+    pub fn into_plain(self) -> Option<R> {
+        match self {
+            CompressedReader::Plain(inner) => Some(inner),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+}
+
+impl<R: Read> Read for CompressedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            CompressedReader::Plain(inner) => inner.read(buf),
+            #[cfg(feature = "gzip")]
+            CompressedReader::Gzip(inner) => inner.read(buf),
+            #[cfg(feature = "compress-zstd")]
+            CompressedReader::Zstd(inner) => inner.read(buf),
+            #[cfg(feature = "compress-xz")]
+            CompressedReader::Xz(inner) => inner.read(buf),
+        }
+    }
+}
+
+/// A writer that compresses its output as it is written.
+///
+/// Call `finish` when done; dropping the writer may leave a truncated stream.
+pub enum CompressedWriter<W: Write> {
+    Plain(W),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<W>),
+    #[cfg(feature = "compress-zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+    #[cfg(feature = "compress-xz")]
+    Xz(xz2::write::XzEncoder<W>),
+}
+
+impl<W: Write> CompressedWriter<W> {
+    /// Wrap `inner`, compressing output as `compression` at the default level.
+    ///
+    /// Returns an error if support for the format wasn't compiled in.
+    pub fn new(inner: W, compression: Compression) -> Result<Self> {
+        match compression {
+            Compression::None => Ok(CompressedWriter::Plain(inner)),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Ok(CompressedWriter::Gzip(flate2::write::GzEncoder::new(
+                inner,
+                flate2::Compression::default(),
+            ))),
+            #[cfg(feature = "compress-zstd")]
+            Compression::Zstd => Ok(CompressedWriter::Zstd(zstd::stream::write::Encoder::new(
+                inner, 0,
+            )?)),
+            #[cfg(feature = "compress-xz")]
+            Compression::Xz => Ok(CompressedWriter::Xz(xz2::write::XzEncoder::new(inner, 6))),
+            #[allow(unreachable_patterns)]
+            other => Err(Error::InvalidArgument(format!(
+                "{:?} compression requires the '{}' feature",
+                other,
+                other.feature().unwrap_or_default()
+            ))),
+        }
+    }
+
+    /// Write any compression trailer, flush, and return the underlying writer
+    pub fn finish(self) -> io::Result<W> {
+        // Without any compression features only `Plain` exists
+        #[allow(clippy::infallible_destructuring_match)]
+        let mut inner = match self {
+            CompressedWriter::Plain(inner) => inner,
+            #[cfg(feature = "gzip")]
+            CompressedWriter::Gzip(encoder) => encoder.finish()?,
+            #[cfg(feature = "compress-zstd")]
+            CompressedWriter::Zstd(encoder) => encoder.finish()?,
+            #[cfg(feature = "compress-xz")]
+            CompressedWriter::Xz(encoder) => encoder.finish()?,
+        };
+        inner.flush()?;
+        Ok(inner)
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            CompressedWriter::Plain(inner) => inner.write(buf),
+            #[cfg(feature = "gzip")]
+            CompressedWriter::Gzip(encoder) => encoder.write(buf),
+            #[cfg(feature = "compress-zstd")]
+            CompressedWriter::Zstd(encoder) => encoder.write(buf),
+            #[cfg(feature = "compress-xz")]
+            CompressedWriter::Xz(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            CompressedWriter::Plain(inner) => inner.flush(),
+            #[cfg(feature = "gzip")]
+            CompressedWriter::Gzip(encoder) => encoder.flush(),
+            #[cfg(feature = "compress-zstd")]
+            CompressedWriter::Zstd(encoder) => encoder.flush(),
+            #[cfg(feature = "compress-xz")]
+            CompressedWriter::Xz(encoder) => encoder.flush(),
+        }
+    }
+}
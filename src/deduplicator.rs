@@ -0,0 +1,375 @@
+//! Incremental deduplication state that lines are fed into one at a time
+use crate::bloom::BloomFilter;
+use crate::error::{Error, Result};
+use crate::fuzzy::BkTree;
+use crate::hash::{KeyMap, key_map};
+use crate::progress::{ProgressReader, ProgressTracker};
+use crate::record::{RecordReader, TerminatedReader, is_empty_record};
+use crate::window::Window;
+use crate::{
+    DeduplicationMode, DeduplicationOptions, DeduplicationStats, EmptyLinePolicy, Origin,
+    RecordOutput, Timer, Utf8Policy,
+};
+use std::cell::Cell;
+use std::io::{BufReader, Read, Write};
+
+/// What to do with a line given to `Deduplicator::push_line`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// The line's key hasn't been seen before
+    Keep,
+    /// The line duplicates an earlier one, or its key is excluded
+    Remove,
+}
+
+/// A line decided by a `Deduplicator`, with the key it was deduplicated by
+/// and the number of times that key has occurred, where they are known
+struct Decided {
+    decision: Decision,
+    key: Option<Vec<u8>>,
+    count: Option<usize>,
+}
+
+impl Decided {
+    /// A line decided without building its key
+    fn without_key(decision: Decision) -> Self {
+        Self {
+            decision,
+            key: None,
+            count: None,
+        }
+    }
+}
+
+/// Keys seen so far (with occurrence counts), a fixed-size Bloom filter,
+/// the kept keys of fuzzy deduplication (with occurrence counts by node), or
+/// the keys of the last records
+enum Seen {
+    Exact(KeyMap<Vec<u8>, usize>),
+    Approximate(BloomFilter),
+    Fuzzy(BkTree, Vec<usize>),
+    Window(Window),
+}
+
+/// The seen-set of a `KeepFirst` deduplication, decided one line at a time.
+///
+/// Lines can come from any number of buffers, files or sockets; they are
+/// all deduplicated against each other. A record terminator at the end of a
+/// line is ignored, and keys are built from the rest as `deduplicate` would.
+/// Options that only affect input framing or output formatting (headers,
+/// `count`, `show_removed`, source annotations) don't apply here.
+///
+/// ```
+/// use uniqr::{Decision, Deduplicator, DeduplicationOptions};
+///
+/// let mut dedup = Deduplicator::new(DeduplicationOptions::default()).unwrap();
+/// assert_eq!(dedup.push_line(b"a\n").unwrap(), Decision::Keep);
+/// assert_eq!(dedup.push_line(b"b").unwrap(), Decision::Keep);
+/// assert_eq!(dedup.push_line(b"a").unwrap(), Decision::Remove);
+/// assert_eq!(dedup.finish().lines_removed, 1);
+/// ```
+pub struct Deduplicator {
+    options: DeduplicationOptions,
+    seen: Seen,
+    stats: DeduplicationStats,
+    timer: Timer,
+}
+
+impl Deduplicator {
+    /// Start an empty seen-set for `options`
+    pub fn new(options: DeduplicationOptions) -> Result<Self> {
+        if options.mode != DeduplicationMode::KeepFirst
+            || options.adjacent
+            || options.group.is_some()
+            || options.sort.is_some()
+            || options.collect_removed_records
+        {
+            return Err(Error::InvalidArgument(
+                "Deduplicator only supports non-adjacent, ungrouped, unsorted KeepFirst mode without removed records"
+                    .to_string(),
+            ));
+        }
+        // The seen-set always lives in memory
+        if options.use_disk || options.state_db.is_some() || options.memory_limit.is_some() {
+            return Err(Error::InvalidArgument(
+                "Deduplicator keeps keys in memory; it supports neither disk-backed storage, a state database nor a memory limit"
+                    .to_string(),
+            ));
+        }
+        if options.column_name.is_some()
+            || options.csv.as_ref().is_some_and(|csv| csv.has_header)
+            || options.header_lines > 0
+            || options.footer_lines > 0
+        {
+            return Err(Error::InvalidArgument(
+                "Deduplicator doesn't support header or footer rows".to_string(),
+            ));
+        }
+        crate::validate_options(&options)?;
+        let seen = match (options.approximate, options.fuzzy, options.window.clone()) {
+            (Some(approximate), _, _) => Seen::Approximate(BloomFilter::new(
+                approximate.expected_lines,
+                approximate.false_positive_rate,
+            )),
+            (None, Some(fuzzy), _) => Seen::Fuzzy(BkTree::new(fuzzy.max_distance), Vec::new()),
+            (None, None, Some(window)) => Seen::Window(Window::new(window, options.hasher)?),
+            (None, None, None) => Seen::Exact(key_map(options.hasher)?),
+        };
+        Ok(Self {
+            options,
+            seen,
+            stats: DeduplicationStats::default(),
+            timer: Timer::start(),
+        })
+    }
+
+    /// Decide whether `line` is kept, remembering its key. Under
+    /// `Utf8Policy::Skip`, lines that aren't valid UTF-8 are kept without
+    /// being remembered, as are lines the `passthrough` filter picks, empty
+    /// lines under `EmptyLinePolicy::Preserve` and lines without a timestamp
+    /// in a time window.
+    pub fn push_line(&mut self, line: &[u8]) -> Result<Decision> {
+        self.decide(line).map(|decided| decided.decision)
+    }
+
+    /// Decide `line` like `push_line`, also returning its key and count
+    fn decide(&mut self, line: &[u8]) -> Result<Decided> {
+        let mut key_line = self.options.record_separator.strip(line);
+        let empty_lines = self.options.empty_lines;
+        if self.options.utf8_policy == Utf8Policy::Skip && std::str::from_utf8(key_line).is_err()
+            || empty_lines == EmptyLinePolicy::Preserve && is_empty_record(key_line)
+            || (self.options.passthrough.as_ref())
+                .is_some_and(|filter| filter.passes_through(key_line))
+        {
+            return Ok(Decided::without_key(self.keep_unremembered(line)));
+        }
+        if empty_lines == EmptyLinePolicy::Drop && is_empty_record(key_line) {
+            self.stats.lines_read += 1;
+            self.stats.bytes_read += line.len() as u64;
+            self.stats.lines_removed += 1;
+            return Ok(Decided::without_key(Decision::Remove));
+        }
+        if let Seen::Window(window) = &mut self.seen {
+            match window.key_line(key_line)? {
+                Some(rest) => key_line = rest,
+                None => return Ok(Decided::without_key(self.keep_unremembered(line))),
+            }
+        }
+        let key = crate::make_key(key_line, &self.options)?;
+        let (is_new, count) = if crate::is_excluded(&key, &self.options) {
+            (false, None)
+        } else {
+            match &mut self.seen {
+                Seen::Exact(map) => match map.get_mut(&key) {
+                    Some(count) => {
+                        *count += 1;
+                        (false, Some(*count))
+                    }
+                    None => {
+                        map.insert(key.clone(), 1);
+                        (true, Some(1))
+                    }
+                },
+                Seen::Approximate(filter) => (!filter.check_and_insert(&key), None),
+                Seen::Window(window) => (!window.check_and_insert(key.clone()), None),
+                Seen::Fuzzy(tree, counts) => match tree.find(&key) {
+                    Some(node) => {
+                        counts[node] += 1;
+                        (false, Some(counts[node]))
+                    }
+                    None => {
+                        tree.insert(key.clone());
+                        counts.push(1);
+                        (true, Some(1))
+                    }
+                },
+            }
+        };
+
+        self.stats.lines_read += 1;
+        self.stats.bytes_read += line.len() as u64;
+        if is_new {
+            self.stats.lines_written += 1;
+        } else {
+            self.stats.lines_removed += 1;
+        }
+        self.stats.unique_lines = match &self.seen {
+            Seen::Exact(map) => map.len(),
+            Seen::Approximate(_) | Seen::Window(_) => self.stats.lines_written,
+            Seen::Fuzzy(tree, _) => tree.len(),
+        };
+        Ok(Decided {
+            decision: if is_new {
+                Decision::Keep
+            } else {
+                Decision::Remove
+            },
+            key: Some(key),
+            count,
+        })
+    }
+
+    /// Keep `line` without deduplicating it
+    fn keep_unremembered(&mut self, line: &[u8]) -> Decision {
+        self.stats.lines_read += 1;
+        self.stats.bytes_read += line.len() as u64;
+        self.stats.lines_written += 1;
+        Decision::Keep
+    }
+
+    /// The options lines are deduplicated by
+    pub fn options(&self) -> &DeduplicationOptions {
+        &self.options
+    }
+
+    /// Statistics for the lines pushed so far; `elapsed` and the key reports
+    /// are only set by `finish`
+    pub fn stats(&self) -> &DeduplicationStats {
+        &self.stats
+    }
+
+    /// Statistics a wrapper keeps up to date itself, such as byte counts
+    pub(crate) fn stats_mut(&mut self) -> &mut DeduplicationStats {
+        &mut self.stats
+    }
+
+    /// The final statistics, with the key reports `options` asks for
+    pub fn finish(mut self) -> DeduplicationStats {
+        self.stats.elapsed = self.timer.elapsed();
+        match &self.seen {
+            Seen::Exact(map) => crate::report_key_counts(&mut self.stats, &self.options, || {
+                map.iter().map(|(k, &c)| (&k[..], c))
+            }),
+            Seen::Fuzzy(tree, counts) => {
+                crate::report_key_counts(&mut self.stats, &self.options, || {
+                    counts
+                        .iter()
+                        .enumerate()
+                        .map(|(node, &c)| (tree.key(node), c))
+                })
+            }
+            Seen::Approximate(_) | Seen::Window(_) => {}
+        }
+        self.stats
+    }
+}
+
+/// What `deduplicate_with_observer` decided about a line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineEvent<'a> {
+    /// Index of the input the line came from
+    pub source: usize,
+    /// 1-based line number within that input
+    pub line: usize,
+    /// The line, with its terminator
+    pub record: &'a [u8],
+    /// The key the line was deduplicated by, or `None` for lines decided
+    /// without one (passed through, empty or without a timestamp)
+    pub key: Option<&'a [u8]>,
+    pub action: Decision,
+    /// Occurrences of the key so far, this line included; `None` without a
+    /// key, for excluded keys, and where keys aren't counted (approximate
+    /// and windowed deduplication)
+    pub count: Option<usize>,
+}
+
+/// Deduplicate `input` like `deduplicate`, passing the decision about each
+/// line to `observer` after the line is written (or not).
+///
+/// Lines are decided by a `Deduplicator`, so the same modes are supported;
+/// `count` and `max_line_length` are not.
+///
+/// ```
+/// use uniqr::{Decision, DeduplicationOptions, LineEvent, deduplicate_with_observer};
+///
+/// let mut output = Vec::new();
+/// let mut removed = Vec::new();
+/// let options = DeduplicationOptions::default();
+/// deduplicate_with_observer(&b"a\nb\na\n"[..], &mut output, &options, |event: LineEvent| {
+///     if event.action == Decision::Remove {
+///         removed.push((event.line, event.count));
+///     }
+/// })
+/// .unwrap();
+/// assert_eq!(output, b"a\nb\n");
+/// assert_eq!(removed, [(3, Some(2))]);
+/// ```
+pub fn deduplicate_with_observer<R, W, F>(
+    input: R,
+    output: &mut W,
+    options: &DeduplicationOptions,
+    observer: F,
+) -> Result<DeduplicationStats>
+where
+    R: Read,
+    W: Write,
+    F: FnMut(LineEvent<'_>),
+{
+    deduplicate_many_with_observer(std::iter::once(input), output, options, observer)
+}
+
+/// Deduplicate `inputs` against each other like `deduplicate_many`, passing
+/// the decision about each line to `observer`
+pub fn deduplicate_many_with_observer<I, R, W, F>(
+    inputs: I,
+    output: &mut W,
+    options: &DeduplicationOptions,
+    mut observer: F,
+) -> Result<DeduplicationStats>
+where
+    I: IntoIterator<Item = R>,
+    R: Read,
+    W: Write,
+    F: FnMut(LineEvent<'_>),
+{
+    if options.count || options.max_line_length.is_some() {
+        return Err(Error::InvalidArgument(
+            "Observed deduplication doesn't support counts or a line length limit".to_string(),
+        ));
+    }
+    let tracker = ProgressTracker::new(options.progress_callback.as_deref());
+    let unterminated = Cell::new(false);
+    let terminator = options.record_separator.terminator();
+    let mut output = RecordOutput::new(output, None, options);
+    let mut inputs: Vec<_> = inputs
+        .into_iter()
+        .map(|input| {
+            let input = ProgressReader::new(input, &tracker);
+            BufReader::new(TerminatedReader::new(input, terminator, &unterminated))
+        })
+        .collect();
+    let options = &crate::prepare_inputs(&mut inputs, &mut output, options)?;
+    let mut dedup = Deduplicator::new(options.clone())?;
+
+    let mut reader = RecordReader::from_inputs(inputs, &options.record_separator)
+        .with_cancel(options.cancel.as_deref())
+        .with_progress(&tracker);
+    let mut line = Vec::new();
+    while reader
+        .read_record(&mut line)
+        .map_err(|e| crate::partial_stats(e.into(), &tracker, &mut output.kept, &dedup.timer))?
+        > 0
+    {
+        let decided = dedup.decide(&line).map_err(|e| reader.at_line(e))?;
+        let origin = Origin::of(&reader);
+        match decided.decision {
+            Decision::Keep => crate::write_record(&mut output, &line, origin, options)?,
+            Decision::Remove => output.write_removed(&line, origin, options)?,
+        }
+        observer(LineEvent {
+            source: origin.source,
+            line: origin.line,
+            record: &line,
+            key: decided.key.as_deref(),
+            action: decided.decision,
+            count: decided.count,
+        });
+        line.clear();
+    }
+    output.finish(&[], unterminated.get())?;
+
+    let mut stats = dedup.finish();
+    stats.bytes_read = tracker.bytes_read();
+    stats.bytes_written = output.kept.get_ref().bytes_written();
+    Ok(stats)
+}
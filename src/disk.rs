@@ -0,0 +1,370 @@
+//! Key-value stores behind disk-backed deduplication
+use crate::error::{Error, Result};
+use crate::hash::{HashAlgorithm, KeyMap, key_map};
+use crate::{DiskBackend, DiskOptions};
+use std::fs::File;
+use std::hash::BuildHasher;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Map from keys to small fixed-size values, kept on disk
+pub(crate) trait DiskStore {
+    /// The value stored for `key`, if any
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Store `value` for `key`, replacing any previous value
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> Result<()>;
+
+    /// Number of keys stored
+    fn len(&self) -> usize;
+
+    /// Call `f` with every key and its value, in no particular order
+    fn for_each(&mut self, f: &mut dyn FnMut(&[u8], &[u8])) -> Result<()>;
+
+    /// Write any buffered changes to disk
+    fn flush(&mut self) -> Result<()>;
+
+    /// The bookkeeping value stored under `name`, apart from the keys
+    fn metadata(&mut self, name: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Store (or with `None`, remove) the bookkeeping value `name`
+    fn set_metadata(&mut self, name: &str, value: Option<&[u8]>) -> Result<()>;
+}
+
+/// Open the store for a disk-backed run: a persistent sled database at
+/// `path` if given, otherwise a temporary store of the configured backend
+/// that is removed when dropped
+pub(crate) fn open_store(
+    disk: &DiskOptions,
+    path: Option<&Path>,
+    hasher: HashAlgorithm,
+) -> Result<Box<dyn DiskStore>> {
+    match path {
+        Some(path) => log::debug!("Opening state database '{}'", path.display()),
+        None => log::debug!("Creating a temporary {:?} key store", disk.backend),
+    }
+    match (disk.backend, path) {
+        (DiskBackend::Sled, _) => Ok(Box::new(SledStore::open(disk, path)?)),
+        (DiskBackend::TempFile, None) => Ok(Box::new(FileStore::create(disk, hasher)?)),
+        (DiskBackend::TempFile, Some(_)) => Err(Error::InvalidArgument(
+            "A state database requires the sled disk backend".to_string(),
+        )),
+    }
+}
+
+fn storage_error<E: std::error::Error + Send + Sync + 'static>(e: E) -> Error {
+    Error::Storage(Box::new(e))
+}
+
+/// Name of the sled tree holding `DiskStore::metadata`
+const METADATA_TREE: &str = "uniqr-metadata";
+
+/// A sled database that removes its directory when dropped, if temporary
+struct SledStore {
+    db: sled::Db,
+    temp_path: Option<PathBuf>,
+}
+
+impl SledStore {
+    fn open(disk: &DiskOptions, path: Option<&Path>) -> Result<Self> {
+        let mut config = sled::Config::new();
+        if let Some(capacity) = disk.cache_capacity {
+            config = config.cache_capacity(capacity);
+        }
+        if disk.low_space {
+            config = config.mode(sled::Mode::LowSpace);
+        }
+
+        match path {
+            Some(path) => {
+                let db = config.path(path).open().map_err(storage_error)?;
+                Ok(Self {
+                    db,
+                    temp_path: None,
+                })
+            }
+            None => {
+                config = config.temporary(true);
+                let temp_path = disk.temp_dir.as_deref().map(unique_temp_path);
+                if let Some(temp_path) = &temp_path {
+                    config = config.path(temp_path);
+                }
+                let db = config.open().map_err(storage_error)?;
+                Ok(Self { db, temp_path })
+            }
+        }
+    }
+}
+
+impl DiskStore for SledStore {
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let value = self.db.get(key).map_err(storage_error)?;
+        Ok(value.map(|value| value.to_vec()))
+    }
+
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.db.insert(key, value).map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.db.len()
+    }
+
+    fn for_each(&mut self, f: &mut dyn FnMut(&[u8], &[u8])) -> Result<()> {
+        for item in self.db.iter() {
+            let (key, value) = item.map_err(storage_error)?;
+            f(&key, &value);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.db.flush().map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn metadata(&mut self, name: &str) -> Result<Option<Vec<u8>>> {
+        let tree = self.db.open_tree(METADATA_TREE).map_err(storage_error)?;
+        let value = tree.get(name).map_err(storage_error)?;
+        Ok(value.map(|value| value.to_vec()))
+    }
+
+    fn set_metadata(&mut self, name: &str, value: Option<&[u8]>) -> Result<()> {
+        let tree = self.db.open_tree(METADATA_TREE).map_err(storage_error)?;
+        match value {
+            Some(value) => tree.insert(name, value),
+            None => tree.remove(name),
+        }
+        .map_err(storage_error)?;
+        Ok(())
+    }
+}
+
+impl Drop for SledStore {
+    fn drop(&mut self) {
+        // sled only cleans up temporary storage once its background threads
+        // let go of it, which may be after the process has exited
+        if let Some(path) = &self.temp_path {
+            let _ = std::fs::remove_dir_all(path);
+        }
+    }
+}
+
+/// A path in `dir` that no other run (in this or another process) uses
+fn unique_temp_path(dir: &Path) -> PathBuf {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.subsec_nanos());
+    dir.join(format!(
+        "uniqr-{}-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed),
+        nanos
+    ))
+}
+
+/// Offset of the next entry in a hash chain, or none
+const NO_ENTRY: u64 = u64::MAX;
+
+/// Entry header: next entry with the same hash (8), key length (4) and
+/// value length (4), all little-endian
+const HEADER_LEN: usize = 16;
+
+/// Appended entries are buffered until there are this many bytes
+const PENDING_LIMIT: usize = 64 * 1024;
+
+/// Keys and values in an append-only, anonymous temporary file, found
+/// through an in-memory index of key hashes.
+///
+/// Only the hash and file offset of each key stay in memory, and a lookup
+/// reads the file only when the hash matches, so unlike sled there is no
+/// write amplification: each key is written once and updated in place.
+/// Values for a key must keep their size.
+struct FileStore {
+    file: File,
+    /// Length of `file`; entries in `pending` start here
+    file_len: u64,
+    /// Entries appended but not yet written to `file`
+    pending: Vec<u8>,
+    /// Offset of the newest entry for each key hash
+    heads: KeyMap<u64, u64>,
+    len: usize,
+    scratch: Vec<u8>,
+}
+
+impl FileStore {
+    fn create(disk: &DiskOptions, hasher: HashAlgorithm) -> Result<Self> {
+        let file = match &disk.temp_dir {
+            Some(dir) => tempfile::tempfile_in(dir).map_err(storage_error)?,
+            None => tempfile::tempfile().map_err(storage_error)?,
+        };
+        Ok(Self {
+            file,
+            file_len: 0,
+            pending: Vec::new(),
+            heads: key_map(hasher)?,
+            len: 0,
+            scratch: Vec::new(),
+        })
+    }
+
+    fn hash(&self, key: &[u8]) -> u64 {
+        self.heads.hasher().hash_one(key)
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        if offset >= self.file_len {
+            let start = (offset - self.file_len) as usize;
+            buf.copy_from_slice(&self.pending[start..start + buf.len()]);
+        } else {
+            self.file
+                .seek(SeekFrom::Start(offset))
+                .map_err(storage_error)?;
+            self.file.read_exact(buf).map_err(storage_error)?;
+        }
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        if offset >= self.file_len {
+            let start = (offset - self.file_len) as usize;
+            self.pending[start..start + data.len()].copy_from_slice(data);
+        } else {
+            self.file
+                .seek(SeekFrom::Start(offset))
+                .map_err(storage_error)?;
+            self.file.write_all(data).map_err(storage_error)?;
+        }
+        Ok(())
+    }
+
+    /// Offset of the value stored for `key` and its length
+    fn find(&mut self, key: &[u8]) -> Result<Option<(u64, usize)>> {
+        let Some(&head) = self.heads.get(&self.hash(key)) else {
+            return Ok(None);
+        };
+        let mut scratch = std::mem::take(&mut self.scratch);
+        let mut offset = head;
+        let found = loop {
+            let mut header = [0u8; HEADER_LEN];
+            self.read_at(offset, &mut header)?;
+            let (next, key_len, value_len) = parse_header(&header);
+            if key_len == key.len() {
+                scratch.resize(key_len, 0);
+                self.read_at(offset + HEADER_LEN as u64, &mut scratch)?;
+                if scratch == key {
+                    break Some((offset + (HEADER_LEN + key_len) as u64, value_len));
+                }
+            }
+            if next == NO_ENTRY {
+                break None;
+            }
+            offset = next;
+        };
+        self.scratch = scratch;
+        Ok(found)
+    }
+
+    fn flush_pending(&mut self) -> Result<()> {
+        if !self.pending.is_empty() {
+            self.file.seek(SeekFrom::End(0)).map_err(storage_error)?;
+            self.file.write_all(&self.pending).map_err(storage_error)?;
+            self.file_len += self.pending.len() as u64;
+            self.pending.clear();
+        }
+        Ok(())
+    }
+}
+
+fn parse_header(header: &[u8; HEADER_LEN]) -> (u64, usize, usize) {
+    let next = u64::from_le_bytes(header[0..8].try_into().unwrap());
+    let key_len = u32::from_le_bytes(header[8..12].try_into().unwrap());
+    let value_len = u32::from_le_bytes(header[12..16].try_into().unwrap());
+    (next, key_len as usize, value_len as usize)
+}
+
+impl DiskStore for FileStore {
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let Some((offset, value_len)) = self.find(key)? else {
+            return Ok(None);
+        };
+        let mut value = vec![0; value_len];
+        self.read_at(offset, &mut value)?;
+        Ok(Some(value))
+    }
+
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        if let Some((offset, value_len)) = self.find(key)? {
+            if value_len != value.len() {
+                return Err(Error::InvalidArgument(
+                    "Temp-file store values must keep their size".to_string(),
+                ));
+            }
+            return self.write_at(offset, value);
+        }
+
+        let (Ok(key_len), Ok(value_len)) = (u32::try_from(key.len()), u32::try_from(value.len()))
+        else {
+            return Err(Error::InvalidArgument(
+                "Key too long for the temp-file store".to_string(),
+            ));
+        };
+        let hash = self.hash(key);
+        let offset = self.file_len + self.pending.len() as u64;
+        let next = self.heads.insert(hash, offset).unwrap_or(NO_ENTRY);
+        self.pending.extend_from_slice(&next.to_le_bytes());
+        self.pending.extend_from_slice(&key_len.to_le_bytes());
+        self.pending.extend_from_slice(&value_len.to_le_bytes());
+        self.pending.extend_from_slice(key);
+        self.pending.extend_from_slice(value);
+        self.len += 1;
+        if self.pending.len() >= PENDING_LIMIT {
+            self.flush_pending()?;
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn for_each(&mut self, f: &mut dyn FnMut(&[u8], &[u8])) -> Result<()> {
+        // Every key has exactly one entry, so a scan of the file sees each once
+        self.flush_pending()?;
+        self.file.seek(SeekFrom::Start(0)).map_err(storage_error)?;
+        let mut reader = BufReader::new(&self.file);
+        let mut entry = Vec::new();
+        let mut position = 0;
+        while position < self.file_len {
+            let mut header = [0u8; HEADER_LEN];
+            reader.read_exact(&mut header).map_err(storage_error)?;
+            let (_, key_len, value_len) = parse_header(&header);
+            entry.resize(key_len + value_len, 0);
+            reader.read_exact(&mut entry).map_err(storage_error)?;
+            f(&entry[..key_len], &entry[key_len..]);
+            position += (HEADER_LEN + key_len + value_len) as u64;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.flush_pending()?;
+        self.file.flush().map_err(storage_error)?;
+        Ok(())
+    }
+
+    // A temporary store outlives no run, so it has nothing to keep track of
+    fn metadata(&mut self, _name: &str) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    fn set_metadata(&mut self, _name: &str, _value: Option<&[u8]>) -> Result<()> {
+        Err(Error::InvalidArgument(
+            "The temp-file store doesn't keep metadata".to_string(),
+        ))
+    }
+}
@@ -0,0 +1,334 @@
+//! Transcoding of inputs to UTF-8 and of outputs from UTF-8
+//!
+//! Deduplication works on bytes, so inputs in encodings that aren't
+//! ASCII-compatible (UTF-16) or that spell the same text differently
+//! (legacy code pages) are decoded to UTF-8 first, and the result encoded
+//! back on the way out. Decoding replaces malformed input with U+FFFD;
+//! encoding fails on characters the output encoding can't represent.
+use crate::error::{Error, Result};
+use std::io::{self, Read, Write};
+
+/// A character encoding of an input or output stream.
+///
+/// Anything other than UTF-8 requires the `encodings` feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextEncoding {
+    #[cfg(feature = "encodings")]
+    encoding: &'static encoding_rs::Encoding,
+}
+
+impl Default for TextEncoding {
+    /// UTF-8, which is passed through unchanged
+    fn default() -> Self {
+        Self {
+            #[cfg(feature = "encodings")]
+            encoding: encoding_rs::UTF_8,
+        }
+    }
+}
+
+impl TextEncoding {
+    /// Look up an encoding by a WHATWG label such as `utf-16le`,
+    /// `windows-1252` or `shift_jis` (case-insensitive)
+    pub fn for_label(label: &str) -> Result<Self> {
+        #[cfg(feature = "encodings")]
+        {
+            encoding_rs::Encoding::for_label(label.trim().as_bytes())
+                .map(|encoding| Self { encoding })
+                .ok_or_else(|| Error::InvalidArgument(format!("Unknown encoding '{}'", label)))
+        }
+        #[cfg(not(feature = "encodings"))]
+        {
+            if label.trim().eq_ignore_ascii_case("utf-8")
+                || label.trim().eq_ignore_ascii_case("utf8")
+            {
+                Ok(Self::default())
+            } else {
+                Err(Error::InvalidArgument(format!(
+                    "Encoding '{}' requires the 'encodings' feature",
+                    label
+                )))
+            }
+        }
+    }
+
+    /// Look up an encoding to write output in. Besides `for_label`'s
+    /// lookup, this rejects encodings that can only be decoded.
+    pub fn for_output_label(label: &str) -> Result<Self> {
+        let encoding = Self::for_label(label)?;
+        #[cfg(feature = "encodings")]
+        if encoding.encoder().is_none() {
+            return Err(Error::InvalidArgument(format!(
+                "Encoding '{}' can't be used for output",
+                label
+            )));
+        }
+        Ok(encoding)
+    }
+
+    /// Canonical name of the encoding, e.g. `UTF-16LE`
+    pub fn name(self) -> &'static str {
+        #[cfg(feature = "encodings")]
+        {
+            self.encoding.name()
+        }
+        #[cfg(not(feature = "encodings"))]
+        {
+            "UTF-8"
+        }
+    }
+
+    /// Whether data in this encoding is used as is
+    pub fn is_utf8(self) -> bool {
+        self == Self::default()
+    }
+
+    /// How UTF-8 is turned into this encoding, or `None` if it can't be.
+    /// encoding_rs only decodes UTF-16, so it is encoded here.
+    #[cfg(feature = "encodings")]
+    fn encoder(self) -> Option<Encoder> {
+        if self.encoding == encoding_rs::UTF_16LE {
+            Some(Encoder::Utf16 { big_endian: false })
+        } else if self.encoding == encoding_rs::UTF_16BE {
+            Some(Encoder::Utf16 { big_endian: true })
+        } else if self.encoding.output_encoding() == self.encoding {
+            Some(Encoder::Legacy(self.encoding.new_encoder()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Size of the chunks read from the underlying reader
+#[cfg(feature = "encodings")]
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// A reader that decodes its input to UTF-8 on the fly.
+///
+/// A byte order mark is decoded like any other character, to U+FEFF, so it
+/// ends up as a UTF-8 byte order mark in front of the decoded text.
+pub struct DecodingReader<R: Read> {
+    inner: R,
+    #[cfg(feature = "encodings")]
+    decoder: Option<encoding_rs::Decoder>,
+    /// Raw bytes read from `inner` but not decoded yet
+    #[cfg(feature = "encodings")]
+    input: Vec<u8>,
+    /// Decoded bytes not returned yet, from `decoded_pos`
+    #[cfg(feature = "encodings")]
+    decoded: Vec<u8>,
+    #[cfg(feature = "encodings")]
+    decoded_pos: usize,
+    #[cfg(feature = "encodings")]
+    eof: bool,
+}
+
+impl<R: Read> DecodingReader<R> {
+    /// Wrap `inner`, decoding it from `encoding`
+    pub fn new(inner: R, encoding: TextEncoding) -> Self {
+        #[cfg(not(feature = "encodings"))]
+        let _ = encoding;
+        Self {
+            inner,
+            #[cfg(feature = "encodings")]
+            decoder: (!encoding.is_utf8())
+                .then(|| encoding.encoding.new_decoder_without_bom_handling()),
+            #[cfg(feature = "encodings")]
+            input: Vec::new(),
+            #[cfg(feature = "encodings")]
+            decoded: Vec::new(),
+            #[cfg(feature = "encodings")]
+            decoded_pos: 0,
+            #[cfg(feature = "encodings")]
+            eof: false,
+        }
+    }
+
+    /// The underlying reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for DecodingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        #[cfg(feature = "encodings")]
+        if let Some(decoder) = &mut self.decoder {
+            while self.decoded_pos == self.decoded.len() {
+                if self.eof && self.input.is_empty() {
+                    return Ok(0);
+                }
+                if !self.eof {
+                    let start = self.input.len();
+                    self.input.resize(start + CHUNK_SIZE, 0);
+                    let read = self.inner.read(&mut self.input[start..]);
+                    let read = read.inspect_err(|_| self.input.truncate(start))?;
+                    self.input.truncate(start + read);
+                    self.eof = read == 0;
+                }
+                let capacity = decoder
+                    .max_utf8_buffer_length(self.input.len())
+                    .unwrap_or(usize::MAX);
+                self.decoded.clear();
+                self.decoded.resize(capacity, 0);
+                let (_, consumed, written, _) =
+                    decoder.decode_to_utf8(&self.input, &mut self.decoded, self.eof);
+                self.input.drain(..consumed);
+                self.decoded.truncate(written);
+                self.decoded_pos = 0;
+            }
+            let len = buf.len().min(self.decoded.len() - self.decoded_pos);
+            buf[..len].copy_from_slice(&self.decoded[self.decoded_pos..][..len]);
+            self.decoded_pos += len;
+            return Ok(len);
+        }
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(feature = "encodings")]
+enum Encoder {
+    Utf16 { big_endian: bool },
+    Legacy(encoding_rs::Encoder),
+}
+
+/// A writer that encodes the UTF-8 written to it on the fly.
+///
+/// Bytes that aren't valid UTF-8 are written as U+FFFD. Call `finish` when
+/// done; dropping the writer loses a character split across the last write.
+pub struct EncodingWriter<W: Write> {
+    inner: W,
+    #[cfg(feature = "encodings")]
+    encoder: Option<Encoder>,
+    /// Start of a character that the next write completes
+    #[cfg(feature = "encodings")]
+    pending: Vec<u8>,
+    #[cfg(feature = "encodings")]
+    encoded: Vec<u8>,
+}
+
+impl<W: Write> EncodingWriter<W> {
+    /// Wrap `inner`, encoding output as `encoding`.
+    ///
+    /// Returns an error if `encoding` can't be written.
+    pub fn new(inner: W, encoding: TextEncoding) -> Result<Self> {
+        #[cfg(feature = "encodings")]
+        let encoder = match encoding.is_utf8() {
+            true => None,
+            false => Some(encoding.encoder().ok_or_else(|| {
+                Error::InvalidArgument(format!(
+                    "Encoding '{}' can't be used for output",
+                    encoding.name()
+                ))
+            })?),
+        };
+        #[cfg(not(feature = "encodings"))]
+        let _ = encoding;
+        Ok(Self {
+            inner,
+            #[cfg(feature = "encodings")]
+            encoder,
+            #[cfg(feature = "encodings")]
+            pending: Vec::new(),
+            #[cfg(feature = "encodings")]
+            encoded: Vec::new(),
+        })
+    }
+
+    /// Write out a character left incomplete by the last write (as U+FFFD),
+    /// flush, and return the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        #[cfg(feature = "encodings")]
+        if !self.pending.is_empty() {
+            self.pending.clear();
+            self.encode(char::REPLACEMENT_CHARACTER.encode_utf8(&mut [0; 4]), true)?;
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    /// Encode `text` and write it to the underlying writer
+    #[cfg(feature = "encodings")]
+    fn encode(&mut self, text: &str, last: bool) -> io::Result<()> {
+        self.encoded.clear();
+        match self.encoder.as_mut() {
+            None => self.encoded.extend_from_slice(text.as_bytes()),
+            Some(Encoder::Utf16 { big_endian }) => {
+                for unit in text.encode_utf16() {
+                    self.encoded.extend_from_slice(&match big_endian {
+                        true => unit.to_be_bytes(),
+                        false => unit.to_le_bytes(),
+                    });
+                }
+            }
+            Some(Encoder::Legacy(encoder)) => {
+                let capacity = encoder
+                    .max_buffer_length_from_utf8_without_replacement(text.len())
+                    .unwrap_or(usize::MAX);
+                self.encoded.resize(capacity, 0);
+                let (result, _, written) =
+                    encoder.encode_from_utf8_without_replacement(text, &mut self.encoded, last);
+                if let encoding_rs::EncoderResult::Unmappable(c) = result {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "'{}' (U+{:04X}) can't be written as {}",
+                            c,
+                            c as u32,
+                            encoder.encoding().name()
+                        ),
+                    ));
+                }
+                self.encoded.truncate(written);
+            }
+        }
+        self.inner.write_all(&self.encoded)
+    }
+}
+
+impl<W: Write> Write for EncodingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        #[cfg(feature = "encodings")]
+        if self.encoder.is_some() {
+            self.pending.extend_from_slice(buf);
+            let mut data = std::mem::take(&mut self.pending);
+            let mut rest = &data[..];
+            while !rest.is_empty() {
+                match std::str::from_utf8(rest) {
+                    Ok(text) => {
+                        self.encode(text, false)?;
+                        rest = &[];
+                    }
+                    Err(e) => {
+                        let (valid, invalid) = rest.split_at(e.valid_up_to());
+                        // Checked by from_utf8
+                        let valid = std::str::from_utf8(valid).unwrap_or_default();
+                        self.encode(valid, false)?;
+                        match e.error_len() {
+                            Some(len) => {
+                                let replacement = char::REPLACEMENT_CHARACTER;
+                                self.encode(replacement.encode_utf8(&mut [0; 4]), false)?;
+                                rest = &invalid[len..];
+                            }
+                            None => {
+                                // The next write may complete the character
+                                self.pending.extend_from_slice(invalid);
+                                rest = &[];
+                            }
+                        }
+                    }
+                }
+            }
+            data.clear();
+            if self.pending.is_empty() {
+                self.pending = data;
+            }
+            return Ok(buf.len());
+        }
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
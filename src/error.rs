@@ -1,48 +1,119 @@
-use std::fmt;
-use std::io;
-
-/// Error types for uniqr
-#[derive(Debug)]
-pub enum Error {
-    /// I/O error
-    Io(io::Error),
-    /// Invalid argument
-    InvalidArgument(String),
-    /// UTF-8 conversion error
-    Utf8Error(std::string::FromUtf8Error),
-}
-
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Error::Io(e) => write!(f, "I/O error: {}", e),
-            Error::InvalidArgument(msg) => write!(f, "Invalid argument: {}", msg),
-            Error::Utf8Error(e) => write!(f, "UTF-8 error: {}", e),
-        }
-    }
-}
-
-impl std::error::Error for Error {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        match self {
-            Error::Io(e) => Some(e),
-            Error::Utf8Error(e) => Some(e),
-            _ => None,
-        }
-    }
-}
-
-impl From<io::Error> for Error {
-    fn from(err: io::Error) -> Self {
-        Error::Io(err)
-    }
-}
-
-impl From<std::string::FromUtf8Error> for Error {
-    fn from(err: std::string::FromUtf8Error) -> Self {
-        Error::Utf8Error(err)
-    }
-}
-
-/// Result type alias for uniqr
-pub type Result<T> = std::result::Result<T, Error>;
+use std::fmt;
+use std::io;
+
+/// Error types for uniqr
+#[derive(Debug)]
+pub enum Error {
+    /// I/O error
+    Io(io::Error),
+    /// Invalid argument
+    InvalidArgument(String),
+    /// UTF-8 conversion error
+    Utf8Error(std::string::FromUtf8Error),
+    /// The key store of disk-backed deduplication (a sled database or the
+    /// temp-file store) failed
+    Storage(Box<dyn std::error::Error + Send + Sync>),
+    /// Seeking back to the start of an input for another pass failed
+    Seek(io::Error),
+    /// Spilling records to a temporary file, or reading them back, failed
+    Spill(io::Error),
+    /// A line was longer than `DeduplicationOptions::max_line_length`
+    /// bytes under `LineLengthPolicy::Error`
+    LineTooLong { max: usize },
+    /// An error reading or building the key of a record, with its position:
+    /// the 1-based record number and the byte offset where the record
+    /// starts, both counted across all inputs
+    AtLine {
+        line: usize,
+        offset: u64,
+        source: Box<Error>,
+    },
+    /// `DeduplicationOptions::cancel` was set. Holds the stats of the run
+    /// so far: the records and bytes read and the bytes written before it
+    /// stopped.
+    Cancelled(Box<crate::DeduplicationStats>),
+}
+
+impl Error {
+    /// The `io::ErrorKind` this error converts to
+    fn kind(&self) -> io::ErrorKind {
+        match self {
+            Error::Io(e) | Error::Seek(e) | Error::Spill(e) => e.kind(),
+            Error::InvalidArgument(_) => io::ErrorKind::InvalidInput,
+            Error::Utf8Error(_) | Error::LineTooLong { .. } => io::ErrorKind::InvalidData,
+            Error::Storage(_) => io::ErrorKind::Other,
+            Error::AtLine { source, .. } => source.kind(),
+            Error::Cancelled(_) => io::ErrorKind::Interrupted,
+        }
+    }
+
+    /// Whether this is a write to a pipe whose reader has gone, as when the
+    /// output is piped into `head`
+    pub fn is_broken_pipe(&self) -> bool {
+        self.kind() == io::ErrorKind::BrokenPipe
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::InvalidArgument(msg) => write!(f, "Invalid argument: {}", msg),
+            Error::Utf8Error(e) => write!(f, "UTF-8 error: {}", e),
+            Error::Storage(e) => write!(f, "Storage error: {}", e),
+            Error::Seek(e) => write!(f, "Seek error: {}", e),
+            Error::Spill(e) => write!(f, "Spill error: {}", e),
+            Error::LineTooLong { max } => write!(f, "Line longer than {} bytes", max),
+            Error::AtLine {
+                line,
+                offset,
+                source,
+            } => write!(f, "Line {} (byte {}): {}", line, offset, source),
+            Error::Cancelled(stats) => {
+                write!(f, "Cancelled after {} records", stats.lines_read)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Utf8Error(e) => Some(e),
+            Error::Storage(e) => Some(e.as_ref()),
+            Error::Seek(e) | Error::Spill(e) => Some(e),
+            Error::AtLine { source, .. } => Some(source.as_ref()),
+            Error::InvalidArgument(_) | Error::LineTooLong { .. } | Error::Cancelled(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        // Unwrap errors of ours that were passed through `io::Error`, such
+        // as those of `RecordReader::read_record`
+        match err.downcast::<Error>() {
+            Ok(inner) => inner,
+            Err(err) => Error::Io(err),
+        }
+    }
+}
+
+impl From<std::string::FromUtf8Error> for Error {
+    fn from(err: std::string::FromUtf8Error) -> Self {
+        Error::Utf8Error(err)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(e) => e,
+            err => io::Error::new(err.kind(), err),
+        }
+    }
+}
+
+/// Result type alias for uniqr
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,244 @@
+//! C ABI for embedding uniqr (requires 'ffi' feature)
+//!
+//! The matching header is `include/uniqr.h`; regenerate it with
+//! `cbindgen --config cbindgen.toml --output include/uniqr.h` after changing
+//! this module.
+use crate::{DeduplicationMode, DeduplicationOptions, DeduplicationStats, Error, RecordSeparator};
+use std::ffi::{CStr, c_char};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+/// Result code returned by every `uniqr_*` function
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UniqrStatus {
+    Ok = 0,
+    /// A required pointer argument was null
+    NullPointer = 1,
+    /// Invalid options, or a path that isn't valid UTF-8
+    InvalidArgument = 2,
+    /// Reading input, writing output or temporary storage failed
+    Io = 3,
+    /// A key was not valid UTF-8 where UTF-8 was required
+    Utf8 = 4,
+    /// An internal error (Rust panic) was caught at the boundary
+    Panic = 5,
+}
+
+/// Deduplication strategy, the values of `UniqrOptions::mode`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UniqrMode {
+    KeepFirst = 0,
+    KeepLast = 1,
+    RemoveAll = 2,
+}
+
+/// C-compatible subset of `DeduplicationOptions`; start from `uniqr_options_default()`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UniqrOptions {
+    /// A `UniqrMode`. Plain `u32` rather than the enum, so a value from C
+    /// outside it is an error instead of undefined behavior
+    pub mode: u32,
+    pub ignore_case: bool,
+    pub count: bool,
+    pub show_removed: bool,
+    /// 1-indexed whitespace-separated key column, or 0 for the whole record
+    pub column: u32,
+    /// Byte that ends each record (`'\n'` by default, `'\0'` for NUL-separated input)
+    pub separator: u8,
+}
+
+/// Deduplication statistics
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UniqrStats {
+    pub lines_read: u64,
+    pub lines_written: u64,
+    pub lines_removed: u64,
+    pub unique_lines: u64,
+}
+
+impl TryFrom<&UniqrOptions> for DeduplicationOptions {
+    type Error = UniqrStatus;
+
+    fn try_from(opts: &UniqrOptions) -> Result<Self, UniqrStatus> {
+        const KEEP_FIRST: u32 = UniqrMode::KeepFirst as u32;
+        const KEEP_LAST: u32 = UniqrMode::KeepLast as u32;
+        const REMOVE_ALL: u32 = UniqrMode::RemoveAll as u32;
+        Ok(Self {
+            mode: match opts.mode {
+                KEEP_FIRST => DeduplicationMode::KeepFirst,
+                KEEP_LAST => DeduplicationMode::KeepLast,
+                REMOVE_ALL => DeduplicationMode::RemoveAll,
+                _ => return Err(UniqrStatus::InvalidArgument),
+            },
+            ignore_case: opts.ignore_case,
+            count: opts.count,
+            show_removed: opts.show_removed,
+            column: (opts.column > 0).then_some(opts.column as usize),
+            record_separator: RecordSeparator::Byte(opts.separator),
+            ..Default::default()
+        })
+    }
+}
+
+impl From<DeduplicationStats> for UniqrStats {
+    fn from(stats: DeduplicationStats) -> Self {
+        Self {
+            lines_read: stats.lines_read as u64,
+            lines_written: stats.lines_written as u64,
+            lines_removed: stats.lines_removed as u64,
+            unique_lines: stats.unique_lines as u64,
+        }
+    }
+}
+
+impl From<Error> for UniqrStatus {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(_) | Error::Storage(_) | Error::Seek(_) | Error::Spill(_) => UniqrStatus::Io,
+            Error::InvalidArgument(_) => UniqrStatus::InvalidArgument,
+            Error::Utf8Error(_) => UniqrStatus::Utf8,
+            Error::LineTooLong { .. } => UniqrStatus::Io,
+            Error::AtLine { source, .. } => (*source).into(),
+            // Not reachable: the C options have no cancellation flag
+            Error::Cancelled(_) => UniqrStatus::Io,
+        }
+    }
+}
+
+/// Default options: keep first, whole-line keys, newline-separated records
+#[unsafe(no_mangle)]
+pub extern "C" fn uniqr_options_default() -> UniqrOptions {
+    UniqrOptions {
+        mode: UniqrMode::KeepFirst as u32,
+        ignore_case: false,
+        count: false,
+        show_removed: false,
+        column: 0,
+        separator: b'\n',
+    }
+}
+
+/// Run `f`, converting errors and panics into a status code
+fn guard(f: impl FnOnce() -> Result<(), UniqrStatus>) -> UniqrStatus {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => UniqrStatus::Ok,
+        Ok(Err(status)) => status,
+        Err(_) => UniqrStatus::Panic,
+    }
+}
+
+/// Deduplicate `input_len` bytes at `input` into a newly allocated buffer.
+///
+/// On success `*output` and `*output_len` describe the result, which must be
+/// released with `uniqr_free_buffer`. `stats` may be null.
+///
+/// # Safety
+///
+/// `input` must point to `input_len` readable bytes (or may be null if
+/// `input_len` is 0). `options`, `output` and `output_len` must be valid
+/// pointers; `stats` must be null or valid.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn uniqr_dedup_buffer(
+    input: *const u8,
+    input_len: usize,
+    options: *const UniqrOptions,
+    output: *mut *mut u8,
+    output_len: *mut usize,
+    stats: *mut UniqrStats,
+) -> UniqrStatus {
+    guard(|| {
+        if (input.is_null() && input_len > 0)
+            || options.is_null()
+            || output.is_null()
+            || output_len.is_null()
+        {
+            return Err(UniqrStatus::NullPointer);
+        }
+        let data: &[u8] = if input_len == 0 {
+            &[]
+        } else {
+            // SAFETY: caller guarantees `input` points to `input_len` bytes
+            unsafe { std::slice::from_raw_parts(input, input_len) }
+        };
+        // SAFETY: checked non-null; caller guarantees validity
+        let options = DeduplicationOptions::try_from(unsafe { &*options })?;
+
+        let mut buffer = Vec::new();
+        let result = crate::deduplicate(data, &mut buffer, &options)?;
+
+        let boxed = buffer.into_boxed_slice();
+        // SAFETY: checked non-null; caller guarantees validity
+        unsafe {
+            *output_len = boxed.len();
+            *output = Box::into_raw(boxed).cast::<u8>();
+            if !stats.is_null() {
+                *stats = result.into();
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Free a buffer returned by `uniqr_dedup_buffer`
+///
+/// # Safety
+///
+/// `buffer` and `len` must come from a single successful `uniqr_dedup_buffer`
+/// call, and the buffer must not be freed twice. Null is ignored.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn uniqr_free_buffer(buffer: *mut u8, len: usize) {
+    if !buffer.is_null() {
+        // SAFETY: caller guarantees the pointer came from a boxed slice of `len`
+        drop(unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(buffer, len)) });
+    }
+}
+
+/// Deduplicate the file at `input_path` into `output_path` (UTF-8 paths).
+///
+/// The output is written atomically with `write_atomically`, so it may be
+/// the input file itself. `stats` may be null.
+///
+/// # Safety
+///
+/// `input_path` and `output_path` must be valid NUL-terminated strings and
+/// `options` a valid pointer; `stats` must be null or valid.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn uniqr_dedup_file(
+    input_path: *const c_char,
+    output_path: *const c_char,
+    options: *const UniqrOptions,
+    stats: *mut UniqrStats,
+) -> UniqrStatus {
+    guard(|| {
+        if input_path.is_null() || output_path.is_null() || options.is_null() {
+            return Err(UniqrStatus::NullPointer);
+        }
+        // SAFETY: checked non-null; caller guarantees NUL-terminated strings
+        let (input_path, output_path) =
+            unsafe { (CStr::from_ptr(input_path), CStr::from_ptr(output_path)) };
+        let input_path = input_path
+            .to_str()
+            .map_err(|_| UniqrStatus::InvalidArgument)?;
+        let output_path = std::path::Path::new(
+            output_path
+                .to_str()
+                .map_err(|_| UniqrStatus::InvalidArgument)?,
+        );
+        // SAFETY: checked non-null; caller guarantees validity
+        let options = DeduplicationOptions::try_from(unsafe { &*options })?;
+
+        let input = std::fs::File::open(input_path).map_err(Error::Io)?;
+        let result = crate::write_atomically(output_path, |writer| {
+            crate::deduplicate_seekable(input, writer, &options)
+        })?;
+
+        if !stats.is_null() {
+            // SAFETY: checked non-null; caller guarantees validity
+            unsafe { *stats = result.into() };
+        }
+        Ok(())
+    })
+}
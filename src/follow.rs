@@ -0,0 +1,64 @@
+//! Read inputs that are still being written, like `tail -f`
+use std::io::{self, Read};
+use std::time::Duration;
+
+/// How long `FollowReader` waits before checking for new data by default
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Input wrapper that waits for more data at end of input instead of
+/// returning EOF, so deduplication continues as a file grows.
+///
+/// Reads never end, so it only suits algorithms that write as they go:
+/// `KeepFirst` without counts, with `ensure_trailing_newline` set so each
+/// line's terminator isn't held back until the next one arrives. Like `tail -f` (not `tail -F`), it keeps
+/// reading the same file handle, so a log that is rotated away is not
+/// reopened.
+///
+/// ```no_run
+/// use uniqr::{deduplicate, DeduplicationOptions, FollowReader};
+///
+/// let log = std::fs::File::open("app.log").unwrap();
+/// // Line-buffered, so each new line is shown as soon as it is kept
+/// let mut stdout = std::io::stdout().lock();
+/// let options = DeduplicationOptions {
+///     ensure_trailing_newline: true,
+///     ..Default::default()
+/// };
+/// deduplicate(FollowReader::new(log), &mut stdout, &options).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct FollowReader<R> {
+    inner: R,
+    interval: Duration,
+}
+
+impl<R> FollowReader<R> {
+    /// Follow `inner`, polling for new data every `DEFAULT_POLL_INTERVAL`
+    pub fn new(inner: R) -> Self {
+        Self::with_interval(inner, DEFAULT_POLL_INTERVAL)
+    }
+
+    /// Follow `inner`, polling for new data every `interval`
+    pub fn with_interval(inner: R, interval: Duration) -> Self {
+        Self { inner, interval }
+    }
+
+    /// Unwrap the underlying reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for FollowReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            match self.inner.read(buf)? {
+                0 => std::thread::sleep(self.interval),
+                read => return Ok(read),
+            }
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! BK-tree of keys for near-duplicate (edit distance) deduplication
+
+/// Keys indexed by Levenshtein distance, for finding a stored key within a
+/// fixed number of edits of a new one without comparing against every key.
+///
+/// Each child edge is labelled with its distance to the parent, so by the
+/// triangle inequality a search only descends into children whose label is
+/// within `max_distance` of the query's distance to the parent.
+pub(crate) struct BkTree {
+    max_distance: usize,
+    nodes: Vec<Node>,
+}
+
+struct Node {
+    key: Vec<u8>,
+    children: Vec<(usize, usize)>,
+}
+
+impl BkTree {
+    pub(crate) fn new(max_distance: usize) -> Self {
+        Self {
+            max_distance,
+            nodes: Vec::new(),
+        }
+    }
+
+    /// Number of keys stored
+    pub(crate) fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// The key stored as node `node`
+    pub(crate) fn key(&self, node: usize) -> &[u8] {
+        &self.nodes[node].key
+    }
+
+    /// A stored key within `max_distance` edits of `key`, if any; the
+    /// earliest stored of the closest ones
+    pub(crate) fn find(&self, key: &[u8]) -> Option<usize> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let mut best: Option<(usize, usize)> = None;
+        let mut pending = vec![0];
+        while let Some(node) = pending.pop() {
+            let distance = levenshtein(key, &self.nodes[node].key);
+            if distance <= self.max_distance && best.is_none_or(|best| (distance, node) < best) {
+                best = Some((distance, node));
+            }
+            let low = distance.saturating_sub(self.max_distance);
+            let high = distance + self.max_distance;
+            pending.extend(
+                self.nodes[node]
+                    .children
+                    .iter()
+                    .filter(|&&(edge, _)| (low..=high).contains(&edge))
+                    .map(|&(_, child)| child),
+            );
+        }
+        best.map(|(_, node)| node)
+    }
+
+    /// Store `key`, returning its node number
+    pub(crate) fn insert(&mut self, key: Vec<u8>) -> usize {
+        let new = self.nodes.len();
+        if new > 0 {
+            let mut node = 0;
+            loop {
+                let distance = levenshtein(&key, &self.nodes[node].key);
+                match self.nodes[node]
+                    .children
+                    .iter()
+                    .find(|&&(edge, _)| edge == distance)
+                {
+                    Some(&(_, child)) => node = child,
+                    None => {
+                        self.nodes[node].children.push((distance, new));
+                        break;
+                    }
+                }
+            }
+        }
+        self.nodes.push(Node {
+            key,
+            children: Vec::new(),
+        });
+        new
+    }
+}
+
+/// Number of single-byte insertions, deletions and substitutions that turn
+/// `a` into `b`
+pub(crate) fn levenshtein(a: &[u8], b: &[u8]) -> usize {
+    // Common prefixes and suffixes never need edits
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let suffix = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a, b) = (&a[..a.len() - suffix], &b[..b.len() - suffix]);
+    if a.is_empty() || b.is_empty() {
+        return a.len().max(b.len());
+    }
+
+    // One row of the edit-distance table at a time
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, &x) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &y) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(x != y);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
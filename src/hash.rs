@@ -0,0 +1,145 @@
+//! Runtime-selectable hashing for the maps that track seen keys
+use crate::error::{Error, Result};
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+
+/// Hash function used for the in-memory key maps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    /// The standard library's randomly seeded SipHash: DoS-resistant
+    Std,
+    /// aHash: fast and randomly seeded (requires 'fast-hash' feature; default when enabled)
+    AHash,
+    /// xxHash64 with a random seed: very fast, not DoS-resistant (requires 'xxhash' feature)
+    XxHash,
+    /// Keyed BLAKE3: cryptographically strong and slowest (requires 'blake3' feature)
+    Blake3,
+}
+
+impl Default for HashAlgorithm {
+    fn default() -> Self {
+        if cfg!(feature = "fast-hash") {
+            HashAlgorithm::AHash
+        } else {
+            HashAlgorithm::Std
+        }
+    }
+}
+
+/// Map from keys to `V` hashed with the selected algorithm
+pub(crate) type KeyMap<K, V> = HashMap<K, V, KeyHasherBuilder>;
+
+/// Create an empty key map hashed with `algorithm`
+pub(crate) fn key_map<K, V>(algorithm: HashAlgorithm) -> Result<KeyMap<K, V>> {
+    Ok(HashMap::with_hasher(KeyHasherBuilder::new(algorithm)?))
+}
+
+/// Randomly seeded state for the selected algorithm
+#[derive(Clone)]
+pub(crate) enum KeyHasherBuilder {
+    Std(std::hash::RandomState),
+    #[cfg(feature = "fast-hash")]
+    AHash(ahash::RandomState),
+    #[cfg(feature = "xxhash")]
+    XxHash(u64),
+    #[cfg(feature = "blake3")]
+    Blake3([u8; 32]),
+}
+
+impl KeyHasherBuilder {
+    fn new(algorithm: HashAlgorithm) -> Result<Self> {
+        match algorithm {
+            HashAlgorithm::Std => Ok(KeyHasherBuilder::Std(std::hash::RandomState::new())),
+            #[cfg(feature = "fast-hash")]
+            HashAlgorithm::AHash => Ok(KeyHasherBuilder::AHash(ahash::RandomState::new())),
+            #[cfg(feature = "xxhash")]
+            HashAlgorithm::XxHash => Ok(KeyHasherBuilder::XxHash(random_u64(0))),
+            #[cfg(feature = "blake3")]
+            HashAlgorithm::Blake3 => {
+                let mut key = [0; 32];
+                for (i, chunk) in key.chunks_mut(8).enumerate() {
+                    chunk.copy_from_slice(&random_u64(i as u64).to_le_bytes());
+                }
+                Ok(KeyHasherBuilder::Blake3(key))
+            }
+            #[allow(unreachable_patterns)]
+            other => Err(Error::InvalidArgument(format!(
+                "{:?} hashing requires the '{}' feature",
+                other,
+                match other {
+                    HashAlgorithm::AHash => "fast-hash",
+                    HashAlgorithm::XxHash => "xxhash",
+                    _ => "blake3",
+                }
+            ))),
+        }
+    }
+}
+
+/// Random seed material from the standard library's per-process keys
+#[cfg(any(feature = "xxhash", feature = "blake3"))]
+fn random_u64(salt: u64) -> u64 {
+    std::hash::RandomState::new().hash_one(salt)
+}
+
+impl BuildHasher for KeyHasherBuilder {
+    type Hasher = KeyHasher;
+
+    fn build_hasher(&self) -> KeyHasher {
+        match self {
+            KeyHasherBuilder::Std(state) => KeyHasher::Std(state.build_hasher()),
+            #[cfg(feature = "fast-hash")]
+            KeyHasherBuilder::AHash(state) => KeyHasher::AHash(state.build_hasher()),
+            #[cfg(feature = "xxhash")]
+            KeyHasherBuilder::XxHash(seed) => {
+                KeyHasher::XxHash(twox_hash::XxHash64::with_seed(*seed))
+            }
+            #[cfg(feature = "blake3")]
+            KeyHasherBuilder::Blake3(key) => {
+                KeyHasher::Blake3(Box::new(blake3::Hasher::new_keyed(key)))
+            }
+        }
+    }
+}
+
+/// Hasher for the selected algorithm
+pub(crate) enum KeyHasher {
+    Std(std::hash::DefaultHasher),
+    #[cfg(feature = "fast-hash")]
+    AHash(ahash::AHasher),
+    #[cfg(feature = "xxhash")]
+    XxHash(twox_hash::XxHash64),
+    #[cfg(feature = "blake3")]
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher for KeyHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        match self {
+            KeyHasher::Std(hasher) => hasher.write(bytes),
+            #[cfg(feature = "fast-hash")]
+            KeyHasher::AHash(hasher) => hasher.write(bytes),
+            #[cfg(feature = "xxhash")]
+            KeyHasher::XxHash(hasher) => hasher.write(bytes),
+            #[cfg(feature = "blake3")]
+            KeyHasher::Blake3(hasher) => {
+                hasher.update(bytes);
+            }
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        match self {
+            KeyHasher::Std(hasher) => hasher.finish(),
+            #[cfg(feature = "fast-hash")]
+            KeyHasher::AHash(hasher) => hasher.finish(),
+            #[cfg(feature = "xxhash")]
+            KeyHasher::XxHash(hasher) => hasher.finish(),
+            #[cfg(feature = "blake3")]
+            KeyHasher::Blake3(hasher) => {
+                let hash = hasher.finalize();
+                u64::from_le_bytes(hash.as_bytes()[..8].try_into().unwrap())
+            }
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! Deduplicate iterators and slices of lines without going through
+//! `Read`/`Write`
+//!
+//! Each item is one record, compared as-is (no terminator is stripped).
+//! Options that only affect input framing or output formatting (separators,
+//! headers, `count`, `show_removed`, source annotations) don't apply here.
+use crate::error::{Error, Result};
+use crate::hash::{KeyMap, key_map};
+use crate::record::is_empty_record;
+use crate::{DeduplicationMode, DeduplicationOptions, EmptyLinePolicy, is_excluded, make_key};
+
+/// Deduplicate `lines`, yielding the kept lines in order.
+///
+/// `KeepFirst` is lazy; the other modes consume the input on the first call
+/// to `next`.
+///
+/// ```
+/// use uniqr::{DeduplicationOptions, iter::dedup_lines};
+///
+/// let lines = ["a", "b", "a"].map(String::from);
+/// let kept: Vec<String> = dedup_lines(lines, &DeduplicationOptions::default())
+///     .collect::<uniqr::Result<_>>()
+///     .unwrap();
+/// assert_eq!(kept, ["a", "b"]);
+/// ```
+pub fn dedup_lines<I>(lines: I, options: &DeduplicationOptions) -> DedupLines<I::IntoIter>
+where
+    I: IntoIterator<Item = String>,
+{
+    DedupLines {
+        lines: lines.into_iter(),
+        options: options.clone(),
+        state: State::Start,
+    }
+}
+
+/// Deduplicate `lines`, returning the kept lines in order with the number of
+/// times each one's key occurred
+pub fn dedup_lines_counted<I>(
+    lines: I,
+    options: &DeduplicationOptions,
+) -> Result<std::vec::IntoIter<(String, usize)>>
+where
+    I: IntoIterator<Item = String>,
+{
+    let lines: Vec<String> = lines.into_iter().collect();
+    let counts = kept_counts(lines.iter().map(String::as_bytes), options)?;
+    let kept: Vec<(String, usize)> = lines
+        .into_iter()
+        .zip(counts)
+        .filter_map(|(line, count)| Some((line, count?)))
+        .collect();
+    Ok(kept.into_iter())
+}
+
+/// Deduplicate lines already in memory, returning copies of the kept lines
+/// in order
+///
+/// ```
+/// use uniqr::{DeduplicationOptions, iter::deduplicate_lines};
+///
+/// let lines = ["a", "b", "a"].map(String::from);
+/// let kept = deduplicate_lines(&lines, &DeduplicationOptions::default()).unwrap();
+/// assert_eq!(kept, ["a", "b"]);
+/// ```
+pub fn deduplicate_lines<S: AsRef<str>>(
+    lines: &[S],
+    options: &DeduplicationOptions,
+) -> Result<Vec<String>> {
+    let counts = kept_counts(lines.iter().map(|line| line.as_ref().as_bytes()), options)?;
+    Ok(lines
+        .iter()
+        .zip(counts)
+        .filter(|(_, count)| count.is_some())
+        .map(|(line, _)| line.as_ref().to_string())
+        .collect())
+}
+
+/// Deduplicate `lines` in place, removing the lines that aren't kept; the
+/// remaining lines keep their order
+pub fn deduplicate_lines_in_place(
+    lines: &mut Vec<String>,
+    options: &DeduplicationOptions,
+) -> Result<()> {
+    let mut counts = kept_counts(lines.iter().map(String::as_bytes), options)?.into_iter();
+    lines.retain(|_| counts.next().flatten().is_some());
+    Ok(())
+}
+
+/// For each of `lines`, the number of times its key occurs if `options`
+/// keeps it, or `None` if it is removed (or its key is excluded)
+fn kept_counts<'a, I>(lines: I, options: &DeduplicationOptions) -> Result<Vec<Option<usize>>>
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    validate(options)?;
+    let mut index: KeyMap<Vec<u8>, usize> = key_map(options.hasher)?;
+    // First index, last index, and count of each distinct key
+    let mut entries: Vec<(usize, usize, usize)> = Vec::new();
+    let mut line_entries = Vec::new();
+
+    for (idx, line) in lines.into_iter().enumerate() {
+        let dropped = options.empty_lines == EmptyLinePolicy::Drop && is_empty_record(line);
+        let key = make_key(line, options)?;
+        if dropped || is_excluded(&key, options) {
+            line_entries.push(None);
+            continue;
+        }
+        let next = entries.len();
+        let entry_idx = *index.entry(key).or_insert(next);
+        if entry_idx == next {
+            entries.push((idx, idx, 0));
+        }
+        let entry = &mut entries[entry_idx];
+        entry.1 = idx;
+        entry.2 += 1;
+        line_entries.push(Some(entry_idx));
+    }
+
+    Ok(line_entries
+        .into_iter()
+        .enumerate()
+        .map(|(idx, entry_idx)| {
+            let (first, last, count) = entries[entry_idx?];
+            options.mode.keeps(idx, first, last, count).then_some(count)
+        })
+        .collect())
+}
+
+/// Iterator returned by `dedup_lines`
+pub struct DedupLines<I> {
+    lines: I,
+    options: DeduplicationOptions,
+    state: State,
+}
+
+enum State {
+    Start,
+    /// KeepFirst: occurrence count of each key seen so far
+    Streaming(KeyMap<Vec<u8>, usize>),
+    /// Other modes: the kept lines, computed up front
+    Buffered(std::vec::IntoIter<(String, usize)>),
+    Done,
+}
+
+impl<I: Iterator<Item = String>> Iterator for DedupLines<I> {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let State::Start = self.state {
+            let state = if self.options.mode == DeduplicationMode::KeepFirst {
+                validate(&self.options)
+                    .and_then(|()| key_map(self.options.hasher))
+                    .map(State::Streaming)
+            } else {
+                dedup_lines_counted(self.lines.by_ref(), &self.options).map(State::Buffered)
+            };
+            match state {
+                Ok(state) => self.state = state,
+                Err(e) => {
+                    self.state = State::Done;
+                    return Some(Err(e));
+                }
+            }
+        }
+
+        match &mut self.state {
+            State::Streaming(seen) => {
+                for line in self.lines.by_ref() {
+                    if self.options.empty_lines == EmptyLinePolicy::Drop
+                        && is_empty_record(line.as_bytes())
+                    {
+                        continue;
+                    }
+                    let key = match make_key(line.as_bytes(), &self.options) {
+                        Ok(key) => key,
+                        Err(e) => return Some(Err(e)),
+                    };
+                    if is_excluded(&key, &self.options) {
+                        continue;
+                    }
+                    let count = seen.entry(key).or_insert(0);
+                    *count += 1;
+                    if *count == 1 {
+                        return Some(Ok(line));
+                    }
+                }
+                None
+            }
+            State::Buffered(kept) => kept.next().map(|(line, _)| Ok(line)),
+            State::Start | State::Done => None,
+        }
+    }
+}
+
+/// Extension methods for deduplicating iterators of lines
+pub trait UniqExt: Iterator<Item = String> + Sized {
+    /// Deduplicate this iterator; see `dedup_lines`
+    fn uniqr(self, options: &DeduplicationOptions) -> DedupLines<Self> {
+        dedup_lines(self, options)
+    }
+
+    /// Deduplicate this iterator, with occurrence counts; see `dedup_lines_counted`
+    fn uniqr_counted(
+        self,
+        options: &DeduplicationOptions,
+    ) -> Result<std::vec::IntoIter<(String, usize)>> {
+        dedup_lines_counted(self, options)
+    }
+}
+
+impl<I: Iterator<Item = String>> UniqExt for I {}
+
+fn validate(options: &DeduplicationOptions) -> Result<()> {
+    // Options that need the streaming algorithms, by name
+    let unsupported = [
+        (options.approximate.is_some(), "approximate deduplication"),
+        (options.fuzzy.is_some(), "fuzzy deduplication"),
+        (options.window.is_some(), "windowed deduplication"),
+        (
+            matches!(options.mode, DeduplicationMode::Similar { .. }),
+            "similar mode",
+        ),
+        (options.use_disk, "disk-backed storage"),
+        (options.memory_limit.is_some(), "a memory limit"),
+        (options.adjacent, "adjacent deduplication"),
+        (options.group.is_some(), "grouping"),
+        (options.sort.is_some(), "sorted output"),
+        (options.max_line_length.is_some(), "a line length limit"),
+        (options.passthrough.is_some(), "a pass-through filter"),
+        (
+            options.empty_lines == EmptyLinePolicy::Preserve,
+            "preserved empty lines",
+        ),
+        (options.header_lines > 0, "a header"),
+        (options.footer_lines > 0, "a footer"),
+    ];
+    match unsupported.iter().find(|(used, _)| *used) {
+        Some((_, what)) => Err(Error::InvalidArgument(format!(
+            "Line iterators don't support {}",
+            what
+        ))),
+        None => Ok(()),
+    }
+}
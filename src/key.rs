@@ -0,0 +1,37 @@
+//! Pluggable key extraction
+use crate::error::Result;
+use crate::{DeduplicationOptions, make_key};
+use std::fmt;
+
+/// Computes the comparison key for a record.
+///
+/// The record is passed with its terminator already stripped. Records with
+/// equal keys are duplicates. `DeduplicationOptions` itself is the default
+/// extractor (columns, trimming, case folding, ...); set
+/// `DeduplicationOptions::key_extractor` to replace it entirely.
+///
+/// Closures of type `Fn(&[u8]) -> Vec<u8>` implement this trait.
+pub trait KeyExtractor: Send + Sync {
+    fn extract_key(&self, record: &[u8]) -> Result<Vec<u8>>;
+}
+
+impl<F> KeyExtractor for F
+where
+    F: Fn(&[u8]) -> Vec<u8> + Send + Sync,
+{
+    fn extract_key(&self, record: &[u8]) -> Result<Vec<u8>> {
+        Ok(self(record))
+    }
+}
+
+impl KeyExtractor for DeduplicationOptions {
+    fn extract_key(&self, record: &[u8]) -> Result<Vec<u8>> {
+        make_key(record, self)
+    }
+}
+
+impl fmt::Debug for dyn KeyExtractor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("KeyExtractor")
+    }
+}
@@ -1,903 +1,7390 @@
-//! # uniqr
-//!
-//! A library for line deduplication with various strategies.
-//!
-//! ## Example
-//!
-//! ```
-//! use uniqr::{deduplicate, DeduplicationMode, DeduplicationOptions};
-//! use std::io::Cursor;
-//!
-//! let input = b"line1\nline2\nline1\nline3\n";
-//! let mut output = Vec::new();
-//!
-//! let options = DeduplicationOptions {
-//!     mode: DeduplicationMode::KeepFirst,
-//!     ignore_case: false,
-//!     count: false,
-//!     show_removed: false,
-//!     column: None,
-//!     use_disk: false,
-//! };
-//!
-//! deduplicate(Cursor::new(input), &mut output, &options).unwrap();
-//! assert_eq!(output, b"line1\nline2\nline3\n");
-//! ```
-
-use std::collections::HashSet;
-use std::io::{BufRead, BufReader, Write};
-
-#[cfg(feature = "fast-hash")]
-use ahash::HashMap as AHashMap;
-
-#[cfg(not(feature = "fast-hash"))]
-use std::collections::HashMap;
-
-pub mod error;
-pub use error::{Error, Result};
-
-/// Deduplication strategy
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum DeduplicationMode {
-    /// Keep first occurrence of each line (default)
-    KeepFirst,
-    /// Keep last occurrence of each line (two-pass)
-    KeepLast,
-    /// Remove all lines that appear more than once (two-pass)
-    RemoveAll,
-}
-
-/// Options for deduplication
-#[derive(Debug, Clone)]
-pub struct DeduplicationOptions {
-    pub mode: DeduplicationMode,
-    pub ignore_case: bool,
-    pub count: bool,
-    pub show_removed: bool,
-    pub column: Option<usize>,
-    /// Use disk-backed storage for massive files (requires 'disk-backed' feature)
-    pub use_disk: bool,
-}
-
-impl Default for DeduplicationOptions {
-    fn default() -> Self {
-        Self {
-            mode: DeduplicationMode::KeepFirst,
-            ignore_case: false,
-            count: false,
-            show_removed: false,
-            column: None,
-            use_disk: false,
-        }
-    }
-}
-
-/// Statistics about deduplication
-#[derive(Debug, Default)]
-pub struct DeduplicationStats {
-    pub lines_read: usize,
-    pub lines_written: usize,
-    pub lines_removed: usize,
-    pub unique_lines: usize,
-}
-
-/// Main deduplication function (safe for non-seekable streams)
-///
-/// Note: This function cannot perform disk-backed two-pass deduplication
-/// (`KeepLast` or `RemoveAll` with `use_disk: true`) because they require
-/// a seekable input source. Use `deduplicate_seekable` for those cases.
-pub fn deduplicate<R: std::io::Read, W: Write>(
-    input: R,
-    output: &mut W,
-    options: &DeduplicationOptions,
-) -> Result<DeduplicationStats> {
-    #[cfg(feature = "disk-backed")]
-    if options.use_disk {
-        match options.mode {
-            DeduplicationMode::KeepFirst => {
-                return deduplicate_keep_first_disk(input, output, options);
-            }
-            DeduplicationMode::KeepLast | DeduplicationMode::RemoveAll => {
-                return Err(Error::InvalidArgument(
-                    "Disk-backed KeepLast and RemoveAll modes require a seekable input. Use deduplicate_seekable() or provide a file.".to_string(),
-                ));
-            }
-        }
-    }
-
-    let stats = match options.mode {
-        DeduplicationMode::KeepFirst => deduplicate_keep_first(input, output, options),
-        DeduplicationMode::KeepLast => deduplicate_keep_last(input, output, options),
-        DeduplicationMode::RemoveAll => deduplicate_remove_all(input, output, options),
-    }?;
-    output.flush()?;
-    Ok(stats)
-}
-
-/// Deduplication function for seekable inputs (supports all modes)
-pub fn deduplicate_seekable<R: std::io::Read + std::io::Seek, W: Write>(
-    input: R,
-    output: &mut W,
-    options: &DeduplicationOptions,
-) -> Result<DeduplicationStats> {
-    #[cfg(feature = "disk-backed")]
-    if options.use_disk {
-        match options.mode {
-            DeduplicationMode::KeepLast => {
-                return deduplicate_keep_last_disk(input, output, options);
-            }
-            DeduplicationMode::RemoveAll => {
-                return deduplicate_remove_all_disk(input, output, options);
-            }
-            _ => {
-                // KeepFirst (disk) and in-memory modes don't strictly *need* Seek,
-                // so we can delegate to the standard function.
-                return deduplicate(input, output, options);
-            }
-        }
-    }
-
-    // Default to standard deduplicate if disk-backed is not used
-    let stats = deduplicate(input, output, options)?;
-    output.flush()?;
-    Ok(stats)
-}
-
-/// One-pass keep-first algorithm
-fn deduplicate_keep_first<R: std::io::Read, W: Write>(
-    input: R,
-    output: &mut W,
-    options: &DeduplicationOptions,
-) -> Result<DeduplicationStats> {
-    let mut reader = BufReader::new(input);
-    let mut stats = DeduplicationStats::default();
-
-    #[cfg(feature = "fast-hash")]
-    type MapType = AHashMap<Vec<u8>, usize>;
-
-    #[cfg(not(feature = "fast-hash"))]
-    type MapType = HashMap<Vec<u8>, usize>;
-
-    let mut seen: MapType = MapType::default();
-    let mut lines_for_count = Vec::new();
-
-    let mut line = Vec::new();
-    while reader.read_until(b'\n', &mut line)? > 0 {
-        stats.lines_read += 1;
-
-        // Strip newline for key generation but keep for output
-        let key_line = if line.ends_with(b"\n") {
-            if line.ends_with(b"\r\n") {
-                &line[..line.len() - 2]
-            } else {
-                &line[..line.len() - 1]
-            }
-        } else {
-            &line[..]
-        };
-
-        let key = make_key(key_line, options)?;
-        let count = seen.entry(key).or_insert(0);
-        *count += 1;
-
-        if *count == 1 {
-            if options.count {
-                lines_for_count.push(line.clone());
-            } else {
-                output.write_all(&line)?;
-            }
-            stats.lines_written += 1;
-        } else {
-            stats.lines_removed += 1;
-            if options.show_removed {
-                write!(output, "[REMOVED] ")?;
-                output.write_all(&line)?;
-            }
-        }
-        line.clear();
-    }
-
-    stats.unique_lines = seen.len();
-
-    // Write counts if requested
-    if options.count {
-        for line in lines_for_count {
-            let _key = make_key(&line, options)?; // Correct key generation logic needed here too if stripping happened above, but line has newline now.
-            // Actually, lines_for_count stores full lines with newlines.
-            // make_key expects just content. We need to strip again or refactor make_key.
-            // Let's strip locally.
-            let key_line = if line.ends_with(b"\n") {
-                if line.ends_with(b"\r\n") {
-                    &line[..line.len() - 2]
-                } else {
-                    &line[..line.len() - 1]
-                }
-            } else {
-                &line[..]
-            };
-
-            let key = make_key(key_line, options)?;
-
-            if let Some(&cnt) = seen.get(&key) {
-                write!(output, "{:>7} ", cnt)?;
-                output.write_all(&line)?;
-            }
-        }
-    }
-
-    Ok(stats)
-}
-
-/// Two-pass keep-last algorithm
-fn deduplicate_keep_last<R: std::io::Read, W: Write>(
-    input: R,
-    output: &mut W,
-    options: &DeduplicationOptions,
-) -> Result<DeduplicationStats> {
-    let mut reader = BufReader::new(input);
-    let mut stats = DeduplicationStats::default();
-
-    #[cfg(feature = "fast-hash")]
-    type MapType = AHashMap<Vec<u8>, (usize, Vec<u8>)>;
-
-    #[cfg(not(feature = "fast-hash"))]
-    type MapType = HashMap<Vec<u8>, (usize, Vec<u8>)>;
-
-    let mut last_occurrence: MapType = MapType::default();
-    let mut lines = Vec::new();
-
-    // First pass: read all lines and track last occurrence
-    let mut line = Vec::new();
-    while reader.read_until(b'\n', &mut line)? > 0 {
-        stats.lines_read += 1;
-
-        let key_line = if line.ends_with(b"\n") {
-            if line.ends_with(b"\r\n") {
-                &line[..line.len() - 2]
-            } else {
-                &line[..line.len() - 1]
-            }
-        } else {
-            &line[..]
-        };
-
-        let key = make_key(key_line, options)?;
-        last_occurrence.insert(key, (stats.lines_read - 1, line.clone()));
-        lines.push(line.clone());
-        line.clear();
-    }
-
-    stats.unique_lines = last_occurrence.len();
-
-    // Build set of indices to keep
-    let kept_indices: HashSet<usize> = last_occurrence.values().map(|(idx, _)| *idx).collect();
-
-    // Second pass: emit only last occurrences in order
-    for (idx, line) in lines.iter().enumerate() {
-        if kept_indices.contains(&idx) {
-            if options.count {
-                let key_line = if line.ends_with(b"\n") {
-                    if line.ends_with(b"\r\n") {
-                        &line[..line.len() - 2]
-                    } else {
-                        &line[..line.len() - 1]
-                    }
-                } else {
-                    &line[..]
-                };
-
-                let key = make_key(key_line, options)?;
-                let count = lines
-                    .iter()
-                    .filter(|l| {
-                        let l_key_line = if l.ends_with(b"\n") {
-                            if l.ends_with(b"\r\n") {
-                                &l[..l.len() - 2]
-                            } else {
-                                &l[..l.len() - 1]
-                            }
-                        } else {
-                            &l[..]
-                        };
-                        make_key(l_key_line, options).ok() == Some(key.clone())
-                    })
-                    .count();
-                write!(output, "{:>7} ", count)?;
-            }
-            output.write_all(line)?;
-            stats.lines_written += 1;
-        } else {
-            stats.lines_removed += 1;
-            if options.show_removed {
-                write!(output, "[REMOVED] ")?;
-                output.write_all(line)?;
-            }
-        }
-    }
-
-    Ok(stats)
-}
-
-/// Two-pass remove-all algorithm
-fn deduplicate_remove_all<R: std::io::Read, W: Write>(
-    input: R,
-    output: &mut W,
-    options: &DeduplicationOptions,
-) -> Result<DeduplicationStats> {
-    let mut reader = BufReader::new(input);
-    let mut stats = DeduplicationStats::default();
-
-    #[cfg(feature = "fast-hash")]
-    type MapType = AHashMap<Vec<u8>, usize>;
-
-    #[cfg(not(feature = "fast-hash"))]
-    type MapType = HashMap<Vec<u8>, usize>;
-
-    let mut counts: MapType = MapType::default();
-    let mut lines = Vec::new();
-
-    // First pass: count all occurrences
-    let mut line = Vec::new();
-    while reader.read_until(b'\n', &mut line)? > 0 {
-        stats.lines_read += 1;
-
-        let key_line = if line.ends_with(b"\n") {
-            if line.ends_with(b"\r\n") {
-                &line[..line.len() - 2]
-            } else {
-                &line[..line.len() - 1]
-            }
-        } else {
-            &line[..]
-        };
-
-        let key = make_key(key_line, options)?;
-        *counts.entry(key).or_insert(0) += 1;
-        lines.push(line.clone());
-        line.clear();
-    }
-
-    // Count unique lines (those appearing exactly once)
-    stats.unique_lines = counts.values().filter(|&&c| c == 1).count();
-
-    // Second pass: emit only lines that appear exactly once
-    for line in lines {
-        let key_line = if line.ends_with(b"\n") {
-            if line.ends_with(b"\r\n") {
-                &line[..line.len() - 2]
-            } else {
-                &line[..line.len() - 1]
-            }
-        } else {
-            &line[..]
-        };
-
-        let key = make_key(key_line, options)?;
-        let count = counts.get(&key).copied().unwrap_or(0);
-
-        if count == 1 {
-            if options.count {
-                write!(output, "{:>7} ", count)?;
-            }
-            output.write_all(&line)?;
-            stats.lines_written += 1;
-        } else {
-            stats.lines_removed += 1;
-            if options.show_removed {
-                write!(output, "[REMOVED] ")?;
-                output.write_all(&line)?;
-            }
-        }
-    }
-
-    Ok(stats)
-}
-
-/// Create deduplication key from line
-fn make_key(line: &[u8], options: &DeduplicationOptions) -> Result<Vec<u8>> {
-    let data = if let Some(col_idx) = options.column {
-        // Extract column (1-indexed) using whitespace splitting
-        // This handles standard whitespace separation more robustly than manual byte checks
-        let text = String::from_utf8_lossy(line);
-        let cols: Vec<&str> = text.split_whitespace().collect();
-
-        if col_idx > 0 && col_idx <= cols.len() {
-            // We need to return an owned Vec<u8> because text is temporary
-            cols[col_idx - 1].as_bytes().to_vec()
-        } else {
-            line.to_vec()
-        }
-    } else {
-        line.to_vec()
-    };
-
-    if options.ignore_case {
-        // Try to convert to lowercase UTF-8
-        match std::str::from_utf8(&data) {
-            Ok(s) => Ok(s.to_lowercase().into_bytes()),
-            Err(_) => Ok(data),
-        }
-    } else {
-        Ok(data)
-    }
-}
-
-/// Disk-backed keep-first algorithm using sled
-#[cfg(feature = "disk-backed")]
-fn deduplicate_keep_first_disk<R: std::io::Read, W: Write>(
-    input: R,
-    output: &mut W,
-    options: &DeduplicationOptions,
-) -> Result<DeduplicationStats> {
-    use sled::Db;
-
-    let mut reader = BufReader::new(input);
-    let mut stats = DeduplicationStats::default();
-
-    // Create temporary sled database
-    let db: Db = sled::Config::new()
-        .temporary(true)
-        .open()
-        .map_err(|e| Error::InvalidArgument(format!("Failed to create temp database: {}", e)))?;
-
-    let mut lines_for_count: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
-
-    let mut line = Vec::new();
-    while reader.read_until(b'\n', &mut line)? > 0 {
-        stats.lines_read += 1;
-
-        // Strip newline for key generation but keep for output
-        let key_line = if line.ends_with(b"\n") {
-            if line.ends_with(b"\r\n") {
-                &line[..line.len() - 2]
-            } else {
-                &line[..line.len() - 1]
-            }
-        } else {
-            &line[..]
-        };
-
-        let key = make_key(key_line, options)?;
-
-        // Check if we've seen this key before
-        let count = if let Some(existing) = db
-            .get(&key)
-            .map_err(|e| Error::InvalidArgument(format!("Database error: {}", e)))?
-        {
-            let mut count_bytes = [0u8; 8];
-            count_bytes.copy_from_slice(&existing);
-            u64::from_le_bytes(count_bytes) + 1
-        } else {
-            1
-        };
-
-        // Store the count
-        db.insert(&key, &count.to_le_bytes())
-            .map_err(|e| Error::InvalidArgument(format!("Database error: {}", e)))?;
-
-        if count == 1 {
-            if options.count {
-                lines_for_count.push((line.clone(), key.clone()));
-            } else {
-                output.write_all(&line)?;
-            }
-            stats.lines_written += 1;
-        } else {
-            stats.lines_removed += 1;
-            if options.show_removed {
-                write!(output, "[REMOVED] ")?;
-                output.write_all(&line)?;
-            }
-        }
-        line.clear();
-    }
-
-    stats.unique_lines = db.len();
-
-    // Write counts if requested
-    if options.count {
-        for (line, key) in lines_for_count {
-            // Key is already computed and stored
-            if let Some(count_bytes) = db
-                .get(&key)
-                .map_err(|e| Error::InvalidArgument(format!("Database error: {}", e)))?
-            {
-                let mut bytes = [0u8; 8];
-                bytes.copy_from_slice(&count_bytes);
-                let cnt = u64::from_le_bytes(bytes);
-                write!(output, "{:>7} ", cnt)?;
-                output.write_all(&line)?;
-            }
-        }
-    }
-
-    Ok(stats)
-}
-
-/// Disk-backed keep-last algorithm using sled (two-pass)
-#[cfg(feature = "disk-backed")]
-fn deduplicate_keep_last_disk<R: std::io::Read + std::io::Seek, W: Write>(
-    mut input: R,
-    output: &mut W,
-    options: &DeduplicationOptions,
-) -> Result<DeduplicationStats> {
-    use sled::Db;
-
-    let mut stats = DeduplicationStats::default();
-
-    // Create temporary sled database
-    let db: Db = sled::Config::new()
-        .temporary(true)
-        .open()
-        .map_err(|e| Error::InvalidArgument(format!("Failed to create temp database: {}", e)))?;
-
-    // Pass 1: Track last occurrence index for each key
-    let mut reader = BufReader::new(&mut input);
-    let mut line = Vec::new();
-    for (line_index, _) in (0..).enumerate() {
-        if reader.read_until(b'\n', &mut line)? == 0 {
-            break;
-        }
-        stats.lines_read += 1;
-
-        let key_line = if line.ends_with(b"\n") {
-            if line.ends_with(b"\r\n") {
-                &line[..line.len() - 2]
-            } else {
-                &line[..line.len() - 1]
-            }
-        } else {
-            &line[..]
-        };
-
-        let key = make_key(key_line, options)?;
-
-        // Retrieve existing data to update count
-        let count = if let Some(existing) = db
-            .get(&key)
-            .map_err(|e| Error::InvalidArgument(format!("Database error: {}", e)))?
-        {
-            // Existing value is 16 bytes: [last_index (8) | count (8)]
-            // Or if we need to migrate/handle unexpected sizes, we can check len.
-            // Since we are creating a temp DB from scratch, we control the layout.
-            if existing.len() == 16 {
-                let mut count_bytes = [0u8; 8];
-                count_bytes.copy_from_slice(&existing[8..16]);
-                u64::from_le_bytes(count_bytes) + 1
-            } else {
-                1
-            }
-        } else {
-            1
-        };
-
-        // Store: line_index (8 bytes) + count (8 bytes)
-        let mut value = [0u8; 16];
-        value[0..8].copy_from_slice(&(line_index as u64).to_le_bytes());
-        value[8..16].copy_from_slice(&count.to_le_bytes());
-
-        db.insert(&key, &value)
-            .map_err(|e| Error::InvalidArgument(format!("Database error: {}", e)))?;
-
-        line.clear();
-    }
-
-    stats.unique_lines = db.len();
-
-    // Pass 2: Re-read file and output only last occurrences
-    input.seek(std::io::SeekFrom::Start(0))?;
-    let mut reader = BufReader::new(&mut input);
-    let mut line = Vec::new();
-
-    for (current_index, _) in (0..).enumerate() {
-        if reader.read_until(b'\n', &mut line)? == 0 {
-            break;
-        }
-
-        let key_line = if line.ends_with(b"\n") {
-            if line.ends_with(b"\r\n") {
-                &line[..line.len() - 2]
-            } else {
-                &line[..line.len() - 1]
-            }
-        } else {
-            &line[..]
-        };
-
-        let key = make_key(key_line, options)?;
-
-        if let Some(last_index_bytes) = db
-            .get(&key)
-            .map_err(|e| Error::InvalidArgument(format!("Database error: {}", e)))?
-        {
-            // Value is 16 bytes: [last_index (8) | count (8)]
-            if last_index_bytes.len() == 16 {
-                let mut index_bytes = [0u8; 8];
-                index_bytes.copy_from_slice(&last_index_bytes[0..8]);
-                let last_index = u64::from_le_bytes(index_bytes);
-
-                if (current_index as u64) == last_index {
-                    if options.count {
-                        let mut count_bytes = [0u8; 8];
-                        count_bytes.copy_from_slice(&last_index_bytes[8..16]);
-                        let count = u64::from_le_bytes(count_bytes);
-                        write!(output, "{:>7} ", count)?;
-                    }
-                    output.write_all(&line)?;
-                    stats.lines_written += 1;
-                } else {
-                    stats.lines_removed += 1;
-                    if options.show_removed {
-                        write!(output, "[REMOVED] ")?;
-                        output.write_all(&line)?;
-                    }
-                }
-            } else {
-                // Fallback for unexpected data format (should not happen with new logic)
-                // Just assume it's index only logic from before? No, let's treat as error or safe fallback using old logic if length is 8.
-                // For now, ignoring to keep simple.
-            }
-        }
-        line.clear();
-    }
-
-    Ok(stats)
-}
-
-/// Disk-backed remove-all algorithm using sled (two-pass)
-#[cfg(feature = "disk-backed")]
-fn deduplicate_remove_all_disk<R: std::io::Read + std::io::Seek, W: Write>(
-    mut input: R,
-    output: &mut W,
-    options: &DeduplicationOptions,
-) -> Result<DeduplicationStats> {
-    use sled::Db;
-
-    let mut stats = DeduplicationStats::default();
-
-    // Create temporary sled database
-    let db: Db = sled::Config::new()
-        .temporary(true)
-        .open()
-        .map_err(|e| Error::InvalidArgument(format!("Failed to create temp database: {}", e)))?;
-
-    // Pass 1: Count occurrences of each key
-    let mut reader = BufReader::new(&mut input);
-    let mut line = Vec::new();
-
-    while reader.read_until(b'\n', &mut line)? > 0 {
-        stats.lines_read += 1;
-
-        let key_line = if line.ends_with(b"\n") {
-            if line.ends_with(b"\r\n") {
-                &line[..line.len() - 2]
-            } else {
-                &line[..line.len() - 1]
-            }
-        } else {
-            &line[..]
-        };
-
-        let key = make_key(key_line, options)?;
-
-        // Get current count and increment
-        let count = if let Some(existing) = db
-            .get(&key)
-            .map_err(|e| Error::InvalidArgument(format!("Database error: {}", e)))?
-        {
-            let mut count_bytes = [0u8; 8];
-            count_bytes.copy_from_slice(&existing);
-            u64::from_le_bytes(count_bytes) + 1
-        } else {
-            1
-        };
-
-        db.insert(&key, &count.to_le_bytes())
-            .map_err(|e| Error::InvalidArgument(format!("Database error: {}", e)))?;
-        line.clear();
-    }
-
-    // Count unique lines (those appearing exactly once)
-    for item in db.iter() {
-        let (_, count_bytes) =
-            item.map_err(|e| Error::InvalidArgument(format!("Database error: {}", e)))?;
-        let mut bytes = [0u8; 8];
-        bytes.copy_from_slice(&count_bytes);
-        let count = u64::from_le_bytes(bytes);
-        if count == 1 {
-            stats.unique_lines += 1;
-        }
-    }
-
-    // Pass 2: Re-read file and output only lines that appear exactly once
-    input.seek(std::io::SeekFrom::Start(0))?;
-    let mut reader = BufReader::new(&mut input);
-    let mut line = Vec::new();
-
-    while reader.read_until(b'\n', &mut line)? > 0 {
-        let key_line = if line.ends_with(b"\n") {
-            if line.ends_with(b"\r\n") {
-                &line[..line.len() - 2]
-            } else {
-                &line[..line.len() - 1]
-            }
-        } else {
-            &line[..]
-        };
-
-        let key = make_key(key_line, options)?;
-
-        if let Some(count_bytes) = db
-            .get(&key)
-            .map_err(|e| Error::InvalidArgument(format!("Database error: {}", e)))?
-        {
-            let mut bytes = [0u8; 8];
-            bytes.copy_from_slice(&count_bytes);
-            let count = u64::from_le_bytes(bytes);
-
-            if count == 1 {
-                if options.count {
-                    write!(output, "{:>7} ", count)?;
-                }
-                output.write_all(&line)?;
-                stats.lines_written += 1;
-            } else {
-                stats.lines_removed += 1;
-                if options.show_removed {
-                    write!(output, "[REMOVED] ")?;
-                    output.write_all(&line)?;
-                }
-            }
-        }
-        line.clear();
-    }
-
-    Ok(stats)
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Cursor;
-
-    #[test]
-    fn test_keep_first_basic() {
-        let input = b"a\nb\na\nc\n";
-        let mut output = Vec::new();
-
-        let opts = DeduplicationOptions::default();
-        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
-
-        assert_eq!(output, b"a\nb\nc\n");
-        assert_eq!(stats.lines_read, 4);
-        assert_eq!(stats.lines_written, 3);
-        assert_eq!(stats.lines_removed, 1);
-    }
-
-    #[test]
-    fn test_ignore_case() {
-        let input = b"Apple\napple\nBanana\n";
-        let mut output = Vec::new();
-
-        let opts = DeduplicationOptions {
-            ignore_case: true,
-            ..Default::default()
-        };
-        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
-
-        assert_eq!(output, b"Apple\nBanana\n");
-        assert_eq!(stats.unique_lines, 2);
-    }
-
-    #[test]
-    fn test_keep_last() {
-        let input = b"a\nb\na\nc\n";
-        let mut output = Vec::new();
-
-        let opts = DeduplicationOptions {
-            mode: DeduplicationMode::KeepLast,
-            ..Default::default()
-        };
-        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
-
-        assert_eq!(output, b"b\na\nc\n");
-        assert_eq!(stats.lines_written, 3);
-    }
-
-    #[test]
-    fn test_remove_all() {
-        let input = b"a\nb\na\nc\n";
-        let mut output = Vec::new();
-
-        let opts = DeduplicationOptions {
-            mode: DeduplicationMode::RemoveAll,
-            ..Default::default()
-        };
-        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
-
-        assert_eq!(output, b"b\nc\n");
-        assert_eq!(stats.unique_lines, 2);
-    }
-
-    #[test]
-    fn test_empty_input() {
-        let input = b"";
-        let mut output = Vec::new();
-
-        let opts = DeduplicationOptions::default();
-        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
-
-        assert_eq!(stats.lines_read, 0);
-        assert_eq!(stats.lines_written, 0);
-    }
-
-    #[test]
-    fn test_non_utf8() {
-        let input = vec![0xFF, 0xFE, b'\n', 0xFF, 0xFE, b'\n', b'a', b'\n'];
-        let mut output = Vec::new();
-
-        let opts = DeduplicationOptions::default();
-        let stats = deduplicate(Cursor::new(&input), &mut output, &opts).unwrap();
-
-        assert_eq!(stats.lines_written, 2);
-    }
-
-    #[cfg(feature = "disk-backed")]
-    #[test]
-    fn test_disk_backed_keep_first() {
-        use std::io::Cursor;
-
-        let input = b"a\nb\na\nc\n";
-        let mut output = Vec::new();
-
-        let opts = DeduplicationOptions {
-            use_disk: true,
-            ..Default::default()
-        };
-        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
-
-        assert_eq!(output, b"a\nb\nc\n");
-        assert_eq!(stats.lines_written, 3);
-        assert_eq!(stats.unique_lines, 3);
-    }
-
-    #[cfg(feature = "disk-backed")]
-    #[test]
-    fn test_disk_backed_keep_last() {
-        use std::io::Cursor;
-
-        let input = b"a\nb\na\nc\n";
-        let mut cursor = Cursor::new(input);
-        let mut output = Vec::new();
-
-        let opts = DeduplicationOptions {
-            mode: DeduplicationMode::KeepLast,
-            use_disk: true,
-            ..Default::default()
-        };
-        let stats = deduplicate_seekable(&mut cursor, &mut output, &opts).unwrap();
-
-        assert_eq!(output, b"b\na\nc\n");
-        assert_eq!(stats.lines_written, 3);
-    }
-
-    #[cfg(feature = "disk-backed")]
-    #[test]
-    fn test_disk_backed_remove_all() {
-        use std::io::Cursor;
-
-        let input = b"a\nb\na\nc\n";
-        let mut cursor = Cursor::new(input);
-        let mut output = Vec::new();
-
-        let opts = DeduplicationOptions {
-            mode: DeduplicationMode::RemoveAll,
-            use_disk: true,
-            ..Default::default()
-        };
-        let stats = deduplicate_seekable(&mut cursor, &mut output, &opts).unwrap();
-
-        assert_eq!(output, b"b\nc\n");
-        assert_eq!(stats.unique_lines, 2);
-    }
-}
+//! # uniqr
+//!
+//! A library for line deduplication with various strategies.
+//!
+//! ## Example
+//!
+//! ```
+//! use uniqr::{deduplicate, DeduplicationMode, DeduplicationOptions};
+//! use std::io::Cursor;
+//!
+//! let input = b"line1\nline2\nline1\nline3\n";
+//! let mut output = Vec::new();
+//!
+//! let options = DeduplicationOptions::builder()
+//!     .mode(DeduplicationMode::KeepFirst)
+//!     .ignore_case(false)
+//!     .build()
+//!     .unwrap();
+//!
+//! deduplicate(Cursor::new(input), &mut output, &options).unwrap();
+//! assert_eq!(output, b"line1\nline2\nline3\n");
+//! ```
+
+#[cfg(all(target_arch = "wasm32", feature = "disk-backed"))]
+compile_error!("the 'disk-backed' feature (sled) is not supported on wasm32");
+
+use std::cell::{Cell, RefCell};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use arena::{RecordArena, RecordKeyIndex};
+#[cfg(feature = "disk-backed")]
+use disk::{DiskStore, open_store};
+use hash::{KeyMap, key_map};
+use progress::{ProgressReader, ProgressTracker};
+use record::{FooterReader, TerminatedReader};
+
+mod arena;
+#[cfg(feature = "async")]
+mod async_io;
+mod bloom;
+mod bom;
+pub mod builder;
+#[cfg(feature = "disk-backed")]
+mod checkpoint;
+pub mod compress;
+mod deduplicator;
+#[cfg(feature = "disk-backed")]
+mod disk;
+pub mod encoding;
+pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod follow;
+mod fuzzy;
+pub mod hash;
+pub mod iter;
+pub mod key;
+pub mod normalize;
+pub mod progress;
+pub mod record;
+pub mod setops;
+pub mod similarity;
+mod sort;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+mod window;
+pub mod writer;
+#[cfg(feature = "async")]
+pub use async_io::{DedupLine, DedupStream, dedup_stream, deduplicate_async};
+pub use builder::DeduplicationOptionsBuilder;
+#[cfg(feature = "disk-backed")]
+pub use checkpoint::{Checkpoint, read_checkpoint};
+pub use compress::{CompressedReader, CompressedWriter, Compression};
+pub use deduplicator::{
+    Decision, Deduplicator, LineEvent, deduplicate_many_with_observer, deduplicate_with_observer,
+};
+pub use encoding::{DecodingReader, EncodingWriter, TextEncoding};
+pub use error::{Error, Result};
+pub use follow::FollowReader;
+pub use hash::HashAlgorithm;
+pub use key::KeyExtractor;
+pub use normalize::Normalizer;
+pub use progress::{Progress, ProgressCallback};
+pub use record::{RecordCallback, RecordFilter, RecordReader, RecordSeparator};
+pub use similarity::{SignatureKind, SimilarityOptions, SimilarityThreshold};
+pub use writer::DeduplicatingWriter;
+
+/// Deduplication strategy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeduplicationMode {
+    /// Keep first occurrence of each line (default)
+    KeepFirst,
+    /// Keep last occurrence of each line (two-pass)
+    KeepLast,
+    /// Remove all lines that appear more than once (two-pass)
+    RemoveAll,
+    /// Keep the first occurrence of each line that appears more than once,
+    /// like `uniq -d` (two-pass)
+    Repeated,
+    /// Keep every occurrence of lines that appear more than once, like
+    /// `uniq -D` (two-pass)
+    AllRepeated,
+    /// Keep the first of each group of records whose text is at least
+    /// `threshold` similar, by the signatures configured in
+    /// `DeduplicationOptions::similarity`
+    Similar { threshold: SimilarityThreshold },
+}
+
+impl DeduplicationMode {
+    /// Whether the record at `idx` is kept, given the first and last index
+    /// and the occurrence count of its key
+    pub(crate) fn keeps(self, idx: usize, first: usize, last: usize, count: usize) -> bool {
+        match self {
+            DeduplicationMode::KeepFirst | DeduplicationMode::Similar { .. } => idx == first,
+            DeduplicationMode::KeepLast => idx == last,
+            DeduplicationMode::RemoveAll => count == 1,
+            DeduplicationMode::Repeated => count > 1 && idx == first,
+            DeduplicationMode::AllRepeated => count > 1,
+        }
+    }
+
+    /// Index of the occurrence a removed record at `idx` is reported as
+    /// duplicating: the kept one, or another one if none is kept. `None` for
+    /// records that are removed for being unique.
+    pub(crate) fn duplicate_of(
+        self,
+        idx: usize,
+        first: usize,
+        last: usize,
+        count: usize,
+    ) -> Option<usize> {
+        if count == 1 {
+            return None;
+        }
+        Some(match self {
+            DeduplicationMode::KeepFirst
+            | DeduplicationMode::Repeated
+            | DeduplicationMode::Similar { .. } => first,
+            DeduplicationMode::KeepLast => last,
+            DeduplicationMode::RemoveAll | DeduplicationMode::AllRepeated if idx == first => last,
+            DeduplicationMode::RemoveAll | DeduplicationMode::AllRepeated => first,
+        })
+    }
+}
+
+/// Whitespace normalization applied to keys before comparison
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrimMode {
+    /// Compare keys as-is (default)
+    None,
+    /// Strip leading whitespace
+    Leading,
+    /// Strip trailing whitespace
+    Trailing,
+    /// Strip trailing spaces and tabs only, leaving leading whitespace alone
+    TrailingBlanks,
+    /// Strip leading and trailing whitespace
+    Both,
+    /// Strip both ends and collapse inner whitespace runs to a single space
+    CollapseInner,
+}
+
+/// Punctuation handling applied to keys before comparison
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PunctuationMode {
+    /// Compare keys as-is (default)
+    None,
+    /// Drop punctuation and symbols, keeping letters, digits and whitespace
+    Strip,
+    /// Keep only letters and digits
+    AlphanumericOnly,
+}
+
+/// Unicode normalization form applied to keys (requires 'unicode' feature)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalizationForm {
+    /// Canonical composition
+    Nfc,
+    /// Canonical decomposition
+    Nfd,
+    /// Compatibility composition
+    Nfkc,
+    /// Compatibility decomposition
+    Nfkd,
+}
+
+/// How keys are case-normalized when `ignore_case` is set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseMode {
+    /// Compare lowercased keys via `str::to_lowercase` (default)
+    Lower,
+    /// Full Unicode case folding, e.g. "ß" matches "SS" (requires 'unicode' feature)
+    Fold,
+}
+
+/// How `ignore_case` treats keys that are not valid UTF-8
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonUtf8Case {
+    /// Fold ASCII letters byte-wise and leave other bytes untouched (default)
+    AsciiFold,
+    /// Fail with a UTF-8 error
+    Error,
+}
+
+/// How records that aren't valid UTF-8 are handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Utf8Policy {
+    /// Deduplicate them like any other record; key options that work on
+    /// text (such as `column`) decode invalid bytes as U+FFFD, so keys that
+    /// only differ in those bytes match (default)
+    #[default]
+    Lossy,
+    /// Fail with a UTF-8 error at the first such record
+    Strict,
+    /// Write them through unchanged, without deduplicating them. Requires
+    /// in-memory KeepFirst mode without counts, sorting, grouping or
+    /// adjacent comparison
+    Skip,
+}
+
+/// How empty records (those with nothing but whitespace before their
+/// terminator) are handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EmptyLinePolicy {
+    /// Deduplicate them like any other record (default)
+    #[default]
+    Dedup,
+    /// Write every one of them through, as structure rather than
+    /// duplicates. Requires in-memory KeepFirst mode without counts,
+    /// sorting, grouping or adjacent comparison
+    Preserve,
+    /// Remove all of them
+    Drop,
+}
+
+/// How keys are interpreted for comparison
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyType {
+    /// Compare keys as text (default)
+    Text,
+    /// Compare keys by numeric value, so `1`, `01`, and `1.0` are equal
+    Numeric,
+    /// Compare keys by numeric value rounded to `decimals` places, so with
+    /// 3 places `3.14159` and `3.1416` are equal
+    Rounded {
+        /// Number of decimal places kept before comparing
+        decimals: u32,
+    },
+}
+
+/// Where `count` mode puts each record's occurrence count
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountPlacement {
+    /// Before the record, like `uniq -c` (default)
+    Prefix,
+    /// After the record's content, before its terminator
+    Suffix,
+}
+
+/// How `count` mode formats occurrence counts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CountFormat {
+    /// Minimum width of the count, right-aligned with spaces (0 for none)
+    pub width: usize,
+    /// Text between the count and the record
+    pub separator: String,
+    pub placement: CountPlacement,
+}
+
+impl Default for CountFormat {
+    /// `uniq -c` style: `{:>7} ` before the record
+    fn default() -> Self {
+        Self {
+            width: 7,
+            separator: " ".to_string(),
+            placement: CountPlacement::Prefix,
+        }
+    }
+}
+
+/// Column used as the key in CSV mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CsvColumn {
+    /// 1-indexed column position
+    Index(usize),
+    /// Column named in the header row
+    Name(String),
+}
+
+/// CSV parsing options (requires 'csv' feature)
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    /// Field delimiter (`b','` by default)
+    pub delimiter: u8,
+    /// Whether the first record is a header, passed through untouched
+    pub has_header: bool,
+    /// Column whose value is used as the key
+    pub key_column: CsvColumn,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            has_header: true,
+            key_column: CsvColumn::Index(1),
+        }
+    }
+}
+
+/// Prefix emitted records with where they came from, like `grep -H`/`-n`
+#[derive(Debug, Clone, Default)]
+pub struct SourceAnnotation {
+    /// Label for each input, in order (inputs without a label use their index)
+    pub names: Vec<String>,
+    /// Also include the 1-based record number within the input
+    pub line_numbers: bool,
+}
+
+/// Store for the keys of disk-backed deduplication
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DiskBackend {
+    /// A `sled` embedded database (default; required for `state_db`)
+    #[default]
+    Sled,
+    /// An append-only temporary file with an in-memory index of key hashes:
+    /// much faster than sled, but keeps a few dozen bytes per unique key in RAM
+    TempFile,
+}
+
+/// Where and how disk-backed deduplication stores its keys
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiskOptions {
+    /// Key store to use
+    pub backend: DiskBackend,
+    /// Directory to create temporary stores in (if unset, sled's default,
+    /// usually `/dev/shm` on Linux, or the system temp directory)
+    pub temp_dir: Option<std::path::PathBuf>,
+    /// Page cache size in bytes (sled's default of 1 GiB if unset; sled only)
+    pub cache_capacity: Option<u64>,
+    /// Favor a smaller database over write throughput (sled only)
+    pub low_space: bool,
+}
+
+/// Settings for approximate deduplication with a fixed-size Bloom filter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ApproximateOptions {
+    /// Chance that a unique record is mistaken for a duplicate and dropped
+    pub false_positive_rate: f64,
+    /// Number of distinct keys the filter is sized for; beyond this the
+    /// false-positive rate rises
+    pub expected_lines: usize,
+}
+
+impl Default for ApproximateOptions {
+    fn default() -> Self {
+        Self {
+            false_positive_rate: 0.001,
+            expected_lines: 10_000_000,
+        }
+    }
+}
+
+/// Where `DeduplicationOptions::group` puts the empty records that
+/// delimit groups, like the methods of `uniq --group`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GroupSeparator {
+    /// Between groups (default)
+    #[default]
+    Separate,
+    /// Before each group
+    Prepend,
+    /// After each group
+    Append,
+    /// Before each group and after the last one
+    Both,
+}
+
+/// Order of the output records under `DeduplicationOptions::sort`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortOrder {
+    /// Byte-wise order of the keys (default)
+    #[default]
+    Text,
+    /// Numeric order of the keys; keys that aren't numbers follow all
+    /// numbers, in text order
+    Numeric,
+    /// Least frequent records first (requires `count`)
+    CountAscending,
+    /// Most frequent records first (requires `count`), like
+    /// `sort | uniq -c | sort -rn`
+    CountDescending,
+}
+
+/// What to do with a line longer than `DeduplicationOptions::max_line_length`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineLengthPolicy {
+    /// Stop with an error (default)
+    #[default]
+    Error,
+    /// Keep the first `max_line_length` bytes of the line and its
+    /// terminator, skipping the rest
+    Truncate,
+    /// Write the line through unchanged, without deduplicating it, reading
+    /// it in pieces of at most `max_line_length` bytes. Requires a
+    /// `RecordSeparator::Byte` separator and KeepFirst mode without counts,
+    /// sorting, grouping, adjacent comparison or disk-backed storage
+    PassThrough,
+}
+
+impl SortOrder {
+    /// Whether records are ordered by their occurrence count, not their key
+    pub fn by_count(self) -> bool {
+        matches!(self, SortOrder::CountAscending | SortOrder::CountDescending)
+    }
+}
+
+/// Settings for fuzzy deduplication by edit distance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FuzzyOptions {
+    /// Most single-byte insertions, deletions and substitutions by which a
+    /// key may differ from a kept key and still count as its duplicate
+    pub max_distance: usize,
+}
+
+impl Default for FuzzyOptions {
+    fn default() -> Self {
+        Self { max_distance: 2 }
+    }
+}
+
+/// How far back windowed deduplication looks for an earlier occurrence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WindowMode {
+    /// A record duplicates one of the last N records before it. Every
+    /// occurrence counts, so a record repeated at least every N records is
+    /// only written once.
+    Lines(usize),
+    /// A record duplicates one of the N distinct keys seen most recently.
+    /// The least recently seen key is forgotten first, like an LRU cache, so
+    /// a rare key can come back after enough others.
+    Keys(usize),
+    /// A record duplicates a kept record at most `within` apart, by the
+    /// timestamps records start with, parsed by the strftime-style `format`
+    /// (such as `%Y-%m-%dT%H:%M:%S%z`). Keys are built from the rest of the
+    /// record, and a record without a timestamp is written through without
+    /// being deduplicated. A repeat `within` after the kept record is kept
+    /// again, so persistent noise comes through once per window. Requires
+    /// the `timestamps` feature.
+    Time {
+        within: std::time::Duration,
+        format: String,
+    },
+}
+
+/// Options for deduplication
+#[derive(Debug, Clone)]
+pub struct DeduplicationOptions {
+    pub mode: DeduplicationMode,
+    /// Only compare each record with the one before it, like `uniq`, so
+    /// repeats that aren't adjacent are kept and memory stays bounded by the
+    /// longest run of equal records
+    pub adjacent: bool,
+    pub ignore_case: bool,
+    /// Case normalization used when `ignore_case` is set
+    pub case_mode: CaseMode,
+    /// Case handling for non-UTF-8 keys when `ignore_case` is set
+    pub non_utf8_case: NonUtf8Case,
+    /// Handling of records that aren't valid UTF-8
+    pub utf8_policy: Utf8Policy,
+    /// Records written through without being deduplicated, such as the
+    /// comments of a config file
+    pub passthrough: Option<RecordFilter>,
+    /// Handling of empty records
+    pub empty_lines: EmptyLinePolicy,
+    pub count: bool,
+    /// Layout of the counts written in `count` mode
+    pub count_format: CountFormat,
+    pub show_removed: bool,
+    /// Write every record, with the records of each key together and
+    /// groups delimited by empty records, instead of removing duplicates.
+    /// Groups are in order of first occurrence, or are the runs of equal
+    /// keys with `adjacent`
+    pub group: Option<GroupSeparator>,
+    /// Write the kept records sorted by key instead of in input order, like
+    /// `sort -u`. Records past an in-memory buffer are sorted in temporary
+    /// files with the 'disk-backed' feature (in `disk.temp_dir`), so output
+    /// of any size can be sorted. Records with equal keys (or counts) stay
+    /// in input order
+    pub sort: Option<SortOrder>,
+    pub column: Option<usize>,
+    /// Deduplicate by the whitespace-separated column with this name in the
+    /// first (header) line; the header is passed through to the output
+    pub column_name: Option<String>,
+    /// Records at the start of the input (before any `column_name` or CSV
+    /// header) copied to the output as they are, without being deduplicated
+    /// or counted. Later inputs start with the same header, which is skipped.
+    pub header_lines: usize,
+    /// Records at the end of the input copied to the output after the others
+    /// as they are, without being deduplicated or counted. Only the last
+    /// input's footer is written. Requires a byte or byte-string separator.
+    pub footer_lines: usize,
+    /// How input is split into records (newline-terminated lines by default)
+    pub record_separator: RecordSeparator,
+    /// Longest line to read, in bytes not counting its terminator, so a
+    /// huge "line" (e.g. a binary file piped in by mistake) can't exhaust
+    /// memory; see `RecordReader::with_max_line_length`
+    pub max_line_length: Option<usize>,
+    /// How lines over `max_line_length` are handled
+    pub line_length_policy: LineLengthPolicy,
+    /// End the output with a record terminator even if the input's last
+    /// record had none. By default the output then ends without one too, so
+    /// a run that removes nothing reproduces its input byte for byte; that
+    /// holds back each terminator until the next record is written, so set
+    /// this when following a growing input with `FollowReader`
+    pub ensure_trailing_newline: bool,
+    /// Write the UTF-8 byte order mark found at the start of the first
+    /// input that has one once at the start of the output. UTF-8 byte order
+    /// marks are always stripped from the inputs, so the first record
+    /// matches its duplicates; without this they are dropped. UTF-16 ones
+    /// are only recognized once decoded by `DecodingReader`.
+    pub emit_bom: bool,
+    /// Parse records as CSV and key on a single field (requires 'csv' feature)
+    pub csv: Option<CsvOptions>,
+    /// Parse records as JSON and key on the value at this JSON pointer,
+    /// e.g. `/user/id` (requires 'json' feature)
+    pub json_pointer: Option<String>,
+    /// Custom key logic that replaces all built-in key options
+    pub key_extractor: Option<std::sync::Arc<dyn KeyExtractor>>,
+    /// Steps applied in order to every key after the options above (or
+    /// `key_extractor`) have produced it
+    pub normalizers: Vec<std::sync::Arc<dyn Normalizer>>,
+    /// Skip the first N blank-separated fields of each record before
+    /// comparing (like `uniq -f`)
+    pub skip_fields: usize,
+    /// Skip the first N characters, after any skipped fields, before
+    /// comparing (like `uniq -s`)
+    pub skip_chars: usize,
+    /// Compare only the first N characters of the key (like `uniq -w`)
+    pub compare_width: Option<usize>,
+    /// Whitespace trimming applied to keys before comparison
+    pub trim: TrimMode,
+    /// Punctuation removed from keys before comparison
+    pub punctuation: PunctuationMode,
+    /// Unicode normalization applied to keys (requires 'unicode' feature)
+    pub normalization: Option<NormalizationForm>,
+    /// Remove accents and other combining marks from keys after canonical
+    /// decomposition, so "résumé" matches "resume" (requires 'unicode'
+    /// feature)
+    pub ignore_diacritics: bool,
+    /// Whether keys compare as text or by numeric value
+    pub key_type: KeyType,
+    /// Keys of records to drop wherever they occur, before the rest are
+    /// deduplicated, such as those `load_reference_keys` reads from a
+    /// reference file. They are compared with keys built by all the options
+    /// above. Dropped records count as read and removed
+    pub exclude_keys: Option<std::sync::Arc<HashSet<Vec<u8>>>>,
+    /// Prefix emitted records with their source input (and line number)
+    pub annotate_source: Option<SourceAnnotation>,
+    /// Track seen keys in a Bloom filter of constant size instead of a map
+    /// (`KeepFirst` only); a few unique records may be dropped
+    pub approximate: Option<ApproximateOptions>,
+    /// Treat records whose keys are within a few edits of a kept record's
+    /// key as its duplicates (`KeepFirst` only). Each record is compared
+    /// with the kept keys through a BK-tree, which is far slower than exact
+    /// matching
+    pub fuzzy: Option<FuzzyOptions>,
+    /// Only treat records as duplicates of recent ones (`KeepFirst` only),
+    /// forgetting older keys so memory stays bounded on endless streams. An
+    /// approximate mode: repeats of forgotten keys are kept again
+    pub window: Option<WindowMode>,
+    /// Shingle size and signature scheme of `DeduplicationMode::Similar`
+    pub similarity: SimilarityOptions,
+    /// Hash function for the in-memory key maps
+    pub hasher: HashAlgorithm,
+    /// Collect the N most repeated keys into `DeduplicationStats::top_duplicates`
+    pub top_duplicates: Option<usize>,
+    /// Collect every key with its occurrence count into `DeduplicationStats::key_counts`
+    pub collect_key_counts: bool,
+    /// Collect how many keys occurred how often into
+    /// `DeduplicationStats::duplicate_histogram`
+    pub collect_histogram: bool,
+    /// Collect the location of every removed record, and of the record it
+    /// duplicates, into `DeduplicationStats::removed_records`. All input is
+    /// held in memory to do so, whatever the mode.
+    pub collect_removed_records: bool,
+    /// Use disk-backed storage for massive files (requires 'disk-backed' feature)
+    pub use_disk: bool,
+    /// Where and how the disk-backed database is stored
+    pub disk: DiskOptions,
+    /// Keep the disk-backed keep-first database at this path instead of a
+    /// temporary one, so records seen by earlier runs count as duplicates.
+    /// Every run should use the same key options.
+    pub state_db: Option<std::path::PathBuf>,
+    /// With `state_db`, flush the output and record a checkpoint in the
+    /// database every this many records, so an interrupted run can be
+    /// resumed with `resume`
+    pub checkpoint_every: Option<usize>,
+    /// Continue the run interrupted after the checkpoint in `state_db`: skip
+    /// the records read before it, and write neither a byte order mark nor
+    /// a header again. The output must be positioned at
+    /// `Checkpoint::output_offset` (see `read_checkpoint`), and the inputs
+    /// and options must be those of the interrupted run.
+    pub resume: bool,
+    /// Approximate memory budget in bytes for the keys of the in-memory
+    /// KeepFirst algorithm. Once it is exceeded, the keys move to a
+    /// temporary disk-backed database and the run continues as if `use_disk`
+    /// had been set (requires 'disk-backed' feature)
+    pub memory_limit: Option<usize>,
+    /// Prefix each written record with its 1-based position across all inputs
+    pub line_numbers: bool,
+    /// Write the key of each kept record (as built by the key options and
+    /// `normalizers`) in place of the record, to list the IDs a column holds
+    /// or check what the key options extract. Records written through
+    /// without a key (see `utf8_policy` and `line_length_policy`) and removed
+    /// records are written as they are.
+    pub print_key: bool,
+    /// Called with each record written to the main output and its location
+    pub record_callback: Option<std::sync::Arc<dyn RecordCallback>>,
+    /// Called with the number of input bytes consumed as deduplication proceeds
+    pub progress_callback: Option<std::sync::Arc<dyn ProgressCallback>>,
+    /// Checked before each record is read: once another thread sets it, the
+    /// run stops with `Error::Cancelled`, which holds the stats so far.
+    /// Whatever was written before then stays written.
+    pub cancel: Option<std::sync::Arc<AtomicBool>>,
+}
+
+impl Default for DeduplicationOptions {
+    fn default() -> Self {
+        Self {
+            mode: DeduplicationMode::KeepFirst,
+            adjacent: false,
+            ignore_case: false,
+            case_mode: CaseMode::Lower,
+            non_utf8_case: NonUtf8Case::AsciiFold,
+            utf8_policy: Utf8Policy::Lossy,
+            passthrough: None,
+            empty_lines: EmptyLinePolicy::Dedup,
+            count: false,
+            count_format: CountFormat::default(),
+            show_removed: false,
+            group: None,
+            sort: None,
+            column: None,
+            column_name: None,
+            header_lines: 0,
+            footer_lines: 0,
+            record_separator: RecordSeparator::default(),
+            max_line_length: None,
+            line_length_policy: LineLengthPolicy::Error,
+            ensure_trailing_newline: false,
+            emit_bom: false,
+            csv: None,
+            json_pointer: None,
+            key_extractor: None,
+            normalizers: Vec::new(),
+            skip_fields: 0,
+            skip_chars: 0,
+            compare_width: None,
+            trim: TrimMode::None,
+            punctuation: PunctuationMode::None,
+            normalization: None,
+            ignore_diacritics: false,
+            key_type: KeyType::Text,
+            exclude_keys: None,
+            annotate_source: None,
+            approximate: None,
+            fuzzy: None,
+            window: None,
+            similarity: SimilarityOptions::default(),
+            hasher: HashAlgorithm::default(),
+            top_duplicates: None,
+            collect_key_counts: false,
+            collect_histogram: false,
+            collect_removed_records: false,
+            use_disk: false,
+            disk: DiskOptions::default(),
+            state_db: None,
+            checkpoint_every: None,
+            resume: false,
+            memory_limit: None,
+            line_numbers: false,
+            print_key: false,
+            record_callback: None,
+            progress_callback: None,
+            cancel: None,
+        }
+    }
+}
+
+impl DeduplicationOptions {
+    /// Use `key_fn` to compute each record's key instead of the built-in key options
+    ///
+    /// ```
+    /// use uniqr::{deduplicate, DeduplicationOptions};
+    ///
+    /// // Ignore a leading timestamp up to the first space
+    /// let options = DeduplicationOptions::default().with_key_fn(|record: &[u8]| {
+    ///     let start = record.iter().position(|&b| b == b' ').map_or(0, |i| i + 1);
+    ///     record[start..].to_vec()
+    /// });
+    /// let mut output = Vec::new();
+    /// deduplicate(&b"10:00 boot\n10:05 boot\n10:06 halt\n"[..], &mut output, &options).unwrap();
+    /// assert_eq!(output, b"10:00 boot\n10:06 halt\n");
+    /// ```
+    pub fn with_key_fn<F>(self, key_fn: F) -> Self
+    where
+        F: Fn(&[u8]) -> Vec<u8> + Send + Sync + 'static,
+    {
+        self.with_key_extractor(key_fn)
+    }
+
+    /// Use `extractor` to compute each record's key instead of the built-in key options
+    pub fn with_key_extractor<K: KeyExtractor + 'static>(mut self, extractor: K) -> Self {
+        self.key_extractor = Some(std::sync::Arc::new(extractor));
+        self
+    }
+
+    /// Pass each record written to the main output, with its location, to `callback`
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use uniqr::{deduplicate, DeduplicationOptions, RecordLocation};
+    ///
+    /// let kept = Arc::new(Mutex::new(Vec::new()));
+    /// let sink = Arc::clone(&kept);
+    /// let options = DeduplicationOptions::default()
+    ///     .with_record_callback(move |_: &[u8], location: RecordLocation| {
+    ///         sink.lock().unwrap().push(location.number);
+    ///     });
+    /// deduplicate(&b"a\na\nb\n"[..], &mut std::io::sink(), &options).unwrap();
+    /// assert_eq!(*kept.lock().unwrap(), [1, 3]);
+    /// ```
+    pub fn with_record_callback<C: RecordCallback + 'static>(mut self, callback: C) -> Self {
+        self.record_callback = Some(std::sync::Arc::new(callback));
+        self
+    }
+
+    /// Report progress to `callback` while inputs are read
+    ///
+    /// ```
+    /// use std::sync::atomic::{AtomicU64, Ordering};
+    /// use std::sync::Arc;
+    /// use uniqr::{deduplicate, DeduplicationOptions, Progress};
+    ///
+    /// let seen = Arc::new(AtomicU64::new(0));
+    /// let counter = Arc::clone(&seen);
+    /// let options = DeduplicationOptions::default().with_progress_callback(move |p: Progress| {
+    ///     counter.store(p.bytes_read, Ordering::Relaxed);
+    /// });
+    /// deduplicate(&b"a\nb\na\n"[..], &mut std::io::sink(), &options).unwrap();
+    /// assert_eq!(seen.load(Ordering::Relaxed), 6);
+    /// ```
+    pub fn with_progress_callback<P: ProgressCallback + 'static>(mut self, callback: P) -> Self {
+        self.progress_callback = Some(std::sync::Arc::new(callback));
+        self
+    }
+}
+
+/// Keys paired with their occurrence counts
+pub type KeyCounts = Vec<(Vec<u8>, usize)>;
+
+/// Where a record was read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordLocation {
+    /// Index of the input the record came from
+    pub source: usize,
+    /// 1-based record number within that input
+    pub line: usize,
+    /// 1-based record number across all inputs
+    pub number: usize,
+}
+
+/// A record left out of the output, and the record it duplicates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemovedRecord {
+    pub location: RecordLocation,
+    /// The occurrence that was kept, or another removed occurrence if none
+    /// was; `None` if the record was removed for being unique
+    pub duplicate_of: Option<RecordLocation>,
+}
+
+/// Statistics about deduplication
+#[derive(Debug, Default)]
+pub struct DeduplicationStats {
+    pub lines_read: usize,
+    pub lines_written: usize,
+    pub lines_removed: usize,
+    pub unique_lines: usize,
+    /// Input bytes consumed (after decompression), including any header
+    pub bytes_read: u64,
+    /// Bytes written to the main output, including any header and annotations
+    pub bytes_written: u64,
+    /// Wall-clock time spent deduplicating (always zero on wasm32)
+    pub elapsed: Duration,
+    /// With `DeduplicationOptions::top_duplicates`, the most repeated keys and
+    /// their occurrence counts, most frequent first (ties by key)
+    pub top_duplicates: KeyCounts,
+    /// With `DeduplicationOptions::collect_key_counts`, every key and its
+    /// occurrence count, most frequent first (ties by key)
+    pub key_counts: KeyCounts,
+    /// With `DeduplicationOptions::collect_histogram`, the number of keys by
+    /// how often they occurred, in buckets keyed by their smallest count:
+    /// 1, 2, 3 (3 to 10 times), 11 (11 to 100 times), 101, 1001 and so on
+    pub duplicate_histogram: BTreeMap<usize, usize>,
+    /// With `DeduplicationOptions::collect_removed_records`, every removed
+    /// record in input order
+    pub removed_records: Vec<RemovedRecord>,
+}
+
+impl DeduplicationStats {
+    /// Input lines processed per second, or 0 if no time was measured
+    pub fn lines_per_second(&self) -> f64 {
+        per_second(self.lines_read as f64, self.elapsed)
+    }
+
+    /// Input bytes processed per second, or 0 if no time was measured
+    pub fn bytes_per_second(&self) -> f64 {
+        per_second(self.bytes_read as f64, self.elapsed)
+    }
+}
+
+fn per_second(amount: f64, elapsed: Duration) -> f64 {
+    if elapsed.is_zero() {
+        0.0
+    } else {
+        amount / elapsed.as_secs_f64()
+    }
+}
+
+/// Keeps the `limit` most repeated keys offered so far
+struct TopKeys {
+    limit: usize,
+    /// Min-heap on count, so the weakest entry is evicted first; among equal
+    /// counts the larger key is weaker
+    heap: BinaryHeap<Reverse<(usize, Reverse<Vec<u8>>)>>,
+}
+
+impl TopKeys {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            heap: BinaryHeap::with_capacity(limit.saturating_add(1).min(1024)),
+        }
+    }
+
+    /// Consider a key that occurred `count` times; keys seen once aren't duplicates
+    fn offer(&mut self, key: &[u8], count: usize) {
+        if count < 2 || self.limit == 0 {
+            return;
+        }
+        if self.heap.len() == self.limit {
+            if let Some(Reverse((min_count, Reverse(min_key)))) = self.heap.peek()
+                && (count, Reverse(key)) <= (*min_count, Reverse(min_key.as_slice()))
+            {
+                return;
+            }
+            self.heap.pop();
+        }
+        self.heap.push(Reverse((count, Reverse(key.to_vec()))));
+    }
+
+    /// The kept keys, most frequent first
+    fn into_sorted(self) -> KeyCounts {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((count, Reverse(key)))| (key, count))
+            .collect()
+    }
+}
+
+/// Fill in the key reports `options` asks for from a run's key counts.
+///
+/// `counts` is called once per report, so the map is only walked as needed.
+fn report_key_counts<'a, I>(
+    stats: &mut DeduplicationStats,
+    options: &DeduplicationOptions,
+    counts: impl Fn() -> I,
+) where
+    I: Iterator<Item = (&'a [u8], usize)>,
+{
+    if let Some(limit) = options.top_duplicates {
+        let mut top = TopKeys::new(limit);
+        for (key, count) in counts() {
+            top.offer(key, count);
+        }
+        stats.top_duplicates = top.into_sorted();
+    }
+    if options.collect_key_counts {
+        stats.key_counts = counts().map(|(key, count)| (key.to_vec(), count)).collect();
+        sort_key_counts(&mut stats.key_counts);
+    }
+    if options.collect_histogram {
+        for (_, count) in counts() {
+            *stats
+                .duplicate_histogram
+                .entry(histogram_bucket(count))
+                .or_insert(0) += 1;
+        }
+    }
+}
+
+/// The `DeduplicationStats::duplicate_histogram` bucket of keys that
+/// occurred `count` times: its smallest count
+pub fn histogram_bucket(count: usize) -> usize {
+    if count < 3 {
+        return count;
+    }
+    // 3-10, 11-100, 101-1000, ...
+    let (mut bucket, mut upper) = (3, 10usize);
+    while count > upper {
+        bucket = upper + 1;
+        match upper.checked_mul(10) {
+            Some(next) => upper = next,
+            None => break,
+        }
+    }
+    bucket
+}
+
+/// Whether `options` asks for any report built from every key's count
+fn reports_key_counts(options: &DeduplicationOptions) -> bool {
+    options.top_duplicates.is_some() || options.collect_key_counts || options.collect_histogram
+}
+
+/// `report_key_counts` for the keys numbered by `index`, given each entry's
+/// count in entry order
+fn arena_report_key_counts(
+    stats: &mut DeduplicationStats,
+    options: &DeduplicationOptions,
+    index: &RecordKeyIndex,
+    records: &RecordArena,
+    counts: impl Iterator<Item = usize>,
+) -> Result<()> {
+    if !reports_key_counts(options) {
+        return Ok(());
+    }
+    let key_counts = counts
+        .enumerate()
+        .map(|(entry, count)| Ok((index.key(entry, records, options)?, count)))
+        .collect::<Result<Vec<_>>>()?;
+    report_key_counts(stats, options, || {
+        key_counts.iter().map(|(key, count)| (&key[..], *count))
+    });
+    Ok(())
+}
+
+/// Sort a key-count table most frequent first, ties by key
+pub(crate) fn sort_key_counts(counts: &mut [(Vec<u8>, usize)]) {
+    counts.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+}
+
+/// Measures how long a run takes. `Instant` panics on wasm32-unknown-unknown,
+/// so there every run takes zero time.
+pub(crate) struct Timer {
+    #[cfg(not(target_arch = "wasm32"))]
+    start: std::time::Instant,
+}
+
+impl Timer {
+    pub(crate) fn start() -> Self {
+        Self {
+            #[cfg(not(target_arch = "wasm32"))]
+            start: std::time::Instant::now(),
+        }
+    }
+
+    pub(crate) fn elapsed(&self) -> Duration {
+        #[cfg(not(target_arch = "wasm32"))]
+        return self.start.elapsed();
+        #[cfg(target_arch = "wasm32")]
+        return Duration::ZERO;
+    }
+}
+
+/// Writer wrapper that counts the bytes passed through it
+pub(crate) struct CountingWriter<W> {
+    inner: W,
+    bytes_written: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self {
+            inner,
+            bytes_written: 0,
+        }
+    }
+
+    pub(crate) fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    pub(crate) fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub(crate) fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes_written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Where the algorithms write records. Kept records (and headers, counts)
+/// go through `Write`; removed records go to the separate `removed` sink if
+/// there is one, or inline with a `[REMOVED]` prefix under `show_removed`.
+/// Under `sort`, kept records are held back and written in order by `finish`.
+pub(crate) struct RecordOutput<'a, W> {
+    kept: HeldTerminator<CountingWriter<W>>,
+    removed: Option<&'a mut dyn Write>,
+    sorter: Option<sort::Sorter>,
+}
+
+impl<'a, W: Write> RecordOutput<'a, W> {
+    fn new(kept: W, removed: Option<&'a mut dyn Write>, options: &DeduplicationOptions) -> Self {
+        Self {
+            kept: HeldTerminator::new(CountingWriter::new(kept), options),
+            removed,
+            sorter: options.sort.map(|order| sort::Sorter::new(order, options)),
+        }
+    }
+
+    /// Bytes written to the main output so far, not counting a held
+    /// terminator
+    #[cfg(feature = "disk-backed")]
+    fn bytes_written(&self) -> u64 {
+        self.kept.get_ref().bytes_written()
+    }
+
+    /// Write any records held back for sorting and then `footer`, end the
+    /// output like the input (`unterminated` if its last record had no
+    /// terminator), and flush
+    fn finish(&mut self, footer: &[u8], unterminated: bool) -> Result<()> {
+        if let Some(sorter) = self.sorter.take() {
+            sorter.finish(&mut self.kept)?;
+        }
+        self.kept.write_all(footer)?;
+        self.kept.finish(unterminated)?;
+        self.flush()?;
+        Ok(())
+    }
+
+    /// Write a record that was dropped as a duplicate
+    fn write_removed(
+        &mut self,
+        record: &[u8],
+        origin: Origin,
+        options: &DeduplicationOptions,
+    ) -> Result<()> {
+        match &mut self.removed {
+            Some(removed) => write_annotated(*removed, record, origin, options),
+            None if options.show_removed => self.write_kept(record, None, options, |output| {
+                write!(output, "[REMOVED] ")?;
+                write_annotated(output, record, origin, options)
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> KeptOutput for RecordOutput<'_, W> {
+    fn write_kept(
+        &mut self,
+        record: &[u8],
+        count: Option<u64>,
+        options: &DeduplicationOptions,
+        format: impl FnOnce(&mut dyn Write) -> Result<()>,
+    ) -> Result<()> {
+        match &mut self.sorter {
+            Some(sorter) => {
+                let mut formatted = Vec::new();
+                format(&mut formatted)?;
+                let key = if sorter.by_count() {
+                    std::borrow::Cow::Borrowed(&[][..])
+                } else {
+                    record_key(record, options)?
+                };
+                sorter.push(&key, count.unwrap_or(0), formatted)
+            }
+            None => format(&mut self.kept),
+        }
+    }
+}
+
+impl<W: Write> Write for RecordOutput<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.kept.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if let Some(removed) = &mut self.removed {
+            removed.flush()?;
+        }
+        self.kept.flush()
+    }
+}
+
+/// Writer wrapper that holds back the record terminator ending each write
+/// until more output follows, so the output can end without one when the
+/// input did (unless `ensure_trailing_newline` is set)
+pub(crate) struct HeldTerminator<W> {
+    inner: W,
+    terminator: Vec<u8>,
+    /// Whether terminators are held back at all
+    holding: bool,
+    held: bool,
+}
+
+impl<W: Write> HeldTerminator<W> {
+    fn new(inner: W, options: &DeduplicationOptions) -> Self {
+        let terminator = options.record_separator.terminator().to_vec();
+        Self {
+            inner,
+            holding: !options.ensure_trailing_newline && !terminator.is_empty(),
+            terminator,
+            held: false,
+        }
+    }
+
+    fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Write any held terminator, unless the input ended `unterminated`
+    fn finish(&mut self, unterminated: bool) -> std::io::Result<()> {
+        if std::mem::take(&mut self.held) && !unterminated {
+            self.inner.write_all(&self.terminator)?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for HeldTerminator<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if std::mem::take(&mut self.held) {
+            self.inner.write_all(&self.terminator)?;
+        }
+        match buf.strip_suffix(&self.terminator[..]) {
+            Some(content) if self.holding => {
+                self.inner.write_all(content)?;
+                self.held = true;
+                Ok(buf.len())
+            }
+            _ => self.inner.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Fill in the byte counts, timing and `excluded` records of a finished run
+fn finish_stats<W: Write>(
+    mut stats: DeduplicationStats,
+    tracker: &ProgressTracker,
+    output: &CountingWriter<W>,
+    timer: &Timer,
+    excluded: &Cell<usize>,
+) -> DeduplicationStats {
+    stats.lines_read += excluded.get();
+    stats.lines_removed += excluded.get();
+    stats.bytes_read = tracker.bytes_read();
+    stats.bytes_written = output.bytes_written();
+    stats.elapsed = timer.elapsed();
+    log::debug!(
+        "Read {} records ({} bytes), wrote {}, removed {} in {:.2?}",
+        stats.lines_read,
+        stats.bytes_read,
+        stats.lines_written,
+        stats.lines_removed,
+        stats.elapsed
+    );
+    stats
+}
+
+/// Fail with `Error::Cancelled` if `cancel` is set. The stats are filled in
+/// by `partial_stats` on the way out.
+pub(crate) fn check_cancel(cancel: Option<&AtomicBool>) -> Result<()> {
+    match cancel {
+        Some(cancel) if cancel.load(Ordering::Relaxed) => Err(Error::Cancelled(Box::default())),
+        _ => Ok(()),
+    }
+}
+
+/// Fill in the stats of a cancelled run from what it read and wrote before
+/// it stopped, first writing the terminator held back after the last record
+/// written so that the output ends in a whole record. A line over the
+/// length limit also ends the output in a whole record; other errors pass
+/// through.
+pub(crate) fn partial_stats<W: Write>(
+    error: Error,
+    tracker: &ProgressTracker,
+    output: &mut HeldTerminator<CountingWriter<W>>,
+    timer: &Timer,
+) -> Error {
+    let long_line = match &error {
+        Error::AtLine { source, .. } => matches!(**source, Error::LineTooLong { .. }),
+        error => matches!(error, Error::LineTooLong { .. }),
+    };
+    if !long_line && !matches!(error, Error::Cancelled(_)) {
+        return error;
+    }
+    if let Err(e) = output.finish(false).and_then(|()| output.flush()) {
+        return e.into();
+    }
+    if long_line {
+        return error;
+    }
+    Error::Cancelled(Box::new(DeduplicationStats {
+        lines_read: tracker.lines_read() as usize,
+        bytes_read: tracker.bytes_read(),
+        bytes_written: output.get_ref().bytes_written(),
+        elapsed: timer.elapsed(),
+        ..Default::default()
+    }))
+}
+
+/// Main deduplication function (safe for non-seekable streams)
+///
+/// Disk-backed two-pass deduplication (`KeepLast` or `RemoveAll` with
+/// `use_disk: true`) needs to read the input twice, so the input is first
+/// spooled to a temporary file (in `DiskOptions::temp_dir` if set). Use
+/// `deduplicate_seekable` to avoid the copy when the input can seek.
+pub fn deduplicate<R: std::io::Read, W: Write>(
+    input: R,
+    output: &mut W,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats> {
+    deduplicate_many(std::iter::once(input), output, options)
+}
+
+/// Deduplication function for seekable inputs (supports all modes)
+pub fn deduplicate_seekable<R: std::io::Read + std::io::Seek, W: Write>(
+    input: R,
+    output: &mut W,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats> {
+    deduplicate_many_seekable(std::iter::once(input), output, options)
+}
+
+/// Deduplicate `input` and also return the full frequency table: every key
+/// with its occurrence count, most frequent first (ties by key).
+///
+/// ```
+/// use uniqr::{deduplicate_with_counts, DeduplicationOptions};
+///
+/// let mut output = Vec::new();
+/// let (_, counts) =
+///     deduplicate_with_counts(&b"a\nb\na\n"[..], &mut output, &DeduplicationOptions::default())
+///         .unwrap();
+/// assert_eq!(counts, [(b"a".to_vec(), 2), (b"b".to_vec(), 1)]);
+/// ```
+pub fn deduplicate_with_counts<R: std::io::Read, W: Write>(
+    input: R,
+    output: &mut W,
+    options: &DeduplicationOptions,
+) -> Result<(DeduplicationStats, KeyCounts)> {
+    let options = DeduplicationOptions {
+        collect_key_counts: true,
+        ..options.clone()
+    };
+    let mut stats = deduplicate(input, output, &options)?;
+    let counts = std::mem::take(&mut stats.key_counts);
+    Ok((stats, counts))
+}
+
+/// Deduplicate `input`, also dropping every record whose key occurs in
+/// `reference`, like `grep -vxF -f reference` with the key options applied
+/// to both.
+///
+/// ```
+/// use uniqr::{deduplicate_with_reference, DeduplicationOptions};
+///
+/// let mut output = Vec::new();
+/// let options = DeduplicationOptions { ignore_case: true, ..Default::default() };
+/// deduplicate_with_reference(&b"B\n"[..], &b"a\nb\nc\na\n"[..], &mut output, &options)
+///     .unwrap();
+/// assert_eq!(output, b"a\nc\n");
+/// ```
+pub fn deduplicate_with_reference<P: std::io::Read, R: std::io::Read, W: Write>(
+    reference: P,
+    input: R,
+    output: &mut W,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats> {
+    let mut keys = load_reference_keys(reference, options)?;
+    if let Some(existing) = &options.exclude_keys {
+        keys.extend(existing.iter().cloned());
+    }
+    let options = DeduplicationOptions {
+        exclude_keys: Some(std::sync::Arc::new(keys)),
+        ..options.clone()
+    };
+    deduplicate(input, output, &options)
+}
+
+/// Keys of the records in `reference`, built as `options` builds them, for
+/// `DeduplicationOptions::exclude_keys`. A header row the options call for
+/// is skipped (and names a key column as in the input).
+pub fn load_reference_keys<R: std::io::Read>(
+    reference: R,
+    options: &DeduplicationOptions,
+) -> Result<HashSet<Vec<u8>>> {
+    let options = DeduplicationOptions {
+        exclude_keys: None,
+        ..options.clone()
+    };
+    validate_options(&options)?;
+    let mut inputs = vec![BufReader::new(reference)];
+    let options = prepare_inputs(&mut inputs, &mut std::io::sink(), &options)?;
+    let unused = Cell::new(0);
+    let mut reader = record_reader(inputs, &options, &unused);
+    let mut keys = HashSet::new();
+    let mut line = Vec::new();
+    while reader.read_record(&mut line)? > 0 {
+        if !reader.passed_through() {
+            keys.insert(
+                make_key(options.record_separator.strip(&line), &options)
+                    .map_err(|e| reader.at_line(e))?,
+            );
+        }
+        line.clear();
+    }
+    Ok(keys)
+}
+
+/// Deduplicate across several inputs, read in order, as if they were one stream.
+///
+/// Records never span two inputs, so an input without a trailing newline
+/// doesn't merge with the next one. Spools inputs to temporary files for the
+/// disk-backed two-pass modes, like `deduplicate`.
+pub fn deduplicate_many<I, R, W>(
+    inputs: I,
+    output: &mut W,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats>
+where
+    I: IntoIterator<Item = R>,
+    R: std::io::Read,
+    W: Write,
+{
+    run_many(inputs, output, None, options)
+}
+
+/// Deduplicate across several seekable inputs (supports all modes)
+pub fn deduplicate_many_seekable<I, R, W>(
+    inputs: I,
+    output: &mut W,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats>
+where
+    I: IntoIterator<Item = R>,
+    R: std::io::Read + std::io::Seek,
+    W: Write,
+{
+    run_many_seekable(inputs, output, None, options)
+}
+
+/// Like `deduplicate`, but removed records are written to `removed` instead
+/// of being dropped (or interleaved with `[REMOVED]` markers).
+///
+/// Removed records are written unchanged apart from any source annotation;
+/// `show_removed` is ignored.
+///
+/// ```
+/// use uniqr::{deduplicate_split, DeduplicationOptions};
+///
+/// let (mut kept, mut removed) = (Vec::new(), Vec::new());
+/// let options = DeduplicationOptions::default();
+/// deduplicate_split(&b"a\nb\na\n"[..], &mut kept, &mut removed, &options).unwrap();
+/// assert_eq!((kept.as_slice(), removed.as_slice()), (&b"a\nb\n"[..], &b"a\n"[..]));
+/// ```
+pub fn deduplicate_split<R: std::io::Read, W: Write, D: Write>(
+    input: R,
+    output: &mut W,
+    removed: &mut D,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats> {
+    deduplicate_many_split(std::iter::once(input), output, removed, options)
+}
+
+/// Write the kept records to `kept` and the duplicates to `duplicates` in
+/// one pass; the same as `deduplicate_split`.
+///
+/// ```
+/// use uniqr::{deduplicate_partition, DeduplicationOptions};
+///
+/// let (mut kept, mut duplicates) = (Vec::new(), Vec::new());
+/// let options = DeduplicationOptions::default();
+/// let stats =
+///     deduplicate_partition(&b"a\nb\na\n"[..], &mut kept, &mut duplicates, &options).unwrap();
+/// assert_eq!((kept.as_slice(), duplicates.as_slice()), (&b"a\nb\n"[..], &b"a\n"[..]));
+/// assert_eq!(stats.lines_removed, 1);
+/// ```
+pub fn deduplicate_partition<R: std::io::Read, W: Write, D: Write>(
+    input: R,
+    kept: &mut W,
+    duplicates: &mut D,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats> {
+    deduplicate_split(input, kept, duplicates, options)
+}
+
+/// `deduplicate_many` with removed records written to `removed`; see `deduplicate_split`
+pub fn deduplicate_many_split<I, R, W, D>(
+    inputs: I,
+    output: &mut W,
+    removed: &mut D,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats>
+where
+    I: IntoIterator<Item = R>,
+    R: std::io::Read,
+    W: Write,
+    D: Write,
+{
+    run_many(inputs, output, Some(removed), options)
+}
+
+/// `deduplicate_many_seekable` with removed records written to `removed`; see
+/// `deduplicate_split`
+pub fn deduplicate_many_split_seekable<I, R, W, D>(
+    inputs: I,
+    output: &mut W,
+    removed: &mut D,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats>
+where
+    I: IntoIterator<Item = R>,
+    R: std::io::Read + std::io::Seek,
+    W: Write,
+    D: Write,
+{
+    run_many_seekable(inputs, output, Some(removed), options)
+}
+
+/// Deduplicate the file at `input` into the file at `output`.
+///
+/// Inputs and outputs with a compressed extension (`.gz`, `.zst`, `.xz`) are
+/// decompressed and compressed as needed; uncompressed inputs are read with
+/// `deduplicate_seekable`, so every mode is supported. The output is written
+/// through `write_atomically`, so it may be the input file itself.
+pub fn deduplicate_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    output: Q,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats> {
+    let (input, output) = (input.as_ref(), output.as_ref());
+    let file = File::open(input).map_err(|e| {
+        Error::Io(std::io::Error::new(
+            e.kind(),
+            format!("Failed to open input file '{}': {}", input.display(), e),
+        ))
+    })?;
+    let reader = CompressedReader::new(file, Compression::from_path(input))?;
+    write_atomically(output, |file| {
+        let mut writer = CompressedWriter::new(file, Compression::from_path(output))?;
+        let stats = match reader {
+            CompressedReader::Plain(file) => deduplicate_seekable(file, &mut writer, options)?,
+            #[allow(unreachable_patterns)]
+            reader => deduplicate(reader, &mut writer, options)?,
+        };
+        writer.finish()?;
+        Ok(stats)
+    })
+}
+
+/// Deduplicate the file at `path`, replacing it only once the deduplicated
+/// copy is complete (see `deduplicate_file`)
+///
+/// ```
+/// use uniqr::{deduplicate_file_in_place, DeduplicationOptions};
+///
+/// let dir = tempfile::tempdir().unwrap();
+/// let path = dir.path().join("app.log");
+/// std::fs::write(&path, "a\nb\na\n").unwrap();
+/// deduplicate_file_in_place(&path, &DeduplicationOptions::default()).unwrap();
+/// assert_eq!(std::fs::read_to_string(&path).unwrap(), "a\nb\n");
+/// ```
+pub fn deduplicate_file_in_place<P: AsRef<Path>>(
+    path: P,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats> {
+    deduplicate_file(path.as_ref(), path.as_ref(), options)
+}
+
+/// Create the file at `path` by passing `write` a temporary file next to it
+/// (`path` with `.tmp` appended), then renaming that over `path`, so readers
+/// never see a partially written file. The temporary file is removed if
+/// `write` fails.
+pub fn write_atomically<P, T, F>(path: P, write: F) -> Result<T>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut BufWriter<File>) -> Result<T>,
+{
+    let path = path.as_ref();
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+    let temp_file = File::create(&temp_path).map_err(|e| {
+        Error::Io(std::io::Error::new(
+            e.kind(),
+            format!(
+                "Failed to create temp file '{}': {}",
+                temp_path.display(),
+                e
+            ),
+        ))
+    })?;
+    let mut writer = BufWriter::new(temp_file);
+    let result = write(&mut writer).and_then(|value| {
+        writer.flush()?;
+        Ok(value)
+    });
+    drop(writer);
+    let result = result.and_then(|value| {
+        std::fs::rename(&temp_path, path).map_err(|e| {
+            Error::Io(std::io::Error::new(
+                e.kind(),
+                format!(
+                    "Failed to rename '{}' to '{}': {}",
+                    temp_path.display(),
+                    path.display(),
+                    e
+                ),
+            ))
+        })?;
+        Ok(value)
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Shared implementation of the streaming entry points
+fn run_many<I, R, W>(
+    inputs: I,
+    output: &mut W,
+    removed: Option<&mut dyn Write>,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats>
+where
+    I: IntoIterator<Item = R>,
+    R: std::io::Read,
+    W: Write,
+{
+    validate_options(options)?;
+
+    // The disk-backed two-pass modes read their inputs twice
+    #[cfg(feature = "disk-backed")]
+    if options.use_disk
+        && matches!(
+            options.mode,
+            DeduplicationMode::KeepLast | DeduplicationMode::RemoveAll
+        )
+    {
+        log::info!("Spooling the input to a temporary file to read it twice");
+        let inputs = spool_inputs(inputs, &options.disk)?;
+        return run_many_seekable(inputs, output, removed, options);
+    }
+
+    let timer = Timer::start();
+    let tracker = ProgressTracker::new(options.progress_callback.as_deref());
+    let unterminated = Cell::new(false);
+    let footer = RefCell::new(Vec::new());
+    let output = &mut RecordOutput::new(output, removed, options);
+    let mut inputs: Vec<_> = inputs
+        .into_iter()
+        .map(|input| {
+            let input = ProgressReader::new(input, &tracker);
+            let terminator = options.record_separator.terminator();
+            let input = TerminatedReader::new(input, terminator, &unterminated);
+            BufReader::new(FooterReader::new(
+                input,
+                options.footer_lines,
+                terminator,
+                &footer,
+            ))
+        })
+        .collect();
+    let options = prepare_inputs(&mut inputs, output, options)?;
+    let excluded = Cell::new(0);
+    let reader = record_reader(inputs, &options, &excluded).with_progress(&tracker);
+    let stats = deduplicate_stream(reader, output, &options)
+        .map_err(|e| partial_stats(e, &tracker, &mut output.kept, &timer))?;
+    output.finish(&footer.borrow(), unterminated.get())?;
+    Ok(finish_stats(
+        stats,
+        &tracker,
+        output.kept.get_ref(),
+        &timer,
+        &excluded,
+    ))
+}
+
+/// Copy each input to an anonymous temporary file (deleted once closed) so
+/// it can be read more than once
+#[cfg(feature = "disk-backed")]
+fn spool_inputs<I, R>(inputs: I, disk: &DiskOptions) -> Result<Vec<std::fs::File>>
+where
+    I: IntoIterator<Item = R>,
+    R: std::io::Read,
+{
+    use std::io::Seek;
+
+    inputs
+        .into_iter()
+        .map(|mut input| {
+            let mut file = match &disk.temp_dir {
+                Some(dir) => tempfile::tempfile_in(dir),
+                None => tempfile::tempfile(),
+            }
+            .map_err(Error::Spill)?;
+            std::io::copy(&mut input, &mut file)?;
+            file.rewind().map_err(Error::Spill)?;
+            Ok(file)
+        })
+        .collect()
+}
+
+/// Shared implementation of the seekable entry points
+fn run_many_seekable<I, R, W>(
+    inputs: I,
+    output: &mut W,
+    removed: Option<&mut dyn Write>,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats>
+where
+    I: IntoIterator<Item = R>,
+    R: std::io::Read + std::io::Seek,
+    W: Write,
+{
+    validate_options(options)?;
+    let timer = Timer::start();
+    let tracker = ProgressTracker::new(options.progress_callback.as_deref());
+    let unterminated = Cell::new(false);
+    let footer = RefCell::new(Vec::new());
+    let output = &mut RecordOutput::new(output, removed, options);
+    let mut inputs: Vec<_> = inputs
+        .into_iter()
+        .map(|input| {
+            let input = ProgressReader::new(input, &tracker);
+            let terminator = options.record_separator.terminator();
+            let input = TerminatedReader::new(input, terminator, &unterminated);
+            BufReader::new(FooterReader::new(
+                input,
+                options.footer_lines,
+                terminator,
+                &footer,
+            ))
+        })
+        .collect();
+    let options = prepare_inputs(&mut inputs, output, options)?;
+    let excluded = Cell::new(0);
+
+    #[cfg(feature = "disk-backed")]
+    if options.use_disk {
+        let stats = match options.mode {
+            DeduplicationMode::KeepLast => {
+                deduplicate_keep_last_disk(&mut inputs, output, &options, &excluded, &tracker)
+            }
+            DeduplicationMode::RemoveAll => {
+                deduplicate_remove_all_disk(&mut inputs, output, &options, &excluded, &tracker)
+            }
+            // KeepFirst (disk) doesn't strictly *need* Seek,
+            // so we can delegate to the standard path (which also
+            // rejects the repeated modes).
+            DeduplicationMode::KeepFirst
+            | DeduplicationMode::Repeated
+            | DeduplicationMode::AllRepeated
+            | DeduplicationMode::Similar { .. } => {
+                let reader = record_reader(inputs, &options, &excluded).with_progress(&tracker);
+                deduplicate_stream(reader, output, &options)
+            }
+        }
+        .map_err(|e| partial_stats(e, &tracker, &mut output.kept, &timer))?;
+        output.finish(&footer.borrow(), unterminated.get())?;
+        return Ok(finish_stats(
+            stats,
+            &tracker,
+            output.kept.get_ref(),
+            &timer,
+            &excluded,
+        ));
+    }
+
+    // Default to standard deduplicate if disk-backed is not used
+    let reader = record_reader(inputs, &options, &excluded).with_progress(&tracker);
+    let stats = deduplicate_stream(reader, output, &options)
+        .map_err(|e| partial_stats(e, &tracker, &mut output.kept, &timer))?;
+    output.finish(&footer.borrow(), unterminated.get())?;
+    Ok(finish_stats(
+        stats,
+        &tracker,
+        output.kept.get_ref(),
+        &timer,
+        &excluded,
+    ))
+}
+
+/// Deduplicate a file by memory-mapping it instead of reading it through a buffer.
+///
+/// Records are scanned with `memchr` and keys borrow from the mapping where
+/// possible, so plain line deduplication doesn't copy or allocate per line.
+/// The two-pass modes revisit the mapping instead of buffering records.
+/// Options that need a header or non-byte separators (paragraphs, CSV, header
+/// column names, disk-backed storage or a memory limit) fall back to the
+/// streaming path over the mapped bytes. Requires the 'mmap' feature.
+///
+/// The file must not be modified while it is being deduplicated.
+#[cfg(feature = "mmap")]
+pub fn deduplicate_mmap<P: AsRef<std::path::Path>, W: Write>(
+    path: P,
+    output: &mut W,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats> {
+    let file = std::fs::File::open(path)?;
+    // SAFETY: the mapping is only read, and callers must not modify the file
+    // while it is mapped (documented above).
+    let mmap = unsafe { memmap2::Mmap::map(&file)? };
+
+    let fast_path = matches!(
+        options.record_separator,
+        RecordSeparator::Byte(_) | RecordSeparator::Bytes(_)
+    ) && options.column_name.is_none()
+        && options.csv.is_none()
+        && options.header_lines == 0
+        && options.footer_lines == 0
+        && options.approximate.is_none()
+        && options.fuzzy.is_none()
+        && options.window.is_none()
+        && options.group.is_none()
+        && !matches!(options.mode, DeduplicationMode::Similar { .. })
+        && !options.adjacent
+        && !options.collect_removed_records
+        && !options.use_disk
+        && options.memory_limit.is_none()
+        && options.max_line_length.is_none()
+        && options.utf8_policy != Utf8Policy::Skip
+        && options.passthrough.is_none()
+        && options.empty_lines == EmptyLinePolicy::Dedup
+        && options.exclude_keys.is_none()
+        // Unterminated input is terminated while it is read
+        && (mmap.is_empty() || mmap.ends_with(options.record_separator.terminator()));
+    if !fast_path {
+        log::debug!("The options need the streaming path over the mapped file");
+        return deduplicate_seekable(std::io::Cursor::new(&mmap[..]), output, options);
+    }
+    log::debug!("Deduplicating {} mapped bytes", mmap.len());
+
+    let timer = Timer::start();
+    let tracker = ProgressTracker::new(options.progress_callback.as_deref());
+    let output = &mut RecordOutput::new(output, None, options);
+    let data = match bom::detect(&mmap) {
+        Some(bom) => {
+            if options.emit_bom {
+                output.write_all(bom)?;
+            }
+            tracker.advance(bom.len() as u64);
+            &mmap[bom.len()..]
+        }
+        None => &mmap[..],
+    };
+    let stats = deduplicate_mapped(data, output, options, &tracker)
+        .map_err(|e| partial_stats(e, &tracker, &mut output.kept, &timer))?;
+    output.finish(&[], false)?;
+    Ok(finish_stats(
+        stats,
+        &tracker,
+        output.kept.get_ref(),
+        &timer,
+        &Cell::new(0),
+    ))
+}
+
+/// Split mapped data into records, each including its terminator
+#[cfg(feature = "mmap")]
+fn mapped_records<'a>(
+    data: &'a [u8],
+    separator: &'a RecordSeparator,
+) -> impl Iterator<Item = &'a [u8]> + 'a {
+    let mut rest = data;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let end = match separator {
+            RecordSeparator::Byte(sep) => memchr::memchr(*sep, rest).map(|idx| idx + 1),
+            RecordSeparator::Bytes(sep) if !sep.is_empty() => {
+                memchr::memmem::find(rest, sep).map(|idx| idx + sep.len())
+            }
+            _ => memchr::memchr(b'\n', rest).map(|idx| idx + 1),
+        }
+        .unwrap_or(rest.len());
+        let (record, tail) = rest.split_at(end);
+        rest = tail;
+        Some(record)
+    })
+}
+
+/// Key for a record (including its terminator), borrowed when no key
+/// transformation applies
+fn record_key<'a>(
+    record: &'a [u8],
+    options: &DeduplicationOptions,
+) -> Result<std::borrow::Cow<'a, [u8]>> {
+    let line = options.record_separator.strip(record);
+    let plain = options.key_extractor.is_none()
+        && options.normalizers.is_empty()
+        && options.csv.is_none()
+        && options.json_pointer.is_none()
+        && options.column.is_none()
+        && options.normalization.is_none()
+        && !options.ignore_diacritics
+        && options.trim == TrimMode::None
+        && options.punctuation == PunctuationMode::None
+        && options.skip_fields == 0
+        && options.skip_chars == 0
+        && options.compare_width.is_none()
+        && !options.ignore_case
+        && options.key_type == KeyType::Text
+        && options.utf8_policy != Utf8Policy::Strict;
+    if plain {
+        Ok(std::borrow::Cow::Borrowed(line))
+    } else {
+        make_key(line, options).map(std::borrow::Cow::Owned)
+    }
+}
+
+/// Bytes of mapped input between progress reports
+#[cfg(feature = "mmap")]
+const MAPPED_PROGRESS_INTERVAL: usize = 64 * 1024;
+
+/// All three modes over mapped data: one pass to index keys, and a second
+/// pass over the mapping for everything except plain keep-first
+#[cfg(feature = "mmap")]
+fn deduplicate_mapped<W: Write>(
+    data: &[u8],
+    output: &mut RecordOutput<'_, W>,
+    options: &DeduplicationOptions,
+    tracker: &ProgressTracker,
+) -> Result<DeduplicationStats> {
+    /// First and last record index and occurrence count of a key
+    struct Entry {
+        first: usize,
+        last: usize,
+        count: usize,
+    }
+
+    let mut stats = DeduplicationStats::default();
+    let mut index: KeyMap<std::borrow::Cow<[u8]>, usize> = key_map(options.hasher)?;
+    let mut entries: Vec<Entry> = Vec::new();
+    let streaming = options.mode == DeduplicationMode::KeepFirst && !options.count;
+    // Entry of each record, needed for the second pass
+    let mut record_entries = Vec::new();
+    let mut unreported = 0;
+
+    for (idx, record) in mapped_records(data, &options.record_separator).enumerate() {
+        check_cancel(options.cancel.as_deref())?;
+        stats.lines_read += 1;
+        tracker.count_line();
+        // Report in chunks, like a buffered reader would
+        unreported += record.len();
+        if unreported >= MAPPED_PROGRESS_INTERVAL {
+            tracker.advance(unreported as u64);
+            unreported = 0;
+        }
+        let key = record_key(record, options).map_err(|e| Error::AtLine {
+            line: idx + 1,
+            offset: (record.as_ptr() as usize - data.as_ptr() as usize) as u64,
+            source: Box::new(e),
+        })?;
+        let next = entries.len();
+        let entry_idx = *index.entry(key).or_insert(next);
+        if entry_idx == next {
+            entries.push(Entry {
+                first: idx,
+                last: idx,
+                count: 0,
+            });
+        }
+        let entry = &mut entries[entry_idx];
+        entry.last = idx;
+        entry.count += 1;
+
+        if options.mode == DeduplicationMode::KeepFirst {
+            let origin = Origin::single(idx);
+            if entry.count == 1 {
+                if streaming {
+                    write_record(output, record, origin, options)?;
+                }
+                stats.lines_written += 1;
+            } else {
+                stats.lines_removed += 1;
+                output.write_removed(record, origin, options)?;
+            }
+        }
+        if !streaming {
+            record_entries.push(entry_idx);
+        }
+    }
+
+    if unreported > 0 {
+        tracker.advance(unreported as u64);
+    }
+
+    stats.unique_lines = entries
+        .iter()
+        .filter(|e| options.mode.keeps(e.first, e.first, e.first, e.count))
+        .count();
+    report_key_counts(&mut stats, options, || {
+        index.iter().map(|(k, &e)| (&k[..], entries[e].count))
+    });
+    if streaming {
+        return Ok(stats);
+    }
+
+    for (idx, record) in mapped_records(data, &options.record_separator).enumerate() {
+        check_cancel(options.cancel.as_deref())?;
+        let entry = &entries[record_entries[idx]];
+        let origin = Origin::single(idx);
+        let keep = options
+            .mode
+            .keeps(idx, entry.first, entry.last, entry.count);
+        if options.mode == DeduplicationMode::KeepFirst {
+            // Removed records and stats were handled in the first pass
+            if keep {
+                write_counted_record(output, record, entry.count as u64, origin, options)?;
+            }
+        } else if keep {
+            if options.count {
+                write_counted_record(output, record, entry.count as u64, origin, options)?;
+            } else {
+                write_record(output, record, origin, options)?;
+            }
+            stats.lines_written += 1;
+        } else {
+            stats.lines_removed += 1;
+            output.write_removed(record, origin, options)?;
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Reject option combinations that no algorithm supports
+/// Reject `options` unless they deduplicate in memory in KeepFirst mode
+/// without counts or removed-record collection, as the options that only
+/// the one-pass KeepFirst algorithms honor need; `what` names the option
+/// for the error
+fn requires_simple_keep_first(options: &DeduplicationOptions, what: &str) -> Result<()> {
+    if options.mode != DeduplicationMode::KeepFirst
+        || options.count
+        || options.collect_removed_records
+        || options.use_disk
+        || options.memory_limit.is_some()
+    {
+        return Err(Error::InvalidArgument(format!(
+            "{} requires in-memory KeepFirst mode without counts or removed-record collection",
+            what
+        )));
+    }
+    Ok(())
+}
+
+fn validate_options(options: &DeduplicationOptions) -> Result<()> {
+    let fold = options.ignore_case && options.case_mode == CaseMode::Fold;
+    if (options.normalization.is_some() || options.ignore_diacritics || fold)
+        && !cfg!(feature = "unicode")
+    {
+        return Err(Error::InvalidArgument(
+            "Normalization, ignoring diacritics and case folding require the 'unicode' feature"
+                .to_string(),
+        ));
+    }
+    if options.footer_lines > 0
+        && !matches!(&options.record_separator, RecordSeparator::Byte(_))
+        && !matches!(&options.record_separator, RecordSeparator::Bytes(sep) if !sep.is_empty())
+    {
+        return Err(Error::InvalidArgument(
+            "Footers require a byte or byte-string separator".to_string(),
+        ));
+    }
+    if options.max_line_length == Some(0) {
+        return Err(Error::InvalidArgument(
+            "Maximum line length must be at least 1".to_string(),
+        ));
+    }
+    if options.column == Some(0) {
+        return Err(Error::InvalidArgument(
+            "Column is 1-indexed; 0 is not a valid column".to_string(),
+        ));
+    }
+    if options.compare_width == Some(0) {
+        return Err(Error::InvalidArgument(
+            "Compare width must be at least 1".to_string(),
+        ));
+    }
+    if matches!(&options.record_separator, RecordSeparator::Bytes(sep) if sep.is_empty()) {
+        return Err(Error::InvalidArgument(
+            "Record separator must not be empty".to_string(),
+        ));
+    }
+    let long_lines_pass = options.max_line_length.is_some()
+        && options.line_length_policy == LineLengthPolicy::PassThrough;
+    if long_lines_pass && !matches!(options.record_separator, RecordSeparator::Byte(_)) {
+        return Err(Error::InvalidArgument(
+            "Passing long lines through requires a byte separator".to_string(),
+        ));
+    }
+    // Records passed through are written as they are read, around the
+    // algorithms that reorder or hold back output
+    let passes_through = if long_lines_pass {
+        Some("Passing long lines through")
+    } else if options.utf8_policy == Utf8Policy::Skip {
+        Some("Passing invalid UTF-8 through")
+    } else if options.passthrough.is_some() || options.empty_lines == EmptyLinePolicy::Preserve {
+        Some("Passing matching or empty records through")
+    } else {
+        None
+    };
+    if let Some(what) = passes_through {
+        requires_simple_keep_first(options, what)?;
+        if options.sort.is_some() || options.group.is_some() || options.adjacent {
+            return Err(Error::InvalidArgument(format!(
+                "{} requires non-adjacent deduplication without sorting or grouping",
+                what
+            )));
+        }
+    }
+    if options.state_db.is_some()
+        && (!options.use_disk || options.mode != DeduplicationMode::KeepFirst || options.adjacent)
+    {
+        return Err(Error::InvalidArgument(
+            "A state database requires disk-backed, non-adjacent KeepFirst mode".to_string(),
+        ));
+    }
+    if options.state_db.is_some() && options.disk.backend != DiskBackend::Sled {
+        return Err(Error::InvalidArgument(
+            "A state database requires the sled disk backend".to_string(),
+        ));
+    }
+    if options.checkpoint_every == Some(0) {
+        return Err(Error::InvalidArgument(
+            "Checkpoint interval must be at least 1 record".to_string(),
+        ));
+    }
+    if (options.checkpoint_every.is_some() || options.resume)
+        && (options.state_db.is_none()
+            || options.count
+            || options.sort.is_some()
+            || options.memory_limit.is_some())
+    {
+        return Err(Error::InvalidArgument(
+            "Checkpoints require a state database, and support neither counts, sorting nor a memory limit"
+                .to_string(),
+        ));
+    }
+    if options.memory_limit.is_some()
+        && (options.mode != DeduplicationMode::KeepFirst
+            || options.adjacent
+            || options.approximate.is_some()
+            || options.collect_removed_records)
+    {
+        return Err(Error::InvalidArgument(
+            "A memory limit only applies to KeepFirst mode without adjacent, approximate or removed-record collection"
+                .to_string(),
+        ));
+    }
+    if options.collect_removed_records && (options.approximate.is_some() || options.use_disk) {
+        return Err(Error::InvalidArgument(
+            "Collecting removed records cannot be combined with approximate mode or disk-backed storage"
+                .to_string(),
+        ));
+    }
+    if options.group.is_some() {
+        requires_simple_keep_first(options, "Grouping")?;
+        if options.approximate.is_some() || options.fuzzy.is_some() {
+            return Err(Error::InvalidArgument(
+                "Grouping only supports exact deduplication".to_string(),
+            ));
+        }
+    }
+    if options.sort.is_some() && options.group.is_some() {
+        return Err(Error::InvalidArgument(
+            "Sorted output cannot be combined with grouping".to_string(),
+        ));
+    }
+    if options.sort.is_some_and(SortOrder::by_count) && (!options.count || options.show_removed) {
+        return Err(Error::InvalidArgument(
+            "Sorting by count requires counts and cannot show removed records inline".to_string(),
+        ));
+    }
+    if options.adjacent
+        && (options.approximate.is_some() || reports_key_counts(options) || options.use_disk)
+    {
+        return Err(Error::InvalidArgument(
+            "Adjacent deduplication cannot be combined with approximate mode, key-count reports or disk-backed storage"
+                .to_string(),
+        ));
+    }
+    if options.fuzzy.is_some()
+        && (options.mode != DeduplicationMode::KeepFirst
+            || options.adjacent
+            || options.approximate.is_some()
+            || options.collect_removed_records
+            || options.use_disk
+            || options.memory_limit.is_some())
+    {
+        return Err(Error::InvalidArgument(
+            "Fuzzy deduplication only supports in-memory KeepFirst mode without adjacent, approximate or removed-record collection"
+                .to_string(),
+        ));
+    }
+    if let Some(window) = &options.window {
+        match window {
+            WindowMode::Lines(0) | WindowMode::Keys(0) => {
+                return Err(Error::InvalidArgument(
+                    "The window must hold at least one line or key".to_string(),
+                ));
+            }
+            WindowMode::Time { format, .. } => window::validate_format(format)?,
+            _ => {}
+        }
+        requires_simple_keep_first(options, "Windowed deduplication")?;
+        if options.adjacent
+            || options.approximate.is_some()
+            || options.fuzzy.is_some()
+            || options.group.is_some()
+            || reports_key_counts(options)
+        {
+            return Err(Error::InvalidArgument(
+                "Windowed deduplication only supports exact, non-adjacent, ungrouped deduplication without key-count reports"
+                    .to_string(),
+            ));
+        }
+    }
+    if let DeduplicationMode::Similar { .. } = options.mode {
+        if options.adjacent || options.collect_removed_records || options.use_disk {
+            return Err(Error::InvalidArgument(
+                "Similar mode only supports in-memory, non-adjacent deduplication without removed-record collection"
+                    .to_string(),
+            ));
+        }
+        if options.similarity.shingle_size == 0 {
+            return Err(Error::InvalidArgument(
+                "Shingle size must be at least 1".to_string(),
+            ));
+        }
+    }
+    if let Some(approximate) = &options.approximate {
+        if options.mode != DeduplicationMode::KeepFirst {
+            return Err(Error::InvalidArgument(
+                "Approximate deduplication only supports KeepFirst mode".to_string(),
+            ));
+        }
+        if options.count || reports_key_counts(options) || options.use_disk {
+            return Err(Error::InvalidArgument(
+                "Approximate deduplication cannot be combined with counting, key-count reports or disk-backed storage"
+                    .to_string(),
+            ));
+        }
+        let rate = approximate.false_positive_rate;
+        if !(rate > 0.0 && rate < 1.0) {
+            return Err(Error::InvalidArgument(format!(
+                "False-positive rate must be between 0 and 1, got {}",
+                rate
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Prepare every input: byte order marks are stripped (and the first one
+/// written if requested), the first input's header (if any) is handled by
+/// `prepare_input`, and the matching header of each later input is skipped.
+fn prepare_inputs<R: std::io::BufRead, W: Write>(
+    inputs: &mut [R],
+    output: &mut W,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationOptions> {
+    // The output a resumed run continues already starts with these
+    let mut sink = std::io::sink();
+    let mut output: &mut dyn Write = if options.resume { &mut sink } else { output };
+
+    let mut first_bom = None;
+    for input in inputs.iter_mut() {
+        let bom = bom::strip(input)?;
+        first_bom = first_bom.or(bom);
+    }
+    if let Some(bom) = first_bom.filter(|_| options.emit_bom) {
+        output.write_all(bom)?;
+    }
+
+    let Some((first, rest)) = inputs.split_first_mut() else {
+        return Ok(options.clone());
+    };
+    let mut header = Vec::new();
+    let mut reader = RecordReader::new(&mut *first, &options.record_separator);
+    for _ in 0..options.header_lines {
+        reader.read_record(&mut header)?;
+    }
+    output.write_all(&header)?;
+    for input in rest.iter_mut() {
+        let mut reader = RecordReader::new(input, &options.record_separator);
+        for _ in 0..options.header_lines {
+            header.clear();
+            reader.read_record(&mut header)?;
+        }
+    }
+    let prepared = prepare_input(first, &mut output, options)?;
+
+    let has_header =
+        options.column_name.is_some() || options.csv.as_ref().is_some_and(|csv| csv.has_header);
+    if has_header {
+        let mut header = Vec::new();
+        for input in rest {
+            RecordReader::new(input, &prepared.record_separator).read_record(&mut header)?;
+            header.clear();
+        }
+    }
+
+    Ok(prepared)
+}
+
+/// Consume any header record and resolve options that depend on it.
+///
+/// The header (if the options call for one) is copied to the output verbatim
+/// and is not counted in the statistics.
+fn prepare_input<R: std::io::BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationOptions> {
+    let mut options = options.clone();
+
+    if let Some(name) = options.column_name.take() {
+        if options.csv.is_some() {
+            return Err(Error::InvalidArgument(
+                "column_name cannot be combined with CSV mode; use CsvColumn::Name".to_string(),
+            ));
+        }
+        let index = resolve_header_column(input, output, &options.record_separator, &name)?;
+        options.column = Some(index);
+    }
+
+    if let Some(csv) = options.csv.take() {
+        let csv = prepare_csv(input, output, &mut options.record_separator, csv)?;
+        options.csv = Some(csv);
+    }
+
+    Ok(options)
+}
+
+/// Read the header record, copy it to the output, and find the 1-indexed
+/// position of the named whitespace-separated column
+fn resolve_header_column<R: std::io::BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    separator: &RecordSeparator,
+    name: &str,
+) -> Result<usize> {
+    let mut header = Vec::new();
+    RecordReader::new(input, separator).read_record(&mut header)?;
+    output.write_all(&header)?;
+
+    String::from_utf8_lossy(separator.strip(&header))
+        .split_whitespace()
+        .position(|col| col == name)
+        .map(|idx| idx + 1)
+        .ok_or_else(|| Error::InvalidArgument(format!("Header has no column named '{}'", name)))
+}
+
+/// Switch to quote-aware records, pass the header through, and resolve a named key column
+#[cfg(feature = "csv")]
+fn prepare_csv<R: std::io::BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    separator: &mut RecordSeparator,
+    mut csv: CsvOptions,
+) -> Result<CsvOptions> {
+    if *separator == RecordSeparator::default() {
+        *separator = RecordSeparator::QuotedLine;
+    }
+
+    if !csv.has_header {
+        if let CsvColumn::Name(name) = &csv.key_column {
+            return Err(Error::InvalidArgument(format!(
+                "CSV column '{}' selected by name but input has no header",
+                name
+            )));
+        }
+        return Ok(csv);
+    }
+
+    let mut header = Vec::new();
+    RecordReader::new(input, separator).read_record(&mut header)?;
+    output.write_all(&header)?;
+
+    if let CsvColumn::Name(name) = &csv.key_column {
+        let index = csv_fields(separator.strip(&header), csv.delimiter)?
+            .iter()
+            .position(|field| field == name.as_bytes())
+            .ok_or_else(|| {
+                Error::InvalidArgument(format!("CSV header has no column named '{}'", name))
+            })?;
+        csv.key_column = CsvColumn::Index(index + 1);
+    }
+    Ok(csv)
+}
+
+#[cfg(not(feature = "csv"))]
+fn prepare_csv<R: std::io::BufRead, W: Write>(
+    _input: &mut R,
+    _output: &mut W,
+    _separator: &mut RecordSeparator,
+    _csv: CsvOptions,
+) -> Result<CsvOptions> {
+    Err(Error::InvalidArgument(
+        "CSV mode requires the 'csv' feature".to_string(),
+    ))
+}
+
+/// Dispatch to the streaming algorithms (no seeking required)
+fn deduplicate_stream<R: std::io::BufRead, W: Write>(
+    reader: RecordReader<'_, R>,
+    output: &mut RecordOutput<'_, W>,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats> {
+    log::debug!(
+        "Deduplicating with the {} algorithm",
+        algorithm_name(options)
+    );
+    #[cfg(feature = "disk-backed")]
+    if options.use_disk {
+        match options.mode {
+            DeduplicationMode::KeepFirst => {
+                let stats = deduplicate_keep_first_disk(reader, output, options)?;
+                output.flush()?;
+                return Ok(stats);
+            }
+            DeduplicationMode::KeepLast | DeduplicationMode::RemoveAll => {
+                return Err(Error::InvalidArgument(
+                    "Disk-backed KeepLast and RemoveAll modes require a seekable input. Use deduplicate_seekable() or provide a file.".to_string(),
+                ));
+            }
+            DeduplicationMode::Repeated
+            | DeduplicationMode::AllRepeated
+            | DeduplicationMode::Similar { .. } => {
+                return Err(Error::InvalidArgument(
+                    "Disk-backed storage doesn't support the Repeated, AllRepeated and Similar modes"
+                        .to_string(),
+                ));
+            }
+        }
+    }
+
+    if let Some(separator) = options.group {
+        let stats = deduplicate_grouped(reader, output, options, separator)?;
+        output.flush()?;
+        return Ok(stats);
+    }
+
+    if options.adjacent {
+        let stats = deduplicate_adjacent(reader, output, options)?;
+        output.flush()?;
+        return Ok(stats);
+    }
+
+    if let Some(approximate) = options.approximate {
+        let stats = deduplicate_keep_first_approximate(reader, output, options, approximate)?;
+        output.flush()?;
+        return Ok(stats);
+    }
+
+    if let Some(fuzzy) = options.fuzzy {
+        let stats = deduplicate_keep_first_fuzzy(reader, output, options, fuzzy)?;
+        output.flush()?;
+        return Ok(stats);
+    }
+
+    if let Some(window) = &options.window {
+        let stats = deduplicate_keep_first_windowed(reader, output, options, window.clone())?;
+        output.flush()?;
+        return Ok(stats);
+    }
+
+    // Locating removed records needs every occurrence of each key, which the
+    // counting algorithm has for all modes
+    if options.collect_removed_records {
+        let stats = deduplicate_by_count(reader, output, options)?;
+        output.flush()?;
+        return Ok(stats);
+    }
+
+    let stats = match options.mode {
+        DeduplicationMode::KeepFirst => deduplicate_keep_first(reader, output, options),
+        DeduplicationMode::KeepLast => deduplicate_keep_last(reader, output, options),
+        DeduplicationMode::RemoveAll
+        | DeduplicationMode::Repeated
+        | DeduplicationMode::AllRepeated => deduplicate_by_count(reader, output, options),
+        DeduplicationMode::Similar { threshold } => {
+            deduplicate_similar(reader, output, options, threshold.get())
+        }
+    }?;
+    output.flush()?;
+    Ok(stats)
+}
+
+/// The algorithm `deduplicate_stream` picks for `options`, for logging
+fn algorithm_name(options: &DeduplicationOptions) -> &'static str {
+    if options.use_disk {
+        "disk-backed"
+    } else if options.group.is_some() {
+        "grouping"
+    } else if options.adjacent {
+        "adjacent"
+    } else if options.approximate.is_some() {
+        "approximate"
+    } else if options.fuzzy.is_some() {
+        "fuzzy"
+    } else if options.window.is_some() {
+        "windowed"
+    } else if options.collect_removed_records {
+        "counting"
+    } else {
+        match options.mode {
+            DeduplicationMode::KeepFirst => "keep-first",
+            DeduplicationMode::KeepLast => "keep-last",
+            DeduplicationMode::Similar { .. } => "similarity",
+            _ => "counting",
+        }
+    }
+}
+
+/// Record reader over `inputs` with the separator, line limit, UTF-8
+/// policy, pass-through filter, empty-line policy, excluded keys and
+/// cancellation flag in `options`, counting the records it skips in `excluded`
+fn record_reader<'a, R: std::io::BufRead>(
+    inputs: Vec<R>,
+    options: &'a DeduplicationOptions,
+    excluded: &'a Cell<usize>,
+) -> RecordReader<'a, R> {
+    let reader = RecordReader::from_inputs(inputs, &options.record_separator)
+        .with_cancel(options.cancel.as_deref())
+        .with_exclusion(options, excluded)
+        .with_utf8_policy(options.utf8_policy)
+        .with_filter(options.passthrough.as_ref())
+        .with_empty_lines(options.empty_lines);
+    match options.max_line_length {
+        Some(max) => reader.with_max_line_length(max, options.line_length_policy),
+        None => reader,
+    }
+}
+
+/// Whether records with `key` are dropped by `DeduplicationOptions::exclude_keys`
+fn is_excluded(key: &[u8], options: &DeduplicationOptions) -> bool {
+    options
+        .exclude_keys
+        .as_ref()
+        .is_some_and(|keys| keys.contains(key))
+}
+
+/// Read the next record to deduplicate into `line`, first writing out the
+/// pieces of any long lines passed through under
+/// `LineLengthPolicy::PassThrough`. Returns false at the end of the input.
+fn next_record<R: std::io::BufRead, W: Write>(
+    reader: &mut RecordReader<'_, R>,
+    line: &mut Vec<u8>,
+    output: &mut RecordOutput<'_, W>,
+    stats: &mut DeduplicationStats,
+) -> Result<bool> {
+    loop {
+        let records_read = reader.records_read();
+        if reader.read_record(line)? == 0 {
+            return Ok(false);
+        }
+        if !reader.passed_through() {
+            return Ok(true);
+        }
+        output.write_all(line)?;
+        line.clear();
+        if reader.records_read() != records_read {
+            stats.lines_read += 1;
+            stats.lines_written += 1;
+        }
+    }
+}
+
+/// One-pass keep-first algorithm
+fn deduplicate_keep_first<R: std::io::BufRead, W: Write>(
+    mut reader: RecordReader<'_, R>,
+    output: &mut RecordOutput<'_, W>,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats> {
+    let mut stats = DeduplicationStats::default();
+
+    let mut seen: KeyMap<Vec<u8>, usize> = key_map(options.hasher)?;
+    let mut lines_for_count = Vec::new();
+    #[cfg(feature = "disk-backed")]
+    let mut memory_used = 0;
+
+    let mut line = Vec::new();
+    while next_record(&mut reader, &mut line, output, &mut stats)? {
+        stats.lines_read += 1;
+        let origin = Origin::of(&reader);
+
+        // Strip newline for key generation but keep for output
+        let key_line = options.record_separator.strip(&line);
+
+        let key = make_key(key_line, options).map_err(|e| reader.at_line(e))?;
+        #[cfg(feature = "disk-backed")]
+        let key_size = key.len() + KEY_ENTRY_OVERHEAD;
+        let count = seen.entry(key).or_insert(0);
+        *count += 1;
+
+        if *count == 1 {
+            if options.count {
+                lines_for_count.push((line.clone(), origin));
+            } else {
+                write_record(output, &line, origin, options)?;
+            }
+            stats.lines_written += 1;
+            #[cfg(feature = "disk-backed")]
+            {
+                memory_used += key_size;
+            }
+        } else {
+            stats.lines_removed += 1;
+            output.write_removed(&line, origin, options)?;
+        }
+        line.clear();
+
+        #[cfg(feature = "disk-backed")]
+        if options
+            .memory_limit
+            .is_some_and(|limit| memory_used > limit)
+        {
+            return spill_keep_first(reader, output, options, stats, seen, lines_for_count);
+        }
+    }
+
+    stats.unique_lines = seen.len();
+    report_key_counts(&mut stats, options, || {
+        seen.iter().map(|(k, &c)| (&k[..], c))
+    });
+
+    // Write counts if requested
+    if options.count {
+        for (line, origin) in lines_for_count {
+            // lines_for_count stores full records, so strip the terminator again
+            let key_line = options.record_separator.strip(&line);
+
+            let key = make_key(key_line, options)?;
+
+            if let Some(&cnt) = seen.get(&key) {
+                write_counted_record(output, &line, cnt as u64, origin, options)?;
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+/// One-pass keep-first algorithm using a Bloom filter instead of a map
+fn deduplicate_keep_first_approximate<R: std::io::BufRead, W: Write>(
+    mut reader: RecordReader<'_, R>,
+    output: &mut RecordOutput<'_, W>,
+    options: &DeduplicationOptions,
+    approximate: ApproximateOptions,
+) -> Result<DeduplicationStats> {
+    let mut stats = DeduplicationStats::default();
+    let mut seen =
+        bloom::BloomFilter::new(approximate.expected_lines, approximate.false_positive_rate);
+
+    let mut line = Vec::new();
+    while next_record(&mut reader, &mut line, output, &mut stats)? {
+        stats.lines_read += 1;
+        let origin = Origin::of(&reader);
+
+        let key = make_key(options.record_separator.strip(&line), options)
+            .map_err(|e| reader.at_line(e))?;
+        if seen.check_and_insert(&key) {
+            stats.lines_removed += 1;
+            output.write_removed(&line, origin, options)?;
+        } else {
+            write_record(output, &line, origin, options)?;
+            stats.lines_written += 1;
+        }
+        line.clear();
+    }
+
+    // The filter can't count distinct keys; every written record was new
+    stats.unique_lines = stats.lines_written;
+    Ok(stats)
+}
+
+/// One-pass keep-first algorithm that only remembers the keys of the records
+/// in `window`
+fn deduplicate_keep_first_windowed<R: std::io::BufRead, W: Write>(
+    mut reader: RecordReader<'_, R>,
+    output: &mut RecordOutput<'_, W>,
+    options: &DeduplicationOptions,
+    window: WindowMode,
+) -> Result<DeduplicationStats> {
+    let mut stats = DeduplicationStats::default();
+    let mut seen = window::Window::new(window, options.hasher)?;
+
+    let mut line = Vec::new();
+    while next_record(&mut reader, &mut line, output, &mut stats)? {
+        stats.lines_read += 1;
+        let origin = Origin::of(&reader);
+
+        let key_line = seen
+            .key_line(options.record_separator.strip(&line))
+            .map_err(|e| reader.at_line(e))?;
+        let Some(key_line) = key_line else {
+            write_record(output, &line, origin, options)?;
+            stats.lines_written += 1;
+            line.clear();
+            continue;
+        };
+        let key = make_key(key_line, options).map_err(|e| reader.at_line(e))?;
+        if seen.check_and_insert(key) {
+            stats.lines_removed += 1;
+            output.write_removed(&line, origin, options)?;
+        } else {
+            write_record(output, &line, origin, options)?;
+            stats.lines_written += 1;
+        }
+        line.clear();
+    }
+
+    // Keys are forgotten as they leave the window; every written record was
+    // new within it
+    stats.unique_lines = stats.lines_written;
+    Ok(stats)
+}
+
+/// One-pass keep-first algorithm where a record duplicates the closest kept
+/// record whose key is within `max_distance` edits of its own
+fn deduplicate_keep_first_fuzzy<R: std::io::BufRead, W: Write>(
+    mut reader: RecordReader<'_, R>,
+    output: &mut RecordOutput<'_, W>,
+    options: &DeduplicationOptions,
+    fuzzy: FuzzyOptions,
+) -> Result<DeduplicationStats> {
+    let mut stats = DeduplicationStats::default();
+    let mut seen = fuzzy::BkTree::new(fuzzy.max_distance);
+    // Occurrences of each kept key, by node number
+    let mut counts = Vec::new();
+    let mut lines_for_count = Vec::new();
+
+    let mut line = Vec::new();
+    while next_record(&mut reader, &mut line, output, &mut stats)? {
+        stats.lines_read += 1;
+        let origin = Origin::of(&reader);
+
+        let key = make_key(options.record_separator.strip(&line), options)
+            .map_err(|e| reader.at_line(e))?;
+        match seen.find(&key) {
+            Some(node) => {
+                counts[node] += 1;
+                stats.lines_removed += 1;
+                output.write_removed(&line, origin, options)?;
+            }
+            None => {
+                let node = seen.insert(key);
+                counts.push(1);
+                if options.count {
+                    lines_for_count.push((line.clone(), origin, node));
+                } else {
+                    write_record(output, &line, origin, options)?;
+                }
+                stats.lines_written += 1;
+            }
+        }
+        line.clear();
+    }
+
+    stats.unique_lines = seen.len();
+    report_key_counts(&mut stats, options, || {
+        counts
+            .iter()
+            .enumerate()
+            .map(|(node, &count)| (seen.key(node), count))
+    });
+
+    for (line, origin, node) in lines_for_count {
+        write_counted_record(output, &line, counts[node] as u64, origin, options)?;
+    }
+
+    Ok(stats)
+}
+
+/// One-pass algorithm for `DeduplicationMode::Similar`: a record duplicates
+/// the most similar kept record at least `threshold` similar to it
+fn deduplicate_similar<R: std::io::BufRead, W: Write>(
+    mut reader: RecordReader<'_, R>,
+    output: &mut RecordOutput<'_, W>,
+    options: &DeduplicationOptions,
+    threshold: f64,
+) -> Result<DeduplicationStats> {
+    let mut stats = DeduplicationStats::default();
+    let mut seen = similarity::SimilarityIndex::new(options.similarity, threshold);
+    // Occurrences of each kept key, by node number
+    let mut counts = Vec::new();
+    let mut lines_for_count = Vec::new();
+
+    let mut line = Vec::new();
+    while reader.read_record(&mut line)? > 0 {
+        stats.lines_read += 1;
+        let origin = Origin::of(&reader);
+
+        let key = make_key(options.record_separator.strip(&line), options)
+            .map_err(|e| reader.at_line(e))?;
+        let signature = seen.signature(&key);
+        match seen.find(&signature) {
+            Some(node) => {
+                counts[node] += 1;
+                stats.lines_removed += 1;
+                output.write_removed(&line, origin, options)?;
+            }
+            None => {
+                let node = seen.insert(key, signature);
+                counts.push(1);
+                if options.count {
+                    lines_for_count.push((line.clone(), origin, node));
+                } else {
+                    write_record(output, &line, origin, options)?;
+                }
+                stats.lines_written += 1;
+            }
+        }
+        line.clear();
+    }
+
+    stats.unique_lines = seen.len();
+    report_key_counts(&mut stats, options, || {
+        counts
+            .iter()
+            .enumerate()
+            .map(|(node, &count)| (seen.key(node), count))
+    });
+
+    for (line, origin, node) in lines_for_count {
+        write_counted_record(output, &line, counts[node] as u64, origin, options)?;
+    }
+
+    Ok(stats)
+}
+
+/// Two-pass keep-last algorithm
+fn deduplicate_keep_last<R: std::io::BufRead, W: Write>(
+    mut reader: RecordReader<'_, R>,
+    output: &mut RecordOutput<'_, W>,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats> {
+    let mut stats = DeduplicationStats::default();
+
+    // Each record is stored once; keys are recomputed from it when needed
+    let mut records = RecordArena::new();
+    let mut index = RecordKeyIndex::new(options)?;
+    // Last index and occurrence count of each key, by entry number
+    let mut last_occurrence: Vec<(usize, usize)> = Vec::new();
+
+    // First pass: read all lines and track last occurrence
+    let mut line = Vec::new();
+    while reader.read_record(&mut line)? > 0 {
+        let idx = stats.lines_read;
+        stats.lines_read += 1;
+
+        let key = record_key(&line, options).map_err(|e| reader.at_line(e))?;
+        let entry = index.entry(&key, idx, &records, options)?;
+        if entry == last_occurrence.len() {
+            last_occurrence.push((idx, 0));
+        }
+        let (last, count) = &mut last_occurrence[entry];
+        *last = idx;
+        *count += 1;
+        records.push(&line, Origin::of(&reader));
+        line.clear();
+    }
+
+    stats.unique_lines = index.len();
+    arena_report_key_counts(
+        &mut stats,
+        options,
+        &index,
+        &records,
+        last_occurrence.iter().map(|&(_, count)| count),
+    )?;
+
+    // Kept records in input order, each with its key's count
+    let mut kept = last_occurrence;
+    kept.sort_unstable_by_key(|&(last, _)| last);
+    let mut kept = kept.into_iter().peekable();
+
+    // Second pass: emit only last occurrences in order
+    for (idx, (line, origin)) in records.iter().enumerate() {
+        if let Some((_, count)) = kept.next_if(|&(last, _)| last == idx) {
+            if options.count {
+                write_counted_record(output, line, count as u64, origin, options)?;
+            } else {
+                write_record(output, line, origin, options)?;
+            }
+            stats.lines_written += 1;
+        } else {
+            stats.lines_removed += 1;
+            output.write_removed(line, origin, options)?;
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Two-pass algorithm for the modes that select records by their key's
+/// count (RemoveAll, Repeated and AllRepeated), and for any mode when
+/// removed records are collected
+fn deduplicate_by_count<R: std::io::BufRead, W: Write>(
+    mut reader: RecordReader<'_, R>,
+    output: &mut RecordOutput<'_, W>,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats> {
+    let mut stats = DeduplicationStats::default();
+
+    // Each record is stored once; keys are recomputed from it when needed
+    let mut records = RecordArena::new();
+    let mut index = RecordKeyIndex::new(options)?;
+    // First and last index and occurrence count of each key, by entry number
+    let mut counts: Vec<(usize, usize, usize)> = Vec::new();
+    // Entry number of each record, for the second pass
+    let mut record_entries = Vec::new();
+
+    // First pass: count all occurrences
+    let mut line = Vec::new();
+    while reader.read_record(&mut line)? > 0 {
+        let idx = stats.lines_read;
+        stats.lines_read += 1;
+
+        let key = record_key(&line, options).map_err(|e| reader.at_line(e))?;
+        let entry = index.entry(&key, idx, &records, options)?;
+        if entry == counts.len() {
+            counts.push((idx, idx, 0));
+        }
+        let (_, last, count) = &mut counts[entry];
+        *last = idx;
+        *count += 1;
+        record_entries.push(entry);
+        records.push(&line, Origin::of(&reader));
+        line.clear();
+    }
+
+    // Count the keys whose records are kept
+    stats.unique_lines = counts
+        .iter()
+        .filter(|&&(first, last, count)| {
+            options.mode.keeps(first, first, last, count)
+                || options.mode.keeps(last, first, last, count)
+        })
+        .count();
+    arena_report_key_counts(
+        &mut stats,
+        options,
+        &index,
+        &records,
+        counts.iter().map(|&(_, _, count)| count),
+    )?;
+
+    // Second pass: emit the records the mode selects
+    for (idx, (line, origin)) in records.iter().enumerate() {
+        let (first, last, count) = counts[record_entries[idx]];
+
+        if options.mode.keeps(idx, first, last, count) {
+            if options.count {
+                write_counted_record(output, line, count as u64, origin, options)?;
+            } else {
+                write_record(output, line, origin, options)?;
+            }
+            stats.lines_written += 1;
+        } else {
+            stats.lines_removed += 1;
+            output.write_removed(line, origin, options)?;
+            if options.collect_removed_records {
+                let duplicate_of = options.mode.duplicate_of(idx, first, last, count);
+                stats.removed_records.push(RemovedRecord {
+                    location: origin.location(),
+                    duplicate_of: duplicate_of.map(|idx| records.origin(idx).location()),
+                });
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+/// One-pass algorithm comparing each record only with the one before it.
+///
+/// Each run of records with equal keys is buffered until it ends, then
+/// filtered by the mode as if the run were the whole input.
+fn deduplicate_adjacent<R: std::io::BufRead, W: Write>(
+    mut reader: RecordReader<'_, R>,
+    output: &mut RecordOutput<'_, W>,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats> {
+    let mut stats = DeduplicationStats::default();
+    let mut run = Vec::new();
+    let mut run_key = None;
+
+    let mut line = Vec::new();
+    while reader.read_record(&mut line)? > 0 {
+        stats.lines_read += 1;
+
+        let key_line = options.record_separator.strip(&line);
+
+        let key = make_key(key_line, options).map_err(|e| reader.at_line(e))?;
+        if run_key.as_ref() != Some(&key) {
+            write_adjacent_run(&mut run, output, options, &mut stats)?;
+            run_key = Some(key);
+        }
+        run.push((std::mem::take(&mut line), Origin::of(&reader)));
+    }
+    write_adjacent_run(&mut run, output, options, &mut stats)?;
+
+    Ok(stats)
+}
+
+/// Emit the records of one run of equal keys in adjacent mode, leaving `run` empty
+fn write_adjacent_run<W: Write>(
+    run: &mut Vec<(Vec<u8>, Origin)>,
+    output: &mut RecordOutput<'_, W>,
+    options: &DeduplicationOptions,
+    stats: &mut DeduplicationStats,
+) -> Result<()> {
+    let count = run.len();
+    let (first, last) = match (run.first(), run.last()) {
+        (Some(first), Some(last)) => (first.1, last.1),
+        _ => return Ok(()),
+    };
+    let mut kept_any = false;
+    for (idx, (line, origin)) in run.drain(..).enumerate() {
+        if options.mode.keeps(idx, 0, count - 1, count) {
+            if options.count {
+                write_counted_record(output, &line, count as u64, origin, options)?;
+            } else {
+                write_record(output, &line, origin, options)?;
+            }
+            stats.lines_written += 1;
+            kept_any = true;
+        } else {
+            stats.lines_removed += 1;
+            output.write_removed(&line, origin, options)?;
+            if options.collect_removed_records {
+                let duplicate_of = options.mode.duplicate_of(idx, 0, count - 1, count);
+                stats.removed_records.push(RemovedRecord {
+                    location: origin.location(),
+                    duplicate_of: duplicate_of
+                        .map(|idx| if idx == 0 { first } else { last }.location()),
+                });
+            }
+        }
+    }
+    if kept_any {
+        stats.unique_lines += 1;
+    }
+    Ok(())
+}
+
+/// Write every record grouped by key, delimiting groups with empty records.
+///
+/// With `adjacent`, each run of equal keys is a group and records stream
+/// straight through; otherwise all records are held until the end, then
+/// written group by group in order of each key's first occurrence.
+fn deduplicate_grouped<R: std::io::BufRead, W: Write>(
+    mut reader: RecordReader<'_, R>,
+    output: &mut RecordOutput<'_, W>,
+    options: &DeduplicationOptions,
+    separator: GroupSeparator,
+) -> Result<DeduplicationStats> {
+    let mut stats = DeduplicationStats::default();
+    let empty = options.record_separator.terminator();
+    let start_group = |output: &mut RecordOutput<'_, W>, stats: &mut DeduplicationStats| {
+        let first = stats.unique_lines == 0;
+        stats.unique_lines += 1;
+        if !first || matches!(separator, GroupSeparator::Prepend | GroupSeparator::Both) {
+            output.write_all(empty)?;
+        }
+        Ok::<_, Error>(())
+    };
+
+    let mut line = Vec::new();
+    if options.adjacent {
+        let mut run_key = None;
+        while reader.read_record(&mut line)? > 0 {
+            stats.lines_read += 1;
+            let key = make_key(options.record_separator.strip(&line), options)
+                .map_err(|e| reader.at_line(e))?;
+            if run_key.as_ref() != Some(&key) {
+                start_group(output, &mut stats)?;
+                run_key = Some(key);
+            }
+            write_record(output, &line, Origin::of(&reader), options)?;
+            stats.lines_written += 1;
+            line.clear();
+        }
+    } else {
+        let mut records = RecordArena::new();
+        let mut index = RecordKeyIndex::new(options)?;
+        let mut counts = Vec::new();
+        // Entry number of each record
+        let mut record_entries = Vec::new();
+        while reader.read_record(&mut line)? > 0 {
+            let idx = stats.lines_read;
+            stats.lines_read += 1;
+            let key = record_key(&line, options).map_err(|e| reader.at_line(e))?;
+            let entry = index.entry(&key, idx, &records, options)?;
+            if entry == counts.len() {
+                counts.push(0);
+            }
+            counts[entry] += 1;
+            record_entries.push(entry);
+            records.push(&line, Origin::of(&reader));
+            line.clear();
+        }
+        arena_report_key_counts(
+            &mut stats,
+            options,
+            &index,
+            &records,
+            counts.iter().copied(),
+        )?;
+
+        // Entries are numbered in order of first occurrence, and a stable
+        // sort keeps each group in input order
+        let mut order: Vec<usize> = (0..records.len()).collect();
+        order.sort_by_key(|&idx| record_entries[idx]);
+        let mut previous = None;
+        for idx in order {
+            if previous != Some(record_entries[idx]) {
+                start_group(output, &mut stats)?;
+                previous = Some(record_entries[idx]);
+            }
+            write_record(output, records.record(idx), records.origin(idx), options)?;
+            stats.lines_written += 1;
+        }
+    }
+
+    if stats.unique_lines > 0 && matches!(separator, GroupSeparator::Append | GroupSeparator::Both)
+    {
+        output.write_all(empty)?;
+    }
+    Ok(stats)
+}
+
+/// Where a record came from: input index, 1-based record number within it,
+/// and 1-based record number across all inputs
+#[derive(Debug, Clone, Copy)]
+struct Origin {
+    source: usize,
+    line: usize,
+    number: usize,
+}
+
+impl Origin {
+    /// Origin of the record most recently read from `reader`
+    fn of<R: std::io::BufRead>(reader: &RecordReader<'_, R>) -> Self {
+        Self {
+            source: reader.source(),
+            line: reader.record_number(),
+            number: reader.records_read(),
+        }
+    }
+
+    /// Origin of the record at 0-based `idx` of a single input
+    fn single(idx: usize) -> Self {
+        Self {
+            source: 0,
+            line: idx + 1,
+            number: idx + 1,
+        }
+    }
+
+    fn location(self) -> RecordLocation {
+        RecordLocation {
+            source: self.source,
+            line: self.line,
+            number: self.number,
+        }
+    }
+}
+
+/// Where kept records are written
+pub(crate) trait KeptOutput: Write + Sized {
+    /// Write the output for a kept `record` with `format`, which may be
+    /// held back to sort it by the record's key or its `count`
+    fn write_kept(
+        &mut self,
+        _record: &[u8],
+        _count: Option<u64>,
+        _options: &DeduplicationOptions,
+        format: impl FnOnce(&mut dyn Write) -> Result<()>,
+    ) -> Result<()> {
+        format(self)
+    }
+}
+
+impl<W: Write> KeptOutput for CountingWriter<W> {}
+
+/// Write a kept record, preceded by any requested annotations
+fn write_record<W: KeptOutput>(
+    output: &mut W,
+    line: &[u8],
+    origin: Origin,
+    options: &DeduplicationOptions,
+) -> Result<()> {
+    if let Some(callback) = &options.record_callback {
+        callback.record_kept(line, origin.location());
+    }
+    output.write_kept(line, None, options, |output| {
+        write_annotated(output, &printed_record(line, options)?, origin, options)
+    })
+}
+
+/// What is written for a kept record: the record, or its key under
+/// `print_key`, followed by the record's terminator
+fn printed_record<'a>(
+    record: &'a [u8],
+    options: &DeduplicationOptions,
+) -> Result<std::borrow::Cow<'a, [u8]>> {
+    if !options.print_key {
+        return Ok(std::borrow::Cow::Borrowed(record));
+    }
+    let mut key = make_key(options.record_separator.strip(record), options)?;
+    key.extend_from_slice(&record[options.record_separator.content_len(record)..]);
+    Ok(std::borrow::Cow::Owned(key))
+}
+
+/// Write a record preceded by its line number and source annotation, if
+/// requested, without reporting it as kept
+fn write_annotated<W: Write + ?Sized>(
+    output: &mut W,
+    line: &[u8],
+    origin: Origin,
+    options: &DeduplicationOptions,
+) -> Result<()> {
+    if options.line_numbers {
+        write!(output, "{}:", origin.number)?;
+    }
+    if let Some(annotation) = &options.annotate_source {
+        match annotation.names.get(origin.source) {
+            Some(name) => write!(output, "{}:", name)?,
+            None => write!(output, "{}:", origin.source)?,
+        }
+        if annotation.line_numbers {
+            write!(output, "{}:", origin.line)?;
+        }
+    }
+    output.write_all(line)?;
+    Ok(())
+}
+
+/// Write a kept record with its occurrence count, laid out by `count_format`
+fn write_counted_record<W: KeptOutput>(
+    output: &mut W,
+    record: &[u8],
+    count: u64,
+    origin: Origin,
+    options: &DeduplicationOptions,
+) -> Result<()> {
+    if let Some(callback) = &options.record_callback {
+        callback.record_kept(record, origin.location());
+    }
+    output.write_kept(record, Some(count), options, |output| {
+        write_counted(
+            output,
+            &printed_record(record, options)?,
+            count,
+            origin,
+            options,
+        )
+    })
+}
+
+/// Format a record with its count as `options.count_format` places it
+fn write_counted(
+    output: &mut dyn Write,
+    record: &[u8],
+    count: impl std::fmt::Display,
+    origin: Origin,
+    options: &DeduplicationOptions,
+) -> Result<()> {
+    let format = &options.count_format;
+    match format.placement {
+        CountPlacement::Prefix => {
+            write!(
+                output,
+                "{:>width$}{}",
+                count,
+                format.separator,
+                width = format.width
+            )?;
+            write_annotated(output, record, origin, options)
+        }
+        CountPlacement::Suffix => {
+            let (content, terminator) =
+                record.split_at(options.record_separator.content_len(record));
+            write_annotated(output, content, origin, options)?;
+            write!(
+                output,
+                "{}{:>width$}",
+                format.separator,
+                count,
+                width = format.width
+            )?;
+            output.write_all(terminator)?;
+            Ok(())
+        }
+    }
+}
+
+/// Create deduplication key from line
+fn make_key(line: &[u8], options: &DeduplicationOptions) -> Result<Vec<u8>> {
+    if options.utf8_policy == Utf8Policy::Strict && std::str::from_utf8(line).is_err() {
+        return Err(String::from_utf8(line.to_vec()).unwrap_err().into());
+    }
+    let key = match &options.key_extractor {
+        Some(extractor) => extractor.extract_key(line)?,
+        None => builtin_key(line, options)?,
+    };
+    options
+        .normalizers
+        .iter()
+        .try_fold(key, |key, normalizer| normalizer.normalize(key))
+}
+
+/// The key given by the built-in key options
+fn builtin_key(line: &[u8], options: &DeduplicationOptions) -> Result<Vec<u8>> {
+    let line = skip_key_prefix(line, options.skip_fields, options.skip_chars);
+
+    let data = if let Some(csv) = &options.csv {
+        csv_key(line, csv)?
+    } else if let Some(pointer) = &options.json_pointer {
+        json_key(line, pointer)?
+    } else if let Some(col_idx) = options.column {
+        column_key(line, col_idx)
+    } else {
+        line.to_vec()
+    };
+
+    let data = match options.normalization {
+        Some(form) => normalize_key(data, form)?,
+        None => data,
+    };
+
+    let data = if options.ignore_diacritics {
+        strip_diacritics(data)?
+    } else {
+        data
+    };
+
+    let data = strip_punctuation(data, options.punctuation);
+
+    let data = trim_key(data, options.trim);
+
+    let data = match options.compare_width {
+        Some(width) => truncate_key(data, width),
+        None => data,
+    };
+
+    let data = if options.ignore_case {
+        // Try to convert to lowercase UTF-8, falling back to ASCII folding
+        match String::from_utf8(data) {
+            Ok(s) => fold_case(&s, options.case_mode)?.into_bytes(),
+            Err(e) => match options.non_utf8_case {
+                NonUtf8Case::AsciiFold => {
+                    let mut bytes = e.into_bytes();
+                    bytes.make_ascii_lowercase();
+                    bytes
+                }
+                NonUtf8Case::Error => return Err(Error::Utf8Error(e)),
+            },
+        }
+    } else {
+        data
+    };
+
+    match options.key_type {
+        KeyType::Text => Ok(data),
+        KeyType::Numeric => Ok(numeric_key(data)),
+        KeyType::Rounded { decimals } => Ok(rounded_key(data, decimals)),
+    }
+}
+
+/// The whitespace-separated column at 1-based `col_idx`, or the whole line
+/// if it has fewer columns
+fn column_key(line: &[u8], col_idx: usize) -> Vec<u8> {
+    // Whitespace splitting handles standard separation more robustly than
+    // manual byte checks
+    let text = String::from_utf8_lossy(line);
+    let cols: Vec<&str> = text.split_whitespace().collect();
+
+    if col_idx > 0 && col_idx <= cols.len() {
+        // We need to return an owned Vec<u8> because text is temporary
+        cols[col_idx - 1].as_bytes().to_vec()
+    } else {
+        line.to_vec()
+    }
+}
+
+/// Canonicalize a numeric key so `1`, `01`, `+1`, and `1.0` compare equal.
+///
+/// Plain decimals are canonicalized textually to avoid losing precision on
+/// long integers; other forms (exponents, `inf`) go through `f64`. Keys that
+/// don't parse as numbers are compared as text.
+fn numeric_key(data: Vec<u8>) -> Vec<u8> {
+    let text = data.trim_ascii();
+    if let Some(canonical) = canonical_decimal(text) {
+        return canonical.into_bytes();
+    }
+    match std::str::from_utf8(text)
+        .ok()
+        .and_then(|t| t.parse::<f64>().ok())
+    {
+        // Adding 0.0 turns -0.0 into 0.0
+        Some(value) => format!("{}", value + 0.0).into_bytes(),
+        None => data,
+    }
+}
+
+/// Round a numeric key to `decimals` places and canonicalize it, so readings
+/// that differ only past that precision compare equal.
+///
+/// As in `numeric_key`, plain decimals are rounded textually (half away from
+/// zero) so long numbers keep every digit; other forms go through `f64`.
+/// Keys that don't parse as numbers are compared as text.
+fn rounded_key(data: Vec<u8>, decimals: u32) -> Vec<u8> {
+    let text = data.trim_ascii();
+    if let Some(canonical) = canonical_decimal(text) {
+        return round_decimal(&canonical, decimals as usize).into_bytes();
+    }
+    match std::str::from_utf8(text)
+        .ok()
+        .and_then(|t| t.parse::<f64>().ok())
+    {
+        Some(value) => {
+            let rounded = format!("{:.*}", decimals as usize, value);
+            // Canonicalizing strips the trailing zeros and turns -0.000 into 0
+            canonical_decimal(rounded.as_bytes())
+                .unwrap_or(rounded)
+                .into_bytes()
+        }
+        None => data,
+    }
+}
+
+/// Round a `canonical_decimal` to `decimals` places, half away from zero
+fn round_decimal(canonical: &str, decimals: usize) -> String {
+    let (sign, unsigned) = match canonical.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", canonical),
+    };
+    let Some((int_part, frac_part)) = unsigned.split_once('.') else {
+        return canonical.to_string();
+    };
+    if frac_part.len() <= decimals {
+        return canonical.to_string();
+    }
+    let mut digits: Vec<u8> = int_part
+        .bytes()
+        .chain(frac_part.bytes().take(decimals))
+        .collect();
+    if frac_part.as_bytes()[decimals] >= b'5' {
+        // Carry through trailing nines, adding a digit if they all are
+        match digits.iter().rposition(|&digit| digit != b'9') {
+            Some(last) => {
+                digits[last] += 1;
+                digits[last + 1..].fill(b'0');
+            }
+            None => {
+                digits.fill(b'0');
+                digits.insert(0, b'1');
+            }
+        }
+    }
+    let point = digits.len() - decimals;
+    let mut rounded = String::from(sign);
+    for (index, &digit) in digits.iter().enumerate() {
+        if index == point {
+            rounded.push('.');
+        }
+        rounded.push(char::from(digit));
+    }
+    // Strips the trailing zeros and turns -0.000 into 0
+    canonical_decimal(rounded.as_bytes()).unwrap_or(rounded)
+}
+
+/// Canonicalize `[+-]digits[.digits]` by stripping redundant zeros and signs
+fn canonical_decimal(text: &[u8]) -> Option<String> {
+    let (negative, unsigned) = match text.split_first() {
+        Some((b'-', rest)) => (true, rest),
+        Some((b'+', rest)) => (false, rest),
+        _ => (false, text),
+    };
+    let (int_part, frac_part) = match unsigned.iter().position(|&b| b == b'.') {
+        Some(dot) => (&unsigned[..dot], &unsigned[dot + 1..]),
+        None => (unsigned, &[][..]),
+    };
+    if int_part.is_empty() && frac_part.is_empty()
+        || !int_part.iter().chain(frac_part).all(u8::is_ascii_digit)
+    {
+        return None;
+    }
+
+    let int_digits = match int_part.iter().position(|&b| b != b'0') {
+        Some(start) => &int_part[start..],
+        None => &[][..],
+    };
+    let frac_digits = match frac_part.iter().rposition(|&b| b != b'0') {
+        Some(end) => &frac_part[..=end],
+        None => &[][..],
+    };
+    if int_digits.is_empty() && frac_digits.is_empty() {
+        return Some("0".to_string());
+    }
+
+    let mut canonical = String::with_capacity(int_digits.len() + frac_digits.len() + 3);
+    if negative {
+        canonical.push('-');
+    }
+    if int_digits.is_empty() {
+        canonical.push('0');
+    } else {
+        canonical.push_str(std::str::from_utf8(int_digits).ok()?);
+    }
+    if !frac_digits.is_empty() {
+        canonical.push('.');
+        canonical.push_str(std::str::from_utf8(frac_digits).ok()?);
+    }
+    Some(canonical)
+}
+
+/// Extract the key field from a CSV record, respecting quoting
+#[cfg(feature = "csv")]
+fn csv_key(line: &[u8], csv: &CsvOptions) -> Result<Vec<u8>> {
+    let CsvColumn::Index(col_idx) = csv.key_column else {
+        return Err(Error::InvalidArgument(
+            "CSV column names must be resolved from the header".to_string(),
+        ));
+    };
+    let fields = csv_fields(line, csv.delimiter)?;
+    match fields.get(col_idx.wrapping_sub(1)) {
+        Some(field) => Ok(field.to_vec()),
+        None => Ok(line.to_vec()),
+    }
+}
+
+#[cfg(not(feature = "csv"))]
+fn csv_key(_line: &[u8], _csv: &CsvOptions) -> Result<Vec<u8>> {
+    Err(Error::InvalidArgument(
+        "CSV mode requires the 'csv' feature".to_string(),
+    ))
+}
+
+/// Split a single CSV record into its unquoted fields
+#[cfg(feature = "csv")]
+fn csv_fields(line: &[u8], delimiter: u8) -> Result<csv::ByteRecord> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(line);
+    let mut record = csv::ByteRecord::new();
+    reader
+        .read_byte_record(&mut record)
+        .map_err(|e| Error::InvalidArgument(format!("Invalid CSV record: {}", e)))?;
+    Ok(record)
+}
+
+/// Extract the key at a JSON pointer from a JSON record.
+///
+/// String values are keyed on their contents; other values on their compact
+/// JSON serialization (object keys sorted). Records that aren't valid JSON or
+/// lack the field are keyed on the whole record.
+#[cfg(feature = "json")]
+fn json_key(line: &[u8], pointer: &str) -> Result<Vec<u8>> {
+    let Ok(value) = serde_json::from_slice::<serde_json::Value>(line) else {
+        return Ok(line.to_vec());
+    };
+    match value.pointer(pointer) {
+        Some(serde_json::Value::String(s)) => Ok(s.as_bytes().to_vec()),
+        Some(other) => Ok(other.to_string().into_bytes()),
+        None => Ok(line.to_vec()),
+    }
+}
+
+#[cfg(not(feature = "json"))]
+fn json_key(_line: &[u8], _pointer: &str) -> Result<Vec<u8>> {
+    Err(Error::InvalidArgument(
+        "JSON mode requires the 'json' feature".to_string(),
+    ))
+}
+
+/// Apply Unicode normalization (keys that are not valid UTF-8 are left untouched)
+#[cfg(feature = "unicode")]
+fn normalize_key(data: Vec<u8>, form: NormalizationForm) -> Result<Vec<u8>> {
+    use unicode_normalization::UnicodeNormalization;
+
+    let Ok(text) = std::str::from_utf8(&data) else {
+        return Ok(data);
+    };
+    let normalized: String = match form {
+        NormalizationForm::Nfc => text.nfc().collect(),
+        NormalizationForm::Nfd => text.nfd().collect(),
+        NormalizationForm::Nfkc => text.nfkc().collect(),
+        NormalizationForm::Nfkd => text.nfkd().collect(),
+    };
+    Ok(normalized.into_bytes())
+}
+
+#[cfg(not(feature = "unicode"))]
+fn normalize_key(_data: Vec<u8>, _form: NormalizationForm) -> Result<Vec<u8>> {
+    Err(Error::InvalidArgument(
+        "Unicode normalization requires the 'unicode' feature".to_string(),
+    ))
+}
+
+/// Remove combining marks from the canonical decomposition of a key, then
+/// recompose the rest (keys that are not valid UTF-8 are left untouched)
+#[cfg(feature = "unicode")]
+fn strip_diacritics(data: Vec<u8>) -> Result<Vec<u8>> {
+    use unicode_normalization::UnicodeNormalization;
+    use unicode_normalization::char::is_combining_mark;
+
+    let Ok(text) = std::str::from_utf8(&data) else {
+        return Ok(data);
+    };
+    let stripped: String = text
+        .nfd()
+        .filter(|&c| !is_combining_mark(c))
+        .nfc()
+        .collect();
+    Ok(stripped.into_bytes())
+}
+
+#[cfg(not(feature = "unicode"))]
+fn strip_diacritics(_data: Vec<u8>) -> Result<Vec<u8>> {
+    Err(Error::InvalidArgument(
+        "Ignoring diacritics requires the 'unicode' feature".to_string(),
+    ))
+}
+
+/// Case-normalize a UTF-8 key according to the selected case mode
+fn fold_case(text: &str, mode: CaseMode) -> Result<String> {
+    match mode {
+        CaseMode::Lower => Ok(text.to_lowercase()),
+        #[cfg(feature = "unicode")]
+        CaseMode::Fold => Ok(caseless::default_case_fold_str(text)),
+        #[cfg(not(feature = "unicode"))]
+        CaseMode::Fold => Err(Error::InvalidArgument(
+            "Unicode case folding requires the 'unicode' feature".to_string(),
+        )),
+    }
+}
+
+/// Apply whitespace trimming (ASCII whitespace only, so non-UTF-8 keys are safe)
+fn trim_key(data: Vec<u8>, mode: TrimMode) -> Vec<u8> {
+    match mode {
+        TrimMode::None => data,
+        TrimMode::Leading => data.trim_ascii_start().to_vec(),
+        TrimMode::Trailing => data.trim_ascii_end().to_vec(),
+        TrimMode::TrailingBlanks => {
+            let end = data
+                .iter()
+                .rposition(|&b| b != b' ' && b != b'\t')
+                .map_or(0, |last| last + 1);
+            data[..end].to_vec()
+        }
+        TrimMode::Both => data.trim_ascii().to_vec(),
+        TrimMode::CollapseInner => {
+            let mut collapsed = Vec::with_capacity(data.len());
+            for word in data
+                .split(|b| b.is_ascii_whitespace())
+                .filter(|w| !w.is_empty())
+            {
+                if !collapsed.is_empty() {
+                    collapsed.push(b' ');
+                }
+                collapsed.extend_from_slice(word);
+            }
+            collapsed
+        }
+    }
+}
+
+/// Remove punctuation (and, for `AlphanumericOnly`, whitespace) from a key.
+/// Keys that aren't valid UTF-8 only lose ASCII bytes.
+fn strip_punctuation(data: Vec<u8>, mode: PunctuationMode) -> Vec<u8> {
+    let keep_char: fn(char) -> bool = match mode {
+        PunctuationMode::None => return data,
+        PunctuationMode::Strip => |c| c.is_alphanumeric() || c.is_whitespace(),
+        PunctuationMode::AlphanumericOnly => char::is_alphanumeric,
+    };
+    match String::from_utf8(data) {
+        Ok(s) => s
+            .chars()
+            .filter(|&c| keep_char(c))
+            .collect::<String>()
+            .into_bytes(),
+        Err(e) => {
+            let mut bytes = e.into_bytes();
+            bytes.retain(|&b| !b.is_ascii() || keep_char(char::from(b)));
+            bytes
+        }
+    }
+}
+
+/// Truncate a key to its first `width` characters (bytes if not valid UTF-8)
+fn truncate_key(mut data: Vec<u8>, width: usize) -> Vec<u8> {
+    let cut = match std::str::from_utf8(&data) {
+        Ok(s) => s.char_indices().nth(width).map(|(idx, _)| idx),
+        Err(_) => Some(width).filter(|&w| w < data.len()),
+    };
+    if let Some(cut) = cut {
+        data.truncate(cut);
+    }
+    data
+}
+
+/// Drop the first `fields` blank-separated fields and then `chars`
+/// characters of a record, like `uniq -f` and `-s`
+fn skip_key_prefix(line: &[u8], fields: usize, chars: usize) -> &[u8] {
+    let is_blank = |b: &u8| *b == b' ' || *b == b'\t';
+    let mut rest = line;
+    for _ in 0..fields {
+        // A field is a run of blanks followed by a run of non-blanks
+        let start = rest.iter().position(|b| !is_blank(b)).unwrap_or(rest.len());
+        let end = rest[start..]
+            .iter()
+            .position(is_blank)
+            .map_or(rest.len(), |len| start + len);
+        rest = &rest[end..];
+    }
+    let cut = match std::str::from_utf8(rest) {
+        Ok(s) => s
+            .char_indices()
+            .nth(chars)
+            .map_or(rest.len(), |(idx, _)| idx),
+        Err(_) => chars.min(rest.len()),
+    };
+    &rest[cut..]
+}
+
+/// Disk-backed keep-first algorithm using sled
+#[cfg(feature = "disk-backed")]
+fn deduplicate_keep_first_disk<R: std::io::BufRead, W: Write>(
+    reader: RecordReader<'_, R>,
+    output: &mut RecordOutput<'_, W>,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats> {
+    // A persistent state database also holds the keys seen by earlier runs
+    let mut db = open_store(&options.disk, options.state_db.as_deref(), options.hasher)?;
+    let checkpointer = checkpoint::Checkpointer::start(db.as_mut(), options)?;
+    keep_first_disk_from(
+        reader,
+        output,
+        options,
+        db.as_mut(),
+        DeduplicationStats::default(),
+        Vec::new(),
+        checkpointer,
+    )
+}
+
+/// Approximate size of a key map entry apart from the key bytes themselves
+#[cfg(feature = "disk-backed")]
+const KEY_ENTRY_OVERHEAD: usize = std::mem::size_of::<(Vec<u8>, usize)>() + 8;
+
+/// Move the in-memory keep-first state to a temporary database once it has
+/// outgrown `memory_limit`, and deduplicate the rest of the input there
+#[cfg(feature = "disk-backed")]
+fn spill_keep_first<R: std::io::BufRead, W: Write>(
+    reader: RecordReader<'_, R>,
+    output: &mut RecordOutput<'_, W>,
+    options: &DeduplicationOptions,
+    stats: DeduplicationStats,
+    seen: KeyMap<Vec<u8>, usize>,
+    lines_for_count: Vec<(Vec<u8>, Origin)>,
+) -> Result<DeduplicationStats> {
+    log::info!(
+        "Memory limit reached after {} records; moving {} keys to disk",
+        stats.lines_read,
+        seen.len()
+    );
+    let mut db = open_store(&options.disk, None, options.hasher)?;
+    for (key, count) in seen {
+        db.insert(&key, &(count as u64).to_le_bytes())?;
+    }
+    // The disk algorithm keeps each counted line's key alongside it
+    let lines_for_count = lines_for_count
+        .into_iter()
+        .map(|(line, origin)| {
+            let key = make_key(options.record_separator.strip(&line), options)?;
+            Ok((line, key, origin))
+        })
+        .collect::<Result<_>>()?;
+    keep_first_disk_from(
+        reader,
+        output,
+        options,
+        db.as_mut(),
+        stats,
+        lines_for_count,
+        None,
+    )
+}
+
+/// The disk-backed keep-first loop, starting from `stats` and the keys
+/// already in `db`, recording checkpoints with `checkpointer` if given
+#[cfg(feature = "disk-backed")]
+fn keep_first_disk_from<R: std::io::BufRead, W: Write>(
+    mut reader: RecordReader<'_, R>,
+    output: &mut RecordOutput<'_, W>,
+    options: &DeduplicationOptions,
+    db: &mut dyn DiskStore,
+    mut stats: DeduplicationStats,
+    mut lines_for_count: Vec<(Vec<u8>, Vec<u8>, Origin)>,
+    checkpointer: Option<checkpoint::Checkpointer>,
+) -> Result<DeduplicationStats> {
+    let mut line = Vec::new();
+    // The output of a resumed run continues after the checkpoint's
+    let mut output_base = 0;
+    if let Some(checkpoint) = checkpointer.as_ref().and_then(|c| c.resumed()) {
+        while (reader.records_read() as u64) < checkpoint.records_read {
+            if reader.read_record(&mut line)? == 0 {
+                break;
+            }
+            line.clear();
+        }
+        if reader.records_read() as u64 != checkpoint.records_read
+            || reader.bytes_read() != checkpoint.input_offset
+        {
+            return Err(Error::InvalidArgument(
+                "The input doesn't match the checkpoint being resumed from".to_string(),
+            ));
+        }
+        stats.lines_read = checkpoint.lines_read as usize;
+        stats.lines_written = checkpoint.lines_written as usize;
+        stats.lines_removed = checkpoint.lines_removed as usize;
+        output.kept.held = checkpoint.terminator_held;
+        output_base = checkpoint.output_offset;
+    }
+
+    while reader.read_record(&mut line)? > 0 {
+        stats.lines_read += 1;
+
+        // Strip newline for key generation but keep for output
+        let key_line = options.record_separator.strip(&line);
+
+        let key = make_key(key_line, options).map_err(|e| reader.at_line(e))?;
+
+        // Check if we've seen this key before. A resumed run reads records
+        // after the checkpoint again, whose keys may be stored already.
+        let existing = db.get(&key)?.filter(|existing| {
+            !checkpointer
+                .as_ref()
+                .is_some_and(|c| c.stored_ahead(existing, reader.records_read()))
+        });
+        let count = match &existing {
+            Some(existing) => {
+                let mut count_bytes = [0u8; 8];
+                count_bytes.copy_from_slice(&existing[..8]);
+                u64::from_le_bytes(count_bytes) + 1
+            }
+            None => 1,
+        };
+
+        // Store the count, and for checkpointed runs when the key was first
+        // seen (kept from its first occurrence)
+        match (&checkpointer, existing) {
+            (Some(checkpointer), None) => {
+                db.insert(&key, &checkpointer.first_seen(reader.records_read()))?
+            }
+            (_, Some(mut existing)) => {
+                existing[..8].copy_from_slice(&count.to_le_bytes());
+                db.insert(&key, &existing)?
+            }
+            (None, None) => db.insert(&key, &count.to_le_bytes())?,
+        }
+
+        if count == 1 {
+            if options.count {
+                lines_for_count.push((line.clone(), key.clone(), Origin::of(&reader)));
+            } else {
+                write_record(output, &line, Origin::of(&reader), options)?;
+            }
+            stats.lines_written += 1;
+        } else {
+            stats.lines_removed += 1;
+            output.write_removed(&line, Origin::of(&reader), options)?;
+        }
+        line.clear();
+
+        if let Some(checkpointer) = checkpointer
+            .as_ref()
+            .filter(|c| c.due(reader.records_read()))
+        {
+            output.flush()?;
+            checkpointer.save(
+                db,
+                reader.records_read(),
+                reader.bytes_read(),
+                output_base + output.bytes_written(),
+                output.kept.held,
+                &stats,
+            )?;
+        }
+    }
+
+    // Keys first seen in this run; a state database may hold older ones
+    stats.unique_lines = stats.lines_written;
+    disk_report_key_counts(&mut stats, options, db, 0)?;
+
+    // Write counts if requested
+    if options.count {
+        for (line, key, origin) in lines_for_count {
+            // Key is already computed and stored
+            if let Some(count_bytes) = db.get(&key)? {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(&count_bytes);
+                let cnt = u64::from_le_bytes(bytes);
+                write_counted_record(output, &line, cnt, origin, options)?;
+            }
+        }
+    }
+
+    match checkpointer {
+        Some(checkpointer) => checkpointer.finish(db)?,
+        None if options.state_db.is_some() => db.flush()?,
+        None => {}
+    }
+
+    Ok(stats)
+}
+
+/// `report_key_counts` for a disk count store, reading each count as a
+/// little-endian u64 at `count_offset` in the value
+#[cfg(feature = "disk-backed")]
+fn disk_report_key_counts(
+    stats: &mut DeduplicationStats,
+    options: &DeduplicationOptions,
+    db: &mut dyn DiskStore,
+    count_offset: usize,
+) -> Result<()> {
+    if !reports_key_counts(options) {
+        return Ok(());
+    }
+    // One scan of the store feeds both reports
+    let mut top = options.top_duplicates.map(TopKeys::new);
+    db.for_each(&mut |key, value| {
+        let mut count_bytes = [0u8; 8];
+        count_bytes.copy_from_slice(&value[count_offset..count_offset + 8]);
+        let count = u64::from_le_bytes(count_bytes) as usize;
+        if let Some(top) = &mut top {
+            top.offer(key, count);
+        }
+        if options.collect_key_counts {
+            stats.key_counts.push((key.to_vec(), count));
+        }
+        if options.collect_histogram {
+            *stats
+                .duplicate_histogram
+                .entry(histogram_bucket(count))
+                .or_insert(0) += 1;
+        }
+    })?;
+    if let Some(top) = top {
+        stats.top_duplicates = top.into_sorted();
+    }
+    sort_key_counts(&mut stats.key_counts);
+    Ok(())
+}
+
+/// Disk-backed keep-last algorithm using sled (two-pass)
+#[cfg(feature = "disk-backed")]
+fn deduplicate_keep_last_disk<R: std::io::BufRead + std::io::Seek, W: Write>(
+    inputs: &mut [R],
+    output: &mut RecordOutput<'_, W>,
+    options: &DeduplicationOptions,
+    excluded: &Cell<usize>,
+    tracker: &ProgressTracker,
+) -> Result<DeduplicationStats> {
+    let mut stats = DeduplicationStats::default();
+    let starts = inputs
+        .iter_mut()
+        .map(|input| input.stream_position())
+        .collect::<std::io::Result<Vec<_>>>()
+        .map_err(Error::Seek)?;
+
+    let mut db = open_store(&options.disk, None, options.hasher)?;
+
+    // Pass 1: Track last occurrence index for each key
+    let mut reader =
+        record_reader(inputs.iter_mut().collect(), options, excluded).with_progress(tracker);
+    let mut line = Vec::new();
+    for (line_index, _) in (0..).enumerate() {
+        if reader.read_record(&mut line)? == 0 {
+            break;
+        }
+        stats.lines_read += 1;
+
+        let key_line = options.record_separator.strip(&line);
+
+        let key = make_key(key_line, options).map_err(|e| reader.at_line(e))?;
+
+        // Retrieve existing data to update count
+        let count = if let Some(existing) = db.get(&key)? {
+            // Existing value is 16 bytes: [last_index (8) | count (8)]
+            // Or if we need to migrate/handle unexpected sizes, we can check len.
+            // Since we are creating a temp DB from scratch, we control the layout.
+            if existing.len() == 16 {
+                let mut count_bytes = [0u8; 8];
+                count_bytes.copy_from_slice(&existing[8..16]);
+                u64::from_le_bytes(count_bytes) + 1
+            } else {
+                1
+            }
+        } else {
+            1
+        };
+
+        // Store: line_index (8 bytes) + count (8 bytes)
+        let mut value = [0u8; 16];
+        value[0..8].copy_from_slice(&(line_index as u64).to_le_bytes());
+        value[8..16].copy_from_slice(&count.to_le_bytes());
+
+        db.insert(&key, &value)?;
+
+        line.clear();
+    }
+
+    stats.unique_lines = db.len();
+    // Values are [last_index (8) | count (8)]
+    disk_report_key_counts(&mut stats, options, db.as_mut(), 8)?;
+    log::debug!(
+        "First pass read {} records with {} distinct keys",
+        stats.lines_read,
+        stats.unique_lines
+    );
+
+    // Pass 2: Re-read file and output only last occurrences
+    for (input, &start) in inputs.iter_mut().zip(&starts) {
+        input
+            .seek(std::io::SeekFrom::Start(start))
+            .map_err(Error::Seek)?;
+    }
+    // Records excluded again are already counted
+    let rescanned = Cell::new(0);
+    let mut reader = record_reader(inputs.iter_mut().collect(), options, &rescanned);
+    let mut line = Vec::new();
+
+    for (current_index, _) in (0..).enumerate() {
+        if reader.read_record(&mut line)? == 0 {
+            break;
+        }
+
+        let key_line = options.record_separator.strip(&line);
+
+        let key = make_key(key_line, options)?;
+
+        if let Some(last_index_bytes) = db.get(&key)? {
+            // Value is 16 bytes: [last_index (8) | count (8)]
+            if last_index_bytes.len() == 16 {
+                let mut index_bytes = [0u8; 8];
+                index_bytes.copy_from_slice(&last_index_bytes[0..8]);
+                let last_index = u64::from_le_bytes(index_bytes);
+
+                if (current_index as u64) == last_index {
+                    if options.count {
+                        let mut count_bytes = [0u8; 8];
+                        count_bytes.copy_from_slice(&last_index_bytes[8..16]);
+                        let count = u64::from_le_bytes(count_bytes);
+                        write_counted_record(output, &line, count, Origin::of(&reader), options)?;
+                    } else {
+                        write_record(output, &line, Origin::of(&reader), options)?;
+                    }
+                    stats.lines_written += 1;
+                } else {
+                    stats.lines_removed += 1;
+                    output.write_removed(&line, Origin::of(&reader), options)?;
+                }
+            } else {
+                // Fallback for unexpected data format (should not happen with new logic)
+                // Just assume it's index only logic from before? No, let's treat as error or safe fallback using old logic if length is 8.
+                // For now, ignoring to keep simple.
+            }
+        }
+        line.clear();
+    }
+
+    Ok(stats)
+}
+
+/// Disk-backed remove-all algorithm using sled (two-pass)
+#[cfg(feature = "disk-backed")]
+fn deduplicate_remove_all_disk<R: std::io::BufRead + std::io::Seek, W: Write>(
+    inputs: &mut [R],
+    output: &mut RecordOutput<'_, W>,
+    options: &DeduplicationOptions,
+    excluded: &Cell<usize>,
+    tracker: &ProgressTracker,
+) -> Result<DeduplicationStats> {
+    let mut stats = DeduplicationStats::default();
+    let starts = inputs
+        .iter_mut()
+        .map(|input| input.stream_position())
+        .collect::<std::io::Result<Vec<_>>>()
+        .map_err(Error::Seek)?;
+
+    let mut db = open_store(&options.disk, None, options.hasher)?;
+
+    // Pass 1: Count occurrences of each key
+    let mut reader =
+        record_reader(inputs.iter_mut().collect(), options, excluded).with_progress(tracker);
+    let mut line = Vec::new();
+
+    while reader.read_record(&mut line)? > 0 {
+        stats.lines_read += 1;
+
+        let key_line = options.record_separator.strip(&line);
+
+        let key = make_key(key_line, options).map_err(|e| reader.at_line(e))?;
+
+        // Get current count and increment
+        let count = if let Some(existing) = db.get(&key)? {
+            let mut count_bytes = [0u8; 8];
+            count_bytes.copy_from_slice(&existing);
+            u64::from_le_bytes(count_bytes) + 1
+        } else {
+            1
+        };
+
+        db.insert(&key, &count.to_le_bytes())?;
+        line.clear();
+    }
+
+    // Count unique lines (those appearing exactly once)
+    db.for_each(&mut |_, count_bytes| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(count_bytes);
+        if u64::from_le_bytes(bytes) == 1 {
+            stats.unique_lines += 1;
+        }
+    })?;
+    disk_report_key_counts(&mut stats, options, db.as_mut(), 0)?;
+    log::debug!(
+        "First pass read {} records with {} distinct keys",
+        stats.lines_read,
+        db.len()
+    );
+
+    // Pass 2: Re-read file and output only lines that appear exactly once
+    for (input, &start) in inputs.iter_mut().zip(&starts) {
+        input
+            .seek(std::io::SeekFrom::Start(start))
+            .map_err(Error::Seek)?;
+    }
+    // Records excluded again are already counted
+    let rescanned = Cell::new(0);
+    let mut reader = record_reader(inputs.iter_mut().collect(), options, &rescanned);
+    let mut line = Vec::new();
+
+    while reader.read_record(&mut line)? > 0 {
+        let key_line = options.record_separator.strip(&line);
+
+        let key = make_key(key_line, options)?;
+
+        if let Some(count_bytes) = db.get(&key)? {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&count_bytes);
+            let count = u64::from_le_bytes(bytes);
+
+            if count == 1 {
+                if options.count {
+                    write_counted_record(output, &line, count, Origin::of(&reader), options)?;
+                } else {
+                    write_record(output, &line, Origin::of(&reader), options)?;
+                }
+                stats.lines_written += 1;
+            } else {
+                stats.lines_removed += 1;
+                output.write_removed(&line, Origin::of(&reader), options)?;
+            }
+        }
+        line.clear();
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_keep_first_basic() {
+        let input = b"a\nb\na\nc\n";
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions::default();
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+
+        assert_eq!(output, b"a\nb\nc\n");
+        assert_eq!(stats.lines_read, 4);
+        assert_eq!(stats.lines_written, 3);
+        assert_eq!(stats.lines_removed, 1);
+    }
+
+    #[test]
+    fn test_ignore_case() {
+        let input = b"Apple\napple\nBanana\n";
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+
+        assert_eq!(output, b"Apple\nBanana\n");
+        assert_eq!(stats.unique_lines, 2);
+    }
+
+    #[test]
+    fn test_keep_last() {
+        let input = b"a\nb\na\nc\n";
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            ..Default::default()
+        };
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+
+        assert_eq!(output, b"b\na\nc\n");
+        assert_eq!(stats.lines_written, 3);
+    }
+
+    #[test]
+    fn test_keep_last_count() {
+        // Counts come from the first pass, so they follow the key options
+        let input: Vec<u8> = (0..3000)
+            .flat_map(|i| {
+                let line = if i / 1000 == 1 { "key" } else { "Key" };
+                format!("{}{}\n", line, i % 1000).into_bytes()
+            })
+            .collect();
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            ignore_case: true,
+            count: true,
+            ..Default::default()
+        };
+        let stats = deduplicate(Cursor::new(&input), &mut output, &opts).unwrap();
+
+        let expected: String = (2000..3000)
+            .map(|i| format!("{:>7} Key{}\n", 3, i % 1000))
+            .collect();
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+        assert_eq!(stats.lines_written, 1000);
+        assert_eq!(stats.lines_removed, 2000);
+    }
+
+    #[test]
+    fn test_keep_last_across_inputs() {
+        // Records are kept once in an arena; origins and transformed keys
+        // must still come out right across inputs
+        let inputs = [&b"A\nb\nd\n"[..], &b"a\nc\nB\n"[..]];
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            ignore_case: true,
+            annotate_source: Some(SourceAnnotation {
+                names: vec!["one".to_string(), "two".to_string()],
+                line_numbers: true,
+            }),
+            line_numbers: true,
+            collect_key_counts: true,
+            ..Default::default()
+        };
+        let stats = deduplicate_many(inputs, &mut output, &opts).unwrap();
+
+        assert_eq!(output, b"3:one:3:d\n4:two:1:a\n5:two:2:c\n6:two:3:B\n");
+        assert_eq!(stats.unique_lines, 4);
+        assert_eq!(
+            stats.key_counts,
+            [
+                (b"a".to_vec(), 2),
+                (b"b".to_vec(), 2),
+                (b"c".to_vec(), 1),
+                (b"d".to_vec(), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_remove_all() {
+        let input = b"a\nb\na\nc\n";
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::RemoveAll,
+            ..Default::default()
+        };
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+
+        assert_eq!(output, b"b\nc\n");
+        assert_eq!(stats.unique_lines, 2);
+    }
+
+    #[test]
+    fn test_remove_all_transformed_keys() {
+        // Keys are recomputed from the stored records, so they must match
+        // the ones computed while reading
+        let input = b" a\nB\nA \nc\n b\n";
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::RemoveAll,
+            ignore_case: true,
+            trim: TrimMode::Both,
+            top_duplicates: Some(1),
+            ..Default::default()
+        };
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+
+        assert_eq!(output, b"c\n");
+        assert_eq!(stats.unique_lines, 1);
+        assert_eq!(stats.top_duplicates, [(b"a".to_vec(), 2)]);
+    }
+
+    #[test]
+    fn test_repeated_modes() {
+        let input = b"a\nb\na\nc\na\n";
+        let cases: [(DeduplicationMode, &[u8]); 2] = [
+            (DeduplicationMode::Repeated, b"a\n"),
+            (DeduplicationMode::AllRepeated, b"a\na\na\n"),
+        ];
+        for (mode, expected) in cases {
+            let opts = DeduplicationOptions {
+                mode,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+            assert_eq!(output, expected, "{:?}", mode);
+            assert_eq!(stats.unique_lines, 1);
+        }
+    }
+
+    #[test]
+    fn test_adjacent() {
+        // Runs behave like uniq: only neighbouring records are compared
+        let input = b"a\na\nb\na\nc\nc\nc";
+        let cases: [(DeduplicationMode, &[u8]); 5] = [
+            (DeduplicationMode::KeepFirst, b"a\nb\na\nc"),
+            (DeduplicationMode::KeepLast, b"a\nb\na\nc"),
+            (DeduplicationMode::RemoveAll, b"b\na"),
+            (DeduplicationMode::Repeated, b"a\nc"),
+            (DeduplicationMode::AllRepeated, b"a\na\nc\nc\nc"),
+        ];
+        for (mode, expected) in cases {
+            let opts = DeduplicationOptions {
+                mode,
+                adjacent: true,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+            assert_eq!(output, expected, "{:?}", mode);
+        }
+
+        let opts = DeduplicationOptions {
+            adjacent: true,
+            count: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, b"      2 a\n      1 b\n      1 a\n      3 c");
+        assert_eq!(stats.unique_lines, 4);
+        assert_eq!(stats.lines_removed, 3);
+    }
+
+    #[test]
+    fn test_skip_fields_and_chars() {
+        // Leading blanks belong to the next field; characters aren't bytes
+        let input = "1 ab\n22 ab\n3 \tab\n4 éb\n5 xb\n";
+        let opts = DeduplicationOptions {
+            skip_fields: 1,
+            skip_chars: 2,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, "1 ab\n3 \tab\n".as_bytes());
+    }
+
+    #[test]
+    fn test_empty_input() {
+        let input = b"";
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions::default();
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+
+        assert_eq!(stats.lines_read, 0);
+        assert_eq!(stats.lines_written, 0);
+    }
+
+    #[test]
+    fn test_non_utf8() {
+        let input = vec![0xFF, 0xFE, b'\n', 0xFF, 0xFE, b'\n', b'a', b'\n'];
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions::default();
+        let stats = deduplicate(Cursor::new(&input), &mut output, &opts).unwrap();
+
+        assert_eq!(stats.lines_written, 2);
+    }
+
+    #[test]
+    fn test_compare_width() {
+        // Multi-byte characters count as one character each
+        let input = "abc1\nabc2\nabd3\nçé1\nçè1\n";
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions {
+            compare_width: Some(3),
+            ..Default::default()
+        };
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+
+        assert_eq!(output, "abc1\nabd3\nçé1\nçè1\n".as_bytes());
+        assert_eq!(stats.unique_lines, 4);
+    }
+
+    #[test]
+    fn test_sort() {
+        let input = b"b 10\na 9\nc 2\na 1\nb 10\nx y\n-3 z\n";
+        let opts = DeduplicationOptions {
+            sort: Some(SortOrder::Text),
+            count: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(
+            output,
+            b"      1 -3 z\n      1 a 1\n      1 a 9\n      2 b 10\n      1 c 2\n      1 x y\n"
+        );
+        assert_eq!(stats.bytes_written, output.len() as u64);
+
+        // Numeric order by the key column; keys that aren't numbers go last
+        // and equal keys stay in input order
+        let opts = DeduplicationOptions {
+            sort: Some(SortOrder::Numeric),
+            column: Some(2),
+            mode: DeduplicationMode::AllRepeated,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        deduplicate(
+            Cursor::new(b"a 10\nb 9\nc x\nd 10\ne -1.5\ng x\nh -1.5\n"),
+            &mut output,
+            &opts,
+        )
+        .unwrap();
+        assert_eq!(output, b"e -1.5\nh -1.5\na 10\nd 10\nc x\ng x\n");
+
+        // Sorted runs spilled to disk merge back in order
+        #[cfg(feature = "disk-backed")]
+        {
+            let opts = DeduplicationOptions::default();
+            let mut sorter = sort::Sorter::with_buffer_limit(SortOrder::Numeric, &opts, 16);
+            for (idx, key) in ["5", "3", "x", "1", "4", "3", "2"].into_iter().enumerate() {
+                sorter
+                    .push(key.as_bytes(), 0, format!("{key}.{idx} ").into_bytes())
+                    .unwrap();
+            }
+            let mut output = Vec::new();
+            sorter.finish(&mut output).unwrap();
+            assert_eq!(output, b"1.3 2.6 3.1 3.5 4.4 5.0 x.2 ");
+        }
+
+        let opts = DeduplicationOptions {
+            sort: Some(SortOrder::Text),
+            group: Some(GroupSeparator::Separate),
+            ..Default::default()
+        };
+        assert!(deduplicate(Cursor::new(input), &mut Vec::new(), &opts).is_err());
+    }
+
+    #[test]
+    fn test_sort_by_count() {
+        let input = b"a\nb\nc\nb\nc\nd\nc\n";
+        let opts = DeduplicationOptions {
+            sort: Some(SortOrder::CountDescending),
+            count: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, b"      3 c\n      2 b\n      1 a\n      1 d\n");
+
+        // Ties stay in input order in both directions
+        let opts = DeduplicationOptions {
+            sort: Some(SortOrder::CountAscending),
+            count: true,
+            mode: DeduplicationMode::KeepLast,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, b"      1 a\n      1 d\n      2 b\n      3 c\n");
+
+        let opts = DeduplicationOptions {
+            sort: Some(SortOrder::CountDescending),
+            ..Default::default()
+        };
+        assert!(deduplicate(Cursor::new(input), &mut Vec::new(), &opts).is_err());
+    }
+
+    #[test]
+    fn test_group() {
+        let input = b"a\nb\nA\nc\nb\n";
+        let cases: [(GroupSeparator, &[u8]); 4] = [
+            (GroupSeparator::Separate, b"a\nA\n\nb\nb\n\nc\n"),
+            (GroupSeparator::Prepend, b"\na\nA\n\nb\nb\n\nc\n"),
+            (GroupSeparator::Append, b"a\nA\n\nb\nb\n\nc\n\n"),
+            (GroupSeparator::Both, b"\na\nA\n\nb\nb\n\nc\n\n"),
+        ];
+        for (separator, expected) in cases {
+            let opts = DeduplicationOptions {
+                group: Some(separator),
+                ignore_case: true,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+            assert_eq!(output, expected, "{:?}", separator);
+            assert_eq!(stats.lines_written, 5);
+            assert_eq!(stats.lines_removed, 0);
+            assert_eq!(stats.unique_lines, 3);
+        }
+
+        // Adjacent groups are runs, and -z groups are delimited by NULs
+        let opts = DeduplicationOptions {
+            group: Some(GroupSeparator::Separate),
+            adjacent: true,
+            record_separator: RecordSeparator::Byte(0),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        deduplicate(Cursor::new(b"a\0a\0b\0a\0"), &mut output, &opts).unwrap();
+        assert_eq!(output, b"a\0a\0\0b\0\0a\0");
+
+        let mut output = Vec::new();
+        deduplicate(Cursor::new(b""), &mut output, &opts).unwrap();
+        assert!(output.is_empty());
+
+        let opts = DeduplicationOptions {
+            group: Some(GroupSeparator::Separate),
+            count: true,
+            ..Default::default()
+        };
+        assert!(deduplicate(Cursor::new(input), &mut Vec::new(), &opts).is_err());
+    }
+
+    #[test]
+    fn test_punctuation_modes() {
+        let input = "Hello, world!\n\"Hello world\"\nhello-world\nHelloworld\ncafé.\ncafé\n\u{201c}quoted\u{201d}\nquoted\n";
+        let cases = [
+            (
+                PunctuationMode::None,
+                "Hello, world!\n\"Hello world\"\nhello-world\nHelloworld\ncafé.\ncafé\n\u{201c}quoted\u{201d}\nquoted\n",
+            ),
+            (
+                PunctuationMode::Strip,
+                "Hello, world!\nhello-world\nHelloworld\ncafé.\n\u{201c}quoted\u{201d}\n",
+            ),
+            (
+                PunctuationMode::AlphanumericOnly,
+                "Hello, world!\nhello-world\ncafé.\n\u{201c}quoted\u{201d}\n",
+            ),
+        ];
+
+        for (punctuation, expected) in cases {
+            let mut output = Vec::new();
+            let opts = DeduplicationOptions {
+                punctuation,
+                ..Default::default()
+            };
+            deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                expected,
+                "{:?}",
+                punctuation
+            );
+        }
+
+        // Invalid UTF-8 keeps its non-ASCII bytes
+        assert_eq!(
+            strip_punctuation(b"a.\xff b".to_vec(), PunctuationMode::AlphanumericOnly),
+            b"a\xffb"
+        );
+    }
+
+    #[test]
+    fn test_trim_modes() {
+        let input = b"  a\na\na  \nx  y\nx y\n";
+        let cases: [(TrimMode, &[u8]); 5] = [
+            (TrimMode::None, b"  a\na\na  \nx  y\nx y\n"),
+            (TrimMode::Leading, b"  a\na  \nx  y\nx y\n"),
+            (TrimMode::Trailing, b"  a\na\nx  y\nx y\n"),
+            (TrimMode::Both, b"  a\nx  y\nx y\n"),
+            (TrimMode::CollapseInner, b"  a\nx  y\n"),
+        ];
+
+        for (trim, expected) in cases {
+            let mut output = Vec::new();
+            let opts = DeduplicationOptions {
+                trim,
+                ..Default::default()
+            };
+            deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+            assert_eq!(output, expected, "trim mode {:?}", trim);
+        }
+
+        // Only trailing spaces and tabs: indentation still counts
+        let opts = DeduplicationOptions {
+            trim: TrimMode::TrailingBlanks,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        deduplicate(
+            Cursor::new(b"key: 1\n  key: 1\nkey: 1 \t\n  key: 1 \n"),
+            &mut output,
+            &opts,
+        )
+        .unwrap();
+        assert_eq!(output, b"key: 1\n  key: 1\n");
+    }
+
+    #[cfg(feature = "unicode")]
+    #[test]
+    fn test_unicode_normalization() {
+        // Composed "café", decomposed "cafe\u{301}", and the "ﬁ" ligature
+        let input = "caf\u{e9}\ncafe\u{301}\n\u{fb01}le\nfile\n";
+
+        let mut output = Vec::new();
+        let opts = DeduplicationOptions {
+            normalization: Some(NormalizationForm::Nfc),
+            ..Default::default()
+        };
+        deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, "caf\u{e9}\n\u{fb01}le\nfile\n".as_bytes());
+
+        let mut output = Vec::new();
+        let opts = DeduplicationOptions {
+            normalization: Some(NormalizationForm::Nfkd),
+            ..Default::default()
+        };
+        deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, "caf\u{e9}\n\u{fb01}le\n".as_bytes());
+    }
+
+    #[cfg(feature = "unicode")]
+    #[test]
+    fn test_ignore_diacritics() {
+        // Composed and decomposed accents, and a non-Latin letter with one
+        let input =
+            "R\u{e9}sum\u{e9}\nResume\nresume\u{301}\nna\u{ef}ve\nnaive\n\u{3ac}\n\u{3b1}\n";
+        let mut output = Vec::new();
+        let opts = DeduplicationOptions::builder()
+            .ignore_diacritics(true)
+            .build()
+            .unwrap();
+        deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(
+            output,
+            "R\u{e9}sum\u{e9}\nresume\u{301}\nna\u{ef}ve\n\u{3ac}\n".as_bytes()
+        );
+
+        // With case folding, the key is the bare lowercase name
+        let opts = DeduplicationOptions {
+            ignore_diacritics: true,
+            ignore_case: true,
+            print_key: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        deduplicate(Cursor::new("Z\u{f6}e\nzoe\n"), &mut output, &opts).unwrap();
+        assert_eq!(output, b"zoe\n");
+    }
+
+    #[cfg(feature = "unicode")]
+    #[test]
+    fn test_case_fold() {
+        let input = "stra\u{df}e\nSTRASSE\nStrasse\n";
+
+        let mut output = Vec::new();
+        let opts = DeduplicationOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, "stra\u{df}e\nSTRASSE\n".as_bytes());
+
+        let mut output = Vec::new();
+        let opts = DeduplicationOptions {
+            ignore_case: true,
+            case_mode: CaseMode::Fold,
+            ..Default::default()
+        };
+        deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, "stra\u{df}e\n".as_bytes());
+    }
+
+    #[cfg(not(feature = "unicode"))]
+    #[test]
+    fn test_normalization_requires_feature() {
+        let mut output = Vec::new();
+        let opts = DeduplicationOptions {
+            normalization: Some(NormalizationForm::Nfc),
+            ..Default::default()
+        };
+        let result = deduplicate(Cursor::new(b"a\n"), &mut output, &opts);
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_ignore_case_non_utf8() {
+        let input = b"ABC\xff\nabc\xff\n";
+
+        let mut output = Vec::new();
+        let opts = DeduplicationOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, b"ABC\xff\n");
+
+        let mut output = Vec::new();
+        let opts = DeduplicationOptions {
+            ignore_case: true,
+            non_utf8_case: NonUtf8Case::Error,
+            ..Default::default()
+        };
+        let result = deduplicate(Cursor::new(input), &mut output, &opts);
+        assert!(matches!(
+            result,
+            Err(Error::AtLine { line: 1, offset: 0, ref source })
+                if matches!(**source, Error::Utf8Error(_))
+        ));
+    }
+
+    #[test]
+    fn test_utf8_policy() {
+        // Different invalid bytes in the first column, both decoded as U+FFFD
+        let input = b"x\xff 1\ny 2\nx\xfe 3\ny 4\n";
+        let opts = DeduplicationOptions {
+            column: Some(1),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, b"x\xff 1\ny 2\n");
+
+        let opts = DeduplicationOptions {
+            utf8_policy: Utf8Policy::Strict,
+            ..opts
+        };
+        let err = deduplicate(Cursor::new(input), &mut Vec::new(), &opts).unwrap_err();
+        assert!(matches!(err, Error::AtLine { line: 1, .. }), "{:?}", err);
+
+        let opts = DeduplicationOptions {
+            utf8_policy: Utf8Policy::Skip,
+            ..opts
+        };
+        let mut output = Vec::new();
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, b"x\xff 1\ny 2\nx\xfe 3\n");
+        assert_eq!((stats.lines_read, stats.lines_written), (4, 3));
+
+        let mut dedup = Deduplicator::new(opts.clone()).unwrap();
+        for line in [&b"a\xff"[..], b"a\xff", b"a", b"a"] {
+            dedup.push_line(line).unwrap();
+        }
+        assert_eq!(dedup.finish().lines_written, 3);
+
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            ..opts
+        };
+        let err = deduplicate(Cursor::new(input), &mut Vec::new(), &opts).unwrap_err();
+        assert!(err.to_string().contains("invalid UTF-8"));
+    }
+
+    #[test]
+    fn test_error_position() {
+        use std::io::Read;
+
+        /// An input that fails once its data has been read
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "bad sector",
+                ))
+            }
+        }
+
+        let input = Cursor::new(b"a\nbb\na\n").chain(Failing);
+        let err = deduplicate(input, &mut Vec::new(), &Default::default()).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::AtLine {
+                    line: 4,
+                    offset: 7,
+                    ..
+                }
+            ),
+            "{:?}",
+            err
+        );
+        assert_eq!(err.to_string(), "Line 4 (byte 7): I/O error: bad sector");
+        assert_eq!(
+            std::io::Error::from(err).kind(),
+            std::io::ErrorKind::InvalidData
+        );
+
+        // Keys that can't be built point at their record, in every mode
+        let opts = DeduplicationOptions {
+            ignore_case: true,
+            non_utf8_case: NonUtf8Case::Error,
+            ..Default::default()
+        };
+        for mode in [
+            DeduplicationMode::KeepFirst,
+            DeduplicationMode::KeepLast,
+            DeduplicationMode::RemoveAll,
+        ] {
+            let opts = DeduplicationOptions {
+                mode,
+                ..opts.clone()
+            };
+            let input = Cursor::new(b"a\nbb\nc\xff\n");
+            let err = deduplicate(input, &mut Vec::new(), &opts).unwrap_err();
+            assert!(
+                matches!(
+                    err,
+                    Error::AtLine {
+                        line: 3,
+                        offset: 5,
+                        ..
+                    }
+                ),
+                "{:?}: {:?}",
+                mode,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_numeric_keys() {
+        let input = b"1\n01\n1.0\n+1.000\n-0\n0.0\n1e3\n1000\n12345678901234567891\n12345678901234567892\nabc\n";
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions {
+            key_type: KeyType::Numeric,
+            ..Default::default()
+        };
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+
+        assert_eq!(
+            output,
+            b"1\n-0\n1e3\n12345678901234567891\n12345678901234567892\nabc\n"
+        );
+        assert_eq!(stats.unique_lines, 6);
+    }
+
+    #[test]
+    fn test_rounded_keys() {
+        let input = b"3.14159\n3.1416\n3.142\n3.2\n3.20\n-0.0001\n0\n1e0\nn/a\n";
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions {
+            key_type: KeyType::Rounded { decimals: 3 },
+            ..Default::default()
+        };
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+
+        assert_eq!(output, b"3.14159\n3.2\n-0.0001\n1e0\nn/a\n");
+        assert_eq!(stats.unique_lines, 5);
+
+        // Decimals are rounded textually, half away from zero, so long
+        // numbers keep their digits
+        let input =
+            b"12345678901234567891\n12345678901234567892\n0.0005\n0.001\n9.9996\n10\n-2.5\n-3\n";
+        let mut output = Vec::new();
+        let opts = DeduplicationOptions {
+            key_type: KeyType::Rounded { decimals: 3 },
+            ..Default::default()
+        };
+        deduplicate(Cursor::new(&input[..]), &mut output, &opts).unwrap();
+        assert_eq!(
+            output,
+            b"12345678901234567891\n12345678901234567892\n0.0005\n9.9996\n-2.5\n-3\n"
+        );
+        let mut output = Vec::new();
+        let opts = DeduplicationOptions {
+            key_type: KeyType::Rounded { decimals: 0 },
+            ..Default::default()
+        };
+        deduplicate(Cursor::new(&b"-2.5\n-3\n2.4\n2\n"[..]), &mut output, &opts).unwrap();
+        assert_eq!(output, b"-2.5\n2.4\n");
+    }
+
+    #[test]
+    fn test_zero_terminated() {
+        let input = b"a\nb\0c\0a\nb\0c\r\0";
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions {
+            record_separator: RecordSeparator::Byte(b'\0'),
+            ..Default::default()
+        };
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+
+        assert_eq!(output, b"a\nb\0c\0c\r\0");
+        assert_eq!(stats.lines_read, 4);
+    }
+
+    #[test]
+    fn test_multi_byte_separator() {
+        let input = b"a\n---\nb\n---\na\n---\nc";
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions {
+            record_separator: RecordSeparator::Bytes(b"---\n".to_vec()),
+            ..Default::default()
+        };
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+
+        assert_eq!(output, b"a\n---\nb\n---\nc");
+        assert_eq!(stats.lines_read, 4);
+    }
+
+    #[test]
+    fn test_paragraph_mode() {
+        let input = b"[a]\nx=1\n\n[b]\ny=2\n\n\n[a]\nx=1\n\n[c]\n";
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions {
+            record_separator: RecordSeparator::Paragraph,
+            ..Default::default()
+        };
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+
+        assert_eq!(output, b"[a]\nx=1\n\n[b]\ny=2\n\n\n[c]\n");
+        assert_eq!(stats.lines_read, 4);
+        assert_eq!(stats.unique_lines, 3);
+    }
+
+    #[test]
+    fn test_bom() {
+        // A UTF-8 BOM doesn't stop the first line matching its duplicates
+        let input = b"\xEF\xBB\xBFa\nb\na\n";
+        let mut output = Vec::new();
+        deduplicate(
+            Cursor::new(input),
+            &mut output,
+            &DeduplicationOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(output, b"a\nb\n");
+
+        let opts = DeduplicationOptions {
+            emit_bom: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let stats = deduplicate_many(vec![&b"x\n"[..], input], &mut output, &opts).unwrap();
+        assert_eq!(output, b"\xEF\xBB\xBFx\na\nb\n");
+        assert_eq!(stats.bytes_written, output.len() as u64);
+
+        let mut writer = DeduplicatingWriter::new(Vec::new(), opts).unwrap();
+        writer.write_all(b"\xEF\xBB\xBFa\nb\na\n").unwrap();
+        assert_eq!(writer.finish().unwrap(), b"\xEF\xBB\xBFa\nb\n");
+
+        // UTF-16 byte order marks are left to decoding
+        let mut writer = DeduplicatingWriter::new(Vec::new(), Default::default()).unwrap();
+        writer.write_all(b"\xFF\xFEa\nb\na\n").unwrap();
+        assert_eq!(writer.finish().unwrap(), b"\xFF\xFEa\nb\na\n");
+
+        assert_eq!(bom::detect(b"\xFE\xFFa"), None);
+        assert_eq!(bom::detect(b"\xEF\xBBa"), None);
+    }
+
+    #[test]
+    fn test_exclude_keys() {
+        let reference = b"B\nmissing\n";
+        let input = b"a\nb\nc\na\nB\n";
+        let opts = DeduplicationOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let stats =
+            deduplicate_with_reference(&reference[..], &input[..], &mut output, &opts).unwrap();
+        assert_eq!(output, b"a\nc\n");
+        assert_eq!((stats.lines_read, stats.lines_removed), (5, 3));
+
+        let keys = load_reference_keys(&reference[..], &opts).unwrap();
+        assert_eq!(keys, HashSet::from([b"b".to_vec(), b"missing".to_vec()]));
+        let opts = DeduplicationOptions {
+            exclude_keys: Some(std::sync::Arc::new(keys)),
+            mode: DeduplicationMode::KeepLast,
+            ..opts
+        };
+        let mut output = Vec::new();
+        deduplicate_seekable(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, b"c\na\n");
+
+        #[cfg(feature = "disk-backed")]
+        {
+            let opts = DeduplicationOptions {
+                use_disk: true,
+                ..opts.clone()
+            };
+            let mut output = Vec::new();
+            let stats = deduplicate_seekable(Cursor::new(input), &mut output, &opts).unwrap();
+            assert_eq!(output, b"c\na\n");
+            assert_eq!((stats.lines_read, stats.lines_removed), (5, 3));
+        }
+
+        // The in-memory and incremental APIs exclude the same keys
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepFirst,
+            ..opts
+        };
+        let kept = crate::iter::deduplicate_lines(&["a", "b", "c", "a"], &opts).unwrap();
+        assert_eq!(kept, ["a", "c"]);
+        let mut dedup = Deduplicator::new(opts).unwrap();
+        assert_eq!(dedup.push_line(b"b\n").unwrap(), Decision::Remove);
+        assert_eq!(dedup.push_line(b"a\n").unwrap(), Decision::Keep);
+    }
+
+    #[test]
+    fn test_set_operations() {
+        use crate::setops::{SetOperation, set_operation};
+
+        let (a, b, c) = (&b"x\nY\nz\nx\n"[..], &b"y\nz\n"[..], &b"z\nw"[..]);
+        let opts = DeduplicationOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        let run = |operation, inputs: &[&[u8]]| {
+            let mut output = Vec::new();
+            let stats = set_operation(operation, inputs.iter().copied(), &mut output, &opts);
+            (String::from_utf8(output).unwrap(), stats.unwrap())
+        };
+
+        let (output, stats) = run(SetOperation::Union, &[a, b, c]);
+        assert_eq!(output, "x\nY\nz\nw");
+        assert_eq!((stats.lines_read, stats.lines_removed), (8, 4));
+        let (output, stats) = run(SetOperation::Intersection, &[a, b, c]);
+        assert_eq!(output, "z\n");
+        assert_eq!((stats.lines_read, stats.lines_written), (4, 1));
+        assert_eq!(run(SetOperation::Intersection, &[a, b]).0, "Y\nz\n");
+        assert_eq!(run(SetOperation::Difference, &[a, b]).0, "x\n");
+        assert_eq!(run(SetOperation::Difference, &[a]).0, "x\nY\nz\n");
+
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            ..Default::default()
+        };
+        let result = set_operation(SetOperation::Union, [a], &mut Vec::new(), &opts);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_merge_sorted() {
+        use crate::setops::merge_sorted;
+
+        let (a, b, c) = (&b"a\nc\nc\ne\n"[..], &b"B\nc\nd"[..], &b""[..]);
+        let opts = DeduplicationOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let stats = merge_sorted([a, b, c], &mut output, &opts).unwrap();
+        assert_eq!(output, b"a\nB\nc\nd\ne\n");
+        assert_eq!((stats.lines_read, stats.lines_removed), (7, 2));
+
+        // The output ends like the input its last record came from
+        let mut output = Vec::new();
+        merge_sorted([&b"a\n"[..], &b"b"[..]], &mut output, &opts).unwrap();
+        assert_eq!(output, b"a\nb");
+
+        // Numeric order, keeping numbers that are equal but spelled differently
+        let opts = DeduplicationOptions {
+            sort: Some(SortOrder::Numeric),
+            ..Default::default()
+        };
+        let (a, b) = (&b"1\n2.0\n10\n"[..], &b"2\n2.0\n9\nx\n"[..]);
+        let mut output = Vec::new();
+        merge_sorted([a, b], &mut output, &opts).unwrap();
+        assert_eq!(output, b"1\n2.0\n2\n9\n10\nx\n");
+
+        let result = merge_sorted([&b"b\na\n"[..]], &mut Vec::new(), &Default::default());
+        assert!(result.unwrap_err().to_string().contains("isn't sorted"));
+    }
+
+    #[cfg(feature = "encodings")]
+    #[test]
+    fn test_encodings() {
+        use crate::encoding::{DecodingReader, EncodingWriter, TextEncoding};
+
+        // A UTF-16 export with a byte order mark
+        let utf16 = TextEncoding::for_label("utf-16le").unwrap();
+        let input: Vec<u8> = "\u{feff}café\nthé\ncafé\n"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let mut output = Vec::new();
+        deduplicate(
+            DecodingReader::new(Cursor::new(input), utf16),
+            &mut output,
+            &DeduplicationOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(output, "café\nthé\n".as_bytes());
+
+        // Written back out in a legacy code page, split inside a character
+        let latin1 = TextEncoding::for_output_label("latin1").unwrap();
+        assert_eq!(latin1.name(), "windows-1252");
+        let mut writer = EncodingWriter::new(Vec::new(), latin1).unwrap();
+        writer.write_all(&output[..4]).unwrap();
+        writer.write_all(&output[4..]).unwrap();
+        assert_eq!(writer.finish().unwrap(), b"caf\xe9\nth\xe9\n");
+
+        // Characters the output encoding lacks are an error
+        let mut writer = EncodingWriter::new(Vec::new(), latin1).unwrap();
+        assert!(writer.write_all("日本\n".as_bytes()).is_err());
+
+        assert!(TextEncoding::for_label("no-such-encoding").is_err());
+        assert!(TextEncoding::for_output_label("replacement").is_err());
+    }
+
+    #[test]
+    fn test_deduplicate_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.log");
+        let output = dir.path().join("out.log");
+        std::fs::write(&input, "a\nb\na\n").unwrap();
+
+        let options = DeduplicationOptions::default();
+        let stats = deduplicate_file(&input, &output, &options).unwrap();
+        assert_eq!(stats.lines_removed, 1);
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "a\nb\n");
+        assert_eq!(std::fs::read_to_string(&input).unwrap(), "a\nb\na\n");
+
+        // Seekable, so two-pass modes work too
+        let options = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            ..Default::default()
+        };
+        deduplicate_file_in_place(&input, &options).unwrap();
+        assert_eq!(std::fs::read_to_string(&input).unwrap(), "b\na\n");
+
+        // A failed run leaves neither the output nor its temp file behind
+        let missing = dir.path().join("missing.log");
+        let options = DeduplicationOptions {
+            max_line_length: Some(0),
+            ..Default::default()
+        };
+        assert!(deduplicate_file(&input, &missing, &options).is_err());
+        let mut names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["in.log", "out.log"]);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_deduplicate_file_compressed() {
+        use std::io::Read;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log.gz");
+        let mut writer =
+            CompressedWriter::new(std::fs::File::create(&path).unwrap(), Compression::Gzip)
+                .unwrap();
+        writer.write_all(b"a\nb\na\n").unwrap();
+        writer.finish().unwrap();
+
+        deduplicate_file_in_place(&path, &DeduplicationOptions::default()).unwrap();
+        let mut output = String::new();
+        CompressedReader::new(std::fs::File::open(&path).unwrap(), Compression::Gzip)
+            .unwrap()
+            .read_to_string(&mut output)
+            .unwrap();
+        assert_eq!(output, "a\nb\n");
+    }
+
+    #[test]
+    fn test_trailing_newline() {
+        let cases: [(&[u8], DeduplicationMode, &[u8]); 4] = [
+            (b"a\nb\na", DeduplicationMode::KeepFirst, b"a\nb"),
+            (b"a\nb\na", DeduplicationMode::KeepLast, b"b\na"),
+            (b"b\na\nb\nc", DeduplicationMode::KeepLast, b"a\nb\nc"),
+            (b"a\nb\na\n", DeduplicationMode::RemoveAll, b"b\n"),
+        ];
+        for (input, mode, expected) in cases {
+            let opts = DeduplicationOptions {
+                mode,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+            assert_eq!(output, expected, "{:?}", mode);
+            assert_eq!(stats.bytes_written, expected.len() as u64);
+        }
+
+        // Sorting moves the unterminated record, but the output still ends
+        // without a terminator
+        let opts = DeduplicationOptions {
+            sort: Some(SortOrder::Text),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        deduplicate(Cursor::new(b"b\nc\na"), &mut output, &opts).unwrap();
+        assert_eq!(output, b"a\nb\nc");
+
+        let opts = DeduplicationOptions {
+            ensure_trailing_newline: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        deduplicate(Cursor::new(b"a\nb\na"), &mut output, &opts).unwrap();
+        assert_eq!(output, b"a\nb\n");
+
+        // Only the last input decides; an empty one doesn't count
+        let mut output = Vec::new();
+        deduplicate_many(
+            vec![&b"x\ny"[..], b"z", b""],
+            &mut output,
+            &DeduplicationOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(output, b"x\ny\nz");
+
+        #[cfg(feature = "disk-backed")]
+        {
+            let opts = DeduplicationOptions {
+                mode: DeduplicationMode::KeepLast,
+                use_disk: true,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            deduplicate_seekable(Cursor::new(b"b\na\nb\nc"), &mut output, &opts).unwrap();
+            assert_eq!(output, b"a\nb\nc");
+        }
+    }
+
+    #[test]
+    fn test_max_line_length() {
+        let input = b"abcdefXX\nab\nabcdefYY\nab\nabcdefghijklmnop";
+        let opts = DeduplicationOptions {
+            max_line_length: Some(6),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let err = deduplicate(Cursor::new(b"ab\nabcdefXX\n"), &mut output, &opts).unwrap_err();
+        assert!(
+            matches!(&err, Error::AtLine { line: 2, offset: 3, source }
+                if matches!(**source, Error::LineTooLong { max: 6 })),
+            "{:?}",
+            err
+        );
+        assert_eq!(err.to_string(), "Line 2 (byte 3): Line longer than 6 bytes");
+        // Records written before the long line keep their terminators
+        assert_eq!(output, b"ab\n");
+
+        // Truncated lines keep their terminator and are keyed on what's left
+        let opts = DeduplicationOptions {
+            max_line_length: Some(6),
+            line_length_policy: LineLengthPolicy::Truncate,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, b"abcdef\nab");
+
+        // Long lines are written whole, in pieces, and never deduplicated
+        let opts = DeduplicationOptions {
+            max_line_length: Some(6),
+            line_length_policy: LineLengthPolicy::PassThrough,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let stats = deduplicate_many(
+            vec![&b"abcdefXX\nab\nabcdefXX"[..], b"ab\n"],
+            &mut output,
+            &opts,
+        )
+        .unwrap();
+        assert_eq!(output, b"abcdefXX\nab\nabcdefXX\n");
+        assert_eq!(stats.lines_read, 4);
+        assert_eq!(stats.lines_written, 3);
+
+        let opts = DeduplicationOptions {
+            max_line_length: Some(6),
+            line_length_policy: LineLengthPolicy::PassThrough,
+            count: true,
+            ..Default::default()
+        };
+        assert!(deduplicate(Cursor::new(input), &mut Vec::new(), &opts).is_err());
+
+        // Paragraphs are limited line by line
+        let opts = DeduplicationOptions {
+            record_separator: RecordSeparator::Paragraph,
+            max_line_length: Some(3),
+            line_length_policy: LineLengthPolicy::Truncate,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        deduplicate(Cursor::new(b"[a]\nx=1\n\n[a]\nx=12\n"), &mut output, &opts).unwrap();
+        assert_eq!(output, b"[a]\nx=1\n\n");
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_csv_key_column() {
+        let input = b"id,name,email\n1,\"Smith, J\",a@x\n2,\"Smith, J\",b@x\n3,\"Multi\nline\",a@x\n4,Jones,c@x\n";
+
+        let mut output = Vec::new();
+        let opts = DeduplicationOptions {
+            csv: Some(CsvOptions {
+                key_column: CsvColumn::Name("name".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(
+            output,
+            b"id,name,email\n1,\"Smith, J\",a@x\n3,\"Multi\nline\",a@x\n4,Jones,c@x\n"
+        );
+        assert_eq!(stats.lines_read, 4);
+
+        let mut output = Vec::new();
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            csv: Some(CsvOptions {
+                key_column: CsvColumn::Index(3),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(
+            output,
+            b"id,name,email\n2,\"Smith, J\",b@x\n3,\"Multi\nline\",a@x\n4,Jones,c@x\n"
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json_pointer() {
+        let input = concat!(
+            "{\"user\":{\"id\":1},\"msg\":\"a\"}\n",
+            "{\"msg\":\"b\",\"user\":{\"id\":1}}\n",
+            "{\"user\":{\"id\":\"1\"}}\n",
+            "{\"user\":{\"id\":2}}\n",
+            "not json\n",
+        );
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions {
+            json_pointer: Some("/user/id".to_string()),
+            ..Default::default()
+        };
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                "{\"user\":{\"id\":1},\"msg\":\"a\"}\n",
+                "{\"user\":{\"id\":2}}\n",
+                "not json\n",
+            )
+        );
+        assert_eq!(stats.unique_lines, 3);
+    }
+
+    #[test]
+    fn test_column_name() {
+        let input = b"id email\n1 a@x\n2 b@x\n3 a@x\n";
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions {
+            column_name: Some("email".to_string()),
+            ..Default::default()
+        };
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+
+        assert_eq!(output, b"id email\n1 a@x\n2 b@x\n");
+        assert_eq!(stats.lines_read, 3);
+
+        let opts = DeduplicationOptions {
+            column_name: Some("missing".to_string()),
+            ..Default::default()
+        };
+        let result = deduplicate(Cursor::new(input), &mut Vec::new(), &opts);
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_deduplicate_many() {
+        // The first input lacks a trailing newline; it must not merge with the next
+        let inputs = [Cursor::new(&b"a\nb"[..]), Cursor::new(&b"c\na\n"[..])];
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions::default();
+        let stats = deduplicate_many(inputs, &mut output, &opts).unwrap();
+
+        assert_eq!(output, b"a\nb\nc\n");
+        assert_eq!(stats.lines_read, 4);
+        assert_eq!(stats.lines_written, 3);
+
+        // Headers of later inputs are skipped
+        let inputs = [
+            Cursor::new(&b"id name\n1 x\n"[..]),
+            Cursor::new(&b"id name\n2 x\n3 y\n"[..]),
+        ];
+        let mut output = Vec::new();
+        let opts = DeduplicationOptions {
+            column_name: Some("name".to_string()),
+            ..Default::default()
+        };
+        deduplicate_many(inputs, &mut output, &opts).unwrap();
+        assert_eq!(output, b"id name\n1 x\n3 y\n");
+    }
+
+    #[test]
+    fn test_annotate_source() {
+        let inputs = [Cursor::new(&b"a\nb\n"[..]), Cursor::new(&b"b\nc\n"[..])];
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            annotate_source: Some(SourceAnnotation {
+                names: vec!["one".to_string(), "two".to_string()],
+                line_numbers: true,
+            }),
+            ..Default::default()
+        };
+        deduplicate_many(inputs, &mut output, &opts).unwrap();
+
+        assert_eq!(output, b"one:1:a\ntwo:1:b\ntwo:2:c\n");
+    }
+
+    #[test]
+    fn test_compressed_round_trip() {
+        let formats = [
+            #[cfg(feature = "gzip")]
+            Compression::Gzip,
+            #[cfg(feature = "compress-zstd")]
+            Compression::Zstd,
+            #[cfg(feature = "compress-xz")]
+            Compression::Xz,
+            Compression::None,
+        ];
+        for compression in formats {
+            let mut writer = CompressedWriter::new(Vec::new(), compression).unwrap();
+            writer.write_all(b"a\nb\na\n").unwrap();
+            let compressed = writer.finish().unwrap();
+
+            let reader = CompressedReader::new(Cursor::new(compressed), compression).unwrap();
+            let mut output = Vec::new();
+            deduplicate(reader, &mut output, &DeduplicationOptions::default()).unwrap();
+            assert_eq!(output, b"a\nb\n", "{:?}", compression);
+        }
+    }
+
+    #[cfg(not(feature = "compress-xz"))]
+    #[test]
+    fn test_compression_requires_feature() {
+        let err = CompressedReader::new(Cursor::new(Vec::new()), Compression::Xz)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("compress-xz"));
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_mmap_matches_streaming() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("input.txt");
+        std::fs::write(&path, "a\nB\nb\r\na\nc\nb\n\nc").unwrap();
+
+        let modes = [
+            DeduplicationMode::KeepFirst,
+            DeduplicationMode::KeepLast,
+            DeduplicationMode::RemoveAll,
+            DeduplicationMode::Repeated,
+            DeduplicationMode::AllRepeated,
+        ];
+        for mode in modes {
+            for (count, show_removed, ignore_case) in [
+                (false, false, false),
+                (true, false, false),
+                (false, true, true),
+                (true, true, true),
+            ] {
+                let opts = DeduplicationOptions {
+                    mode,
+                    count,
+                    show_removed,
+                    ignore_case,
+                    ..Default::default()
+                };
+                let mut expected = Vec::new();
+                let expected_stats =
+                    deduplicate(std::fs::File::open(&path).unwrap(), &mut expected, &opts).unwrap();
+                let mut output = Vec::new();
+                let stats = deduplicate_mmap(&path, &mut output, &opts).unwrap();
+
+                assert_eq!(
+                    output,
+                    expected,
+                    "{:?} {:?}",
+                    mode,
+                    (count, show_removed, ignore_case)
+                );
+                assert_eq!(stats.lines_read, expected_stats.lines_read);
+                assert_eq!(stats.lines_written, expected_stats.lines_written);
+                assert_eq!(stats.lines_removed, expected_stats.lines_removed);
+                assert_eq!(stats.unique_lines, expected_stats.unique_lines);
+            }
+        }
+
+        // Paragraph mode falls back to the streaming path
+        std::fs::write(&path, "a\nb\n\nc\n\na\nb\n").unwrap();
+        let opts = DeduplicationOptions {
+            record_separator: RecordSeparator::Paragraph,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        deduplicate_mmap(&path, &mut output, &opts).unwrap();
+        assert_eq!(output, b"a\nb\n\nc\n\n");
+
+        std::fs::write(&path, "").unwrap();
+        let mut output = Vec::new();
+        let stats = deduplicate_mmap(&path, &mut output, &DeduplicationOptions::default()).unwrap();
+        assert!(output.is_empty());
+        assert_eq!(stats.lines_read, 0);
+    }
+
+    #[test]
+    fn test_deduplicator() {
+        let opts = DeduplicationOptions {
+            ignore_case: true,
+            top_duplicates: Some(1),
+            ..Default::default()
+        };
+        let mut dedup = Deduplicator::new(opts).unwrap();
+        // Two sources share one seen-set, with or without terminators
+        let decisions: Vec<Decision> = [&b"a\n"[..], b"B\n", b"A"]
+            .into_iter()
+            .chain([&b"b"[..], b"c\n"])
+            .map(|line| dedup.push_line(line).unwrap())
+            .collect();
+        assert_eq!(
+            decisions,
+            [
+                Decision::Keep,
+                Decision::Keep,
+                Decision::Remove,
+                Decision::Remove,
+                Decision::Keep
+            ]
+        );
+        assert_eq!(dedup.stats().unique_lines, 3);
+        let stats = dedup.finish();
+        assert_eq!((stats.lines_read, stats.lines_removed), (5, 2));
+        assert_eq!(stats.top_duplicates, [(b"a".to_vec(), 2)]);
+
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            ..Default::default()
+        };
+        assert!(Deduplicator::new(opts).is_err());
+
+        // Options for other key stores are rejected rather than ignored
+        for opts in [
+            DeduplicationOptions {
+                use_disk: true,
+                ..Default::default()
+            },
+            DeduplicationOptions {
+                use_disk: true,
+                state_db: Some("state.db".into()),
+                ..Default::default()
+            },
+            DeduplicationOptions {
+                memory_limit: Some(1 << 20),
+                ..Default::default()
+            },
+        ] {
+            assert!(Deduplicator::new(opts.clone()).is_err());
+            assert!(DeduplicatingWriter::new(Vec::new(), opts).is_err());
+        }
+    }
+
+    #[test]
+    fn test_deduplicate_with_observer() {
+        let opts = DeduplicationOptions {
+            ignore_case: true,
+            empty_lines: EmptyLinePolicy::Preserve,
+            show_removed: true,
+            ..Default::default()
+        };
+        let mut events = Vec::new();
+        let mut output = Vec::new();
+        let stats = deduplicate_with_observer(
+            &b"a\n\nA\nb\na"[..],
+            &mut output,
+            &opts,
+            |event: LineEvent| {
+                events.push((
+                    event.line,
+                    event.key.map(<[u8]>::to_vec),
+                    event.action,
+                    event.count,
+                ))
+            },
+        )
+        .unwrap();
+        assert_eq!(output, b"a\n\n[REMOVED] A\nb\n[REMOVED] a");
+        assert_eq!(
+            events,
+            [
+                (1, Some(b"a".to_vec()), Decision::Keep, Some(1)),
+                (2, None, Decision::Keep, None),
+                (3, Some(b"a".to_vec()), Decision::Remove, Some(2)),
+                (4, Some(b"b".to_vec()), Decision::Keep, Some(1)),
+                (5, Some(b"a".to_vec()), Decision::Remove, Some(3)),
+            ]
+        );
+        assert_eq!((stats.lines_read, stats.lines_removed), (5, 2));
+        assert_eq!(stats.bytes_read, 8);
+
+        let opts = DeduplicationOptions {
+            count: true,
+            ..Default::default()
+        };
+        assert!(deduplicate_with_observer(&b"a\n"[..], &mut Vec::new(), &opts, |_| {}).is_err());
+    }
+
+    #[test]
+    fn test_deduplicating_writer() {
+        let opts = DeduplicationOptions {
+            ignore_case: true,
+            show_removed: true,
+            ..Default::default()
+        };
+        let mut writer = DeduplicatingWriter::new(Vec::new(), opts).unwrap();
+        // Records split across writes are reassembled before comparison
+        writer.write_all(b"a\nb").unwrap();
+        writer.write_all(b"\nA\n").unwrap();
+        writer.write_all(b"c").unwrap();
+        assert_eq!(writer.get_ref(), b"a\nb\n[REMOVED] A\n");
+        assert_eq!(writer.stats().lines_read, 3);
+
+        assert_eq!(writer.finish().unwrap(), b"a\nb\n[REMOVED] A\nc");
+
+        let opts = DeduplicationOptions {
+            record_separator: RecordSeparator::Bytes(b"--".to_vec()),
+            ..Default::default()
+        };
+        let mut writer = DeduplicatingWriter::new(Vec::new(), opts).unwrap();
+        for chunk in [&b"x-"[..], b"-y-", b"-x--"] {
+            writer.write_all(chunk).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), b"x--y--");
+
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            ..Default::default()
+        };
+        assert!(DeduplicatingWriter::new(Vec::new(), opts).is_err());
+    }
+
+    #[test]
+    fn test_iter_dedup_lines() {
+        use crate::iter::{UniqExt, dedup_lines};
+
+        let lines = || ["a", "B", "b", "c", "a"].map(String::from).into_iter();
+        let opts = DeduplicationOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        let kept: Vec<String> = dedup_lines(lines(), &opts).collect::<Result<_>>().unwrap();
+        assert_eq!(kept, ["a", "B", "c"]);
+
+        let counted: Vec<(String, usize)> = lines().uniqr_counted(&opts).unwrap().collect();
+        assert_eq!(
+            counted,
+            [
+                ("a".to_string(), 2),
+                ("B".to_string(), 2),
+                ("c".to_string(), 1)
+            ]
+        );
+
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            ..Default::default()
+        };
+        let kept: Vec<String> = lines().uniqr(&opts).collect::<Result<_>>().unwrap();
+        assert_eq!(kept, ["B", "b", "c", "a"]);
+
+        // Slices and vectors already in memory share the same key logic
+        let slice = ["a", "B", "b", "c", "a"];
+        assert_eq!(
+            crate::iter::deduplicate_lines(&slice, &opts).unwrap(),
+            ["B", "b", "c", "a"]
+        );
+        let mut vec: Vec<String> = lines().collect();
+        crate::iter::deduplicate_lines_in_place(&mut vec, &opts).unwrap();
+        assert_eq!(vec, ["B", "b", "c", "a"]);
+
+        // KeepFirst is lazy, so it works on endless iterators
+        let mut endless = std::iter::repeat_with(|| "x".to_string())
+            .take(3)
+            .chain((0..).map(|i| i.to_string()))
+            .uniqr(&DeduplicationOptions::default());
+        assert_eq!(endless.next().unwrap().unwrap(), "x");
+        assert_eq!(endless.next().unwrap().unwrap(), "0");
+
+        // The error names the option iterators can't handle
+        let opts = DeduplicationOptions {
+            sort: Some(SortOrder::Text),
+            ..Default::default()
+        };
+        let err = lines().uniqr_counted(&opts).unwrap_err();
+        assert!(
+            err.to_string().contains("don't support sorted output"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_byte_stats() {
+        // The header row counts towards both byte totals
+        let input = b"id name\n1 a\n2 b\n1 c\n";
+        let opts = DeduplicationOptions {
+            column_name: Some("id".to_string()),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, b"id name\n1 a\n2 b\n");
+        assert_eq!(stats.bytes_read, input.len() as u64);
+        assert_eq!(stats.bytes_written, output.len() as u64);
+
+        let mut writer = DeduplicatingWriter::new(Vec::new(), Default::default()).unwrap();
+        writer.write_all(b"x\ny\nx\n").unwrap();
+        let (output, stats) = writer.finish_with_stats().unwrap();
+        assert_eq!(
+            (stats.bytes_read, stats.bytes_written),
+            (6, output.len() as u64)
+        );
+        assert_eq!(DeduplicationStats::default().lines_per_second(), 0.0);
+    }
+
+    #[test]
+    fn test_top_duplicates() {
+        let input = b"b\na\nc\na\nb\na\nd\nb\nc\n";
+        for mode in [
+            DeduplicationMode::KeepFirst,
+            DeduplicationMode::KeepLast,
+            DeduplicationMode::RemoveAll,
+        ] {
+            let opts = DeduplicationOptions {
+                mode,
+                top_duplicates: Some(2),
+                ..Default::default()
+            };
+            let stats = deduplicate(Cursor::new(input), &mut std::io::sink(), &opts).unwrap();
+            // a and b tie on 3; the smaller key wins; d (seen once) never appears
+            let expected = vec![(b"a".to_vec(), 3), (b"b".to_vec(), 3)];
+            assert_eq!(stats.top_duplicates, expected, "{:?}", mode);
+
+            #[cfg(feature = "disk-backed")]
+            {
+                let opts = DeduplicationOptions {
+                    use_disk: true,
+                    ..opts.clone()
+                };
+                let stats =
+                    deduplicate_seekable(Cursor::new(input), &mut std::io::sink(), &opts).unwrap();
+                assert_eq!(stats.top_duplicates, expected, "disk {:?}", mode);
+            }
+        }
+
+        let mut writer = DeduplicatingWriter::new(
+            Vec::new(),
+            DeduplicationOptions::builder()
+                .top_duplicates(1)
+                .build()
+                .unwrap(),
+        )
+        .unwrap();
+        writer.write_all(input).unwrap();
+        let (_, stats) = writer.finish_with_stats().unwrap();
+        assert_eq!(stats.top_duplicates, vec![(b"a".to_vec(), 3)]);
+    }
+
+    #[test]
+    fn test_duplicate_histogram() {
+        let buckets: Vec<usize> = [1, 2, 3, 10, 11, 100, 101, 5000, usize::MAX]
+            .into_iter()
+            .map(histogram_bucket)
+            .collect();
+        assert_eq!(
+            buckets,
+            [1, 2, 3, 3, 11, 11, 101, 1001, 10_000_000_000_000_000_001]
+        );
+
+        // a: 12 times, b: 3, c: 2, d and e: once
+        let mut input = b"a\n".repeat(12);
+        input.extend_from_slice(b"b\nc\nb\nd\nc\ne\nb\n");
+        let expected = BTreeMap::from([(1, 2), (2, 1), (3, 1), (11, 1)]);
+        for mode in [DeduplicationMode::KeepFirst, DeduplicationMode::RemoveAll] {
+            let opts = DeduplicationOptions {
+                mode,
+                collect_histogram: true,
+                ..Default::default()
+            };
+            let stats = deduplicate(Cursor::new(&input), &mut std::io::sink(), &opts).unwrap();
+            assert_eq!(stats.duplicate_histogram, expected, "{:?}", mode);
+
+            #[cfg(feature = "disk-backed")]
+            {
+                let opts = DeduplicationOptions {
+                    use_disk: true,
+                    ..opts.clone()
+                };
+                let stats =
+                    deduplicate_seekable(Cursor::new(&input), &mut std::io::sink(), &opts).unwrap();
+                assert_eq!(stats.duplicate_histogram, expected, "disk {:?}", mode);
+            }
+        }
+
+        let stats = deduplicate(
+            Cursor::new(&input),
+            &mut std::io::sink(),
+            &Default::default(),
+        );
+        assert!(stats.unwrap().duplicate_histogram.is_empty());
+    }
+
+    #[test]
+    fn test_key_counts() {
+        // Counts are per key, so case-folded variants are combined
+        let input = b"B\na\nb\nc\nA\nb\n";
+        let opts = DeduplicationOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        let expected = vec![(b"b".to_vec(), 3), (b"a".to_vec(), 2), (b"c".to_vec(), 1)];
+
+        let mut output = Vec::new();
+        let (stats, counts) =
+            deduplicate_with_counts(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, b"B\na\nc\n");
+        assert_eq!(counts, expected);
+        assert!(stats.key_counts.is_empty());
+
+        #[cfg(feature = "disk-backed")]
+        for mode in [DeduplicationMode::KeepFirst, DeduplicationMode::KeepLast] {
+            let opts = DeduplicationOptions {
+                mode,
+                use_disk: true,
+                collect_key_counts: true,
+                ..opts.clone()
+            };
+            let stats =
+                deduplicate_seekable(Cursor::new(input), &mut std::io::sink(), &opts).unwrap();
+            assert_eq!(stats.key_counts, expected, "disk {:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_count_format() {
+        let input = b"a\r\nb\na\r\nc";
+        let mut opts = DeduplicationOptions {
+            count: true,
+            mode: DeduplicationMode::KeepLast,
+            count_format: CountFormat {
+                width: 0,
+                separator: "\t".to_string(),
+                placement: CountPlacement::Prefix,
+            },
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, b"1\tb\n2\ta\r\n1\tc");
+
+        // Suffix counts go before the record terminator
+        opts.count_format.placement = CountPlacement::Suffix;
+        opts.count_format.width = 3;
+        let mut output = Vec::new();
+        deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, b"b\t  1\na\t  2\r\nc\t  1");
+    }
+
+    #[test]
+    fn test_removed_records() {
+        let location = |source, line| RecordLocation {
+            source,
+            line,
+            number: line,
+        };
+        let removed = |line, original: Option<usize>| RemovedRecord {
+            location: location(0, line),
+            duplicate_of: original.map(|line| location(0, line)),
+        };
+        let input = b"a\nb\na\nc\na\n";
+        let cases = [
+            (
+                DeduplicationMode::KeepFirst,
+                false,
+                vec![removed(3, Some(1)), removed(5, Some(1))],
+            ),
+            (
+                DeduplicationMode::KeepLast,
+                false,
+                vec![removed(1, Some(5)), removed(3, Some(5))],
+            ),
+            (
+                DeduplicationMode::RemoveAll,
+                false,
+                vec![
+                    removed(1, Some(5)),
+                    removed(3, Some(1)),
+                    removed(5, Some(1)),
+                ],
+            ),
+            (
+                DeduplicationMode::Repeated,
+                false,
+                vec![
+                    removed(2, None),
+                    removed(3, Some(1)),
+                    removed(4, None),
+                    removed(5, Some(1)),
+                ],
+            ),
+            (DeduplicationMode::KeepFirst, true, vec![]),
+        ];
+        for (mode, adjacent, expected) in cases {
+            let opts = DeduplicationOptions {
+                mode,
+                adjacent,
+                collect_removed_records: true,
+                ..Default::default()
+            };
+            let stats = deduplicate(Cursor::new(input), &mut Vec::new(), &opts).unwrap();
+            assert_eq!(stats.removed_records, expected, "{:?}", mode);
+        }
+
+        // Runs report against their own first or last record
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            adjacent: true,
+            collect_removed_records: true,
+            ..Default::default()
+        };
+        let stats = deduplicate(Cursor::new(b"a\na\na\nb\na\n"), &mut Vec::new(), &opts).unwrap();
+        assert_eq!(
+            stats.removed_records,
+            [removed(1, Some(3)), removed(2, Some(3))]
+        );
+    }
+
+    #[test]
+    fn test_line_numbers() {
+        use std::sync::Mutex;
+
+        let kept = std::sync::Arc::new(Mutex::new(Vec::new()));
+        let sink = std::sync::Arc::clone(&kept);
+        let opts = DeduplicationOptions {
+            line_numbers: true,
+            mode: DeduplicationMode::KeepLast,
+            show_removed: true,
+            ..Default::default()
+        }
+        .with_record_callback(move |record: &[u8], location: RecordLocation| {
+            sink.lock().unwrap().push((record.to_vec(), location));
+        });
+
+        // Numbers continue across inputs; removed records are not reported
+        let inputs = [Cursor::new(&b"a\nb"[..]), Cursor::new(&b"a\n"[..])];
+        let mut output = Vec::new();
+        deduplicate_many(inputs, &mut output, &opts).unwrap();
+        assert_eq!(output, b"[REMOVED] 1:a\n2:b\n3:a\n");
+        assert_eq!(
+            *kept.lock().unwrap(),
+            [
+                (
+                    b"b\n".to_vec(),
+                    RecordLocation {
+                        source: 0,
+                        line: 2,
+                        number: 2
+                    }
+                ),
+                (
+                    b"a\n".to_vec(),
+                    RecordLocation {
+                        source: 1,
+                        line: 1,
+                        number: 3
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_print_key() {
+        let opts = DeduplicationOptions {
+            column: Some(2),
+            ignore_case: true,
+            print_key: true,
+            count: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        deduplicate(
+            Cursor::new(b"1 Alice x\r\n2 ALICE y\r\n3 Bob"),
+            &mut output,
+            &opts,
+        )
+        .unwrap();
+        assert_eq!(output, b"      2 alice\r\n      1 bob");
+
+        // Removed records are written whole
+        let opts = DeduplicationOptions {
+            key_type: KeyType::Numeric,
+            print_key: true,
+            show_removed: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        deduplicate(Cursor::new(b"01\n1.0\n+2\n"), &mut output, &opts).unwrap();
+        assert_eq!(output, b"1\n[REMOVED] 1.0\n2\n");
+    }
+
+    #[test]
+    fn test_follow_reader() {
+        use std::io::Read;
+
+        /// Yields each chunk, with an empty read (EOF so far) between them
+        struct Growing(Vec<&'static [u8]>, bool);
+        impl Read for Growing {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.1 = !self.1;
+                if self.1 || self.0.is_empty() {
+                    return Ok(0);
+                }
+                let chunk = self.0.remove(0);
+                buf[..chunk.len()].copy_from_slice(chunk);
+                Ok(chunk.len())
+            }
+        }
+
+        let input = Growing(vec![b"a\nb", b"\na\n", b"c\n"], false);
+        let mut reader =
+            std::io::BufReader::new(FollowReader::with_interval(input, Duration::from_millis(1)));
+        let mut records = Vec::new();
+        for _ in 0..4 {
+            let mut line = Vec::new();
+            std::io::BufRead::read_until(&mut reader, b'\n', &mut line).unwrap();
+            records.push(line);
+        }
+        assert_eq!(records, [&b"a\n"[..], b"b\n", b"a\n", b"c\n"]);
+    }
+
+    #[cfg(feature = "disk-backed")]
+    #[test]
+    fn test_state_db() {
+        let dir = tempfile::tempdir().unwrap();
+        let opts = DeduplicationOptions {
+            use_disk: true,
+            state_db: Some(dir.path().join("seen")),
+            ..Default::default()
+        };
+
+        let mut output = Vec::new();
+        deduplicate(Cursor::new(b"a\nb\n"), &mut output, &opts).unwrap();
+        assert_eq!(output, b"a\nb\n");
+
+        let mut output = Vec::new();
+        let stats = deduplicate(Cursor::new(b"b\nc\nc\n"), &mut output, &opts).unwrap();
+        assert_eq!(output, b"c\n");
+        assert_eq!(stats.unique_lines, 1);
+        assert_eq!(stats.lines_removed, 2);
+
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            ..opts
+        };
+        let err = deduplicate_seekable(Cursor::new(b"a\n"), &mut Vec::new(), &opts).unwrap_err();
+        assert!(err.to_string().contains("state database"));
+    }
+
+    #[cfg(feature = "disk-backed")]
+    #[test]
+    fn test_storage_errors() {
+        use std::io::{Read, Seek};
+
+        /// An input that can be read but not seeked
+        struct Unseekable(Cursor<&'static [u8]>);
+        impl Read for Unseekable {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.0.read(buf)
+            }
+        }
+        impl Seek for Unseekable {
+            fn seek(&mut self, _pos: std::io::SeekFrom) -> std::io::Result<u64> {
+                Err(std::io::Error::other("not seekable"))
+            }
+        }
+
+        // A state database can't be opened where a file is in the way
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("seen");
+        std::fs::write(&path, b"not a database").unwrap();
+        let opts = DeduplicationOptions {
+            use_disk: true,
+            state_db: Some(path),
+            ..Default::default()
+        };
+        let err = deduplicate(Cursor::new(b"a\n"), &mut Vec::new(), &opts).unwrap_err();
+        assert!(matches!(err, Error::Storage(_)), "{:?}", err);
+        assert!(std::error::Error::source(&err).is_some());
+
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            use_disk: true,
+            ..Default::default()
+        };
+        let input = Unseekable(Cursor::new(b"a\nb\na\n"));
+        let err = deduplicate_seekable(input, &mut Vec::new(), &opts).unwrap_err();
+        assert!(matches!(err, Error::Seek(_)), "{:?}", err);
+        assert_eq!(std::io::Error::from(err).kind(), std::io::ErrorKind::Other);
+    }
+
+    #[cfg(feature = "disk-backed")]
+    #[test]
+    fn test_checkpoint_resume() {
+        use std::io::Read;
+
+        /// An input that fails once its data has been read
+        struct Interrupted;
+        impl Read for Interrupted {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("interrupted"))
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let opts = DeduplicationOptions::builder()
+            .state_db(dir.path().join("seen"))
+            .checkpoint_every(2)
+            .build()
+            .unwrap();
+        let input = b"a\nb\na\nc\nd\ne\nd\n";
+        let mut output = Vec::new();
+        let interrupted = Cursor::new(&input[..10]).chain(Interrupted);
+        assert!(deduplicate(interrupted, &mut output, &opts).is_err());
+
+        // The checkpoint after "c", the 4th record; "d" was stored after it
+        let checkpoint = read_checkpoint(&opts).unwrap().unwrap();
+        assert_eq!(checkpoint.records_read, 4);
+        assert_eq!(checkpoint.input_offset, 8);
+        assert_eq!(&output[..checkpoint.output_offset as usize], b"a\nb\nc");
+
+        let opts = DeduplicationOptions {
+            resume: true,
+            ..opts
+        };
+        let err = deduplicate(Cursor::new(b"a\nb\n"), &mut Vec::new(), &opts).unwrap_err();
+        assert!(err.to_string().contains("doesn't match"));
+
+        output.truncate(checkpoint.output_offset as usize);
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, b"a\nb\nc\nd\ne\n");
+        assert_eq!((stats.lines_read, stats.lines_removed), (7, 2));
+        assert_eq!(read_checkpoint(&opts).unwrap(), None);
+        let err = deduplicate(Cursor::new(input), &mut Vec::new(), &opts).unwrap_err();
+        assert!(err.to_string().contains("no checkpoint"));
+    }
+
+    #[cfg(feature = "disk-backed")]
+    #[test]
+    fn test_memory_limit_spill() {
+        let input = b"a\nb\na\nc\nb\nd\nc\n";
+        for count in [false, true] {
+            let memory = DeduplicationOptions {
+                count,
+                top_duplicates: Some(2),
+                ..Default::default()
+            };
+            // A budget of one byte spills on the first record, a large one never does
+            for memory_limit in [1, 1 << 20] {
+                let spilled = DeduplicationOptions {
+                    memory_limit: Some(memory_limit),
+                    ..memory.clone()
+                };
+                let (mut expected, mut output) = (Vec::new(), Vec::new());
+                let expected_stats = deduplicate(&input[..], &mut expected, &memory).unwrap();
+                let stats = deduplicate(&input[..], &mut output, &spilled).unwrap();
+                assert_eq!(output, expected);
+                assert_eq!(stats.lines_removed, expected_stats.lines_removed);
+                assert_eq!(stats.unique_lines, expected_stats.unique_lines);
+                assert_eq!(stats.top_duplicates, expected_stats.top_duplicates);
+            }
+        }
+
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            memory_limit: Some(1),
+            ..Default::default()
+        };
+        assert!(deduplicate(&input[..], &mut Vec::new(), &opts).is_err());
+    }
+
+    #[cfg(feature = "disk-backed")]
+    #[test]
+    fn test_disk_backends_agree() {
+        // Enough distinct keys to flush the temp-file store's write buffer
+        let input: Vec<u8> = (0..20_000)
+            .flat_map(|i| format!("key {}\n", i % 7_000 * 3 % 9_001).into_bytes())
+            .collect();
+        let modes = [
+            DeduplicationMode::KeepFirst,
+            DeduplicationMode::KeepLast,
+            DeduplicationMode::RemoveAll,
+        ];
+        for mode in modes {
+            let memory = DeduplicationOptions {
+                mode,
+                count: true,
+                top_duplicates: Some(3),
+                ..Default::default()
+            };
+            let mut expected = Vec::new();
+            let expected_stats =
+                deduplicate_seekable(Cursor::new(&input), &mut expected, &memory).unwrap();
+
+            let opts = DeduplicationOptions {
+                use_disk: true,
+                disk: DiskOptions {
+                    backend: DiskBackend::TempFile,
+                    ..Default::default()
+                },
+                ..memory
+            };
+            let mut output = Vec::new();
+            let stats = deduplicate_seekable(Cursor::new(&input), &mut output, &opts).unwrap();
+            assert_eq!(output, expected, "{:?}", mode);
+            assert_eq!(stats.unique_lines, expected_stats.unique_lines);
+            assert_eq!(stats.top_duplicates, expected_stats.top_duplicates);
+        }
+
+        let opts = DeduplicationOptions {
+            use_disk: true,
+            state_db: Some("seen.db".into()),
+            disk: DiskOptions {
+                backend: DiskBackend::TempFile,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(deduplicate(&b"a\n"[..], &mut Vec::new(), &opts).is_err());
+    }
+
+    #[cfg(feature = "disk-backed")]
+    #[test]
+    fn test_disk_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = b"a\nb\na\nc\nb\n";
+        let cases: [(DeduplicationMode, &[u8]); 3] = [
+            (DeduplicationMode::KeepFirst, b"a\nb\nc\n"),
+            (DeduplicationMode::KeepLast, b"a\nc\nb\n"),
+            (DeduplicationMode::RemoveAll, b"c\n"),
+        ];
+        for backend in [DiskBackend::Sled, DiskBackend::TempFile] {
+            for (mode, expected) in cases {
+                let opts = DeduplicationOptions {
+                    mode,
+                    use_disk: true,
+                    disk: DiskOptions {
+                        backend,
+                        temp_dir: Some(dir.path().to_path_buf()),
+                        cache_capacity: Some(1024 * 1024),
+                        low_space: true,
+                    },
+                    ..Default::default()
+                };
+                let mut output = Vec::new();
+                deduplicate_seekable(Cursor::new(input), &mut output, &opts).unwrap();
+                assert_eq!(output, expected, "{:?} {:?}", backend, mode);
+                // The temporary store is removed once the run is over
+                assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_deduplicate_split() {
+        let input = b"a\nb\na\nc\nb\n";
+        let cases: [(DeduplicationMode, &[u8], &[u8]); 3] = [
+            (DeduplicationMode::KeepFirst, b"a\nb\nc\n", b"a\nb\n"),
+            (DeduplicationMode::KeepLast, b"a\nc\nb\n", b"a\nb\n"),
+            (DeduplicationMode::RemoveAll, b"c\n", b"a\nb\na\nb\n"),
+        ];
+        for (mode, kept_expected, removed_expected) in cases {
+            // show_removed doesn't add markers when removed records have their own sink
+            let opts = DeduplicationOptions {
+                mode,
+                show_removed: true,
+                ..Default::default()
+            };
+            let (mut kept, mut removed) = (Vec::new(), Vec::new());
+            let stats =
+                deduplicate_split(Cursor::new(input), &mut kept, &mut removed, &opts).unwrap();
+            assert_eq!(kept, kept_expected, "{:?}", mode);
+            assert_eq!(removed, removed_expected, "{:?}", mode);
+            assert_eq!(stats.bytes_written, kept.len() as u64);
+
+            #[cfg(feature = "disk-backed")]
+            {
+                let opts = DeduplicationOptions {
+                    use_disk: true,
+                    ..opts.clone()
+                };
+                let (mut kept, mut removed) = (Vec::new(), Vec::new());
+                deduplicate_many_split_seekable(
+                    [Cursor::new(input)],
+                    &mut kept,
+                    &mut removed,
+                    &opts,
+                )
+                .unwrap();
+                assert_eq!(kept, kept_expected, "disk {:?}", mode);
+                assert_eq!(removed, removed_expected, "disk {:?}", mode);
+            }
+        }
+    }
+
+    #[test]
+    fn test_progress_callback() {
+        use std::sync::Mutex;
+
+        let reports = std::sync::Arc::new(Mutex::new(Vec::new()));
+        let sink = std::sync::Arc::clone(&reports);
+        let opts = DeduplicationOptions::default()
+            .with_progress_callback(move |p: Progress| sink.lock().unwrap().push(p.bytes_read));
+
+        let inputs = [
+            Cursor::new(b"a\nb\n".to_vec()),
+            Cursor::new(b"a\nc\n".to_vec()),
+        ];
+        let mut output = Vec::new();
+        deduplicate_many_seekable(inputs, &mut output, &opts).unwrap();
+        assert_eq!(output, b"a\nb\nc\n");
+
+        let reports = reports.lock().unwrap();
+        assert!(reports.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(reports.last(), Some(&8));
+
+        // Records are reported with the bytes read after them
+        let reports = std::sync::Arc::new(Mutex::new(Vec::new()));
+        let sink = std::sync::Arc::clone(&reports);
+        let opts = DeduplicationOptions::default()
+            .with_progress_callback(move |p: Progress| sink.lock().unwrap().push(p.lines_read));
+        let input: Vec<u8> = (0..10_000)
+            .flat_map(|i| format!("{}\n", i % 100).into_bytes())
+            .collect();
+        deduplicate(Cursor::new(input), &mut Vec::new(), &opts).unwrap();
+        let reports = reports.lock().unwrap();
+        assert!(reports.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!((5_000..10_000).contains(reports.last().unwrap()));
+
+        // ...and each as it is read, to callbacks that ask
+        struct Lines(std::sync::atomic::AtomicU64);
+        impl ProgressCallback for Lines {
+            fn report(&self, _: Progress) {}
+            fn record_read(&self, lines_read: u64) {
+                self.0
+                    .store(lines_read, std::sync::atomic::Ordering::Relaxed);
+            }
+        }
+        let lines = std::sync::Arc::new(Lines(Default::default()));
+        let opts = DeduplicationOptions {
+            progress_callback: Some(lines.clone()),
+            ..Default::default()
+        };
+        deduplicate(Cursor::new(b"a\nb\na"), &mut Vec::new(), &opts).unwrap();
+        assert_eq!(lines.0.load(std::sync::atomic::Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_cancel() {
+        use std::sync::Arc;
+        use std::sync::atomic::AtomicBool;
+
+        // Cancelled by another thread as the second record is read
+        struct CancelAt(u64, Arc<AtomicBool>);
+        impl ProgressCallback for CancelAt {
+            fn report(&self, _: Progress) {}
+            fn record_read(&self, lines_read: u64) {
+                if lines_read == self.0 {
+                    self.1.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+        let cancel = Arc::new(AtomicBool::new(false));
+        let opts = DeduplicationOptions::builder()
+            .progress_callback(CancelAt(2, cancel.clone()))
+            .cancel(cancel.clone())
+            .build()
+            .unwrap();
+        let mut output = Vec::new();
+        let Err(Error::Cancelled(stats)) =
+            deduplicate(Cursor::new(b"a\nb\na\nc\n"), &mut output, &opts)
+        else {
+            panic!("expected a cancellation");
+        };
+        assert_eq!(output, b"a\nb\n");
+        assert_eq!(stats.lines_read, 2);
+        assert_eq!(stats.bytes_read, 8);
+        assert_eq!(stats.bytes_written, 4);
+
+        // Two-pass modes stop before writing anything
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            ..opts
+        };
+        cancel.store(false, Ordering::Relaxed);
+        let mut output = Vec::new();
+        let result = deduplicate(Cursor::new(b"a\nb\na\nc\n"), &mut output, &opts);
+        assert!(matches!(result, Err(Error::Cancelled(stats)) if stats.lines_read == 2));
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_normalizers() {
+        use normalize::{CaseFold, Column, Trim};
+
+        let input = b"1 Alpha7 x\n2 alpha x\n3  ALPHA99  \n4 Beta\n";
+        // Column, then case, then digits: all three alphas match
+        let opts = DeduplicationOptions::builder()
+            .normalizer(Column(2))
+            .normalizer(Trim(TrimMode::Both))
+            .normalizer(CaseFold(CaseMode::Lower))
+            .normalizer(|key: &[u8]| {
+                key.iter()
+                    .copied()
+                    .filter(|b| !b.is_ascii_digit())
+                    .collect()
+            })
+            .count(true)
+            .build()
+            .unwrap();
+        let mut output = Vec::new();
+        deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, b"      3 1 Alpha7 x\n      1 4 Beta\n");
+
+        // Normalizers apply after the built-in options and custom extractors
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            ignore_case: true,
+            ..Default::default()
+        }
+        .with_key_fn(|record: &[u8]| record[2..].to_vec());
+        let opts = DeduplicationOptions {
+            normalizers: vec![std::sync::Arc::new(Column(1))],
+            ..opts
+        };
+        let mut output = Vec::new();
+        deduplicate(Cursor::new(b"1 a x\n2 a y\n3 b\n"), &mut output, &opts).unwrap();
+        assert_eq!(output, b"2 a y\n3 b\n");
+
+        #[cfg(feature = "regex")]
+        {
+            let opts = DeduplicationOptions::builder()
+                .normalizer(normalize::RegexReplace::new(r"id=\d+", "id=N").unwrap())
+                .build()
+                .unwrap();
+            let mut output = Vec::new();
+            deduplicate(
+                Cursor::new(b"GET id=1\nGET id=22\nPUT id=1\n"),
+                &mut output,
+                &opts,
+            )
+            .unwrap();
+            assert_eq!(output, b"GET id=1\nPUT id=1\n");
+            assert!(normalize::RegexReplace::new("(", "").is_err());
+        }
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_log_presets() {
+        use normalize::{LogFormat, LogPreset};
+
+        let dedup = |format, input: &[u8]| {
+            let opts = DeduplicationOptions::builder()
+                .normalizer(LogPreset::new(format).unwrap())
+                .build()
+                .unwrap();
+            let mut output = Vec::new();
+            deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+            output
+        };
+
+        let syslog = b"Jan  2 15:04:05 host sshd[42]: Accepted key\n\
+            <34>1 2024-01-02T15:04:06.123Z host sshd[43]: Accepted key\n\
+            Jan  2 15:04:07 host sshd[44]: Failed key\n";
+        assert_eq!(
+            dedup(LogFormat::Syslog, syslog),
+            b"Jan  2 15:04:05 host sshd[42]: Accepted key\n\
+            Jan  2 15:04:07 host sshd[44]: Failed key\n"
+        );
+
+        let nginx = b"1.2.3.4 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200\n\
+            1.2.3.4 - - [10/Oct/2000:13:55:37 -0700] \"GET / HTTP/1.1\" 200\n\
+            2024/01/02 15:04:05 [error] 42#42: *7 upstream timed out\n\
+            2024/01/02 15:04:09 [error] 43#43: *8 upstream timed out\n";
+        assert_eq!(
+            dedup(LogFormat::Nginx, nginx),
+            b"1.2.3.4 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200\n\
+            2024/01/02 15:04:05 [error] 42#42: *7 upstream timed out\n"
+        );
+
+        let apache = b"[Wed Oct 11 14:32:52.123 2000] [core:error] [pid 42:tid 43] \
+            [client 1.2.3.4:5678] File does not exist\n\
+            [Wed Oct 11 14:33:01.456 2000] [core:error] [pid 44:tid 45] \
+            [client 1.2.3.4:5690] File does not exist\n\
+            req 123e4567-e89b-12d3-a456-426614174000 done\n\
+            req 00000000-e89b-12d3-a456-426614174000 done\n";
+        assert_eq!(
+            dedup(LogFormat::Apache, apache),
+            b"[Wed Oct 11 14:32:52.123 2000] [core:error] [pid 42:tid 43] \
+            [client 1.2.3.4:5678] File does not exist\n\
+            req 123e4567-e89b-12d3-a456-426614174000 done\n"
+        );
+
+        let journald = b"Jan 02 15:04:05.123456 host app[42]: started\n\
+            Jan 02 15:05:00.000001 host app[99]: started\n";
+        assert_eq!(
+            dedup(LogFormat::Journald, journald),
+            b"Jan 02 15:04:05.123456 host app[42]: started\n"
+        );
+    }
+
+    #[cfg(feature = "url")]
+    #[test]
+    fn test_canonical_url() {
+        use normalize::{CanonicalUrl, Normalizer};
+
+        let canonical = |url: &str| String::from_utf8(CanonicalUrl.normalize(url.into()).unwrap());
+        assert_eq!(
+            canonical("HTTPS://Example.COM:443/a?b=2&utm_source=x&a=1&a=0#top").unwrap(),
+            "https://example.com/a?a=1&a=0&b=2"
+        );
+        assert_eq!(
+            canonical("http://example.com:80?fbclid=1").unwrap(),
+            "http://example.com/"
+        );
+        assert_eq!(canonical("not a url").unwrap(), "not a url");
+
+        let opts = DeduplicationOptions::builder()
+            .normalizer(CanonicalUrl)
+            .build()
+            .unwrap();
+        let input =
+            b"https://example.com/\nhttps://EXAMPLE.com:443/#x\nhttps://example.com/?gclid=7\n\
+            https://example.com/?page=2\n";
+        let mut output = Vec::new();
+        deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(
+            output,
+            b"https://example.com/\nhttps://example.com/?page=2\n"
+        );
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_passthrough() {
+        let input = b"# db\nhost=a\n\n# cache\nhost=a\n\nport=1\nport=1\n";
+        let opts = DeduplicationOptions {
+            passthrough: Some(RecordFilter::pass_matching(r"^\s*(#|$)").unwrap()),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, b"# db\nhost=a\n\n# cache\n\nport=1\n");
+        assert_eq!((stats.lines_written, stats.lines_removed), (6, 2));
+
+        let mut dedup = Deduplicator::new(opts).unwrap();
+        assert_eq!(dedup.push_line(b"#\n").unwrap(), Decision::Keep);
+        assert_eq!(dedup.push_line(b"#\n").unwrap(), Decision::Keep);
+
+        let opts = DeduplicationOptions {
+            passthrough: Some(RecordFilter::only_matching("^host=").unwrap()),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, b"# db\nhost=a\n\n# cache\n\nport=1\nport=1\n");
+
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            ..opts
+        };
+        assert!(deduplicate(Cursor::new(input), &mut Vec::new(), &opts).is_err());
+        assert!(RecordFilter::pass_matching("(").is_err());
+    }
+
+    #[test]
+    fn test_header_footer() {
+        let input = b"id,name\n1,a\n2,b\n1,a\n# 3 rows\n# 3 rows\n";
+        let opts = DeduplicationOptions {
+            header_lines: 1,
+            footer_lines: 2,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, b"id,name\n1,a\n2,b\n# 3 rows\n# 3 rows\n");
+        assert_eq!((stats.lines_read, stats.lines_removed), (3, 1));
+
+        // Later inputs' headers are skipped, and only the last footer written
+        let inputs = [
+            Cursor::new(&b"h\nx\nf"[..]),
+            Cursor::new(&b"h\ny\nx\nf"[..]),
+        ];
+        let opts = DeduplicationOptions {
+            header_lines: 1,
+            footer_lines: 1,
+            mode: DeduplicationMode::KeepLast,
+            sort: Some(SortOrder::default()),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        deduplicate_many(inputs, &mut output, &opts).unwrap();
+        assert_eq!(output, b"h\nx\ny\nf");
+
+        // A short input is all header and footer
+        let mut output = Vec::new();
+        deduplicate(Cursor::new(b"h\nf\n"), &mut output, &opts).unwrap();
+        assert_eq!(output, b"h\nf\n");
+
+        #[cfg(feature = "disk-backed")]
+        {
+            let opts = DeduplicationOptions {
+                footer_lines: 1,
+                mode: DeduplicationMode::KeepLast,
+                use_disk: true,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            deduplicate_seekable(Cursor::new(b"a\nb\na\nend\n"), &mut output, &opts).unwrap();
+            assert_eq!(output, b"b\na\nend\n");
+        }
+
+        let opts = DeduplicationOptions {
+            footer_lines: 1,
+            record_separator: RecordSeparator::Paragraph,
+            ..Default::default()
+        };
+        assert!(deduplicate(Cursor::new(b"a\n"), &mut Vec::new(), &opts).is_err());
+    }
+
+    #[test]
+    fn test_empty_lines() {
+        let input = b"a\n\na\n  \nb\n\n";
+        let dedup = |empty_lines| {
+            let opts = DeduplicationOptions {
+                empty_lines,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+            (output, stats.lines_removed)
+        };
+        assert_eq!(dedup(EmptyLinePolicy::Dedup), (b"a\n\n  \nb\n".to_vec(), 2));
+        assert_eq!(
+            dedup(EmptyLinePolicy::Preserve),
+            (b"a\n\n  \nb\n\n".to_vec(), 1)
+        );
+        assert_eq!(dedup(EmptyLinePolicy::Drop), (b"a\nb\n".to_vec(), 4));
+
+        // Dropping works in every mode; preserving needs keep-first
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            empty_lines: EmptyLinePolicy::Drop,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, b"a\nb\n");
+        let opts = DeduplicationOptions {
+            empty_lines: EmptyLinePolicy::Preserve,
+            ..opts
+        };
+        assert!(deduplicate(Cursor::new(input), &mut Vec::new(), &opts).is_err());
+
+        let opts = DeduplicationOptions {
+            empty_lines: EmptyLinePolicy::Preserve,
+            ..Default::default()
+        };
+        assert!(setops::merge_sorted([&b"a\n"[..]], &mut Vec::new(), &opts).is_err());
+        let mut dedup = Deduplicator::new(opts).unwrap();
+        assert_eq!(dedup.push_line(b"\n").unwrap(), Decision::Keep);
+        assert_eq!(dedup.push_line(b"\n").unwrap(), Decision::Keep);
+    }
+
+    #[test]
+    fn test_key_extractor() {
+        /// Keys on the text after the last `/`
+        struct Basename;
+        impl KeyExtractor for Basename {
+            fn extract_key(&self, record: &[u8]) -> Result<Vec<u8>> {
+                let start = record.iter().rposition(|&b| b == b'/').map_or(0, |i| i + 1);
+                Ok(record[start..].to_vec())
+            }
+        }
+
+        let input = b"a/x.txt\nb/y.txt\nc/x.txt\n";
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            ..Default::default()
+        }
+        .with_key_extractor(Basename);
+        let mut output = Vec::new();
+        deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, b"b/y.txt\nc/x.txt\n");
+
+        // A closure replaces the built-in options such as ignore_case
+        let opts = DeduplicationOptions {
+            ignore_case: true,
+            ..Default::default()
+        }
+        .with_key_fn(|record: &[u8]| record.to_vec());
+        let mut output = Vec::new();
+        deduplicate(Cursor::new(b"a\nA\n"), &mut output, &opts).unwrap();
+        assert_eq!(output, b"a\nA\n");
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_deduplicate_async() {
+        for mode in [
+            DeduplicationMode::KeepFirst,
+            DeduplicationMode::KeepLast,
+            DeduplicationMode::RemoveAll,
+        ] {
+            let opts = DeduplicationOptions {
+                mode,
+                ..Default::default()
+            };
+            let input = b"a\nb\na\nc";
+            let mut expected = Vec::new();
+            deduplicate(Cursor::new(input), &mut expected, &opts).unwrap();
+
+            let mut output = Vec::new();
+            let stats = deduplicate_async(&input[..], &mut output, &opts)
+                .await
+                .unwrap();
+            assert_eq!(output, expected, "{:?}", mode);
+            assert_eq!(stats.lines_read, 4);
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_dedup_stream() {
+        use futures_core::Stream;
+        use std::pin::Pin;
+
+        let opts = DeduplicationOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        // A tiny buffer so records span several reads
+        let input = tokio::io::BufReader::with_capacity(2, &b"apple\nb\nAPPLE\nc"[..]);
+        let mut stream = dedup_stream(input, &opts).unwrap();
+        let mut kept = Vec::new();
+        while let Some(line) = std::future::poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await
+        {
+            kept.push(line.unwrap());
+        }
+        let lines: Vec<_> = kept
+            .iter()
+            .map(|kept| (kept.line, &kept.record[..]))
+            .collect();
+        assert_eq!(lines, [(1, &b"apple\n"[..]), (2, b"b\n"), (4, b"c")]);
+        assert_eq!(stream.stats().lines_removed, 1);
+
+        let opts = DeduplicationOptions {
+            record_separator: RecordSeparator::Bytes(b"--\n".to_vec()),
+            ..Default::default()
+        };
+        let mut stream = dedup_stream(&b"a\n--\nb-\n--\na\n--\n"[..], &opts).unwrap();
+        let mut kept = Vec::new();
+        while let Some(line) = std::future::poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await
+        {
+            kept.extend(line.unwrap().record);
+        }
+        assert_eq!(kept, b"a\n--\nb-\n--\n");
+
+        // An empty separator can never match
+        let opts = DeduplicationOptions {
+            record_separator: RecordSeparator::Bytes(Vec::new()),
+            ..Default::default()
+        };
+        assert!(dedup_stream(&b"a\nb\na\n"[..], &opts).is_err());
+
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            ..Default::default()
+        };
+        assert!(dedup_stream(&b""[..], &opts).is_err());
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn test_wasm_dedup_text() {
+        let mut opts = wasm::DedupOptions::new();
+        opts.ignore_case = true;
+        opts.mode = wasm::Mode::KeepLast;
+        let output = wasm::dedup_text("a\nb\nA\n", &opts).unwrap();
+        assert_eq!(output, "b\nA\n");
+    }
+
+    #[cfg(feature = "ffi")]
+    #[test]
+    fn test_ffi_dedup_buffer() {
+        use crate::ffi::*;
+
+        let mut options = uniqr_options_default();
+        options.mode = UniqrMode::RemoveAll as u32;
+        let input = b"a\nb\na\n";
+        let mut output = std::ptr::null_mut();
+        let mut output_len = 0;
+        let mut stats = UniqrStats::default();
+
+        let status = unsafe {
+            uniqr_dedup_buffer(
+                input.as_ptr(),
+                input.len(),
+                &options,
+                &mut output,
+                &mut output_len,
+                &mut stats,
+            )
+        };
+        assert_eq!(status, UniqrStatus::Ok);
+        assert_eq!(
+            unsafe { std::slice::from_raw_parts(output, output_len) },
+            b"b\n"
+        );
+        assert_eq!(stats.lines_removed, 2);
+        unsafe { uniqr_free_buffer(output, output_len) };
+
+        let status = unsafe {
+            uniqr_dedup_buffer(
+                input.as_ptr(),
+                input.len(),
+                std::ptr::null(),
+                &mut output,
+                &mut output_len,
+                std::ptr::null_mut(),
+            )
+        };
+        assert_eq!(status, UniqrStatus::NullPointer);
+
+        // A mode outside UniqrMode is rejected
+        options.mode = 7;
+        let status = unsafe {
+            uniqr_dedup_buffer(
+                input.as_ptr(),
+                input.len(),
+                &options,
+                &mut output,
+                &mut output_len,
+                std::ptr::null_mut(),
+            )
+        };
+        assert_eq!(status, UniqrStatus::InvalidArgument);
+    }
+
+    #[cfg(feature = "ffi")]
+    #[test]
+    fn test_ffi_dedup_file() {
+        use crate::ffi::*;
+        use std::ffi::CString;
+
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("a.csv");
+        std::fs::write(&input, "a\nb\na\n").unwrap();
+        let path = |p: &std::path::Path| CString::new(p.to_str().unwrap()).unwrap();
+        let options = uniqr_options_default();
+
+        // Deduplicating in place works and leaves no temp file behind
+        let status = unsafe {
+            uniqr_dedup_file(
+                path(&input).as_ptr(),
+                path(&input).as_ptr(),
+                &options,
+                std::ptr::null_mut(),
+            )
+        };
+        assert_eq!(status, UniqrStatus::Ok);
+        assert_eq!(std::fs::read_to_string(&input).unwrap(), "a\nb\n");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let missing = dir.path().join("missing.txt");
+        let status = unsafe {
+            uniqr_dedup_file(
+                path(&missing).as_ptr(),
+                path(&input).as_ptr(),
+                &options,
+                std::ptr::null_mut(),
+            )
+        };
+        assert_eq!(status, UniqrStatus::Io);
+    }
+
+    #[test]
+    fn test_builder_validation() {
+        let opts = DeduplicationOptions::builder()
+            .mode(DeduplicationMode::RemoveAll)
+            .trim(TrimMode::Both)
+            .column(1)
+            .build()
+            .unwrap();
+        assert_eq!(opts.mode, DeduplicationMode::RemoveAll);
+        assert_eq!(opts.trim, TrimMode::Both);
+        assert_eq!(opts.column, Some(1));
+
+        assert!(DeduplicationOptions::builder().column(0).build().is_err());
+        // Every entry point applies the same checks as the builder
+        for options in [
+            DeduplicationOptions {
+                column: Some(0),
+                ..Default::default()
+            },
+            DeduplicationOptions {
+                compare_width: Some(0),
+                ..Default::default()
+            },
+            DeduplicationOptions {
+                record_separator: RecordSeparator::Bytes(Vec::new()),
+                ..Default::default()
+            },
+        ] {
+            let mut output = Vec::new();
+            assert!(deduplicate(Cursor::new("a\n"), &mut output, &options).is_err());
+            assert!(Deduplicator::new(options).is_err());
+        }
+        assert!(
+            DeduplicationOptions::builder()
+                .column(1)
+                .column_name("id")
+                .build()
+                .is_err()
+        );
+        assert!(
+            DeduplicationOptions::builder()
+                .mode(DeduplicationMode::KeepLast)
+                .approximate(ApproximateOptions::default())
+                .build()
+                .is_err()
+        );
+        assert_eq!(
+            DeduplicationOptions::builder()
+                .use_disk(true)
+                .build()
+                .is_ok(),
+            cfg!(feature = "disk-backed")
+        );
+    }
+
+    #[test]
+    fn test_hash_algorithms() {
+        let algorithms = [
+            HashAlgorithm::Std,
+            #[cfg(feature = "fast-hash")]
+            HashAlgorithm::AHash,
+            #[cfg(feature = "xxhash")]
+            HashAlgorithm::XxHash,
+            #[cfg(feature = "blake3")]
+            HashAlgorithm::Blake3,
+        ];
+        for hasher in algorithms {
+            let cases: [(DeduplicationMode, &[u8]); 4] = [
+                (DeduplicationMode::KeepFirst, b"a\nb\nc\n"),
+                (DeduplicationMode::KeepLast, b"b\na\nc\n"),
+                (DeduplicationMode::RemoveAll, b"b\nc\n"),
+                (DeduplicationMode::Repeated, b"a\n"),
+            ];
+            for (mode, expected) in cases {
+                let mut output = Vec::new();
+                let opts = DeduplicationOptions {
+                    mode,
+                    hasher,
+                    ..Default::default()
+                };
+                deduplicate(Cursor::new(b"a\nb\na\nc\n"), &mut output, &opts).unwrap();
+                assert_eq!(output, expected, "{:?} {:?}", hasher, mode);
+            }
+        }
+    }
+
+    #[cfg(not(feature = "blake3"))]
+    #[test]
+    fn test_hash_algorithm_requires_feature() {
+        let opts = DeduplicationOptions {
+            hasher: HashAlgorithm::Blake3,
+            ..Default::default()
+        };
+        let err = deduplicate(Cursor::new(b"a\n"), &mut Vec::new(), &opts).unwrap_err();
+        assert!(err.to_string().contains("'blake3' feature"));
+    }
+
+    #[test]
+    fn test_approximate() {
+        let input = b"a\nb\na\nc\nb\n";
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions {
+            approximate: Some(ApproximateOptions::default()),
+            ..Default::default()
+        };
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+
+        assert_eq!(output, b"a\nb\nc\n");
+        assert_eq!(stats.lines_removed, 2);
+        assert_eq!(stats.unique_lines, 3);
+
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            approximate: Some(ApproximateOptions::default()),
+            ..Default::default()
+        };
+        assert!(deduplicate(Cursor::new(input), &mut Vec::new(), &opts).is_err());
+    }
+
+    #[test]
+    fn test_window() {
+        // A repeat keeps the key in the window
+        let input = b"a\nb\nc\na\nd\ne\nf\na\nx\nx\nx\nx\n";
+        let mut output = Vec::new();
+        let opts = DeduplicationOptions {
+            window: Some(WindowMode::Lines(3)),
+            ..Default::default()
+        };
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(output, b"a\nb\nc\nd\ne\nf\na\nx\n");
+        assert_eq!(stats.lines_removed, 4);
+        assert_eq!(stats.unique_lines, 8);
+
+        let mut dedup = Deduplicator::new(opts.clone()).unwrap();
+        let decisions: Vec<_> = [&b"a"[..], b"b", b"a", b"c", b"d", b"e", b"a"]
+            .iter()
+            .map(|line| dedup.push_line(line).unwrap())
+            .collect();
+        assert_eq!(
+            decisions,
+            [
+                Decision::Keep,
+                Decision::Keep,
+                Decision::Remove,
+                Decision::Keep,
+                Decision::Keep,
+                Decision::Keep,
+                Decision::Keep
+            ]
+        );
+
+        // Seeing `a` again keeps it over `b`, the least recently seen key
+        let mut output = Vec::new();
+        let keys = DeduplicationOptions {
+            window: Some(WindowMode::Keys(2)),
+            ..Default::default()
+        };
+        deduplicate(Cursor::new(b"a\nb\na\nc\na\nb\n"), &mut output, &keys).unwrap();
+        assert_eq!(output, b"a\nb\nc\nb\n");
+
+        for opts in [
+            DeduplicationOptions {
+                window: Some(WindowMode::Lines(0)),
+                ..Default::default()
+            },
+            DeduplicationOptions {
+                window: Some(WindowMode::Keys(0)),
+                ..Default::default()
+            },
+            DeduplicationOptions {
+                count: true,
+                ..opts.clone()
+            },
+            DeduplicationOptions {
+                mode: DeduplicationMode::KeepLast,
+                ..opts
+            },
+        ] {
+            assert!(deduplicate(Cursor::new(input), &mut Vec::new(), &opts).is_err());
+        }
+    }
+
+    #[cfg(feature = "timestamps")]
+    #[test]
+    fn test_time_window() {
+        let input = b"2024-01-01T10:00:00Z disk full\n\
+            2024-01-01T10:04:00Z disk full\n\
+            2024-01-01T12:03:00+02:00 disk full\n\
+            no timestamp\n\
+            no timestamp\n\
+            2024-01-01T10:05:30Z disk full\n\
+            2024-01-01T10:06:00Z cpu hot\n";
+        let opts = DeduplicationOptions {
+            window: Some(WindowMode::Time {
+                within: std::time::Duration::from_secs(300),
+                format: "%Y-%m-%dT%H:%M:%S%#z".to_string(),
+            }),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let stats = deduplicate(Cursor::new(&input[..]), &mut output, &opts).unwrap();
+        // 12:03 at +02:00 is 10:03 UTC; 10:05:30 is more than 5 minutes
+        // after the kept 10:00 line
+        assert_eq!(
+            output,
+            b"2024-01-01T10:00:00Z disk full\n\
+            no timestamp\n\
+            no timestamp\n\
+            2024-01-01T10:05:30Z disk full\n\
+            2024-01-01T10:06:00Z cpu hot\n"
+        );
+        assert_eq!(stats.lines_removed, 2);
+
+        // Syslog timestamps have no year
+        let mut dedup = Deduplicator::new(DeduplicationOptions {
+            window: Some(WindowMode::Time {
+                within: std::time::Duration::from_secs(60),
+                format: "%b %d %H:%M:%S".to_string(),
+            }),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            dedup.push_line(b"Feb 29 23:59:30 a").unwrap(),
+            Decision::Keep
+        );
+        assert_eq!(
+            dedup.push_line(b"Mar 01 00:00:10 a").unwrap(),
+            Decision::Remove
+        );
+        assert_eq!(
+            dedup.push_line(b"Mar 01 00:01:10 a").unwrap(),
+            Decision::Keep
+        );
+
+        let opts = DeduplicationOptions {
+            window: Some(WindowMode::Time {
+                within: std::time::Duration::from_secs(60),
+                format: "%Q".to_string(),
+            }),
+            ..Default::default()
+        };
+        assert!(deduplicate(Cursor::new(&input[..]), &mut Vec::new(), &opts).is_err());
+    }
+
+    #[test]
+    fn test_fuzzy() {
+        let cases: [(&[u8], &[u8], usize); 6] = [
+            (b"", b"abc", 3),
+            (b"kitten", b"sitting", 3),
+            (b"flaw", b"lawn", 2),
+            (b"id=1234 ok", b"id=1299 ok", 2),
+            (b"same", b"same", 0),
+            (b"abc", b"", 3),
+        ];
+        for (a, b, expected) in cases {
+            assert_eq!(fuzzy::levenshtein(a, b), expected);
+            assert_eq!(fuzzy::levenshtein(b, a), expected);
+        }
+
+        let input =
+            b"GET /a id=1234\nGET /a id=1299\nPOST /b id=1\nGET /a id=9999\nGET /a id=1230\n";
+        let opts = DeduplicationOptions {
+            fuzzy: Some(FuzzyOptions::default()),
+            count: true,
+            top_duplicates: Some(1),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+        assert_eq!(
+            output,
+            b"      3 GET /a id=1234\n      1 POST /b id=1\n      1 GET /a id=9999\n"
+        );
+        assert_eq!(stats.unique_lines, 3);
+        assert_eq!(stats.top_duplicates, [(b"GET /a id=1234".to_vec(), 3)]);
+
+        // The writer (and so the async path) agrees
+        let opts = DeduplicationOptions {
+            count: false,
+            ..opts
+        };
+        let mut writer = DeduplicatingWriter::new(Vec::new(), opts.clone()).unwrap();
+        writer.write_all(input).unwrap();
+        let (written, _) = writer.finish_with_stats().unwrap();
+        assert_eq!(written, b"GET /a id=1234\nPOST /b id=1\nGET /a id=9999\n");
+
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::RemoveAll,
+            ..opts
+        };
+        assert!(deduplicate(Cursor::new(input), &mut Vec::new(), &opts).is_err());
+    }
+
+    #[test]
+    fn test_similar() {
+        let jumps = "the quick brown fox jumps over the lazy dog near the river bank";
+        let jumped = "the quick brown fox jumped over the lazy dog near the river bank";
+        let stocks = "stock prices fell sharply on monday after the central bank announcement";
+        let input = format!("{jumps}\n{stocks}\n{jumped}\n{stocks}!\n");
+
+        for (signature, threshold) in [
+            (SignatureKind::MinHash, 0.6),
+            (SignatureKind::SimHash, 0.75),
+        ] {
+            let similarity = SimilarityOptions {
+                signature,
+                shingle_size: 2,
+            };
+            let estimate = similarity::similarity(jumps.as_bytes(), jumped.as_bytes(), &similarity);
+            assert!(estimate >= threshold, "{:?}: {}", signature, estimate);
+            assert!(
+                similarity::similarity(jumps.as_bytes(), stocks.as_bytes(), &similarity)
+                    < threshold
+            );
+
+            let opts = DeduplicationOptions {
+                mode: DeduplicationMode::Similar {
+                    threshold: SimilarityThreshold::new(threshold).unwrap(),
+                },
+                similarity,
+                count: true,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            let stats = deduplicate(Cursor::new(&input), &mut output, &opts).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                format!("      2 {jumps}\n      2 {stocks}\n"),
+                "{:?}",
+                signature
+            );
+            assert_eq!(stats.unique_lines, 2);
+            assert_eq!(stats.lines_removed, 2);
+        }
+
+        // Exact repeats are always similar, even at a threshold of 1
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::Similar {
+                threshold: SimilarityThreshold::new(1.0).unwrap(),
+            },
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        deduplicate(
+            Cursor::new(format!("{jumps}\n{jumped}\n{jumps}\n")),
+            &mut output,
+            &opts,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("{jumps}\n{jumped}\n")
+        );
+
+        assert!(SimilarityThreshold::new(1.5).is_err());
+        assert!(SimilarityThreshold::new(0.0).is_err());
+        let threshold = SimilarityThreshold::new(0.8).unwrap();
+        for opts in [
+            DeduplicationOptions {
+                mode: DeduplicationMode::Similar { threshold },
+                adjacent: true,
+                ..Default::default()
+            },
+            DeduplicationOptions {
+                mode: DeduplicationMode::Similar { threshold },
+                similarity: SimilarityOptions {
+                    shingle_size: 0,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        ] {
+            assert!(deduplicate(Cursor::new(input.as_bytes()), &mut Vec::new(), &opts).is_err());
+        }
+    }
+
+    #[test]
+    fn test_bloom_filter_false_positive_rate() {
+        let mut filter = bloom::BloomFilter::new(10_000, 0.01);
+        // Every key is distinct, so each "already present" is a false positive
+        let false_positives = (0..10_000)
+            .filter(|i| filter.check_and_insert(format!("key-{}", i).as_bytes()))
+            .count();
+        assert!(false_positives < 100, "{} false positives", false_positives);
+
+        // No false negatives
+        for i in 0..10_000 {
+            assert!(filter.check_and_insert(format!("key-{}", i).as_bytes()));
+        }
+    }
+
+    #[cfg(feature = "disk-backed")]
+    #[test]
+    fn test_disk_backed_keep_first() {
+        use std::io::Cursor;
+
+        let input = b"a\nb\na\nc\n";
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions {
+            use_disk: true,
+            ..Default::default()
+        };
+        let stats = deduplicate(Cursor::new(input), &mut output, &opts).unwrap();
+
+        assert_eq!(output, b"a\nb\nc\n");
+        assert_eq!(stats.lines_written, 3);
+        assert_eq!(stats.unique_lines, 3);
+    }
+
+    #[cfg(feature = "disk-backed")]
+    #[test]
+    fn test_disk_backed_keep_last() {
+        use std::io::Cursor;
+
+        let input = b"a\nb\na\nc\n";
+        let mut cursor = Cursor::new(input);
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            use_disk: true,
+            ..Default::default()
+        };
+        let stats = deduplicate_seekable(&mut cursor, &mut output, &opts).unwrap();
+
+        assert_eq!(output, b"b\na\nc\n");
+        assert_eq!(stats.lines_written, 3);
+    }
+
+    #[cfg(feature = "disk-backed")]
+    #[test]
+    fn test_disk_backed_remove_all() {
+        use std::io::Cursor;
+
+        let input = b"a\nb\na\nc\n";
+        let mut cursor = Cursor::new(input);
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::RemoveAll,
+            use_disk: true,
+            ..Default::default()
+        };
+        let stats = deduplicate_seekable(&mut cursor, &mut output, &opts).unwrap();
+
+        assert_eq!(output, b"b\nc\n");
+        assert_eq!(stats.unique_lines, 2);
+    }
+
+    #[cfg(feature = "disk-backed")]
+    #[test]
+    fn test_disk_backed_many_seekable() {
+        let inputs = [Cursor::new(&b"a\nb\n"[..]), Cursor::new(&b"a\nc\n"[..])];
+        let mut output = Vec::new();
+
+        let opts = DeduplicationOptions {
+            mode: DeduplicationMode::KeepLast,
+            use_disk: true,
+            ..Default::default()
+        };
+        deduplicate_many_seekable(inputs, &mut output, &opts).unwrap();
+
+        assert_eq!(output, b"b\na\nc\n");
+    }
+
+    #[cfg(feature = "disk-backed")]
+    #[test]
+    fn test_disk_backed_spools_streams() {
+        // &[u8] can't seek, so the two-pass modes spool it to a temp file
+        let inputs: [&[u8]; 2] = [b"a\nb\n", b"a\nc\n"];
+        let cases: [(DeduplicationMode, &[u8]); 2] = [
+            (DeduplicationMode::KeepLast, b"b\na\nc\n"),
+            (DeduplicationMode::RemoveAll, b"b\nc\n"),
+        ];
+        for (mode, expected) in cases {
+            let opts = DeduplicationOptions {
+                mode,
+                use_disk: true,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            let stats = deduplicate_many(inputs, &mut output, &opts).unwrap();
+            assert_eq!(output, expected, "{:?}", mode);
+            assert_eq!(stats.lines_read, 4);
+            assert_eq!(stats.bytes_read, 8);
+        }
+    }
+}
@@ -1,8 +1,18 @@
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use std::ffi::OsString;
 use std::fs::File;
-use std::io::{self, BufWriter, Write};
-use std::path::PathBuf;
-use uniqr::{DeduplicationMode, DeduplicationOptions, Error, deduplicate};
+use std::io::{self, BufWriter, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uniqr::{
+    CaseMode, CompressedReader, CompressedWriter, Compression, CountFormat, CountPlacement,
+    Decision, DecodingReader, DeduplicationMode, DeduplicationOptions, DeduplicationStats,
+    EmptyLinePolicy, EncodingWriter, Error, FollowReader, HashAlgorithm, KeyCounts, KeyType,
+    LineEvent, NonUtf8Case, PunctuationMode, RecordLocation, RecordSeparator, RemovedRecord,
+    TextEncoding, TrimMode, Utf8Policy, WindowMode, deduplicate,
+    setops::{SetOperation, merge_sorted, set_operation},
+};
 
 /// Deduplication mode arguments (mutually exclusive)
 #[derive(clap::Args, Debug, Default, Clone, Copy)]
@@ -13,8 +23,200 @@ struct ModeArgs {
     keep_last: bool,
 
     /// Remove all lines that appear more than once (two-pass)
-    #[arg(long)]
+    #[arg(short = 'u', long, visible_alias = "unique")]
     remove_all: bool,
+
+    /// Only print lines that appear more than once, one per key (two-pass)
+    #[arg(short = 'd', long)]
+    repeated: bool,
+
+    /// Print every occurrence of lines that appear more than once (two-pass)
+    #[arg(short = 'D', long)]
+    all_repeated: bool,
+
+    /// Treat lines at least THRESHOLD similar (0 to 1, by shared word
+    /// shingles) to an earlier kept line as its duplicates
+    #[arg(long, value_name = "THRESHOLD")]
+    similar: Option<f64>,
+}
+
+/// Hash function for `--hash`
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+enum HashArg {
+    Std,
+    Ahash,
+    Xxhash,
+    Blake3,
+}
+
+/// Key store for `--disk-backend`
+#[cfg(feature = "disk-backed")]
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default)]
+enum DiskBackendArg {
+    /// sled embedded database
+    #[default]
+    Sled,
+    /// Temporary file indexed in memory by key hash (faster, more RAM)
+    TempFile,
+}
+
+/// Signature scheme for `--signature`
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default)]
+enum SignatureArg {
+    /// Estimate the share of shingles in common
+    #[default]
+    Minhash,
+    /// Compare 64-bit fingerprints (faster; for thresholds of 0.9 and up)
+    Simhash,
+}
+
+/// Output format for `--stats`
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default)]
+enum StatsFormat {
+    /// Human-readable summary
+    #[default]
+    Text,
+    /// A single JSON object
+    Json,
+}
+
+/// Output format for `--audit-log`
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default)]
+enum AuditFormat {
+    /// Tab-separated columns under a header line
+    #[default]
+    Tsv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+/// Which ends of a line `--trim` strips
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+enum TrimArg {
+    Leading,
+    Trailing,
+    Both,
+}
+
+/// Where `--group` puts the blank lines between groups
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+enum GroupArg {
+    Separate,
+    Prepend,
+    Append,
+    Both,
+}
+
+/// What `--append` adds to the output file
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum AppendArg {
+    /// Every line written, as without --append
+    All,
+    /// Only lines whose key isn't in the output file already
+    New,
+}
+
+/// Key order for `--sort`
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+enum SortArg {
+    Text,
+    Numeric,
+}
+
+/// Order of the `uniqr count` table
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default)]
+enum TableOrderArg {
+    /// Most frequent first, ties by key
+    #[default]
+    Count,
+    /// By key
+    Key,
+}
+
+/// Direction for `--sort-by-count`
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+enum CountOrderArg {
+    Asc,
+    Desc,
+}
+
+/// Handling of lines over `--max-line-length`
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+enum LongLinesArg {
+    Error,
+    Truncate,
+    PassThrough,
+}
+
+/// Unicode normalization form for `--normalize`
+#[cfg(feature = "unicode")]
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+enum NormalizeArg {
+    Nfc,
+    Nfd,
+    Nfkc,
+    Nfkd,
+}
+
+/// Kind of line `--preset` normalizes
+#[cfg(any(feature = "regex", feature = "url"))]
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+enum PresetArg {
+    #[cfg(feature = "regex")]
+    Syslog,
+    #[cfg(feature = "regex")]
+    Nginx,
+    #[cfg(feature = "regex")]
+    Apache,
+    #[cfg(feature = "regex")]
+    Journald,
+    /// Canonical URLs, without fragments or tracking parameters
+    #[cfg(feature = "url")]
+    Url,
+}
+
+/// How `--ignore-case` handles lines that are not valid UTF-8
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default)]
+enum NonUtf8CaseArg {
+    /// Fold ASCII letters only
+    #[default]
+    Ascii,
+    /// Fail with an error
+    Error,
+}
+
+/// How lines that are not valid UTF-8 are handled
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default)]
+enum Utf8Arg {
+    /// Deduplicate them, decoding invalid bytes as U+FFFD where needed
+    #[default]
+    Lossy,
+    /// Fail with an error
+    Strict,
+    /// Write them through without deduplicating them
+    Skip,
+}
+
+/// How empty lines are handled
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default)]
+enum EmptyLinesArg {
+    /// Deduplicate them like any other line
+    #[default]
+    Dedup,
+    /// Write every one of them through
+    Preserve,
+    /// Remove all of them
+    Drop,
+}
+
+/// When `--color` colors the output
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default)]
+enum ColorArg {
+    /// When stdout is a terminal and NO_COLOR isn't set
+    #[default]
+    Auto,
+    Always,
+    Never,
 }
 
 /// A fast line deduplication tool that preserves order
@@ -22,23 +224,100 @@ struct ModeArgs {
 #[command(name = "uniqr")]
 #[command(version = "0.1.0")]
 #[command(about = "Remove duplicate lines while preserving order", long_about = None)]
+// Defaults from the config file and UNIQR_OPTS come first, to be overridden
+#[command(args_override_self = true)]
 struct Cli {
-    /// Input file (uses stdin if not provided)
+    /// Input files or glob patterns, deduplicated together in order (uses stdin if not provided)
     #[arg(value_name = "FILE")]
-    input: Option<PathBuf>,
+    input: Vec<PathBuf>,
+
+    /// Behave like GNU uniq: only compare adjacent lines, and take at most
+    /// one INPUT and an optional OUTPUT ('-' for stdin/stdout)
+    #[arg(long, conflicts_with_all = ["recursive", "in_place"])]
+    compat_uniq: bool,
+
+    /// Only treat a line as a duplicate of the line right before it, like uniq
+    #[arg(long)]
+    adjacent: bool,
+
+    /// Read all files under directory inputs, recursively
+    #[arg(short = 'r', long)]
+    recursive: bool,
+
+    /// With --recursive, only read files whose name matches this glob (repeatable)
+    #[arg(long, value_name = "GLOB", requires = "recursive")]
+    include: Vec<String>,
+
+    /// Rewrite each input file in place, keeping a backup with SUFFIX if given (e.g. --in-place=.bak)
+    #[arg(
+        long,
+        value_name = "SUFFIX",
+        num_args = 0..=1,
+        require_equals = true,
+        conflicts_with_all = ["output", "annotate_source"]
+    )]
+    in_place: Option<Option<String>>,
 
     /// Output file (uses stdout if not provided)
     #[arg(short, long, value_name = "FILE")]
     output: Option<PathBuf>,
 
+    /// Also write the output to FILE (atomically, like --output), while
+    /// still writing it to stdout
+    #[arg(long, value_name = "FILE", conflicts_with_all = [
+        "output", "in_place", "dry_run", "check", "follow"
+    ])]
+    tee: Option<PathBuf>,
+
+    /// Append to the --output file instead of replacing it (new to also
+    /// drop lines whose key is in the file already)
+    #[arg(long, value_name = "WHAT", num_args = 0..=1, require_equals = true,
+        default_missing_value = "all", requires = "output", conflicts_with = "in_place")]
+    append: Option<AppendArg>,
+
     /// Prefix lines with occurrence count
     #[arg(short, long)]
     count: bool,
 
+    /// Layout for --count: '{count}SEP{line}' or '{line}SEP{count}', where
+    /// '{count:N}' right-aligns in N columns (default '{count:7} {line}')
+    #[arg(long, value_name = "TEMPLATE", requires = "count")]
+    count_format: Option<String>,
+
     /// Ignore case when comparing lines
     #[arg(short = 'i', long)]
     ignore_case: bool,
 
+    /// Ignore case using full Unicode case folding (requires 'unicode' feature)
+    #[cfg(feature = "unicode")]
+    #[arg(long)]
+    case_fold: bool,
+
+    /// Case handling for non-UTF-8 lines with --ignore-case
+    #[arg(long, value_name = "POLICY", default_value = "ascii")]
+    non_utf8_case: NonUtf8CaseArg,
+
+    /// Handling of lines that are not valid UTF-8
+    #[arg(long, value_name = "POLICY", default_value = "lossy")]
+    utf8: Utf8Arg,
+
+    /// Handling of empty (or whitespace-only) lines
+    #[arg(long, value_name = "POLICY", default_value = "dedup")]
+    empty_lines: EmptyLinesArg,
+
+    /// Write lines matching PATTERN through without deduplicating them, such
+    /// as the comments and blank lines of a config file (requires 'regex'
+    /// feature)
+    #[cfg(feature = "regex")]
+    #[arg(long, value_name = "PATTERN")]
+    passthrough_regex: Option<String>,
+
+    /// Only deduplicate lines matching PATTERN, writing the others through
+    /// (requires 'regex' feature)
+    #[cfg(feature = "regex")]
+    #[arg(long, value_name = "PATTERN", conflicts_with = "passthrough_regex")]
+    only_match: Option<String>,
+
     /// Deduplication mode
     #[command(flatten)]
     mode: ModeArgs,
@@ -47,177 +326,2785 @@ struct Cli {
     #[arg(long)]
     show_removed: bool,
 
+    /// Color counts and [REMOVED] markers on stdout
+    #[arg(long, value_name = "WHEN", default_value = "auto")]
+    color: ColorArg,
+
+    /// Write removed duplicate lines to FILE instead of discarding them
+    #[arg(
+        long,
+        value_name = "FILE",
+        visible_alias = "duplicates-output",
+        conflicts_with = "show_removed"
+    )]
+    removed_output: Option<PathBuf>,
+
+    /// Print every line, with the lines of each key together and groups
+    /// delimited by blank lines, like `uniq --group`
+    #[arg(long, value_name = "METHOD", num_args = 0..=1, require_equals = true,
+        default_missing_value = "separate", conflicts_with_all = [
+            "keep_last", "remove_all", "repeated", "all_repeated", "similar", "count",
+            "fuzzy", "approximate", "report_removed"
+        ])]
+    group: Option<GroupArg>,
+
+    /// Sort the output by key, like `sort -u` (numeric to compare keys as
+    /// numbers); large outputs are sorted in temporary files with 'disk-backed'
+    #[arg(long, value_name = "ORDER", num_args = 0..=1, require_equals = true,
+        default_missing_value = "text", conflicts_with_all = ["group", "follow"])]
+    sort: Option<SortArg>,
+
+    /// Count occurrences and order the output by count, most frequent first
+    /// (asc for least frequent), like 'sort | uniq -c | sort -rn'
+    #[arg(long, value_name = "ORDER", num_args = 0..=1, require_equals = true,
+        default_missing_value = "desc", conflicts_with_all = [
+            "sort", "group", "follow", "show_removed", "only_removed", "approximate"
+        ])]
+    sort_by_count: Option<CountOrderArg>,
+
+    /// Output only the lines that would be removed, unprefixed, instead of
+    /// the lines that are kept
+    #[arg(long, conflicts_with_all = [
+        "show_removed", "removed_output", "in_place", "count", "check", "follow"
+    ])]
+    only_removed: bool,
+
+    /// Prefix each output line with its original 1-based line number
+    /// (counted across all inputs, as if they were concatenated)
+    #[arg(short = 'n', long)]
+    line_numbers: bool,
+
+    /// Output the key each kept line is compared by (after the column, CSV,
+    /// JSON and normalization options) instead of the line
+    #[arg(long, conflicts_with_all = ["in_place", "only_removed"])]
+    print_key: bool,
+
+    /// Prefix each output line with the file it came from
+    #[arg(long)]
+    annotate_source: bool,
+
+    /// With --annotate-source, also include the line number within the file
+    #[arg(long, requires = "annotate_source")]
+    source_line_numbers: bool,
+
     /// Show deduplication statistics
     #[arg(long)]
     stats: bool,
 
+    /// Format for --stats (implies --stats)
+    #[arg(long, value_name = "FORMAT")]
+    stats_format: Option<StatsFormat>,
+
+    /// Write statistics to FILE instead of stderr (implies --stats)
+    #[arg(long, value_name = "FILE")]
+    stats_output: Option<PathBuf>,
+
+    /// Include how many lines occur once, twice, 3-10 times, 11-100 times
+    /// and so on in the statistics (implies --stats)
+    #[arg(long)]
+    histogram: bool,
+
+    /// After processing, report the N most repeated keys with their counts
+    #[arg(long, value_name = "N")]
+    top_duplicates: Option<usize>,
+
+    /// Write the --top-duplicates report to FILE instead of stderr
+    #[arg(long, value_name = "FILE", requires = "top_duplicates")]
+    top_duplicates_output: Option<PathBuf>,
+
+    /// Write every key and its occurrence count, tab-separated, to FILE
+    #[arg(long, value_name = "FILE", conflicts_with = "approximate")]
+    counts_output: Option<PathBuf>,
+
+    /// Instead of the kept lines, write every key and its occurrence count,
+    /// tab-separated and most frequent first
+    #[arg(long, conflicts_with_all = [
+        "output", "in_place", "tee", "removed_output", "check", "follow", "approximate"
+    ])]
+    summary: bool,
+
+    /// List only the N most frequent keys in the --summary
+    #[arg(long, value_name = "N", requires = "summary")]
+    top: Option<usize>,
+
+    /// Show a progress bar with bytes processed and ETA on stderr
+    #[cfg(feature = "progress")]
+    #[arg(long)]
+    progress: bool,
+
+    /// Log what the run does (algorithm, spills, passes) to stderr; repeat
+    /// for more detail. RUST_LOG overrides the level
+    #[arg(short = 'v', long, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Log only errors, not even warnings
+    #[arg(short = 'q', long)]
+    quiet: bool,
+
+    /// Don't point out that input is being read from the terminal when stdin
+    /// is one and no FILE is given
+    #[arg(long)]
+    no_hint: bool,
+
+    /// Ignore the defaults in the config file and UNIQR_OPTS
+    #[arg(long)]
+    no_config: bool,
+
     /// Preview changes without writing output
     #[arg(long)]
     dry_run: bool,
 
+    /// Report each removed line and the line it duplicates on stderr
+    /// (e.g. "line 532 duplicates line 17"); useful with --dry-run
+    #[arg(long, conflicts_with = "approximate")]
+    report_removed: bool,
+
+    /// Write a record of every input line to FILE: its line number, whether
+    /// it was kept or removed, how often its key had occurred and a hash of
+    /// the key. Only for streaming keep-first runs
+    #[arg(long, value_name = "FILE", conflicts_with_all = [
+        "in_place", "removed_output", "only_removed", "follow"
+    ])]
+    audit_log: Option<PathBuf>,
+
+    /// Format for --audit-log
+    #[arg(long, value_name = "FORMAT", requires = "audit_log")]
+    audit_format: Option<AuditFormat>,
+
+    /// Keep reading the input file as it grows, like `tail -f`, writing each
+    /// new unique line as soon as it arrives (stop with Ctrl-C)
+    #[arg(long, conflicts_with_all = [
+        "in_place", "output", "removed_output", "dry_run", "check", "keep_last", "remove_all",
+        "repeated", "all_repeated", "count"
+    ])]
+    follow: bool,
+
+    /// Write at most N lines a second to stdout, each flushed as it is
+    /// written, so that what reads the output gets no bursts
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with_all = ["in_place", "output", "dry_run", "check", "summary"])]
+    rate_limit: Option<u32>,
+
+    /// Write nothing; exit with status 1 if any duplicates are found, 0 otherwise
+    #[arg(long, conflicts_with_all = ["output", "in_place", "removed_output"])]
+    check: bool,
+
     /// Deduplicate by specific column (1-indexed, whitespace-separated)
     #[arg(long, value_name = "N")]
     column: Option<usize>,
 
+    /// Deduplicate by the column with this name in the header line (header is kept)
+    #[arg(long, value_name = "NAME", conflicts_with = "column")]
+    column_name: Option<String>,
+
+    /// Copy the first N lines (of the first input; skipped in the others) to
+    /// the output as they are, without deduplicating or counting them
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    header: usize,
+
+    /// Copy the last N lines (of the last input) to the end of the output as
+    /// they are, without deduplicating or counting them
+    #[arg(long, value_name = "N", default_value_t = 0, conflicts_with = "follow")]
+    footer: usize,
+
+    /// Line delimiter is NUL, not newline
+    #[arg(short = 'z', long, conflicts_with_all = ["separator", "paragraph"])]
+    zero_terminated: bool,
+
+    /// Custom record separator (supports \n, \t, \r, \0 and \\ escapes)
+    #[arg(long, value_name = "SEP", conflicts_with = "paragraph")]
+    separator: Option<String>,
+
+    /// Treat blank-line-separated paragraphs as records
+    #[arg(long)]
+    paragraph: bool,
+
+    /// Longest line to read, in bytes, so a huge line (e.g. a binary file
+    /// piped in by mistake) can't exhaust memory
+    #[arg(long, value_name = "BYTES")]
+    max_line_length: Option<usize>,
+
+    /// What to do with lines over --max-line-length: fail, truncate them,
+    /// or write them through without deduplicating them
+    #[arg(
+        long,
+        value_name = "POLICY",
+        default_value = "error",
+        requires = "max_line_length"
+    )]
+    long_lines: LongLinesArg,
+
+    /// End the output with a newline (or record separator) even if the
+    /// input's last line has none; by default the output ends like the input
+    #[arg(long)]
+    ensure_trailing_newline: bool,
+
+    /// Write the input's byte order mark at the start of the output; by
+    /// default it is dropped (it is never part of a line's key)
+    #[arg(long)]
+    keep_bom: bool,
+
+    /// Parse input as CSV, respecting quoted fields (requires 'csv' feature)
+    #[cfg(feature = "csv")]
+    #[arg(long)]
+    #[cfg_attr(feature = "json", arg(conflicts_with = "json"))]
+    csv: bool,
+
+    /// CSV column to deduplicate on, by header name or 1-based index
+    #[cfg(feature = "csv")]
+    #[arg(long, value_name = "COL", requires = "csv")]
+    key_column: Option<String>,
+
+    /// CSV field delimiter
+    #[cfg(feature = "csv")]
+    #[arg(long, value_name = "CHAR", default_value = ",", requires = "csv")]
+    csv_delimiter: char,
+
+    /// CSV input has no header row
+    #[cfg(feature = "csv")]
+    #[arg(long, requires = "csv")]
+    no_header: bool,
+
+    /// Parse each line as JSON (requires 'json' feature)
+    #[cfg(feature = "json")]
+    #[arg(long)]
+    json: bool,
+
+    /// JSON field to deduplicate on, as a path (.user.id) or JSON pointer (/user/id)
+    #[cfg(feature = "json")]
+    #[arg(long = "key", value_name = "PATH", requires = "json")]
+    json_key: Option<String>,
+
+    /// Compare lines (or columns) by numeric value, so 1, 01, and 1.0 match
+    #[arg(long)]
+    numeric: bool,
+
+    /// Compare lines (or columns) by numeric value rounded to N decimal places,
+    /// so with 3 places 3.14159 and 3.1416 match; implies --numeric
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(..=100))]
+    numeric_precision: Option<u32>,
+
+    /// Skip the first N blank-separated fields of each line when comparing
+    #[arg(short = 'f', long, value_name = "N", default_value_t = 0)]
+    skip_fields: usize,
+
+    /// Skip the first N characters (after skipped fields) when comparing
+    #[arg(short = 's', long, value_name = "N", default_value_t = 0)]
+    skip_chars: usize,
+
+    /// Compare no more than N characters of each line (or column)
+    #[arg(short = 'w', long = "check-chars", value_name = "N")]
+    compare_width: Option<usize>,
+
+    /// Ignore leading/trailing whitespace when comparing lines
+    #[arg(
+        long,
+        value_name = "WHERE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "both"
+    )]
+    trim: Option<TrimArg>,
+
+    /// Trim and collapse runs of inner whitespace when comparing lines
+    #[arg(long)]
+    collapse_spaces: bool,
+
+    /// Ignore trailing spaces and tabs when comparing lines, but not
+    /// indentation (unlike --trim)
+    #[arg(long, conflicts_with_all = ["trim", "collapse_spaces"])]
+    ignore_trailing_space: bool,
+
+    /// Ignore punctuation and symbols such as periods and quotes when comparing lines
+    #[arg(long)]
+    ignore_punct: bool,
+
+    /// Compare only the letters and digits of each line
+    #[arg(long, conflicts_with = "ignore_punct")]
+    alnum_only: bool,
+
+    /// Apply Unicode normalization before comparing lines (requires 'unicode' feature)
+    #[cfg(feature = "unicode")]
+    #[arg(long, value_name = "FORM")]
+    normalize: Option<NormalizeArg>,
+
+    /// Ignore accents and other combining marks when comparing lines, so
+    /// "résumé" and "resume" match (requires 'unicode' feature)
+    #[cfg(feature = "unicode")]
+    #[arg(long)]
+    ignore_diacritics: bool,
+
+    /// Strip the timestamps, PIDs and request IDs of a common log format before
+    /// comparing lines (requires 'regex' feature), or canonicalize URLs (requires
+    /// 'url' feature)
+    #[cfg(any(feature = "regex", feature = "url"))]
+    #[arg(long, value_name = "FORMAT")]
+    preset: Option<PresetArg>,
+
+    /// Track seen lines in a fixed-size Bloom filter (constant memory, keep-first only);
+    /// a small fraction of unique lines may be dropped
+    #[arg(long, conflicts_with_all = [
+        "keep_last", "remove_all", "repeated", "all_repeated", "adjacent", "compat_uniq",
+        "count", "top_duplicates"
+    ])]
+    approximate: bool,
+
+    /// Chance of dropping a unique line in --approximate mode
+    #[arg(
+        long,
+        value_name = "RATE",
+        default_value_t = 0.001,
+        requires = "approximate"
+    )]
+    false_positive_rate: f64,
+
+    /// Number of distinct lines the --approximate filter is sized for
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 10_000_000,
+        requires = "approximate"
+    )]
+    expected_lines: usize,
+
+    /// Treat lines within --max-distance edits of an earlier kept line as its
+    /// duplicates (keep-first only; much slower than exact matching)
+    #[arg(long, conflicts_with_all = [
+        "keep_last", "remove_all", "repeated", "all_repeated", "adjacent", "compat_uniq",
+        "approximate", "report_removed"
+    ])]
+    fuzzy: bool,
+
+    /// Most byte insertions, deletions and substitutions between --fuzzy duplicates
+    #[arg(long, value_name = "N", default_value_t = 2, requires = "fuzzy")]
+    max_distance: usize,
+
+    /// Only treat a line as a duplicate if it occurred in the N lines before
+    /// it, forgetting older lines (keep-first only; memory bounded by N)
+    #[arg(long, value_name = "N", conflicts_with_all = [
+        "keep_last", "remove_all", "repeated", "all_repeated", "adjacent", "compat_uniq",
+        "approximate", "fuzzy", "count", "report_removed"
+    ])]
+    window: Option<usize>,
+
+    /// Remember at most N keys, forgetting the least recently seen first, so
+    /// memory stays bounded on endless streams (keep-first only; a line whose
+    /// key was forgotten is kept again)
+    #[arg(long, value_name = "N", conflicts_with_all = [
+        "window", "keep_last", "remove_all", "repeated", "all_repeated", "adjacent",
+        "compat_uniq", "approximate", "fuzzy", "count", "report_removed"
+    ])]
+    max_keys: Option<usize>,
+
+    /// Only treat a line as a duplicate of a kept line at most DURATION
+    /// apart (e.g. 90s, 5m, 2h), by the timestamps lines start with
+    #[arg(long, value_name = "DURATION", requires = "timestamp_format", conflicts_with_all = [
+        "window", "max_keys", "keep_last", "remove_all", "repeated", "all_repeated", "adjacent",
+        "compat_uniq", "approximate", "fuzzy", "count", "report_removed"
+    ])]
+    within: Option<String>,
+
+    /// strftime-style format of the timestamp lines start with for --within,
+    /// e.g. '%Y-%m-%d %H:%M:%S' (requires 'timestamps' feature)
+    #[arg(long, value_name = "FORMAT", requires = "within")]
+    timestamp_format: Option<String>,
+
+    /// Number of consecutive words in each --similar shingle
+    #[arg(long, value_name = "N", default_value_t = 2, requires = "similar")]
+    shingle_size: usize,
+
+    /// How --similar summarizes lines for comparison
+    #[arg(
+        long,
+        value_name = "SCHEME",
+        default_value = "minhash",
+        requires = "similar"
+    )]
+    signature: SignatureArg,
+
+    /// Also drop every line whose key (built by the same key options) occurs
+    /// in FILE, like `grep -vxF -f FILE`
+    #[arg(long, value_name = "FILE")]
+    exclude_from: Option<PathBuf>,
+
+    /// Hash function for tracking seen lines: std is DoS-resistant, ahash/xxhash are
+    /// fastest, blake3 is cryptographic (ahash by default when built with 'fast-hash')
+    #[arg(long, value_name = "ALGORITHM")]
+    hash: Option<HashArg>,
+
+    /// Decompress all inputs, including stdin, as gzip (.gz files are detected automatically)
+    #[cfg(feature = "gzip")]
+    #[arg(long)]
+    gzip: bool,
+
+    /// Decode inputs from this encoding before deduplicating, e.g. utf-16le or
+    /// windows-1252 (default: utf-8)
+    #[cfg(feature = "encodings")]
+    #[arg(long, value_name = "ENCODING")]
+    input_encoding: Option<String>,
+
+    /// Encode output in this encoding, e.g. utf-16le or windows-1252 (default: utf-8)
+    #[cfg(feature = "encodings")]
+    #[arg(long, value_name = "ENCODING")]
+    output_encoding: Option<String>,
+
     /// Use disk-backed storage for massive files (requires 'disk-backed' feature)
     #[cfg(feature = "disk-backed")]
     #[arg(long)]
     use_disk: bool,
+
+    /// Where --use-disk keeps seen lines
+    #[cfg(feature = "disk-backed")]
+    #[arg(long, value_enum, value_name = "BACKEND", default_value_t)]
+    disk_backend: DiskBackendArg,
+
+    /// Directory for --use-disk temporary storage (default: sled's, usually
+    /// /dev/shm, or the system temp directory for the temp-file backend)
+    #[cfg(feature = "disk-backed")]
+    #[arg(long, value_name = "DIR")]
+    temp_dir: Option<PathBuf>,
+
+    /// Page cache size for the --use-disk database, in MiB (default: 1024)
+    #[cfg(feature = "disk-backed")]
+    #[arg(long, value_name = "MIB")]
+    disk_cache_mb: Option<u64>,
+
+    /// Make the --use-disk database favor a smaller footprint over write speed
+    #[cfg(feature = "disk-backed")]
+    #[arg(long)]
+    disk_low_space: bool,
+
+    /// Remember seen lines in a database at PATH, so later runs drop lines seen
+    /// by earlier ones (implies --use-disk; keep-first only)
+    #[cfg(feature = "disk-backed")]
+    #[arg(long, value_name = "PATH", conflicts_with_all = [
+        "keep_last", "remove_all", "repeated", "all_repeated", "adjacent", "compat_uniq"
+    ])]
+    state_db: Option<PathBuf>,
+
+    /// With --state-db, flush the output and record a checkpoint in the
+    /// database every N lines, so an interrupted run can continue with
+    /// --resume. --output is then written in place rather than atomically
+    #[cfg(feature = "disk-backed")]
+    #[arg(long, value_name = "N", requires = "state_db", conflicts_with_all = [
+        "in_place", "removed_output", "only_removed"
+    ])]
+    checkpoint_every: Option<usize>,
+
+    /// Continue the run interrupted after the last checkpoint in --state-db,
+    /// cutting its --output back to the checkpoint and appending to it. Give
+    /// the same inputs and options as the interrupted run
+    #[cfg(feature = "disk-backed")]
+    #[arg(long, requires_all = ["state_db", "output"], conflicts_with_all = [
+        "in_place", "removed_output", "only_removed"
+    ])]
+    resume: bool,
+
+    /// Keep seen lines in memory up to about MIB mebibytes, then move them to
+    /// disk as with --use-disk (keep-first only)
+    #[cfg(feature = "disk-backed")]
+    #[arg(long, value_name = "MIB", conflicts_with_all = [
+        "keep_last", "remove_all", "repeated", "all_repeated", "adjacent", "compat_uniq"
+    ])]
+    memory_limit_mb: Option<usize>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// The table `uniqr count` writes in place of the --summary
+    #[arg(skip)]
+    frequency_table: Option<FrequencyTable>,
+}
+
+/// What to do with the input. Without a subcommand, uniqr deduplicates the
+/// FILEs given before any subcommand, as `uniqr dedup` does
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Deduplicate FILEs (the default); also reads FILEs named like a
+    /// subcommand, as in `uniqr dedup count`
+    Dedup {
+        #[arg(value_name = "FILE")]
+        files: Vec<PathBuf>,
+    },
+    /// Write every key with its occurrence count, as `count<TAB>key`, instead
+    /// of the kept lines
+    Count {
+        #[command(flatten)]
+        table: FrequencyTable,
+        #[arg(value_name = "FILE")]
+        files: Vec<PathBuf>,
+    },
+    /// Print each distinct line of A whose key occurs in no B
+    Diff {
+        #[arg(value_name = "A")]
+        first: PathBuf,
+        #[arg(value_name = "B", required = true)]
+        others: Vec<PathBuf>,
+    },
+    /// Print each distinct line of A whose key also occurs in every B, in
+    /// the order of A (as `setops intersect`)
+    #[command(alias = "intersect")]
+    Common {
+        #[arg(value_name = "A")]
+        first: PathBuf,
+        #[arg(value_name = "B", required = true)]
+        others: Vec<PathBuf>,
+    },
+    /// Merge FILEs already sorted by key (numerically with --sort=numeric)
+    /// into one sorted, deduplicated output, without holding them in memory
+    Merge {
+        #[arg(value_name = "FILE", required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Compare whole files as sets of keys, by the key options given before
+    /// the subcommand (e.g. `uniqr -i setops intersect A B`)
+    Setops {
+        #[command(subcommand)]
+        operation: SetCommand,
+    },
+    /// Same as `setops union`
+    #[command(hide = true)]
+    Union {
+        #[arg(value_name = "FILE", required = true)]
+        files: Vec<PathBuf>,
+    },
+}
+
+/// Which keys `uniqr count` lists, and in what order
+#[derive(clap::Args, Debug, Clone)]
+struct FrequencyTable {
+    /// Order of the table
+    #[arg(long, value_enum, default_value_t)]
+    sort: TableOrderArg,
+
+    /// List only keys that occur at least N times
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    min: usize,
+
+    /// List only the N most frequent keys
+    #[arg(long, value_name = "N")]
+    top: Option<usize>,
+}
+
+impl FrequencyTable {
+    /// Write the selected `counts`, which come most frequent first, as
+    /// `count<TAB>key` lines; keys are written as raw bytes
+    fn write(&self, mut writer: impl Write, counts: &[(Vec<u8>, usize)]) -> io::Result<()> {
+        let mut rows: Vec<_> = counts
+            .iter()
+            .filter(|(_, count)| *count >= self.min)
+            .take(self.top.unwrap_or(usize::MAX))
+            .collect();
+        if let TableOrderArg::Key = self.sort {
+            rows.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        }
+        for (key, count) in rows {
+            write!(writer, "{}\t", count)?;
+            writer.write_all(key)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()
+    }
+}
+
+/// Set operations on the lines of whole files
+#[derive(clap::Subcommand, Debug)]
+enum SetCommand {
+    /// Print each distinct line of A whose key also occurs in every B
+    Intersect {
+        #[arg(value_name = "A")]
+        first: PathBuf,
+        #[arg(value_name = "B", required = true)]
+        others: Vec<PathBuf>,
+    },
+    /// Print each distinct line of all the FILEs, in order
+    Union {
+        #[arg(value_name = "FILE", required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Print each distinct line of A whose key occurs in no B
+    Diff {
+        #[arg(value_name = "A")]
+        first: PathBuf,
+        #[arg(value_name = "B", required = true)]
+        others: Vec<PathBuf>,
+    },
+}
+
+/// A subcommand that operates on whole files rather than on a stream of
+/// lines: a set operation, or a sorted merge without one
+#[derive(Debug)]
+struct FileCommand {
+    operation: Option<SetOperation>,
+    files: Vec<PathBuf>,
+}
+
+impl FileCommand {
+    fn set(operation: SetOperation, first: PathBuf, others: Vec<PathBuf>) -> Self {
+        FileCommand {
+            operation: Some(operation),
+            files: std::iter::once(first).chain(others).collect(),
+        }
+    }
+
+    /// Write the result of the operation on `files` to `output`
+    fn execute<R: Read, W: Write>(
+        &self,
+        files: Vec<R>,
+        output: &mut W,
+        options: &DeduplicationOptions,
+    ) -> Result<DeduplicationStats, Error> {
+        match self.operation {
+            Some(operation) => set_operation(operation, files, output, options),
+            None => merge_sorted(files, output, options),
+        }
+    }
+}
+
+impl From<SetCommand> for FileCommand {
+    fn from(command: SetCommand) -> Self {
+        match command {
+            SetCommand::Intersect { first, others } => {
+                FileCommand::set(SetOperation::Intersection, first, others)
+            }
+            SetCommand::Union { files } => FileCommand {
+                operation: Some(SetOperation::Union),
+                files,
+            },
+            SetCommand::Diff { first, others } => {
+                FileCommand::set(SetOperation::Difference, first, others)
+            }
+        }
+    }
+}
+
+/// Fold `dedup` and `count` into the flags of the bare invocation they stand
+/// for, leaving the subcommand that operates on whole files, if any
+fn take_command(cli: &mut Cli) -> Result<Option<FileCommand>, Error> {
+    Ok(match cli.command.take() {
+        None => None,
+        Some(Command::Dedup { files }) => {
+            cli.input.extend(files);
+            None
+        }
+        Some(Command::Count { table, files }) => {
+            if cli.output.is_some()
+                || cli.in_place.is_some()
+                || cli.tee.is_some()
+                || cli.removed_output.is_some()
+                || cli.check
+                || cli.follow
+                || cli.approximate
+            {
+                return Err(Error::InvalidArgument(
+                    "count supports neither --output, --in-place, --tee, --removed-output, --check, --follow nor --approximate"
+                        .to_string(),
+                ));
+            }
+            // Counted like --summary, but written as the table
+            cli.summary = true;
+            cli.frequency_table = Some(table);
+            cli.input.extend(files);
+            None
+        }
+        Some(Command::Diff { first, others }) => {
+            Some(FileCommand::set(SetOperation::Difference, first, others))
+        }
+        Some(Command::Merge { files }) => Some(FileCommand {
+            operation: None,
+            files,
+        }),
+        Some(Command::Setops { operation }) => Some(operation.into()),
+        Some(Command::Common { first, others }) => {
+            Some(SetCommand::Intersect { first, others }.into())
+        }
+        Some(Command::Union { files }) => Some(SetCommand::Union { files }.into()),
+    })
 }
 
 fn main() {
-    if let Err(e) = run() {
-        eprintln!("Error: {}", e);
-        std::process::exit(1);
+    match run() {
+        Ok(true) => {}
+        // --check found duplicates
+        Ok(false) => std::process::exit(1),
+        // Whatever read the output stopped early, as `uniqr big.log | head`
+        // does: that's not a failure of ours
+        Err(e) if e.is_broken_pipe() => {}
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
     }
 }
 
-fn run() -> Result<(), Error> {
-    let cli = Cli::parse();
+/// Send the library's log messages to stderr, at the level of `--verbose`
+/// and `--quiet` unless RUST_LOG says otherwise
+fn init_logging(verbose: u8, quiet: bool) {
+    let level = match (quiet, verbose) {
+        (true, _) => log::LevelFilter::Error,
+        (false, 0) => log::LevelFilter::Warn,
+        (false, 1) => log::LevelFilter::Info,
+        (false, 2) => log::LevelFilter::Debug,
+        (false, _) => log::LevelFilter::Trace,
+    };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .init();
+}
+
+/// Returns false if `--check` found duplicates
+fn run() -> Result<bool, Error> {
+    // First of all, so the threads started later leave SIGUSR1 to it
+    let status = status::listen();
+    let mut cli = parse_cli()?;
+    init_logging(cli.verbose, cli.quiet);
+    let file_command = take_command(&mut cli)?;
+    // A check is a dry run that only reports whether anything was removed,
+    // and a summary one that only reports the counts
+    cli.dry_run |= cli.check || cli.summary;
+    if cli.compat_uniq {
+        take_uniq_operands(&mut cli)?;
+    }
+    let inputs = expand_inputs(&cli.input, cli.recursive, &cli.include)?;
 
     // Determine deduplication mode (clap ensures only one is set)
     let mode = if cli.mode.keep_last {
         DeduplicationMode::KeepLast
     } else if cli.mode.remove_all {
         DeduplicationMode::RemoveAll
+    } else if cli.mode.repeated {
+        DeduplicationMode::Repeated
+    } else if cli.mode.all_repeated {
+        DeduplicationMode::AllRepeated
+    } else if let Some(threshold) = cli.mode.similar {
+        DeduplicationMode::Similar {
+            threshold: uniqr::SimilarityThreshold::new(threshold)?,
+        }
     } else {
         DeduplicationMode::KeepFirst
     };
 
+    let trim = if cli.collapse_spaces {
+        TrimMode::CollapseInner
+    } else if cli.ignore_trailing_space {
+        TrimMode::TrailingBlanks
+    } else {
+        match cli.trim {
+            Some(TrimArg::Leading) => TrimMode::Leading,
+            Some(TrimArg::Trailing) => TrimMode::Trailing,
+            Some(TrimArg::Both) => TrimMode::Both,
+            None => TrimMode::None,
+        }
+    };
+
+    #[cfg(feature = "unicode")]
+    let case_fold = cli.case_fold;
+    #[cfg(not(feature = "unicode"))]
+    let case_fold = false;
+
+    let record_separator = if cli.zero_terminated {
+        RecordSeparator::Byte(b'\0')
+    } else if cli.paragraph {
+        RecordSeparator::Paragraph
+    } else if let Some(sep) = &cli.separator {
+        match unescape_separator(sep) {
+            bytes if bytes.is_empty() => {
+                return Err(Error::InvalidArgument(
+                    "--separator must not be empty".to_string(),
+                ));
+            }
+            bytes if bytes.len() == 1 => RecordSeparator::Byte(bytes[0]),
+            bytes => RecordSeparator::Bytes(bytes),
+        }
+    } else {
+        RecordSeparator::default()
+    };
+
+    #[cfg(feature = "csv")]
+    let csv = if cli.csv {
+        if !cli.csv_delimiter.is_ascii() {
+            return Err(Error::InvalidArgument(
+                "--csv-delimiter must be a single ASCII character".to_string(),
+            ));
+        }
+        let key_column = match cli.key_column.as_deref() {
+            None => uniqr::CsvColumn::Index(1),
+            Some(col) => match col.parse::<usize>() {
+                Ok(index) => uniqr::CsvColumn::Index(index),
+                Err(_) => uniqr::CsvColumn::Name(col.to_string()),
+            },
+        };
+        Some(uniqr::CsvOptions {
+            delimiter: cli.csv_delimiter as u8,
+            has_header: !cli.no_header,
+            key_column,
+        })
+    } else {
+        None
+    };
+    #[cfg(not(feature = "csv"))]
+    let csv = None;
+
+    #[cfg(feature = "json")]
+    let json_pointer = cli
+        .json
+        .then(|| json_path_to_pointer(cli.json_key.as_deref().unwrap_or("")));
+    #[cfg(not(feature = "json"))]
+    let json_pointer = None;
+
+    #[cfg(feature = "gzip")]
+    let force_gzip = cli.gzip;
+    #[cfg(not(feature = "gzip"))]
+    let force_gzip = false;
+
+    #[cfg(feature = "encodings")]
+    let (input_encoding, output_encoding) = (
+        cli.input_encoding
+            .as_deref()
+            .map(TextEncoding::for_label)
+            .transpose()?
+            .unwrap_or_default(),
+        cli.output_encoding
+            .as_deref()
+            .map(TextEncoding::for_output_label)
+            .transpose()?
+            .unwrap_or_default(),
+    );
+    #[cfg(not(feature = "encodings"))]
+    let (input_encoding, output_encoding) = (TextEncoding::default(), TextEncoding::default());
+
+    #[cfg(feature = "progress")]
+    let progress = ProgressDisplay::new(cli.progress, &inputs, force_gzip);
+    let progress_callback = || {
+        progress_callback(
+            #[cfg(feature = "progress")]
+            &progress,
+            status.as_ref(),
+        )
+    };
+
     let options = DeduplicationOptions {
         mode,
-        ignore_case: cli.ignore_case,
-        count: cli.count,
+        adjacent: cli.adjacent || cli.compat_uniq,
+        ignore_case: cli.ignore_case || case_fold,
+        case_mode: if case_fold {
+            CaseMode::Fold
+        } else {
+            CaseMode::Lower
+        },
+        non_utf8_case: match cli.non_utf8_case {
+            NonUtf8CaseArg::Ascii => NonUtf8Case::AsciiFold,
+            NonUtf8CaseArg::Error => NonUtf8Case::Error,
+        },
+        utf8_policy: match cli.utf8 {
+            Utf8Arg::Lossy => Utf8Policy::Lossy,
+            Utf8Arg::Strict => Utf8Policy::Strict,
+            Utf8Arg::Skip => Utf8Policy::Skip,
+        },
+        #[cfg(feature = "regex")]
+        passthrough: match (&cli.passthrough_regex, &cli.only_match) {
+            (Some(pattern), _) => Some(uniqr::RecordFilter::pass_matching(pattern)?),
+            (None, Some(pattern)) => Some(uniqr::RecordFilter::only_matching(pattern)?),
+            (None, None) => None,
+        },
+        #[cfg(not(feature = "regex"))]
+        passthrough: None,
+        empty_lines: match cli.empty_lines {
+            EmptyLinesArg::Dedup => EmptyLinePolicy::Dedup,
+            EmptyLinesArg::Preserve => EmptyLinePolicy::Preserve,
+            EmptyLinesArg::Drop => EmptyLinePolicy::Drop,
+        },
+        count: cli.count || cli.sort_by_count.is_some(),
+        count_format: match &cli.count_format {
+            Some(template) => parse_count_format(template)?,
+            None => CountFormat::default(),
+        },
         show_removed: cli.show_removed,
+        sort: match (cli.sort, cli.sort_by_count) {
+            (_, Some(CountOrderArg::Asc)) => Some(uniqr::SortOrder::CountAscending),
+            (_, Some(CountOrderArg::Desc)) => Some(uniqr::SortOrder::CountDescending),
+            (Some(SortArg::Text), None) => Some(uniqr::SortOrder::Text),
+            (Some(SortArg::Numeric), None) => Some(uniqr::SortOrder::Numeric),
+            (None, None) => None,
+        },
+        group: cli.group.map(|group| match group {
+            GroupArg::Separate => uniqr::GroupSeparator::Separate,
+            GroupArg::Prepend => uniqr::GroupSeparator::Prepend,
+            GroupArg::Append => uniqr::GroupSeparator::Append,
+            GroupArg::Both => uniqr::GroupSeparator::Both,
+        }),
         column: cli.column,
+        column_name: cli.column_name,
+        header_lines: cli.header,
+        footer_lines: cli.footer,
+        record_separator,
+        max_line_length: cli.max_line_length,
+        line_length_policy: match cli.long_lines {
+            LongLinesArg::Error => uniqr::LineLengthPolicy::Error,
+            LongLinesArg::Truncate => uniqr::LineLengthPolicy::Truncate,
+            LongLinesArg::PassThrough => uniqr::LineLengthPolicy::PassThrough,
+        },
+        // Following writes each line as it arrives, so can't hold back its
+        // newline; GNU uniq always ends its output with one
+        ensure_trailing_newline: cli.ensure_trailing_newline || cli.follow || cli.compat_uniq,
+        emit_bom: cli.keep_bom,
+        csv,
+        json_pointer,
+        key_extractor: None,
+        #[cfg(any(feature = "regex", feature = "url"))]
+        normalizers: cli
+            .preset
+            .map(preset_normalizer)
+            .transpose()?
+            .into_iter()
+            .collect(),
+        #[cfg(not(any(feature = "regex", feature = "url")))]
+        normalizers: Vec::new(),
+        skip_fields: cli.skip_fields,
+        skip_chars: cli.skip_chars,
+        compare_width: cli.compare_width,
+        trim,
+        punctuation: if cli.alnum_only {
+            PunctuationMode::AlphanumericOnly
+        } else if cli.ignore_punct {
+            PunctuationMode::Strip
+        } else {
+            PunctuationMode::None
+        },
+        #[cfg(feature = "unicode")]
+        normalization: cli.normalize.map(|form| match form {
+            NormalizeArg::Nfc => uniqr::NormalizationForm::Nfc,
+            NormalizeArg::Nfd => uniqr::NormalizationForm::Nfd,
+            NormalizeArg::Nfkc => uniqr::NormalizationForm::Nfkc,
+            NormalizeArg::Nfkd => uniqr::NormalizationForm::Nfkd,
+        }),
+        #[cfg(not(feature = "unicode"))]
+        normalization: None,
+        #[cfg(feature = "unicode")]
+        ignore_diacritics: cli.ignore_diacritics,
+        #[cfg(not(feature = "unicode"))]
+        ignore_diacritics: false,
+        annotate_source: cli.annotate_source.then(|| uniqr::SourceAnnotation {
+            names: if inputs.is_empty() {
+                vec!["(standard input)".to_string()]
+            } else {
+                inputs.iter().map(|p| p.display().to_string()).collect()
+            },
+            line_numbers: cli.source_line_numbers,
+        }),
+        approximate: cli.approximate.then_some(uniqr::ApproximateOptions {
+            false_positive_rate: cli.false_positive_rate,
+            expected_lines: cli.expected_lines,
+        }),
+        fuzzy: cli.fuzzy.then_some(uniqr::FuzzyOptions {
+            max_distance: cli.max_distance,
+        }),
+        window: match (&cli.within, &cli.timestamp_format) {
+            (Some(within), Some(format)) => Some(WindowMode::Time {
+                within: parse_duration(within)?,
+                format: format.clone(),
+            }),
+            _ => cli
+                .window
+                .map(WindowMode::Lines)
+                .or(cli.max_keys.map(WindowMode::Keys)),
+        },
+        similarity: uniqr::SimilarityOptions {
+            signature: match cli.signature {
+                SignatureArg::Minhash => uniqr::SignatureKind::MinHash,
+                SignatureArg::Simhash => uniqr::SignatureKind::SimHash,
+            },
+            shingle_size: cli.shingle_size,
+        },
+        top_duplicates: cli.top_duplicates,
+        collect_key_counts: cli.counts_output.is_some() || cli.summary,
+        collect_histogram: cli.histogram,
+        collect_removed_records: cli.report_removed,
+        hasher: match cli.hash {
+            Some(HashArg::Std) => HashAlgorithm::Std,
+            Some(HashArg::Ahash) => HashAlgorithm::AHash,
+            Some(HashArg::Xxhash) => HashAlgorithm::XxHash,
+            Some(HashArg::Blake3) => HashAlgorithm::Blake3,
+            None => HashAlgorithm::default(),
+        },
+        key_type: match cli.numeric_precision {
+            Some(decimals) => KeyType::Rounded { decimals },
+            None if cli.numeric => KeyType::Numeric,
+            None => KeyType::Text,
+        },
+        exclude_keys: None,
         #[cfg(feature = "disk-backed")]
-        use_disk: cli.use_disk,
+        use_disk: cli.use_disk || cli.state_db.is_some(),
         #[cfg(not(feature = "disk-backed"))]
         use_disk: false,
+        #[cfg(feature = "disk-backed")]
+        disk: uniqr::DiskOptions {
+            backend: match cli.disk_backend {
+                DiskBackendArg::Sled => uniqr::DiskBackend::Sled,
+                DiskBackendArg::TempFile => uniqr::DiskBackend::TempFile,
+            },
+            temp_dir: cli.temp_dir.clone(),
+            cache_capacity: cli.disk_cache_mb.map(|mb| mb * 1024 * 1024),
+            low_space: cli.disk_low_space,
+        },
+        #[cfg(not(feature = "disk-backed"))]
+        disk: uniqr::DiskOptions::default(),
+        #[cfg(feature = "disk-backed")]
+        state_db: cli.state_db.clone(),
+        #[cfg(not(feature = "disk-backed"))]
+        state_db: None,
+        #[cfg(feature = "disk-backed")]
+        checkpoint_every: cli.checkpoint_every,
+        #[cfg(not(feature = "disk-backed"))]
+        checkpoint_every: None,
+        #[cfg(feature = "disk-backed")]
+        resume: cli.resume,
+        #[cfg(not(feature = "disk-backed"))]
+        resume: false,
+        #[cfg(feature = "disk-backed")]
+        memory_limit: cli.memory_limit_mb.map(|mb| mb * 1024 * 1024),
+        #[cfg(not(feature = "disk-backed"))]
+        memory_limit: None,
+        line_numbers: cli.line_numbers,
+        print_key: cli.print_key,
+        record_callback: status.as_ref().map(status::Status::record_callback),
+        progress_callback: progress_callback(),
+        cancel: None,
     };
 
-    // Validate disk-backed modes that require seeking
-    #[cfg(feature = "disk-backed")]
-    if options.use_disk
-        && (mode == DeduplicationMode::KeepLast || mode == DeduplicationMode::RemoveAll)
-        && cli.input.is_none()
-    {
-        return Err(Error::InvalidArgument(
-            "Disk-backed --keep-last and --remove-all require a file input (not stdin)".to_string(),
-        ));
-    }
+    // Reference keys are built with the final key options
+    let options = match &cli.exclude_from {
+        Some(path) => {
+            let reference = DecodingReader::new(open_input(path, force_gzip)?, input_encoding);
+            DeduplicationOptions {
+                exclude_keys: Some(Arc::new(uniqr::load_reference_keys(reference, &options)?)),
+                ..options
+            }
+        }
+        None => options,
+    };
 
-    // Open input and perform deduplication with appropriate trait bounds
-    let stats = if let Some(path) = cli.input {
-        // File input is seekable
-        let file = File::open(&path).map_err(|e| {
+    // Appending only new lines treats the output's keys as seen already
+    let options = match (&cli.output, cli.append) {
+        (Some(path), Some(AppendArg::New)) if path.exists() => {
+            let mut keys = uniqr::load_reference_keys(open_input(path, false)?, &options)?;
+            if let Some(existing) = &options.exclude_keys {
+                keys.extend(existing.iter().cloned());
+            }
+            DeduplicationOptions {
+                exclude_keys: Some(Arc::new(keys)),
+                ..options
+            }
+        }
+        _ => options,
+    };
+
+    let colored = color::enabled(cli.color);
+
+    if cli.follow {
+        let [path] = inputs.as_slice() else {
+            return Err(Error::InvalidArgument(
+                "--follow requires exactly one input file".to_string(),
+            ));
+        };
+        let file = File::open(path).map_err(|e| {
             Error::Io(io::Error::new(
                 e.kind(),
                 format!("Failed to open input file '{}': {}", path.display(), e),
             ))
         })?;
+        // Stdout is line-buffered, so each kept line is flushed as it is written
+        let stdout = EncodingWriter::new(io::stdout().lock(), output_encoding)?;
+        let mut writer = color::ColorWriter::new(stdout, &options, colored);
+        deduplicate(
+            DecodingReader::new(FollowReader::new(file), input_encoding),
+            &mut RateLimited::new(&mut writer, cli.rate_limit, &options),
+            &options,
+        )?;
+        return Ok(true);
+    }
 
-        // Prepare output
-        if cli.dry_run {
-            let mut null_output = io::sink();
-            uniqr::deduplicate_seekable(file, &mut null_output, &options)?
-        } else if let Some(output_path) = cli.output {
-            // Atomic file write setup
-            let temp_path = output_path.with_extension("tmp");
-            let temp_file = File::create(&temp_path).map_err(|e| {
-                Error::Io(io::Error::new(
-                    e.kind(),
-                    format!(
-                        "Failed to create temp file '{}': {}",
-                        temp_path.display(),
-                        e
-                    ),
-                ))
-            })?;
-            let mut writer = BufWriter::new(temp_file);
-
-            let stats = uniqr::deduplicate_seekable(file, &mut writer, &options)?;
+    if file_command.is_some()
+        && (!inputs.is_empty()
+            || cli.follow
+            || cli.in_place.is_some()
+            || cli.compat_uniq
+            || cli.removed_output.is_some()
+            || cli.audit_log.is_some()
+            || cli.summary)
+    {
+        return Err(Error::InvalidArgument(
+            "diff, common, merge and setops take their files after the subcommand name, and support neither --follow, --in-place, --compat-uniq, --removed-output, --audit-log nor --summary"
+                .to_string(),
+        ));
+    }
 
-            writer.flush()?;
-            drop(writer);
-            std::fs::rename(&temp_path, &output_path).map_err(|e| {
-                Error::Io(io::Error::new(
-                    e.kind(),
-                    format!(
-                        "Failed to rename '{}' to '{}': {}",
-                        temp_path.display(),
-                        output_path.display(),
-                        e
-                    ),
+    // Without a file to read, a bare `uniqr` looks like it hangs
+    if inputs.is_empty()
+        && file_command.is_none()
+        && cli.in_place.is_none()
+        && !cli.no_hint
+        && !cli.quiet
+        && io::stdin().is_terminal()
+    {
+        eprintln!(
+            "uniqr: reading from terminal; pipe input or pass a file, {} to end",
+            if cfg!(windows) {
+                "Ctrl-Z then Enter"
+            } else {
+                "Ctrl-D"
+            }
+        );
+    }
+
+    // Removed records go to their own file; a dry run writes nothing
+    let mut removed = match &cli.removed_output {
+        Some(path) if !cli.dry_run => Some(create_output(path, output_encoding)?),
+        _ => None,
+    };
+    let mut audit = cli
+        .audit_log
+        .as_deref()
+        .map(|path| AuditLog::create(path, cli.audit_format.unwrap_or_default(), &inputs))
+        .transpose()?;
+
+    // Open input and perform deduplication with appropriate trait bounds
+    let stats = if let Some(command) = &file_command {
+        let files = command
+            .files
+            .iter()
+            .map(|path| {
+                Ok(DecodingReader::new(
+                    open_input(path, force_gzip)?,
+                    input_encoding,
                 ))
-            })?;
-            stats
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        if cli.dry_run {
+            command.execute(files, &mut io::sink(), &options)?
+        } else if let Some(output_path) = &cli.output {
+            match cli.append {
+                Some(_) => append_output(output_path, output_encoding, &options, |writer| {
+                    command.execute(files, writer, &options)
+                })?,
+                None => write_atomically(output_path, output_encoding, |writer| {
+                    command.execute(files, writer, &options)
+                })?,
+            }
+        } else {
+            write_stdout(
+                cli.tee.as_deref(),
+                output_encoding,
+                &options,
+                colored,
+                cli.rate_limit,
+                |mut writer| command.execute(files, &mut writer, &options),
+            )?
+        }
+    } else if let Some(suffix) = &cli.in_place {
+        if inputs.is_empty() {
+            return Err(Error::InvalidArgument(
+                "--in-place requires a file input (not stdin)".to_string(),
+            ));
+        }
+        // Each file is deduplicated on its own and replaced, like `sed -i`
+        let mut total = DeduplicationStats::default();
+        for (source, path) in inputs.iter().enumerate() {
+            let file = open_input(path, force_gzip)?;
+            // Each run counts from zero; continue the bar and the status
+            // report from where the last file ended. Key reports are built
+            // once from every file's full counts, below.
+            let options = DeduplicationOptions {
+                progress_callback: progress_callback(),
+                top_duplicates: None,
+                collect_key_counts: options.top_duplicates.is_some()
+                    || options.collect_key_counts
+                    || options.collect_histogram,
+                collect_histogram: false,
+                ..options.clone()
+            };
+            let stats = if cli.dry_run {
+                deduplicate_files(
+                    vec![file],
+                    &mut io::sink(),
+                    None,
+                    None,
+                    &options,
+                    input_encoding,
+                )?
+            } else {
+                if let Some(suffix) = suffix {
+                    let mut backup = path.clone().into_os_string();
+                    backup.push(suffix);
+                    std::fs::copy(path, &backup).map_err(|e| {
+                        Error::Io(io::Error::new(
+                            e.kind(),
+                            format!("Failed to back up '{}': {}", path.display(), e),
+                        ))
+                    })?;
+                }
+                write_atomically(path, output_encoding, |writer| {
+                    deduplicate_files(
+                        vec![file],
+                        writer,
+                        removed_sink(&mut removed),
+                        None,
+                        &options,
+                        input_encoding,
+                    )
+                })?
+            };
+            total.lines_read += stats.lines_read;
+            total.lines_written += stats.lines_written;
+            total.lines_removed += stats.lines_removed;
+            total.unique_lines += stats.unique_lines;
+            total.bytes_read += stats.bytes_read;
+            total.bytes_written += stats.bytes_written;
+            total.elapsed += stats.elapsed;
+            total.key_counts.extend(stats.key_counts);
+            // Each file is input 0 of its own run
+            total
+                .removed_records
+                .extend(stats.removed_records.into_iter().map(|mut record| {
+                    record.location.source = source;
+                    if let Some(original) = &mut record.duplicate_of {
+                        original.source = source;
+                    }
+                    record
+                }));
+        }
+        // Keys repeated in several files are reported with their combined count
+        let key_counts = combine_key_counts(std::mem::take(&mut total.key_counts));
+        if let Some(limit) = options.top_duplicates {
+            total.top_duplicates = key_counts
+                .iter()
+                .take_while(|(_, count)| *count >= 2)
+                .take(limit)
+                .cloned()
+                .collect();
+        }
+        if options.collect_histogram {
+            for (_, count) in &key_counts {
+                *total
+                    .duplicate_histogram
+                    .entry(uniqr::histogram_bucket(*count))
+                    .or_insert(0) += 1;
+            }
+        }
+        if options.collect_key_counts {
+            total.key_counts = key_counts;
+        }
+        total
+    } else if !inputs.is_empty() {
+        // Uncompressed file input is seekable
+        let files = inputs
+            .iter()
+            .map(|path| open_input(path, force_gzip))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        // Prepare output
+        if cli.dry_run {
+            let mut null_output = io::sink();
+            deduplicate_files(
+                files,
+                &mut null_output,
+                None,
+                audit.as_mut(),
+                &options,
+                input_encoding,
+            )?
+        } else if let Some(output_path) = cli.output {
+            let append = cli.append.is_some();
+            write_output(&output_path, output_encoding, &options, append, |writer| {
+                let (mut kept, removed) =
+                    route_records(writer, removed_sink(&mut removed), cli.only_removed);
+                deduplicate_files(
+                    files,
+                    &mut kept,
+                    removed,
+                    audit.as_mut(),
+                    &options,
+                    input_encoding,
+                )
+            })?
         } else {
-            // Write to stdout
-            let stdout = io::stdout();
-            let mut writer = BufWriter::new(stdout.lock());
-            let stats = uniqr::deduplicate_seekable(file, &mut writer, &options)?;
-            writer.flush()?;
-            stats
+            // Write to stdout, and to the --tee file
+            write_stdout(
+                cli.tee.as_deref(),
+                output_encoding,
+                &options,
+                colored,
+                cli.rate_limit,
+                |writer| {
+                    let (mut kept, removed) =
+                        route_records(writer, removed_sink(&mut removed), cli.only_removed);
+                    deduplicate_files(
+                        files,
+                        &mut kept,
+                        removed,
+                        audit.as_mut(),
+                        &options,
+                        input_encoding,
+                    )
+                },
+            )?
         }
     } else {
         // Stdin input (not seekable via standard Stdin handle)
         let stdin = io::stdin();
-        let input = stdin.lock(); // StdinLock implements Read
+        let compression = if force_gzip {
+            Compression::Gzip
+        } else {
+            Compression::None
+        };
+        let input = DecodingReader::new(
+            CompressedReader::new(stdin.lock(), compression)?,
+            input_encoding,
+        );
 
         // Prepare output
         if cli.dry_run {
             let mut null_output = io::sink();
-            deduplicate(input, &mut null_output, &options)?
+            deduplicate_readers(
+                vec![input],
+                &mut null_output,
+                None,
+                audit.as_mut(),
+                &options,
+            )?
         } else if let Some(output_path) = cli.output {
-            // Atomic file write setup for Stdin input
-            let temp_path = output_path.with_extension("tmp");
-            let temp_file = File::create(&temp_path).map_err(|e| {
+            let append = cli.append.is_some();
+            write_output(&output_path, output_encoding, &options, append, |writer| {
+                let (mut kept, removed) =
+                    route_records(writer, removed_sink(&mut removed), cli.only_removed);
+                deduplicate_readers(vec![input], &mut kept, removed, audit.as_mut(), &options)
+            })?
+        } else {
+            // Write to stdout, and to the --tee file
+            write_stdout(
+                cli.tee.as_deref(),
+                output_encoding,
+                &options,
+                colored,
+                cli.rate_limit,
+                |writer| {
+                    let (mut kept, removed) =
+                        route_records(writer, removed_sink(&mut removed), cli.only_removed);
+                    deduplicate_readers(vec![input], &mut kept, removed, audit.as_mut(), &options)
+                },
+            )?
+        }
+    };
+
+    if let Some(removed) = removed {
+        removed.finish()?.finish()?;
+    }
+    if let Some(audit) = audit {
+        audit.finish()?;
+    }
+
+    #[cfg(feature = "progress")]
+    progress.finish();
+
+    // Print statistics if requested
+    if cli.stats || cli.stats_format.is_some() || cli.stats_output.is_some() || cli.histogram {
+        let report = format_stats(&stats, cli.stats_format.unwrap_or_default());
+        match &cli.stats_output {
+            Some(path) => std::fs::write(path, report).map_err(|e| {
                 Error::Io(io::Error::new(
                     e.kind(),
-                    format!(
-                        "Failed to create temp file '{}': {}",
-                        temp_path.display(),
-                        e
-                    ),
+                    format!("Failed to write stats to '{}': {}", path.display(), e),
                 ))
-            })?;
-            let mut writer = BufWriter::new(temp_file);
+            })?,
+            None => eprint!("{}", report),
+        }
+    }
+
+    if cli.report_removed {
+        eprint!(
+            "{}",
+            format_removed_records(&stats.removed_records, &inputs)
+        );
+    }
 
-            let stats = deduplicate(input, &mut writer, &options)?;
+    if cli.top_duplicates.is_some() {
+        let report = format_top_duplicates(&stats.top_duplicates);
+        match &cli.top_duplicates_output {
+            Some(path) => std::fs::write(path, report).map_err(|e| {
+                Error::Io(io::Error::new(
+                    e.kind(),
+                    format!("Failed to write report to '{}': {}", path.display(), e),
+                ))
+            })?,
+            None => eprint!("{}", report),
+        }
+    }
 
-            writer.flush()?;
-            drop(writer);
-            std::fs::rename(&temp_path, &output_path).map_err(|e| {
+    if let Some(path) = &cli.counts_output {
+        File::create(path)
+            .and_then(|file| write_key_counts(BufWriter::new(file), &stats.key_counts))
+            .map_err(|e| {
                 Error::Io(io::Error::new(
                     e.kind(),
-                    format!(
-                        "Failed to rename '{}' to '{}': {}",
-                        temp_path.display(),
-                        output_path.display(),
-                        e
-                    ),
+                    format!("Failed to write counts to '{}': {}", path.display(), e),
                 ))
             })?;
-            stats
-        } else {
-            // Write to stdout
-            let stdout = io::stdout();
-            let mut writer = BufWriter::new(stdout.lock());
-            let stats = deduplicate(input, &mut writer, &options)?;
-            writer.flush()?;
-            stats
+    }
+
+    if let Some(table) = &cli.frequency_table {
+        table.write(BufWriter::new(io::stdout().lock()), &stats.key_counts)?;
+    } else if cli.summary {
+        let top = cli.top.unwrap_or(stats.key_counts.len());
+        let counts = &stats.key_counts[..top.min(stats.key_counts.len())];
+        write_key_counts(BufWriter::new(io::stdout().lock()), counts)?;
+    }
+
+    if cli.check && stats.lines_removed > 0 {
+        eprintln!(
+            "Found {} duplicate {}",
+            stats.lines_removed,
+            if stats.lines_removed == 1 {
+                "line"
+            } else {
+                "lines"
+            }
+        );
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// Render `--report-removed`, naming the input file of each line when there
+/// are several
+fn format_removed_records(records: &[RemovedRecord], inputs: &[PathBuf]) -> String {
+    let locate = |location: &RecordLocation| match inputs {
+        [] | [_] => format!("line {}", location.line),
+        _ => format!("{}:{}", inputs[location.source].display(), location.line),
+    };
+    let mut report = String::new();
+    for record in records {
+        match &record.duplicate_of {
+            Some(original) => report.push_str(&format!(
+                "{} duplicates {}\n",
+                locate(&record.location),
+                locate(original)
+            )),
+            None => report.push_str(&format!("{} is unique\n", locate(&record.location))),
         }
+    }
+    report
+}
+
+/// Sum the counts of equal keys and sort most frequent first, ties by key
+fn combine_key_counts(counts: KeyCounts) -> KeyCounts {
+    let mut combined: std::collections::HashMap<Vec<u8>, usize> = std::collections::HashMap::new();
+    for (key, count) in counts {
+        *combined.entry(key).or_insert(0) += count;
+    }
+    let mut combined: Vec<_> = combined.into_iter().collect();
+    combined.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    combined
+}
+
+/// Write `key<TAB>count` lines for `--counts-output` and `--summary`; keys
+/// are written as raw bytes
+fn write_key_counts(mut writer: impl Write, counts: &[(Vec<u8>, usize)]) -> io::Result<()> {
+    for (key, count) in counts {
+        writer.write_all(key)?;
+        writeln!(writer, "\t{}", count)?;
+    }
+    writer.flush()
+}
+
+/// Format the `--top-duplicates` report: counts padded like `uniq -c`, then the key
+fn format_top_duplicates(top: &[(Vec<u8>, usize)]) -> String {
+    let mut report = String::from("Top duplicates:\n");
+    for (key, count) in top {
+        report.push_str(&format!("{:>7} {}\n", count, String::from_utf8_lossy(key)));
+    }
+    report
+}
+
+/// Parse the command line over the defaults in the config file and in
+/// UNIQR_OPTS (whitespace-separated flags), in increasing precedence. Config
+/// settings for options given on the command line or in UNIQR_OPTS, or that
+/// conflict with them, are dropped.
+fn parse_cli() -> Result<Cli, Error> {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let Some((program, explicit)) = args.split_first() else {
+        return Ok(Cli::parse());
     };
+    if explicit.iter().any(|arg| arg == "--no-config") {
+        return Ok(Cli::parse_from(args));
+    }
+    let env_args: Vec<OsString> = std::env::var_os("UNIQR_OPTS")
+        .map(|opts| {
+            opts.to_string_lossy()
+                .split_whitespace()
+                .map(OsString::from)
+                .collect()
+        })
+        .unwrap_or_default();
 
-    // Print statistics if requested
-    if cli.stats {
-        eprintln!("Statistics:");
-        eprintln!("  Lines read:    {}", stats.lines_read);
-        eprintln!("  Lines written: {}", stats.lines_written);
-        eprintln!("  Lines removed: {}", stats.lines_removed);
-        eprintln!("  Unique lines:  {}", stats.unique_lines);
+    let command = Cli::command();
+    let given = command
+        .clone()
+        .try_get_matches_from(std::iter::once(program).chain(&env_args).chain(explicit))
+        .unwrap_or_else(|e| e.exit());
+    let config_args = match config_path() {
+        Some((path, required)) => config_args(&path, required, &command, &given)?,
+        None => Vec::new(),
+    };
+    let matches = command
+        .try_get_matches_from(
+            std::iter::once(program)
+                .chain(&config_args)
+                .chain(&env_args)
+                .chain(explicit),
+        )
+        .unwrap_or_else(|e| e.exit());
+    Ok(Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit()))
+}
+
+/// The config file: UNIQR_CONFIG (which must exist), or `uniqr/config.toml`
+/// in the XDG config directory if there is one
+fn config_path() -> Option<(PathBuf, bool)> {
+    if let Some(path) = std::env::var_os("UNIQR_CONFIG") {
+        return Some((path.into(), true));
     }
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some((dir.join("uniqr").join("config.toml"), false))
+}
 
+/// The flags for the settings in the config file at `path`, such as
+/// `ignore_case = true` or `temp_dir = "/scratch"`, leaving out those of
+/// arguments in `given` from the command line
+fn config_args(
+    path: &Path,
+    required: bool,
+    command: &clap::Command,
+    given: &clap::ArgMatches,
+) -> Result<Vec<OsString>, Error> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !required => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(Error::Io(io::Error::new(
+                e.kind(),
+                format!("Failed to read config file '{}': {}", path.display(), e),
+            )));
+        }
+    };
+    let invalid = |message: String| {
+        Error::InvalidArgument(format!("Config file '{}': {}", path.display(), message))
+    };
+    let table: toml::Table = text.parse().map_err(|e| invalid(format!("{}", e)))?;
+
+    let is_given = |arg: &clap::Arg| {
+        given.value_source(arg.get_id().as_str()) == Some(clap::parser::ValueSource::CommandLine)
+    };
+    let mut args = Vec::new();
+    for (key, value) in &table {
+        let Some(arg) = command.get_arguments().find(|arg| arg.get_id() == key) else {
+            return Err(invalid(format!("Unknown option '{}'", key)));
+        };
+        let Some(long) = arg.get_long() else {
+            return Err(invalid(format!("'{}' can't be set in a config file", key)));
+        };
+        let overridden = is_given(arg)
+            || command
+                .get_arg_conflicts_with(arg)
+                .into_iter()
+                .any(is_given)
+            || command
+                .get_groups()
+                .filter(|group| {
+                    !clap::ArgGroup::clone(group).is_multiple()
+                        && group.get_args().any(|id| id == key)
+                })
+                .flat_map(|group| group.get_args())
+                .filter_map(|id| command.get_arguments().find(|arg| arg.get_id() == id))
+                .any(is_given);
+        if overridden {
+            continue;
+        }
+
+        let action = arg.get_action();
+        match value {
+            toml::Value::Boolean(set) if !action.takes_values() => {
+                if *set {
+                    args.push(format!("--{}", long).into());
+                }
+            }
+            toml::Value::Integer(times) if matches!(action, clap::ArgAction::Count) => {
+                for _ in 0..*times {
+                    args.push(format!("--{}", long).into());
+                }
+            }
+            _ if !action.takes_values() => {
+                return Err(invalid(format!(
+                    "'{}' is a flag; set it to true or false",
+                    key
+                )));
+            }
+            value => {
+                let values = match value {
+                    toml::Value::Array(values) => values.iter().collect(),
+                    value => vec![value],
+                };
+                for value in values {
+                    let value = match value {
+                        toml::Value::String(text) => text.clone(),
+                        toml::Value::Integer(number) => number.to_string(),
+                        toml::Value::Float(number) => number.to_string(),
+                        toml::Value::Boolean(set) => set.to_string(),
+                        _ => {
+                            return Err(invalid(format!(
+                                "'{}' must be a string, number or array of them",
+                                key
+                            )));
+                        }
+                    };
+                    args.push(format!("--{}={}", long, value).into());
+                }
+            }
+        }
+    }
+    Ok(args)
+}
+
+/// Apply GNU uniq's `[INPUT [OUTPUT]]` operands: the second one becomes the
+/// output, and `-` means stdin or stdout
+fn take_uniq_operands(cli: &mut Cli) -> Result<(), Error> {
+    if let Some(extra) = cli.input.get(2) {
+        return Err(Error::InvalidArgument(format!(
+            "extra operand '{}' (--compat-uniq takes at most INPUT and OUTPUT)",
+            extra.display()
+        )));
+    }
+    if cli.input.len() == 2 {
+        if cli.output.is_some() {
+            return Err(Error::InvalidArgument(
+                "--output cannot be combined with an OUTPUT operand".to_string(),
+            ));
+        }
+        cli.output = cli.input.pop().filter(|path| path != Path::new("-"));
+    }
+    cli.input.retain(|path| path != Path::new("-"));
     Ok(())
 }
+
+/// Parse a `--count-format` template such as `{count}\t{line}` or `{line} = {count:3}`.
+///
+/// Escapes are handled as in `--separator`.
+fn parse_count_format(template: &str) -> Result<CountFormat, Error> {
+    let invalid = || {
+        Error::InvalidArgument(format!(
+            "Invalid --count-format '{}': expected '{{count}}SEP{{line}}' or '{{line}}SEP{{count}}'",
+            template
+        ))
+    };
+    let template = String::from_utf8_lossy(&unescape_separator(template)).into_owned();
+
+    let (placement, count, separator) = if let Some(rest) = template.strip_suffix("{line}") {
+        let (count, separator) = rest.split_at(rest.find('}').ok_or_else(invalid)? + 1);
+        (CountPlacement::Prefix, count, separator)
+    } else if let Some(rest) = template.strip_prefix("{line}") {
+        let (separator, count) = rest.split_at(rest.rfind('{').ok_or_else(invalid)?);
+        (CountPlacement::Suffix, count, separator)
+    } else {
+        return Err(invalid());
+    };
+
+    let width = match count {
+        "{count}" => 0,
+        _ => count
+            .strip_prefix("{count:")
+            .and_then(|rest| rest.strip_suffix('}'))
+            .and_then(|width| width.parse().ok())
+            .ok_or_else(invalid)?,
+    };
+    if separator.contains("{count") || separator.contains("{line}") {
+        return Err(invalid());
+    }
+    Ok(CountFormat {
+        width,
+        separator: separator.to_string(),
+        placement,
+    })
+}
+
+/// The normalizer of a `--preset`
+#[cfg(any(feature = "regex", feature = "url"))]
+fn preset_normalizer(preset: PresetArg) -> Result<Arc<dyn uniqr::normalize::Normalizer>, Error> {
+    #[cfg(feature = "regex")]
+    use uniqr::normalize::{LogFormat, LogPreset};
+
+    Ok(match preset {
+        #[cfg(feature = "regex")]
+        PresetArg::Syslog => Arc::new(LogPreset::new(LogFormat::Syslog)?),
+        #[cfg(feature = "regex")]
+        PresetArg::Nginx => Arc::new(LogPreset::new(LogFormat::Nginx)?),
+        #[cfg(feature = "regex")]
+        PresetArg::Apache => Arc::new(LogPreset::new(LogFormat::Apache)?),
+        #[cfg(feature = "regex")]
+        PresetArg::Journald => Arc::new(LogPreset::new(LogFormat::Journald)?),
+        #[cfg(feature = "url")]
+        PresetArg::Url => Arc::new(uniqr::normalize::CanonicalUrl),
+    })
+}
+
+/// Parse a `--within` duration: a number with a unit of ms, s, m, h or d
+/// (seconds without one), such as `90s` or `1.5h`
+fn parse_duration(text: &str) -> Result<std::time::Duration, Error> {
+    let invalid = || {
+        Error::InvalidArgument(format!(
+            "Invalid duration '{}': expected a number with a unit of ms, s, m, h or d",
+            text
+        ))
+    };
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let seconds = match unit.trim() {
+        "ms" => 0.001,
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86400.0,
+        _ => return Err(invalid()),
+    };
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    std::time::Duration::try_from_secs_f64(number * seconds).map_err(|_| invalid())
+}
+
+/// Convert a `.user.id` style path into a JSON pointer (`/user/id`).
+///
+/// Paths that already start with `/` (or are empty) are used as-is.
+#[cfg(feature = "json")]
+fn json_path_to_pointer(path: &str) -> String {
+    if path.starts_with('/') {
+        return path.to_string();
+    }
+    let path = path.trim_start_matches('.');
+    if path.is_empty() {
+        return String::new();
+    }
+    path.split('.')
+        .map(|segment| format!("/{}", segment.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+fn format_stats(stats: &DeduplicationStats, format: StatsFormat) -> String {
+    let histogram = stats
+        .duplicate_histogram
+        .iter()
+        .map(|(&bucket, &keys)| (histogram_label(bucket), keys));
+    match format {
+        StatsFormat::Text => {
+            let mut report = format!(
+                "Statistics:\n  Lines read:    {}\n  Lines written: {}\n  Lines removed: {}\n  Unique lines:  {}\n  Bytes read:    {}\n  Bytes written: {}\n  Elapsed:       {:.3}s\n  Throughput:    {:.0} lines/s ({:.2} MiB/s)\n",
+                stats.lines_read,
+                stats.lines_written,
+                stats.lines_removed,
+                stats.unique_lines,
+                stats.bytes_read,
+                stats.bytes_written,
+                stats.elapsed.as_secs_f64(),
+                stats.lines_per_second(),
+                stats.bytes_per_second() / (1024.0 * 1024.0)
+            );
+            if !stats.duplicate_histogram.is_empty() {
+                report.push_str("  Distinct lines by occurrences:\n");
+                for (label, keys) in histogram {
+                    report.push_str(&format!("    {:<10} {}\n", label, keys));
+                }
+            }
+            report
+        }
+        StatsFormat::Json => {
+            let histogram = match stats.duplicate_histogram.is_empty() {
+                true => String::new(),
+                false => format!(
+                    ",\"duplicate_histogram\":{{{}}}",
+                    histogram
+                        .map(|(label, keys)| format!("\"{}\":{}", label, keys))
+                        .collect::<Vec<_>>()
+                        .join(",")
+                ),
+            };
+            format!(
+                "{{\"lines_read\":{},\"lines_written\":{},\"lines_removed\":{},\"unique_lines\":{},\"bytes_read\":{},\"bytes_written\":{},\"elapsed_seconds\":{:.6},\"lines_per_second\":{:.1},\"bytes_per_second\":{:.1}{}}}\n",
+                stats.lines_read,
+                stats.lines_written,
+                stats.lines_removed,
+                stats.unique_lines,
+                stats.bytes_read,
+                stats.bytes_written,
+                stats.elapsed.as_secs_f64(),
+                stats.lines_per_second(),
+                stats.bytes_per_second(),
+                histogram
+            )
+        }
+    }
+}
+
+/// Occurrence range of a `duplicate_histogram` bucket, e.g. `3-10`
+fn histogram_label(bucket: usize) -> String {
+    match bucket {
+        0..=2 => bucket.to_string(),
+        3 => "3-10".to_string(),
+        _ => match (bucket - 1).checked_mul(10) {
+            Some(upper) => format!("{}-{}", bucket, upper),
+            None => format!("{}+", bucket),
+        },
+    }
+}
+
+/// Progress display for `--progress`: a bar sized to the input files, or a
+/// running byte count when the total isn't known (stdin or compressed input)
+#[cfg(feature = "progress")]
+struct ProgressDisplay {
+    bar: Option<indicatif::ProgressBar>,
+}
+
+#[cfg(feature = "progress")]
+impl ProgressDisplay {
+    fn new(enabled: bool, inputs: &[PathBuf], force_gzip: bool) -> Self {
+        if !enabled {
+            return Self { bar: None };
+        }
+        let compressed = force_gzip
+            || inputs
+                .iter()
+                .any(|path| Compression::from_path(path) != Compression::None);
+        let total: Option<u64> = if inputs.is_empty() || compressed {
+            None
+        } else {
+            inputs
+                .iter()
+                .map(|path| std::fs::metadata(path).ok().map(|meta| meta.len()))
+                .sum()
+        };
+
+        let bar = match total {
+            Some(total) => indicatif::ProgressBar::new(total).with_style(
+                indicatif::ProgressStyle::with_template(
+                    "{bar:40} {bytes}/{total_bytes} ({bytes_per_sec}, ETA {eta})",
+                )
+                .expect("valid progress template"),
+            ),
+            None => indicatif::ProgressBar::new_spinner().with_style(
+                indicatif::ProgressStyle::with_template("{spinner} {bytes} ({bytes_per_sec})")
+                    .expect("valid progress template"),
+            ),
+        };
+        Self { bar: Some(bar) }
+    }
+
+    /// Callback that advances the display from its current position
+    fn callback(&self) -> Option<std::sync::Arc<dyn uniqr::ProgressCallback>> {
+        let bar = self.bar.clone()?;
+        let base = bar.position();
+        Some(std::sync::Arc::new(move |progress: uniqr::Progress| {
+            bar.set_position(base + progress.bytes_read)
+        }))
+    }
+
+    fn finish(&self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+/// Callback passing the progress of a run on to the `--progress` display and
+/// the status report, each continuing from where it is now
+fn progress_callback(
+    #[cfg(feature = "progress")] display: &ProgressDisplay,
+    status: Option<&Arc<status::Status>>,
+) -> Option<Arc<dyn uniqr::ProgressCallback>> {
+    /// Callbacks that all receive the same progress
+    struct Each(Vec<Arc<dyn uniqr::ProgressCallback>>);
+
+    impl uniqr::ProgressCallback for Each {
+        fn report(&self, progress: uniqr::Progress) {
+            self.0.iter().for_each(|callback| callback.report(progress));
+        }
+
+        fn record_read(&self, lines_read: u64) {
+            self.0
+                .iter()
+                .for_each(|callback| callback.record_read(lines_read));
+        }
+    }
+
+    let callbacks: Vec<_> = [
+        #[cfg(feature = "progress")]
+        display.callback(),
+        status.map(status::Status::progress_callback),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    match callbacks.len() {
+        0 => None,
+        _ => Some(Arc::new(Each(callbacks))),
+    }
+}
+
+/// Open an input file, decompressing it if it has a compressed extension or `force_gzip` is set
+fn open_input(path: &Path, force_gzip: bool) -> Result<CompressedReader<File>, Error> {
+    let file = File::open(path).map_err(|e| {
+        Error::Io(io::Error::new(
+            e.kind(),
+            format!("Failed to open input file '{}': {}", path.display(), e),
+        ))
+    })?;
+    let compression = if force_gzip {
+        Compression::Gzip
+    } else {
+        Compression::from_path(path)
+    };
+    CompressedReader::new(file, compression)
+}
+
+/// Deduplicate files decoded from `encoding`, seeking when none of them are
+/// compressed or need decoding and no audit log is kept. Removed records go
+/// to `removed` if given.
+fn deduplicate_files<W: Write>(
+    files: Vec<CompressedReader<File>>,
+    output: &mut W,
+    removed: Option<&mut dyn Write>,
+    audit: Option<&mut AuditLog>,
+    options: &DeduplicationOptions,
+    encoding: TextEncoding,
+) -> Result<DeduplicationStats, Error> {
+    if !encoding.is_utf8() {
+        let files = files
+            .into_iter()
+            .map(|file| DecodingReader::new(file, encoding))
+            .collect();
+        return deduplicate_readers(files, output, removed, audit, options);
+    }
+    if audit.is_some()
+        || !files
+            .iter()
+            .all(|file| matches!(file, CompressedReader::Plain(_)))
+    {
+        return deduplicate_readers(files, output, removed, audit, options);
+    }
+    let files = files.into_iter().filter_map(CompressedReader::into_plain);
+    match removed {
+        Some(mut removed) => {
+            uniqr::deduplicate_many_split_seekable(files, output, &mut removed, options)
+        }
+        None => uniqr::deduplicate_many_seekable(files, output, options),
+    }
+}
+
+/// Deduplicate non-seekable inputs, sending removed records to `removed` if
+/// given and the decision about each line to `audit`
+fn deduplicate_readers<R: io::Read, W: Write>(
+    inputs: Vec<R>,
+    output: &mut W,
+    removed: Option<&mut dyn Write>,
+    audit: Option<&mut AuditLog>,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats, Error> {
+    if let Some(audit) = audit {
+        return uniqr::deduplicate_many_with_observer(inputs, output, options, |event| {
+            audit.record(&event)
+        });
+    }
+    match removed {
+        Some(mut removed) => uniqr::deduplicate_many_split(inputs, output, &mut removed, options),
+        None => uniqr::deduplicate_many(inputs, output, options),
+    }
+}
+
+/// The `--audit-log` file, with a record of the decision about each line
+struct AuditLog {
+    writer: BufWriter<File>,
+    format: AuditFormat,
+    /// Names of the inputs, if there are several
+    inputs: Vec<String>,
+    /// The first error writing the log, reported once the run is done
+    error: Option<io::Error>,
+}
+
+impl AuditLog {
+    fn create(path: &Path, format: AuditFormat, inputs: &[PathBuf]) -> Result<Self, Error> {
+        let writer = File::create(path).map(BufWriter::new).map_err(|e| {
+            Error::Io(io::Error::new(
+                e.kind(),
+                format!("Failed to create audit log '{}': {}", path.display(), e),
+            ))
+        })?;
+        let inputs = match inputs {
+            [] | [_] => Vec::new(),
+            _ => inputs
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect(),
+        };
+        let mut log = Self {
+            writer,
+            format,
+            inputs,
+            error: None,
+        };
+        if let AuditFormat::Tsv = format {
+            let file = if log.inputs.is_empty() { "" } else { "file\t" };
+            log.write(format_args!("{}line\taction\tcount\tkey_hash\n", file));
+        }
+        Ok(log)
+    }
+
+    /// Log one line's decision. Lines decided without a key, or whose key
+    /// isn't counted, have no count or hash.
+    fn record(&mut self, event: &LineEvent) {
+        let action = match event.action {
+            Decision::Keep => "kept",
+            Decision::Remove => "removed",
+        };
+        let count = event.count.map(|count| count.to_string());
+        let hash = event.key.map(|key| format!("{:016x}", key_hash(key)));
+        let file = self.inputs.get(event.source);
+        match self.format {
+            AuditFormat::Tsv => self.write(format_args!(
+                "{}{}\t{}\t{}\t{}\n",
+                file.map(|name| format!("{}\t", name)).unwrap_or_default(),
+                event.line,
+                action,
+                count.as_deref().unwrap_or("-"),
+                hash.as_deref().unwrap_or("-")
+            )),
+            AuditFormat::Jsonl => self.write(format_args!(
+                "{{{}\"line\":{},\"action\":\"{}\",\"count\":{},\"key_hash\":{}}}\n",
+                file.map(|name| format!("\"file\":{},", json_string(name)))
+                    .unwrap_or_default(),
+                event.line,
+                action,
+                count.as_deref().unwrap_or("null"),
+                hash.map_or("null".to_string(), |hash| format!("\"{}\"", hash))
+            )),
+        }
+    }
+
+    fn write(&mut self, line: std::fmt::Arguments) {
+        if self.error.is_none()
+            && let Err(e) = self.writer.write_fmt(line)
+        {
+            self.error = Some(e);
+        }
+    }
+
+    fn finish(mut self) -> Result<(), Error> {
+        match self.error.take() {
+            Some(e) => Err(Error::Io(io::Error::new(
+                e.kind(),
+                format!("Failed to write audit log: {}", e),
+            ))),
+            None => Ok(self.writer.flush()?),
+        }
+    }
+}
+
+/// 64-bit FNV-1a hash of `key`, which (unlike the seeded hashes of the key
+/// maps) is the same on every run, so audit logs can be compared
+fn key_hash(key: &[u8]) -> u64 {
+    key.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// `text` as a JSON string literal
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The `--removed-output` writer, if any, as a sink for the library
+fn removed_sink(
+    removed: &mut Option<EncodingWriter<CompressedWriter<BufWriter<File>>>>,
+) -> Option<&mut dyn Write> {
+    removed.as_mut().map(|writer| writer as &mut dyn Write)
+}
+
+/// Where kept and removed records go: kept records to `output` and removed
+/// ones to `removed`, or with `--only-removed`, removed records to `output`
+/// and kept ones nowhere
+fn route_records<'a>(
+    output: &'a mut dyn Write,
+    removed: Option<&'a mut dyn Write>,
+    only_removed: bool,
+) -> (Box<dyn Write + 'a>, Option<&'a mut dyn Write>) {
+    if only_removed {
+        (Box::new(io::sink()), Some(output))
+    } else {
+        (Box::new(output), removed)
+    }
+}
+
+/// Create an output file in `encoding`, compressed if `path` has a
+/// compressed extension
+fn create_output(
+    path: &Path,
+    encoding: TextEncoding,
+) -> Result<EncodingWriter<CompressedWriter<BufWriter<File>>>, Error> {
+    let file = File::create(path).map_err(|e| {
+        Error::Io(io::Error::new(
+            e.kind(),
+            format!("Failed to create '{}': {}", path.display(), e),
+        ))
+    })?;
+    EncodingWriter::new(
+        CompressedWriter::new(BufWriter::new(file), Compression::from_path(path))?,
+        encoding,
+    )
+}
+
+/// Write `path` atomically with `uniqr::write_atomically`. Output is written
+/// in `encoding`, and compressed if `path` has a compressed extension.
+fn write_atomically<F>(
+    path: &Path,
+    encoding: TextEncoding,
+    dedup: F,
+) -> Result<DeduplicationStats, Error>
+where
+    F: FnOnce(
+        &mut EncodingWriter<CompressedWriter<&mut BufWriter<File>>>,
+    ) -> Result<DeduplicationStats, Error>,
+{
+    uniqr::write_atomically(path, |file| {
+        let mut writer = EncodingWriter::new(
+            CompressedWriter::new(file, Compression::from_path(path))?,
+            encoding,
+        )?;
+        let stats = dedup(&mut writer)?;
+        writer.finish()?.finish()?;
+        Ok(stats)
+    })
+}
+
+/// Write to stdout with `dedup`. With a `tee` file, the output is written
+/// to it as well, atomically like `write_atomically`.
+fn write_stdout<F>(
+    tee: Option<&Path>,
+    encoding: TextEncoding,
+    options: &DeduplicationOptions,
+    color: bool,
+    rate_limit: Option<u32>,
+    dedup: F,
+) -> Result<DeduplicationStats, Error>
+where
+    F: FnOnce(&mut dyn Write) -> Result<DeduplicationStats, Error>,
+{
+    let stdout = io::stdout();
+    let mut writer = color::ColorWriter::new(
+        EncodingWriter::new(BufWriter::new(stdout.lock()), encoding)?,
+        options,
+        color,
+    );
+    let mut limited = RateLimited::new(&mut writer, rate_limit, options);
+    let stats = match tee {
+        Some(path) => write_atomically(path, encoding, |file| dedup(&mut Tee(&mut limited, file)))?,
+        None => dedup(&mut limited)?,
+    };
+    writer.finish()?.finish()?;
+    Ok(stats)
+}
+
+/// A writer that starts at most `per_second` lines (records, with `-z` or
+/// `--separator`) a second, waiting before any that would come too soon,
+/// and flushes each line it has written. Without a limit it passes writes
+/// straight through.
+struct RateLimited<W: Write> {
+    inner: W,
+    interval: Option<Duration>,
+    terminator: u8,
+    /// Whether a line has been started and not yet finished
+    in_line: bool,
+    /// When the next line may start
+    next: Option<Instant>,
+}
+
+impl<W: Write> RateLimited<W> {
+    fn new(inner: W, per_second: Option<u32>, options: &DeduplicationOptions) -> Self {
+        RateLimited {
+            inner,
+            interval: per_second.map(|n| Duration::from_secs(1) / n),
+            terminator: options
+                .record_separator
+                .terminator()
+                .last()
+                .copied()
+                .unwrap_or(b'\n'),
+            in_line: false,
+            next: None,
+        }
+    }
+}
+
+impl<W: Write> Write for RateLimited<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(interval) = self.interval else {
+            return self.inner.write(buf);
+        };
+        let mut rest = buf;
+        while !rest.is_empty() {
+            if !self.in_line {
+                let now = Instant::now();
+                let start = self.next.map_or(now, |next| next.max(now));
+                std::thread::sleep(start - now);
+                self.next = Some(start + interval);
+                self.in_line = true;
+            }
+            match rest.iter().position(|&byte| byte == self.terminator) {
+                Some(end) => {
+                    self.inner.write_all(&rest[..=end])?;
+                    self.inner.flush()?;
+                    self.in_line = false;
+                    rest = &rest[end + 1..];
+                }
+                None => {
+                    self.inner.write_all(rest)?;
+                    rest = &[];
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A writer that writes everything to both of its writers
+struct Tee<A: Write, B: Write>(A, B);
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write_all(buf)?;
+        self.1.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()?;
+        self.1.flush()
+    }
+}
+
+/// Write `path` with `dedup` like `write_atomically`, unless the run records
+/// checkpoints: then it is written in place, so what was written survives
+/// an interruption, and a resumed run continues it. With `append`, the
+/// output is added to the end of `path` instead.
+fn write_output<F>(
+    path: &Path,
+    encoding: TextEncoding,
+    options: &DeduplicationOptions,
+    append: bool,
+    dedup: F,
+) -> Result<DeduplicationStats, Error>
+where
+    F: FnOnce(
+        &mut EncodingWriter<CompressedWriter<&mut BufWriter<File>>>,
+    ) -> Result<DeduplicationStats, Error>,
+{
+    #[cfg(feature = "disk-backed")]
+    if options.checkpoint_every.is_some() || options.resume {
+        if append {
+            return Err(Error::InvalidArgument(
+                "Checkpointed runs can't append to their output".to_string(),
+            ));
+        }
+        return write_checkpointed(path, encoding, options, dedup);
+    }
+    if append {
+        return append_output(path, encoding, options, dedup);
+    }
+    write_atomically(path, encoding, dedup)
+}
+
+/// Append to `path` with `dedup`, creating it if needed. It is written in
+/// place, since replacing it atomically would mean copying what it holds.
+/// A last record left without a terminator gets one first, so the first
+/// record appended doesn't run into it.
+fn append_output<F>(
+    path: &Path,
+    encoding: TextEncoding,
+    options: &DeduplicationOptions,
+    dedup: F,
+) -> Result<DeduplicationStats, Error>
+where
+    F: FnOnce(
+        &mut EncodingWriter<CompressedWriter<&mut BufWriter<File>>>,
+    ) -> Result<DeduplicationStats, Error>,
+{
+    use std::io::{Seek, SeekFrom};
+
+    if Compression::from_path(path) != Compression::None || !encoding.is_utf8() {
+        return Err(Error::InvalidArgument(
+            "Appended output must be uncompressed UTF-8".to_string(),
+        ));
+    }
+    let mut file = File::options()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)
+        .map_err(|e| {
+            Error::Io(io::Error::new(
+                e.kind(),
+                format!("Failed to open '{}': {}", path.display(), e),
+            ))
+        })?;
+    let terminator = options.record_separator.terminator();
+    let len = file.metadata()?.len();
+    let mut tail = vec![0; terminator.len().min(len as usize)];
+    file.seek(SeekFrom::End(-(tail.len() as i64)))?;
+    file.read_exact(&mut tail)?;
+
+    let mut file = BufWriter::new(file);
+    if len > 0 && tail != terminator {
+        file.write_all(terminator)?;
+    }
+    let mut writer = EncodingWriter::new(
+        CompressedWriter::new(&mut file, Compression::None)?,
+        encoding,
+    )?;
+    let stats = dedup(&mut writer)?;
+    writer.finish()?.finish()?;
+    file.flush()?;
+    Ok(stats)
+}
+
+/// Write `path` in place for a checkpointed run. A resumed run first cuts
+/// it back to the length it had at the checkpoint.
+#[cfg(feature = "disk-backed")]
+fn write_checkpointed<F>(
+    path: &Path,
+    encoding: TextEncoding,
+    options: &DeduplicationOptions,
+    dedup: F,
+) -> Result<DeduplicationStats, Error>
+where
+    F: FnOnce(
+        &mut EncodingWriter<CompressedWriter<&mut BufWriter<File>>>,
+    ) -> Result<DeduplicationStats, Error>,
+{
+    use std::io::{Seek, SeekFrom};
+
+    if Compression::from_path(path) != Compression::None || !encoding.is_utf8() {
+        return Err(Error::InvalidArgument(
+            "Checkpointed output must be uncompressed UTF-8".to_string(),
+        ));
+    }
+    let open_error = |e: io::Error| {
+        Error::Io(io::Error::new(
+            e.kind(),
+            format!("Failed to open '{}': {}", path.display(), e),
+        ))
+    };
+    let mut file = if options.resume {
+        let checkpoint = uniqr::read_checkpoint(options)?.ok_or_else(|| {
+            Error::InvalidArgument(
+                "The state database has no checkpoint to resume from".to_string(),
+            )
+        })?;
+        let mut file = File::options().write(true).open(path).map_err(open_error)?;
+        file.set_len(checkpoint.output_offset)?;
+        file.seek(SeekFrom::End(0))?;
+        BufWriter::new(file)
+    } else {
+        BufWriter::new(File::create(path).map_err(open_error)?)
+    };
+    let mut writer = EncodingWriter::new(
+        CompressedWriter::new(&mut file, Compression::None)?,
+        encoding,
+    )?;
+    let stats = dedup(&mut writer)?;
+    writer.finish()?.finish()?;
+    file.flush()?;
+    Ok(stats)
+}
+
+/// Expand glob patterns and (with `recursive`) directories into a list of files.
+///
+/// Directory walks are sorted by file name so the deduplication order is stable.
+fn expand_inputs(
+    inputs: &[PathBuf],
+    recursive: bool,
+    include: &[String],
+) -> Result<Vec<PathBuf>, Error> {
+    let include = include
+        .iter()
+        .map(|pattern| {
+            glob::Pattern::new(pattern).map_err(|e| {
+                Error::InvalidArgument(format!("Invalid --include pattern '{}': {}", pattern, e))
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let mut files = Vec::new();
+    for input in inputs {
+        let text = input.to_string_lossy();
+        let paths = if !input.exists() && text.contains(['*', '?', '[']) {
+            let matches = glob::glob(&text)
+                .map_err(|e| {
+                    Error::InvalidArgument(format!("Invalid glob pattern '{}': {}", text, e))
+                })?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| Error::Io(e.into()))?;
+            if matches.is_empty() {
+                return Err(Error::InvalidArgument(format!(
+                    "No files match pattern '{}'",
+                    text
+                )));
+            }
+            matches
+        } else {
+            vec![input.clone()]
+        };
+
+        for path in paths {
+            if !path.is_dir() {
+                files.push(path);
+                continue;
+            }
+            if !recursive {
+                return Err(Error::InvalidArgument(format!(
+                    "'{}' is a directory (use --recursive)",
+                    path.display()
+                )));
+            }
+            for entry in walkdir::WalkDir::new(&path).sort_by_file_name() {
+                let entry = entry.map_err(|e| Error::Io(e.into()))?;
+                if entry.file_type().is_file()
+                    && (include.is_empty()
+                        || include
+                            .iter()
+                            .any(|p| p.matches_path(Path::new(entry.file_name()))))
+                {
+                    files.push(entry.into_path());
+                }
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Expand backslash escapes in a `--separator` argument
+fn unescape_separator(sep: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(sep.len());
+    let mut chars = sep.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0u8; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('r') => bytes.push(b'\r'),
+            Some('0') => bytes.push(b'\0'),
+            Some('\\') | None => bytes.push(b'\\'),
+            Some(other) => {
+                bytes.push(b'\\');
+                let mut buf = [0u8; 4];
+                bytes.extend_from_slice(other.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+    bytes
+}
+
+/// Status reports on SIGUSR1, like `dd`'s: a thread waits for the signal
+/// and prints the running totals of the run to stderr
+mod status {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Instant;
+    use uniqr::{DeduplicationStats, Progress, ProgressCallback, RecordCallback, RecordLocation};
+
+    /// Totals across the runs of one invocation
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub struct Status {
+        bytes_read: AtomicU64,
+        lines_read: AtomicU64,
+        lines_written: AtomicU64,
+        start: Instant,
+    }
+
+    /// Start waiting for SIGUSR1 on a thread of its own. Call this before
+    /// starting any other thread, which would otherwise receive the signal
+    /// (and be killed by it).
+    #[cfg(unix)]
+    pub fn listen() -> Option<Arc<Status>> {
+        let status = Arc::new(Status {
+            bytes_read: AtomicU64::new(0),
+            lines_read: AtomicU64::new(0),
+            lines_written: AtomicU64::new(0),
+            start: Instant::now(),
+        });
+        // SAFETY: the set is initialized by sigemptyset before it is used,
+        // and threads started later inherit the mask
+        let signals = unsafe {
+            let mut signals: libc::sigset_t = std::mem::zeroed();
+            libc::sigemptyset(&mut signals);
+            libc::sigaddset(&mut signals, libc::SIGUSR1);
+            if libc::pthread_sigmask(libc::SIG_BLOCK, &signals, std::ptr::null_mut()) != 0 {
+                return None;
+            }
+            signals
+        };
+        let reported = Arc::clone(&status);
+        std::thread::spawn(move || {
+            let mut signal = 0;
+            // SAFETY: the set was initialized above
+            while unsafe { libc::sigwait(&signals, &mut signal) } == 0 {
+                eprint!("{}", reported.report());
+            }
+        });
+        Some(status)
+    }
+
+    #[cfg(not(unix))]
+    pub fn listen() -> Option<Arc<Status>> {
+        None
+    }
+
+    /// The progress of one run, added to the totals before it
+    struct RunProgress {
+        status: Arc<Status>,
+        bytes_base: u64,
+        lines_base: u64,
+    }
+
+    impl ProgressCallback for RunProgress {
+        fn report(&self, progress: Progress) {
+            let bytes_read = self.bytes_base + progress.bytes_read;
+            self.status.bytes_read.store(bytes_read, Ordering::Relaxed);
+        }
+
+        fn record_read(&self, lines_read: u64) {
+            let lines_read = self.lines_base + lines_read;
+            self.status.lines_read.store(lines_read, Ordering::Relaxed);
+        }
+    }
+
+    impl Status {
+        /// Callback adding the progress of a run, which counts from zero, to
+        /// the totals so far
+        pub fn progress_callback(self: &Arc<Self>) -> Arc<dyn ProgressCallback> {
+            Arc::new(RunProgress {
+                bytes_base: self.bytes_read.load(Ordering::Relaxed),
+                lines_base: self.lines_read.load(Ordering::Relaxed),
+                status: Arc::clone(self),
+            })
+        }
+
+        /// Callback counting the lines written
+        pub fn record_callback(self: &Arc<Self>) -> Arc<dyn RecordCallback> {
+            let status = Arc::clone(self);
+            Arc::new(move |_: &[u8], _: RecordLocation| {
+                status.lines_written.fetch_add(1, Ordering::Relaxed);
+            })
+        }
+
+        /// One line with the totals so far and the rates they were reached at
+        fn report(&self) -> String {
+            let stats = DeduplicationStats {
+                lines_read: self.lines_read.load(Ordering::Relaxed) as usize,
+                lines_written: self.lines_written.load(Ordering::Relaxed) as usize,
+                bytes_read: self.bytes_read.load(Ordering::Relaxed),
+                elapsed: self.start.elapsed(),
+                ..Default::default()
+            };
+            format!(
+                "{} lines read, {} written, {} bytes in {:.1}s ({:.0} lines/s, {:.2} MiB/s)\n",
+                stats.lines_read,
+                stats.lines_written,
+                stats.bytes_read,
+                stats.elapsed.as_secs_f64(),
+                stats.lines_per_second(),
+                stats.bytes_per_second() / (1024.0 * 1024.0)
+            )
+        }
+    }
+}
+
+/// ANSI colors for counts and `[REMOVED]` markers on a terminal
+mod color {
+    use super::ColorArg;
+    use std::io::{self, IsTerminal, Write};
+    use uniqr::{CountFormat, CountPlacement, DeduplicationOptions};
+
+    const COUNT: &[u8] = b"\x1b[33m";
+    const REMOVED: &[u8] = b"\x1b[31m";
+    const RESET: &[u8] = b"\x1b[0m";
+
+    const MARKER: &[u8] = b"[REMOVED]";
+
+    /// Whether `--color` colors stdout. Auto follows the no-color.org
+    /// convention of a non-empty NO_COLOR turning colors off.
+    pub fn enabled(when: ColorArg) -> bool {
+        match when {
+            ColorArg::Always => true,
+            ColorArg::Never => false,
+            ColorArg::Auto => {
+                io::stdout().is_terminal()
+                    && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                    && std::env::var_os("TERM").is_none_or(|term| term != "dumb")
+            }
+        }
+    }
+
+    /// A writer that colors the counts and `[REMOVED]` markers of the
+    /// records written to it. Records are held until their terminator is
+    /// written, so a marker split across writes is still found. Disabled,
+    /// it writes through unchanged.
+    pub struct ColorWriter<W: Write> {
+        inner: W,
+        enabled: bool,
+        terminator: Vec<u8>,
+        count_format: Option<CountFormat>,
+        show_removed: bool,
+        /// Start of the record being written
+        record: Vec<u8>,
+        /// Whether the start of the record was flushed out already
+        started: bool,
+    }
+
+    impl<W: Write> ColorWriter<W> {
+        /// Wrap `inner`, coloring the records `options` writes if `enabled`
+        pub fn new(inner: W, options: &DeduplicationOptions, enabled: bool) -> Self {
+            Self {
+                inner,
+                enabled,
+                terminator: options.record_separator.terminator().to_vec(),
+                count_format: options.count.then(|| options.count_format.clone()),
+                show_removed: options.show_removed,
+                record: Vec::new(),
+                started: false,
+            }
+        }
+
+        /// Write out a record left without a terminator, flush, and return
+        /// the underlying writer
+        pub fn finish(mut self) -> io::Result<W> {
+            self.flush()?;
+            Ok(self.inner)
+        }
+
+        /// Write `record` (with its terminator, if it has one) in color
+        fn paint(&mut self, record: &[u8]) -> io::Result<()> {
+            if let Some(rest) = record.strip_prefix(MARKER)
+                && self.show_removed
+            {
+                return self.write_colored(&[], REMOVED, MARKER, rest);
+            }
+            let Some(format) = &self.count_format else {
+                return self.inner.write_all(record);
+            };
+            let separator = format.separator.as_bytes();
+            match format.placement {
+                CountPlacement::Prefix => {
+                    let padding = record.iter().take_while(|&&b| b == b' ').count();
+                    let digits = count_digits(record[padding..].iter());
+                    let (before, rest) = record.split_at(padding);
+                    let (count, rest) = rest.split_at(digits);
+                    match digits > 0 && rest.starts_with(separator) {
+                        true => self.write_colored(before, COUNT, count, rest),
+                        false => self.inner.write_all(record),
+                    }
+                }
+                CountPlacement::Suffix => {
+                    let content = record.strip_suffix(&self.terminator[..]).unwrap_or(record);
+                    let digits = count_digits(content.iter().rev());
+                    let (before, count) = content.split_at(content.len() - digits);
+                    let padding = before.iter().rev().take_while(|&&b| b == b' ').count();
+                    let separated =
+                        (0..=padding).any(|len| before[..before.len() - len].ends_with(separator));
+                    match digits > 0 && separated {
+                        true => self.write_colored(before, COUNT, count, &record[content.len()..]),
+                        false => self.inner.write_all(record),
+                    }
+                }
+            }
+        }
+
+        fn write_colored(
+            &mut self,
+            before: &[u8],
+            color: &[u8],
+            text: &[u8],
+            after: &[u8],
+        ) -> io::Result<()> {
+            self.inner.write_all(before)?;
+            self.inner.write_all(color)?;
+            self.inner.write_all(text)?;
+            self.inner.write_all(RESET)?;
+            self.inner.write_all(after)
+        }
+    }
+
+    /// Number of ASCII digits `bytes` starts with
+    fn count_digits<'a>(bytes: impl Iterator<Item = &'a u8>) -> usize {
+        bytes.take_while(|b| b.is_ascii_digit()).count()
+    }
+
+    impl<W: Write> Write for ColorWriter<W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if !self.enabled {
+                return self.inner.write(buf);
+            }
+            self.record.extend_from_slice(buf);
+            while let Some(pos) = self
+                .record
+                .windows(self.terminator.len())
+                .position(|window| window == self.terminator)
+            {
+                let rest = self.record.split_off(pos + self.terminator.len());
+                let record = std::mem::replace(&mut self.record, rest);
+                match std::mem::take(&mut self.started) {
+                    true => self.inner.write_all(&record)?,
+                    false => self.paint(&record)?,
+                }
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            if !self.record.is_empty() {
+                let record = std::mem::take(&mut self.record);
+                match std::mem::replace(&mut self.started, true) {
+                    true => self.inner.write_all(&record)?,
+                    false => self.paint(&record)?,
+                }
+            }
+            self.inner.flush()
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! Composable key normalization
+use crate::error::Result;
+use crate::{
+    CaseMode, PunctuationMode, TrimMode, column_key, fold_case, strip_punctuation, trim_key,
+};
+use std::fmt;
+
+/// One step of the key-normalization pipeline.
+///
+/// `DeduplicationOptions::normalizers` run in order on the key produced by
+/// the built-in key options (or by `DeduplicationOptions::key_extractor`),
+/// each receiving the previous one's output, so steps such as "lowercase
+/// then strip digits" compose in whatever order they are listed.
+///
+/// Closures of type `Fn(&[u8]) -> Vec<u8>` implement this trait.
+pub trait Normalizer: Send + Sync {
+    fn normalize(&self, key: Vec<u8>) -> Result<Vec<u8>>;
+}
+
+impl<F> Normalizer for F
+where
+    F: Fn(&[u8]) -> Vec<u8> + Send + Sync,
+{
+    fn normalize(&self, key: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(self(&key))
+    }
+}
+
+impl fmt::Debug for dyn Normalizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Normalizer")
+    }
+}
+
+/// Keep only the whitespace-separated column at this 1-based index, or the
+/// whole key if it has fewer columns (like `DeduplicationOptions::column`)
+#[derive(Debug, Clone, Copy)]
+pub struct Column(pub usize);
+
+impl Normalizer for Column {
+    fn normalize(&self, key: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(column_key(&key, self.0))
+    }
+}
+
+/// Trim whitespace (like `DeduplicationOptions::trim`)
+#[derive(Debug, Clone, Copy)]
+pub struct Trim(pub TrimMode);
+
+impl Normalizer for Trim {
+    fn normalize(&self, key: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(trim_key(key, self.0))
+    }
+}
+
+/// Ignore case; keys that aren't valid UTF-8 are folded as ASCII
+#[derive(Debug, Clone, Copy)]
+pub struct CaseFold(pub CaseMode);
+
+impl Normalizer for CaseFold {
+    fn normalize(&self, key: Vec<u8>) -> Result<Vec<u8>> {
+        match String::from_utf8(key) {
+            Ok(s) => Ok(fold_case(&s, self.0)?.into_bytes()),
+            Err(e) => {
+                let mut bytes = e.into_bytes();
+                bytes.make_ascii_lowercase();
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+/// Remove punctuation (like `DeduplicationOptions::punctuation`)
+#[derive(Debug, Clone, Copy)]
+pub struct Punctuation(pub PunctuationMode);
+
+impl Normalizer for Punctuation {
+    fn normalize(&self, key: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(strip_punctuation(key, self.0))
+    }
+}
+
+/// Replace every match of a regular expression, e.g. `\d+` with nothing to
+/// ignore numbers (requires 'regex' feature). The replacement may refer to
+/// capture groups as `$1` or `$name`.
+#[cfg(feature = "regex")]
+#[derive(Debug, Clone)]
+pub struct RegexReplace {
+    regex: regex::bytes::Regex,
+    replacement: Vec<u8>,
+}
+
+#[cfg(feature = "regex")]
+impl RegexReplace {
+    pub fn new(pattern: &str, replacement: &str) -> Result<Self> {
+        let regex = regex::bytes::Regex::new(pattern).map_err(|e| {
+            crate::Error::InvalidArgument(format!("Invalid regex '{}': {}", pattern, e))
+        })?;
+        Ok(Self {
+            regex,
+            replacement: replacement.as_bytes().to_vec(),
+        })
+    }
+}
+
+#[cfg(feature = "regex")]
+impl Normalizer for RegexReplace {
+    fn normalize(&self, key: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(self
+            .regex
+            .replace_all(&key, self.replacement.as_slice())
+            .into_owned())
+    }
+}
+
+/// Log formats with a built-in `LogPreset`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// BSD (`Jan  2 15:04:05 host sshd[42]: ...`) and RFC 5424 syslog lines
+    Syslog,
+    /// nginx access logs (combined format) and error logs
+    Nginx,
+    /// Apache access logs (common and combined formats) and error logs
+    Apache,
+    /// `journalctl` output in its short, short-precise and short-iso formats
+    Journald,
+}
+
+/// RFC 3339 timestamps, as in RFC 5424 syslog and `journalctl -o short-iso`
+#[cfg(feature = "regex")]
+const ISO_TIMESTAMP: &str =
+    r"\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(?:[.,]\d+)?(?:Z|[+-]\d{2}:?\d{2})?";
+
+/// `Jan  2 15:04:05` timestamps of BSD syslog and `journalctl`
+#[cfg(feature = "regex")]
+const BSD_TIMESTAMP: &str = r"[A-Za-z]{3} [ \d]\d \d{2}:\d{2}:\d{2}(?:\.\d+)?";
+
+/// `[10/Oct/2000:13:55:36 -0700]` timestamps of web server access logs
+#[cfg(feature = "regex")]
+const ACCESS_TIMESTAMP: &str = r"\[\d{2}/[A-Za-z]{3}/\d{4}:\d{2}:\d{2}:\d{2} [+-]\d{4}\]";
+
+/// Request and trace IDs: UUIDs, and the 32 hex digits of nginx's
+/// `$request_id` and journald's IDs
+#[cfg(feature = "regex")]
+const REQUEST_ID: &str = r"\b(?:[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}|[0-9a-fA-F]{32})\b";
+
+/// Strip what changes between repeats of the same log line in a common
+/// format (timestamps, process IDs, request IDs), so they key alike
+/// (requires 'regex' feature). Case-insensitive, so it works after
+/// `ignore_case` too.
+#[cfg(feature = "regex")]
+#[derive(Debug, Clone)]
+pub struct LogPreset {
+    steps: Vec<RegexReplace>,
+}
+
+#[cfg(feature = "regex")]
+impl LogPreset {
+    /// The steps that strip the varying parts of `format`
+    pub fn new(format: LogFormat) -> Result<Self> {
+        let steps: &[(&str, &str)] = match format {
+            LogFormat::Syslog => &[
+                // Priority, version and timestamp, then `sshd[42]:`
+                (r"^(?:<\d+>(?:1 )?)?", ""),
+                (&format!(r"^(?:{}|{})\s*", BSD_TIMESTAMP, ISO_TIMESTAMP), ""),
+                (r"\[\d+\]:", ":"),
+                (REQUEST_ID, ""),
+            ],
+            LogFormat::Nginx => &[
+                (ACCESS_TIMESTAMP, ""),
+                // Error log: `2024/01/02 15:04:05 [error] 42#42: *7 ...`
+                (r"^\d{4}/\d{2}/\d{2} \d{2}:\d{2}:\d{2} ", ""),
+                (r"\d+#\d+: (?:\*\d+ )?", ""),
+                (REQUEST_ID, ""),
+            ],
+            LogFormat::Apache => &[
+                (ACCESS_TIMESTAMP, ""),
+                // Error log: `[Wed Oct 11 14:32:52.123 2000] [core:error]
+                // [pid 42:tid 43] [client 1.2.3.4:5678] ...`
+                (
+                    r"\[[A-Za-z]{3} [A-Za-z]{3} [ \d]\d \d{2}:\d{2}:\d{2}(?:\.\d+)? \d{4}\]",
+                    "",
+                ),
+                (r"\[pid \d+(?::tid \d+)?\]", ""),
+                (r"(\[client [^\]]+):\d+\]", "$1]"),
+                (REQUEST_ID, ""),
+            ],
+            LogFormat::Journald => &[
+                (&format!(r"^(?:{}|{})\s*", BSD_TIMESTAMP, ISO_TIMESTAMP), ""),
+                (r"\[\d+\]:", ":"),
+                (REQUEST_ID, ""),
+            ],
+        };
+        let steps = steps
+            .iter()
+            .map(|(pattern, replacement)| {
+                RegexReplace::new(&format!("(?i){}", pattern), replacement)
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { steps })
+    }
+}
+
+#[cfg(feature = "regex")]
+impl Normalizer for LogPreset {
+    fn normalize(&self, key: Vec<u8>) -> Result<Vec<u8>> {
+        self.steps
+            .iter()
+            .try_fold(key, |key, step| step.normalize(key))
+    }
+}
+
+/// Query parameters that only track where a visitor came from
+#[cfg(feature = "url")]
+const TRACKING_PARAMS: &[&str] = &[
+    "fbclid", "gclid", "dclid", "gbraid", "wbraid", "msclkid", "yclid", "igshid", "mc_cid",
+    "mc_eid", "_ga", "_gl",
+];
+
+/// Canonicalize URLs (requires 'url' feature): lowercase the scheme and
+/// host, drop default ports and fragments, remove tracking query parameters
+/// (`utm_*`, `gclid`, `fbclid` and the like) and sort the rest by name, so
+/// links to the same page key alike. Keys that aren't absolute URLs are left
+/// as they are.
+#[cfg(feature = "url")]
+#[derive(Debug, Clone, Copy)]
+pub struct CanonicalUrl;
+
+#[cfg(feature = "url")]
+impl Normalizer for CanonicalUrl {
+    fn normalize(&self, key: Vec<u8>) -> Result<Vec<u8>> {
+        // Parsing lowercases the scheme and host and drops default ports
+        let Some(mut url) = std::str::from_utf8(&key)
+            .ok()
+            .and_then(|text| url::Url::parse(text).ok())
+        else {
+            return Ok(key);
+        };
+        url.set_fragment(None);
+        let mut params: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(name, _)| {
+                let name = name.to_ascii_lowercase();
+                !name.starts_with("utm_") && !TRACKING_PARAMS.contains(&name.as_str())
+            })
+            .map(|(name, value)| (name.into_owned(), value.into_owned()))
+            .collect();
+        // Stable, so repeats of a parameter keep their order
+        params.sort_by(|a, b| a.0.cmp(&b.0));
+        if params.is_empty() {
+            url.set_query(None);
+        } else {
+            url.query_pairs_mut().clear().extend_pairs(params);
+        }
+        Ok(String::from(url).into_bytes())
+    }
+}
@@ -0,0 +1,133 @@
+//! Progress reporting for long-running deduplication
+use std::cell::Cell;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Snapshot of how far deduplication has got
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Input bytes consumed so far, across all inputs (after decompression)
+    pub bytes_read: u64,
+    /// Records read so far, across all inputs, including those skipped for
+    /// `exclude_keys`. Records read again by a second pass aren't counted
+    /// again.
+    pub lines_read: u64,
+}
+
+/// Receives progress updates while inputs are read.
+///
+/// Called from the deduplicating thread each time a buffer of input is
+/// consumed, so implementations should be cheap (or throttle themselves).
+/// Closures of type `Fn(Progress)` implement this trait.
+pub trait ProgressCallback: Send + Sync {
+    fn report(&self, progress: Progress);
+
+    /// Called each time a record is read, with the number read so far (as
+    /// in `Progress::lines_read`), for counts that are current between
+    /// reports. Does nothing by default.
+    fn record_read(&self, _lines_read: u64) {}
+}
+
+impl<F> ProgressCallback for F
+where
+    F: Fn(Progress) + Send + Sync,
+{
+    fn report(&self, progress: Progress) {
+        self(progress)
+    }
+}
+
+impl fmt::Debug for dyn ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressCallback")
+    }
+}
+
+/// Running byte and record counts shared by the inputs of one deduplication
+/// run, for progress reports and the final statistics
+pub(crate) struct ProgressTracker<'a> {
+    callback: Option<&'a dyn ProgressCallback>,
+    bytes_read: Cell<u64>,
+    lines_read: Cell<u64>,
+}
+
+impl<'a> ProgressTracker<'a> {
+    pub(crate) fn new(callback: Option<&'a dyn ProgressCallback>) -> Self {
+        Self {
+            callback,
+            bytes_read: Cell::new(0),
+            lines_read: Cell::new(0),
+        }
+    }
+
+    /// Bytes counted so far
+    pub(crate) fn bytes_read(&self) -> u64 {
+        self.bytes_read.get()
+    }
+
+    pub(crate) fn advance(&self, bytes: u64) {
+        let bytes_read = self.bytes_read.get() + bytes;
+        self.bytes_read.set(bytes_read);
+        if let Some(callback) = self.callback {
+            callback.report(Progress {
+                bytes_read,
+                lines_read: self.lines_read.get(),
+            });
+        }
+    }
+
+    /// Records counted so far
+    pub(crate) fn lines_read(&self) -> u64 {
+        self.lines_read.get()
+    }
+
+    /// Count a record read
+    pub(crate) fn count_line(&self) {
+        let lines_read = self.lines_read.get() + 1;
+        self.lines_read.set(lines_read);
+        if let Some(callback) = self.callback {
+            callback.record_read(lines_read);
+        }
+    }
+}
+
+/// Input wrapper that reports newly read bytes to a `ProgressTracker`.
+///
+/// Bytes re-read after seeking back (the second pass of the disk-backed
+/// modes) are not counted again, so progress never exceeds the input size.
+pub(crate) struct ProgressReader<'t, 'a, R> {
+    inner: R,
+    tracker: &'t ProgressTracker<'a>,
+    position: u64,
+    high_water: u64,
+}
+
+impl<'t, 'a, R> ProgressReader<'t, 'a, R> {
+    pub(crate) fn new(inner: R, tracker: &'t ProgressTracker<'a>) -> Self {
+        Self {
+            inner,
+            tracker,
+            position: 0,
+            high_water: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<'_, '_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.position += read as u64;
+        if self.position > self.high_water {
+            self.tracker.advance(self.position - self.high_water);
+            self.high_water = self.position;
+        }
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for ProgressReader<'_, '_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = self.inner.seek(pos)?;
+        Ok(self.position)
+    }
+}
@@ -0,0 +1,758 @@
+use crate::error::Error;
+use crate::progress::ProgressTracker;
+use crate::{DeduplicationOptions, EmptyLinePolicy, LineLengthPolicy, RecordLocation, Utf8Policy};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+use std::sync::atomic::AtomicBool;
+
+/// How input is split into records
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordSeparator {
+    /// Records end with a single byte (`b'\n'` for lines, `b'\0'` for `-z`)
+    Byte(u8),
+    /// Records end with a multi-byte separator such as `"---\n"`
+    Bytes(Vec<u8>),
+    /// Records are paragraphs: runs of lines separated by blank lines
+    Paragraph,
+    /// Newline-terminated records where newlines inside double quotes don't
+    /// end the record (CSV-style quoting)
+    QuotedLine,
+}
+
+impl Default for RecordSeparator {
+    fn default() -> Self {
+        RecordSeparator::Byte(b'\n')
+    }
+}
+
+impl RecordSeparator {
+    /// Terminator appended to a record that ends at an input boundary
+    pub fn terminator(&self) -> &[u8] {
+        match self {
+            RecordSeparator::Byte(sep) => std::slice::from_ref(sep),
+            RecordSeparator::Bytes(sep) => sep,
+            RecordSeparator::Paragraph | RecordSeparator::QuotedLine => b"\n",
+        }
+    }
+
+    /// Length of a record without its terminator (and, for newline records, a
+    /// preceding `\r`; for paragraphs, the trailing blank lines)
+    pub(crate) fn content_len(&self, record: &[u8]) -> usize {
+        match self {
+            RecordSeparator::Paragraph => record
+                .iter()
+                .rposition(|b| !b.is_ascii_whitespace())
+                .map_or(0, |idx| idx + 1),
+            RecordSeparator::QuotedLine => RecordSeparator::Byte(b'\n').content_len(record),
+            // These only strip a suffix, so the content is a prefix of the record
+            RecordSeparator::Byte(_) | RecordSeparator::Bytes(_) => self.strip(record).len(),
+        }
+    }
+
+    /// Strip the terminator from a record, leaving the content used for keys.
+    ///
+    /// Newline records also drop a preceding `\r`. Paragraph records drop the
+    /// surrounding blank lines.
+    pub fn strip<'a>(&self, record: &'a [u8]) -> &'a [u8] {
+        match self {
+            RecordSeparator::Byte(sep) => match record.strip_suffix(&[*sep]) {
+                Some(content) if *sep == b'\n' => content.strip_suffix(b"\r").unwrap_or(content),
+                Some(content) => content,
+                None => record,
+            },
+            RecordSeparator::Bytes(sep) => record.strip_suffix(&sep[..]).unwrap_or(record),
+            RecordSeparator::QuotedLine => RecordSeparator::Byte(b'\n').strip(record),
+            RecordSeparator::Paragraph => {
+                let start = record
+                    .iter()
+                    .position(|b| !b.is_ascii_whitespace())
+                    .unwrap_or(record.len());
+                let end = record
+                    .iter()
+                    .rposition(|b| !b.is_ascii_whitespace())
+                    .map_or(start, |idx| idx + 1);
+                &record[start..end]
+            }
+        }
+    }
+}
+
+/// Receives each record written to the main output, with where it was read from.
+///
+/// Called in output order, before the record is written. The record includes
+/// its terminator but no count or source annotation. Closures of type
+/// `Fn(&[u8], RecordLocation)` implement this trait.
+pub trait RecordCallback: Send + Sync {
+    fn record_kept(&self, record: &[u8], location: RecordLocation);
+}
+
+impl<F> RecordCallback for F
+where
+    F: Fn(&[u8], RecordLocation) + Send + Sync,
+{
+    fn record_kept(&self, record: &[u8], location: RecordLocation) {
+        self(record, location)
+    }
+}
+
+impl fmt::Debug for dyn RecordCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RecordCallback")
+    }
+}
+
+/// Records that are written through without being deduplicated, picked by
+/// a regular expression matched against their content (requires 'regex'
+/// feature). Useful for comments and blank lines of config files, which
+/// must never be removed.
+#[derive(Debug, Clone)]
+pub struct RecordFilter {
+    #[cfg(feature = "regex")]
+    regex: regex::bytes::Regex,
+    /// Records that don't match pass through, rather than those that do
+    invert: bool,
+}
+
+impl RecordFilter {
+    /// Pass records matching `pattern` through
+    pub fn pass_matching(pattern: &str) -> crate::Result<Self> {
+        Self::new(pattern, false)
+    }
+
+    /// Only deduplicate records matching `pattern`, passing the others through
+    pub fn only_matching(pattern: &str) -> crate::Result<Self> {
+        Self::new(pattern, true)
+    }
+
+    #[cfg(feature = "regex")]
+    fn new(pattern: &str, invert: bool) -> crate::Result<Self> {
+        let regex = regex::bytes::Regex::new(pattern)
+            .map_err(|e| Error::InvalidArgument(format!("Invalid regex '{}': {}", pattern, e)))?;
+        Ok(Self { regex, invert })
+    }
+
+    #[cfg(not(feature = "regex"))]
+    fn new(_pattern: &str, _invert: bool) -> crate::Result<Self> {
+        Err(Error::InvalidArgument(
+            "Pass-through patterns require the 'regex' feature".to_string(),
+        ))
+    }
+
+    /// Whether a record with `content` (without its terminator) passes through
+    pub(crate) fn passes_through(&self, content: &[u8]) -> bool {
+        #[cfg(feature = "regex")]
+        let matched = self.regex.is_match(content);
+        // Filters can't be built without the feature
+        #[cfg(not(feature = "regex"))]
+        let matched = {
+            let _ = content;
+            false
+        };
+        matched != self.invert
+    }
+}
+
+/// Reads records from one or more buffered readers according to a `RecordSeparator`.
+///
+/// Each record is returned with its terminator attached so it can be written
+/// back out unchanged. With several inputs, records are read from each in
+/// turn and never span two inputs.
+pub struct RecordReader<'a, R> {
+    inputs: VecDeque<R>,
+    separator: &'a RecordSeparator,
+    /// Index of the input the last record came from
+    source: usize,
+    /// 1-based position of the last record within its input
+    record_number: usize,
+    /// Records read so far across all inputs
+    records_read: usize,
+    /// Input bytes consumed by those records
+    bytes_read: u64,
+    /// Offset in the inputs at which the last record starts
+    record_offset: u64,
+    /// Line read past the end of a paragraph, held for the next record
+    pending: Vec<u8>,
+    /// Longest line allowed, and what to do with longer ones
+    line_limit: Option<LineLimit>,
+    /// The last record was a piece of a line over the limit, passed through
+    passed_through: bool,
+    /// The rest of that line is still to be read
+    long_line: bool,
+    /// Records that aren't valid UTF-8 are passed through
+    skip_invalid_utf8: bool,
+    /// Records this picks are passed through
+    filter: Option<&'a RecordFilter>,
+    /// Empty records are passed through
+    preserve_empty: bool,
+    /// Options whose excluded keys are skipped, and the count of records skipped
+    exclusion: Option<(&'a DeduplicationOptions, &'a Cell<usize>)>,
+    /// Where records read are counted for progress reports
+    tracker: Option<&'a ProgressTracker<'a>>,
+    /// Reading fails with `Error::Cancelled` once this is set
+    cancel: Option<&'a AtomicBool>,
+}
+
+#[derive(Clone, Copy)]
+struct LineLimit {
+    max: usize,
+    policy: LineLengthPolicy,
+}
+
+impl<'a, R: BufRead> RecordReader<'a, R> {
+    /// Create a record reader over `inner`
+    pub fn new(inner: R, separator: &'a RecordSeparator) -> Self {
+        Self::from_inputs(vec![inner], separator)
+    }
+
+    /// Create a record reader that reads each input in order
+    pub fn from_inputs(inputs: Vec<R>, separator: &'a RecordSeparator) -> Self {
+        Self {
+            inputs: inputs.into(),
+            separator,
+            source: 0,
+            record_number: 0,
+            records_read: 0,
+            bytes_read: 0,
+            record_offset: 0,
+            pending: Vec::new(),
+            line_limit: None,
+            passed_through: false,
+            long_line: false,
+            skip_invalid_utf8: false,
+            filter: None,
+            preserve_empty: false,
+            exclusion: None,
+            tracker: None,
+            cancel: None,
+        }
+    }
+
+    /// Skip records whose keys are in `options.exclude_keys`, and empty
+    /// records under `EmptyLinePolicy::Drop`, counting them in `excluded`
+    pub(crate) fn with_exclusion(
+        mut self,
+        options: &'a DeduplicationOptions,
+        excluded: &'a Cell<usize>,
+    ) -> Self {
+        if options.exclude_keys.is_some() || options.empty_lines == EmptyLinePolicy::Drop {
+            self.exclusion = Some((options, excluded));
+        }
+        self
+    }
+
+    /// Limit lines to `max` bytes, not counting their terminator, handling
+    /// longer ones by `policy`. Lines are the records for byte separators,
+    /// and the newline-terminated lines that make up quoted-line and
+    /// paragraph records; for byte-string separators, the parts ending in
+    /// the separator's last byte. `LineLengthPolicy::PassThrough` only
+    /// applies to byte separators; with others it acts like `Error`.
+    pub fn with_max_line_length(mut self, max: usize, policy: LineLengthPolicy) -> Self {
+        self.line_limit = Some(LineLimit { max, policy });
+        self
+    }
+
+    /// Pass records that aren't valid UTF-8 through under `Utf8Policy::Skip`
+    pub fn with_utf8_policy(mut self, policy: Utf8Policy) -> Self {
+        self.skip_invalid_utf8 = policy == Utf8Policy::Skip;
+        self
+    }
+
+    /// Pass empty records through under `EmptyLinePolicy::Preserve`
+    pub fn with_empty_lines(mut self, policy: EmptyLinePolicy) -> Self {
+        self.preserve_empty = policy == EmptyLinePolicy::Preserve;
+        self
+    }
+
+    /// Count the records read in `tracker`'s progress reports. Readers that
+    /// read the inputs again for a second pass go without one.
+    pub(crate) fn with_progress(mut self, tracker: &'a ProgressTracker<'a>) -> Self {
+        self.tracker = Some(tracker);
+        self
+    }
+
+    /// Fail with `Error::Cancelled` before reading a record once `cancel` is set
+    pub(crate) fn with_cancel(mut self, cancel: Option<&'a AtomicBool>) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Pass the records `filter` picks through
+    pub fn with_filter(mut self, filter: Option<&'a RecordFilter>) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Whether the last record read is a piece (of at most the maximum line
+    /// length) of a longer line under `LineLengthPolicy::PassThrough`, a
+    /// record that isn't valid UTF-8 under `Utf8Policy::Skip`, an empty
+    /// record under `EmptyLinePolicy::Preserve`, or a record picked by the
+    /// filter, to be written as is rather than deduplicated
+    pub fn passed_through(&self) -> bool {
+        self.passed_through
+    }
+
+    /// Whether the next record read continues the passed-through line
+    pub fn line_continues(&self) -> bool {
+        self.long_line
+    }
+
+    /// Index of the input the most recently read record came from
+    pub fn source(&self) -> usize {
+        self.source
+    }
+
+    /// 1-based position of the most recently read record within its input
+    pub fn record_number(&self) -> usize {
+        self.record_number
+    }
+
+    /// Records read so far across all inputs, i.e. the 1-based position of
+    /// the most recently read record in their concatenation
+    pub fn records_read(&self) -> usize {
+        self.records_read
+    }
+
+    /// Input bytes consumed by the records read so far across all inputs,
+    /// including skipped records and the skipped parts of truncated lines
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// `error`, in the most recently read record
+    pub(crate) fn at_line(&self, error: Error) -> Error {
+        Error::AtLine {
+            line: self.records_read,
+            offset: self.record_offset,
+            source: Box::new(error),
+        }
+    }
+
+    /// Append the next record to `buf`, returning the number of bytes read (0 at EOF).
+    ///
+    /// If an input ends without a terminator and more inputs follow, the
+    /// terminator is appended to the record (like `cat` with a newline
+    /// between files); the returned count excludes it.
+    ///
+    /// Lines over the limit set by `with_max_line_length` are truncated or
+    /// passed through in pieces, or fail with `Error::LineTooLong`.
+    /// Errors carry an `Error::AtLine` with the position of the record, which
+    /// `Error`'s `From<io::Error>` unwraps.
+    pub fn read_record(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        crate::check_cancel(self.cancel)?;
+        let start = buf.len();
+        loop {
+            let read = self.read_next(buf).map_err(|e| Error::AtLine {
+                line: self.records_read + 1,
+                offset: self.bytes_read,
+                source: Box::new(e.into()),
+            })?;
+            let content = self.separator.strip(&buf[start..]);
+            if read > 0
+                && (self.skip_invalid_utf8 && std::str::from_utf8(content).is_err()
+                    || self.preserve_empty && is_empty_record(content)
+                    || self
+                        .filter
+                        .is_some_and(|filter| filter.passes_through(content)))
+            {
+                self.passed_through = true;
+            }
+            // Pieces of passed-through lines aren't deduplicated, so never excluded
+            let skippable = read > 0 && !self.passed_through;
+            let Some((options, excluded)) = self.exclusion.filter(|_| skippable) else {
+                return Ok(read);
+            };
+            let dropped = options.empty_lines == EmptyLinePolicy::Drop && is_empty_record(content);
+            if !dropped {
+                if options.exclude_keys.is_none() {
+                    return Ok(read);
+                }
+                let key = crate::make_key(content, options).map_err(|e| self.at_line(e))?;
+                if !crate::is_excluded(&key, options) {
+                    return Ok(read);
+                }
+            }
+            excluded.set(excluded.get() + 1);
+            buf.truncate(start);
+        }
+    }
+
+    /// `read_record` without skipping excluded records
+    fn read_next(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let separator = self.separator;
+        let limit = self.line_limit;
+        let start = buf.len();
+        let continuing = std::mem::take(&mut self.long_line);
+        self.passed_through = continuing;
+        while let Some(input) = self.inputs.front_mut() {
+            let (read, over) = match separator {
+                RecordSeparator::Byte(sep) => read_line(input, *sep, buf, limit)?,
+                RecordSeparator::Bytes(sep) => read_until_bytes(input, sep, buf, limit)?,
+                RecordSeparator::Paragraph => read_paragraph(input, &mut self.pending, buf, limit)?,
+                RecordSeparator::QuotedLine => read_quoted_line(input, buf, limit)?,
+            };
+            if let (true, Some(limit)) = (over, limit) {
+                match (limit.policy, separator) {
+                    (LineLengthPolicy::PassThrough, RecordSeparator::Byte(_)) => {
+                        self.passed_through = true;
+                        self.long_line = true;
+                    }
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            Error::LineTooLong { max: limit.max },
+                        ));
+                    }
+                }
+            }
+            if read > 0 {
+                if !continuing {
+                    self.record_offset = self.bytes_read;
+                }
+                self.bytes_read += read as u64;
+                let terminator = separator.terminator();
+                if !self.long_line && self.inputs.len() > 1 && !buf[start..].ends_with(terminator) {
+                    buf.extend_from_slice(terminator);
+                }
+                if !continuing {
+                    self.record_number += 1;
+                    self.records_read += 1;
+                    if let Some(tracker) = self.tracker {
+                        tracker.count_line();
+                    }
+                }
+                return Ok(read);
+            }
+            // A passed-through line that ended with its input is complete
+            self.passed_through = false;
+            self.inputs.pop_front();
+            if !self.inputs.is_empty() {
+                self.source += 1;
+                self.record_number = 0;
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// Whether a record's content (without its terminator) is empty, apart
+/// from whitespace
+pub(crate) fn is_empty_record(content: &[u8]) -> bool {
+    content.iter().all(u8::is_ascii_whitespace)
+}
+
+/// Like `BufRead::read_until`, but under a line limit: a line over the
+/// limit is cut short and the rest skipped under `LineLengthPolicy::Truncate`,
+/// or cut short and reported as over the limit (the bool) otherwise. Returns
+/// the number of bytes consumed.
+fn read_line<R: BufRead>(
+    input: &mut R,
+    delim: u8,
+    buf: &mut Vec<u8>,
+    limit: Option<LineLimit>,
+) -> io::Result<(usize, bool)> {
+    let Some(limit) = limit else {
+        return Ok((input.read_until(delim, buf)?, false));
+    };
+    let mut read = 0;
+    loop {
+        let available = input.fill_buf()?;
+        if available.is_empty() {
+            return Ok((read, false));
+        }
+        let room = limit.max - read;
+        // Some(over) once the line ends or reaches the limit
+        let (used, end) = match available.iter().position(|&b| b == delim) {
+            Some(idx) if idx <= room => (idx + 1, Some(false)),
+            _ if available.len() <= room => (available.len(), None),
+            _ => (room, Some(true)),
+        };
+        buf.extend_from_slice(&available[..used]);
+        input.consume(used);
+        read += used;
+        match end {
+            Some(false) => return Ok((read, false)),
+            Some(true) => break,
+            None => {}
+        }
+    }
+    if limit.policy != LineLengthPolicy::Truncate {
+        return Ok((read, true));
+    }
+    // Skip the rest of the line, keeping its delimiter
+    loop {
+        let available = input.fill_buf()?;
+        if available.is_empty() {
+            return Ok((read, false));
+        }
+        match available.iter().position(|&b| b == delim) {
+            Some(idx) => {
+                input.consume(idx + 1);
+                buf.push(delim);
+                return Ok((read + idx + 1, false));
+            }
+            None => {
+                let len = available.len();
+                input.consume(len);
+                read += len;
+            }
+        }
+    }
+}
+
+fn read_until_bytes<R: BufRead>(
+    input: &mut R,
+    sep: &[u8],
+    buf: &mut Vec<u8>,
+    limit: Option<LineLimit>,
+) -> io::Result<(usize, bool)> {
+    // An empty separator can never match; treat it as a newline
+    let Some(&last) = sep.last() else {
+        return read_line(input, b'\n', buf, limit);
+    };
+    let start = buf.len();
+    loop {
+        let (read, over) = read_line(input, last, buf, limit)?;
+        if over || read == 0 || buf[start..].ends_with(sep) {
+            return Ok((buf.len() - start, over));
+        }
+    }
+}
+
+fn read_quoted_line<R: BufRead>(
+    input: &mut R,
+    buf: &mut Vec<u8>,
+    limit: Option<LineLimit>,
+) -> io::Result<(usize, bool)> {
+    let start = buf.len();
+    let mut quotes = 0;
+    loop {
+        let chunk_start = buf.len();
+        let (read, over) = read_line(input, b'\n', buf, limit)?;
+        if over || read == 0 {
+            return Ok((buf.len() - start, over));
+        }
+        // An odd number of quotes means the newline is inside a quoted field
+        quotes += buf[chunk_start..].iter().filter(|&&b| b == b'"').count();
+        if quotes % 2 == 0 {
+            return Ok((buf.len() - start, false));
+        }
+    }
+}
+
+fn read_paragraph<R: BufRead>(
+    input: &mut R,
+    pending: &mut Vec<u8>,
+    buf: &mut Vec<u8>,
+    limit: Option<LineLimit>,
+) -> io::Result<(usize, bool)> {
+    let start = buf.len();
+    let mut has_content = false;
+    let mut after_content = false;
+    loop {
+        if pending.is_empty() {
+            let (read, over) = read_line(input, b'\n', pending, limit)?;
+            if over {
+                return Ok((buf.len() - start, true));
+            }
+            if read == 0 {
+                return Ok((buf.len() - start, false));
+            }
+        }
+        let blank = pending.trim_ascii().is_empty();
+        if !blank && after_content {
+            // This line starts the next paragraph; keep it pending
+            return Ok((buf.len() - start, false));
+        }
+        after_content |= blank && has_content;
+        has_content |= !blank;
+        buf.extend_from_slice(pending);
+        pending.clear();
+    }
+}
+
+/// Input wrapper that adds `terminator` at the end of an input that doesn't
+/// end with it, so its last record is terminated like the others, and sets
+/// `unterminated` to whether it had to. Empty inputs leave it as it was, so
+/// it ends up describing the last input that had any records.
+pub(crate) struct TerminatedReader<'t, R> {
+    inner: R,
+    terminator: &'t [u8],
+    unterminated: &'t Cell<bool>,
+    /// The last bytes read, up to the terminator's length
+    tail: Vec<u8>,
+    /// How much of the terminator has been added, once the input has ended
+    added: Option<usize>,
+}
+
+impl<'t, R> TerminatedReader<'t, R> {
+    pub(crate) fn new(inner: R, terminator: &'t [u8], unterminated: &'t Cell<bool>) -> Self {
+        Self {
+            inner,
+            terminator,
+            unterminated,
+            tail: Vec::new(),
+            added: None,
+        }
+    }
+}
+
+impl<R: Read> Read for TerminatedReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if let Some(added) = self.added {
+            let rest = &self.terminator[added..];
+            let len = rest.len().min(buf.len());
+            buf[..len].copy_from_slice(&rest[..len]);
+            self.added = Some(added + len);
+            return Ok(len);
+        }
+        let read = self.inner.read(buf)?;
+        let keep = self.terminator.len();
+        if read >= keep {
+            self.tail.clear();
+            self.tail.extend_from_slice(&buf[read - keep..read]);
+        } else if read > 0 {
+            self.tail.extend_from_slice(&buf[..read]);
+            let excess = self.tail.len().saturating_sub(keep);
+            self.tail.drain(..excess);
+        } else if !self.tail.is_empty() {
+            let unterminated = !self.tail.ends_with(self.terminator);
+            self.unterminated.set(unterminated);
+            self.tail.clear();
+            if unterminated {
+                self.added = Some(0);
+                return self.read(buf);
+            }
+        }
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for TerminatedReader<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.tail.clear();
+        self.added = None;
+        self.inner.seek(pos)
+    }
+}
+
+/// Input wrapper that holds back the last `lines` records of an input, each
+/// ending in `terminator` (or the input), and stores them in `footer` once
+/// the input ends instead of returning them. Each input replaces the footer
+/// of the one before, so it ends up holding the last input's.
+pub(crate) struct FooterReader<'t, R> {
+    inner: R,
+    lines: usize,
+    terminator: &'t [u8],
+    footer: &'t RefCell<Vec<u8>>,
+    /// Bytes read from `inner` and not returned yet
+    held: Vec<u8>,
+    /// Ends of the records in `held`, and how far it has been searched
+    ends: VecDeque<usize>,
+    searched: usize,
+    /// Length of the start of `held` that is ready to be returned
+    ready: usize,
+    eof: bool,
+}
+
+impl<'t, R> FooterReader<'t, R> {
+    pub(crate) fn new(
+        inner: R,
+        lines: usize,
+        terminator: &'t [u8],
+        footer: &'t RefCell<Vec<u8>>,
+    ) -> Self {
+        Self {
+            inner,
+            lines,
+            terminator,
+            footer,
+            held: Vec::new(),
+            ends: VecDeque::new(),
+            searched: 0,
+            ready: 0,
+            eof: false,
+        }
+    }
+
+    /// Record the ends of the records read since the last search
+    fn find_ends(&mut self) {
+        let len = self.terminator.len().max(1);
+        while self.searched + len <= self.held.len() {
+            if self.held[self.searched..].starts_with(self.terminator) {
+                self.searched += len;
+                self.ends.push_back(self.searched);
+            } else {
+                self.searched += 1;
+            }
+        }
+    }
+
+    /// Hold back the last `lines` records of the input read so far, and
+    /// all of them at its end, where the rest become the footer
+    fn release(&mut self) {
+        // A last record without a terminator counts too
+        let unterminated = self.eof && self.ends.back().copied().unwrap_or(0) < self.held.len();
+        let records = self.ends.len() + unterminated as usize;
+        if records > self.lines {
+            self.ready = self.ends[records - self.lines - 1];
+        }
+        if self.eof {
+            *self.footer.borrow_mut() = self.held[self.ready..].to_vec();
+            self.held.truncate(self.ready);
+        }
+    }
+}
+
+impl<R: Read> Read for FooterReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.lines == 0 {
+            return self.inner.read(buf);
+        }
+        while self.ready == 0 && !self.eof {
+            let start = self.held.len();
+            self.held.resize(start + buf.len().max(8 * 1024), 0);
+            let read = self.inner.read(&mut self.held[start..]);
+            let read = read.inspect_err(|_| self.held.truncate(start))?;
+            self.held.truncate(start + read);
+            self.eof = read == 0;
+            self.find_ends();
+            self.release();
+        }
+        let len = self.ready.min(buf.len());
+        buf[..len].copy_from_slice(&self.held[..len]);
+        self.held.drain(..len);
+        self.ready -= len;
+        self.searched -= len;
+        while self.ends.front().is_some_and(|&end| end <= len) {
+            self.ends.pop_front();
+        }
+        self.ends.iter_mut().for_each(|end| *end -= len);
+        Ok(len)
+    }
+}
+
+impl<R: Seek> Seek for FooterReader<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        // Bytes held back haven't been read yet, as far as callers know
+        let pos = match pos {
+            SeekFrom::Current(offset) => {
+                let held = self.held.len() as u64;
+                SeekFrom::Start(
+                    (self.inner.stream_position()? - held).saturating_add_signed(offset),
+                )
+            }
+            pos => pos,
+        };
+        self.held.clear();
+        self.ends.clear();
+        self.searched = 0;
+        self.ready = 0;
+        self.eof = false;
+        self.inner.seek(pos)
+    }
+}
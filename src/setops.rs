@@ -0,0 +1,338 @@
+//! Set operations on the records of several inputs, compared by key
+//!
+//! Records match when their keys do, with keys built by the same options as
+//! deduplication. Each operation writes every matching key once, as its
+//! record first appears. Options that only affect output formatting
+//! (`count`, `show_removed`, source annotations) don't apply here.
+//!
+//! `merge_sorted` is a union of inputs that are already sorted, which only
+//! needs to remember the last key it wrote.
+use crate::error::{Error, Result};
+use crate::hash::{KeyMap, key_map};
+use crate::progress::{ProgressReader, ProgressTracker};
+use crate::record::{FooterReader, TerminatedReader};
+use crate::sort::sort_key;
+use crate::{
+    CountingWriter, DeduplicationMode, DeduplicationOptions, DeduplicationStats, EmptyLinePolicy,
+    HeldTerminator, LineLengthPolicy, RecordReader, SortOrder, Timer, Utf8Policy,
+    load_reference_keys, make_key, partial_stats, prepare_inputs, record_reader,
+};
+use std::cell::{Cell, RefCell};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use std::io::{BufReader, Read, Write};
+
+/// Which records of the inputs a set operation writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOperation {
+    /// Records of every input, in input order
+    Union,
+    /// Records of the first input whose key occurs in every other input
+    Intersection,
+    /// Records of the first input whose key occurs in no other input
+    Difference,
+}
+
+/// Write the records of `inputs` selected by `operation` to `output`.
+///
+/// The other inputs of an intersection or difference are read (into a set
+/// of their keys) before the first one. Records that aren't written count
+/// as removed.
+///
+/// ```
+/// use uniqr::DeduplicationOptions;
+/// use uniqr::setops::{SetOperation, set_operation};
+///
+/// let (a, b) = (&b"x\ny\nz\nx\n"[..], &b"z\nx\n"[..]);
+/// let mut output = Vec::new();
+/// let options = DeduplicationOptions::default();
+/// set_operation(SetOperation::Intersection, [a, b], &mut output, &options).unwrap();
+/// assert_eq!(output, b"x\nz\n");
+/// ```
+pub fn set_operation<I, R, W>(
+    operation: SetOperation,
+    inputs: I,
+    output: &mut W,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats>
+where
+    I: IntoIterator<Item = R>,
+    R: Read,
+    W: Write,
+{
+    validate(options)?;
+    if options.sort.is_some() {
+        return Err(Error::InvalidArgument(
+            "Set operations write records in input order, not sorted".to_string(),
+        ));
+    }
+    crate::validate_options(options)?;
+    let timer = Timer::start();
+
+    let mut inputs = inputs.into_iter();
+    let (records, others) = match operation {
+        SetOperation::Union => (inputs.collect(), Vec::new()),
+        SetOperation::Intersection | SetOperation::Difference => {
+            let first = inputs.next().ok_or_else(|| {
+                Error::InvalidArgument(format!("{:?} needs at least one input", operation))
+            })?;
+            let others = inputs
+                .map(|input| load_reference_keys(input, options))
+                .collect::<Result<Vec<HashSet<Vec<u8>>>>>()?;
+            (vec![first], others)
+        }
+    };
+
+    let tracker = ProgressTracker::new(options.progress_callback.as_deref());
+    let unterminated = Cell::new(false);
+    let footer = RefCell::new(Vec::new());
+    let mut output = HeldTerminator::new(CountingWriter::new(output), options);
+    let mut records: Vec<_> = records
+        .into_iter()
+        .map(|input| {
+            let input = ProgressReader::new(input, &tracker);
+            let terminator = options.record_separator.terminator();
+            let input = TerminatedReader::new(input, terminator, &unterminated);
+            BufReader::new(FooterReader::new(
+                input,
+                options.footer_lines,
+                terminator,
+                &footer,
+            ))
+        })
+        .collect();
+    let options = prepare_inputs(&mut records, &mut output, options)?;
+    let excluded = Cell::new(0);
+    let mut reader = record_reader(records, &options, &excluded).with_progress(&tracker);
+
+    let mut stats = DeduplicationStats::default();
+    let mut written: KeyMap<Vec<u8>, ()> = key_map(options.hasher)?;
+    let mut line = Vec::new();
+    loop {
+        let records_read = reader.records_read();
+        let read = reader
+            .read_record(&mut line)
+            .map_err(|e| partial_stats(e.into(), &tracker, &mut output, &timer))?;
+        if read == 0 {
+            break;
+        }
+        let new_record = reader.records_read() != records_read;
+        if reader.passed_through() {
+            output.write_all(&line)?;
+            line.clear();
+            if new_record {
+                stats.lines_read += 1;
+                stats.lines_written += 1;
+            }
+            continue;
+        }
+        stats.lines_read += 1;
+
+        let key = make_key(options.record_separator.strip(&line), &options)
+            .map_err(|e| reader.at_line(e))?;
+        let selected = match operation {
+            SetOperation::Union => true,
+            SetOperation::Intersection => others.iter().all(|keys| keys.contains(&key)),
+            SetOperation::Difference => !others.iter().any(|keys| keys.contains(&key)),
+        };
+        if selected && written.insert(key, ()).is_none() {
+            output.write_all(&line)?;
+            stats.lines_written += 1;
+        } else {
+            stats.lines_removed += 1;
+        }
+        line.clear();
+    }
+    output.write_all(&footer.borrow())?;
+    output.finish(unterminated.get())?;
+    output.flush()?;
+
+    stats.unique_lines = written.len();
+    stats.lines_read += excluded.get();
+    stats.lines_removed += excluded.get();
+    stats.bytes_read = tracker.bytes_read();
+    stats.bytes_written = output.get_ref().bytes_written();
+    stats.elapsed = timer.elapsed();
+    Ok(stats)
+}
+
+/// Merge `inputs`, each sorted by key, into `output` in sorted order,
+/// writing each key once.
+///
+/// Inputs are sorted in the order of `options.sort`, by text if it's
+/// `None`; orders by count aren't supported. Of records with equal keys,
+/// the one from the earliest input is written. Memory use doesn't grow with
+/// the inputs, apart from the distinct keys that sort equally (such as `1`
+/// and `1.0` in numeric order). Returns an error when an input turns out
+/// not to be sorted, after writing the records before that point.
+///
+/// ```
+/// use uniqr::DeduplicationOptions;
+/// use uniqr::setops::merge_sorted;
+///
+/// let (a, b) = (&b"apple\ncherry\n"[..], &b"banana\ncherry\ndate\n"[..]);
+/// let mut output = Vec::new();
+/// merge_sorted([a, b], &mut output, &DeduplicationOptions::default()).unwrap();
+/// assert_eq!(output, b"apple\nbanana\ncherry\ndate\n");
+/// ```
+pub fn merge_sorted<I, R, W>(
+    inputs: I,
+    output: &mut W,
+    options: &DeduplicationOptions,
+) -> Result<DeduplicationStats>
+where
+    I: IntoIterator<Item = R>,
+    R: Read,
+    W: Write,
+{
+    validate(options)?;
+    let order = options.sort.unwrap_or_default();
+    if order.by_count() {
+        return Err(Error::InvalidArgument(
+            "Sorted inputs are merged in text or numeric order, not by count".to_string(),
+        ));
+    }
+    if options.max_line_length.is_some()
+        && options.line_length_policy == LineLengthPolicy::PassThrough
+        || options.utf8_policy == Utf8Policy::Skip
+        || options.passthrough.is_some()
+        || options.empty_lines == EmptyLinePolicy::Preserve
+    {
+        return Err(Error::InvalidArgument(
+            "Merging sorted inputs doesn't support passing long lines, invalid UTF-8, matching or empty records through"
+                .to_string(),
+        ));
+    }
+    if options.footer_lines > 0 {
+        return Err(Error::InvalidArgument(
+            "Merging sorted inputs doesn't support footers".to_string(),
+        ));
+    }
+    crate::validate_options(options)?;
+    let timer = Timer::start();
+
+    let tracker = ProgressTracker::new(options.progress_callback.as_deref());
+    let inputs: Vec<R> = inputs.into_iter().collect();
+    // Whether each input lacked a final terminator, so the output can end
+    // like the input its last record came from
+    let unterminated: Vec<Cell<bool>> = inputs.iter().map(|_| Cell::new(false)).collect();
+    let mut output = HeldTerminator::new(CountingWriter::new(output), options);
+    let mut inputs: Vec<_> = inputs
+        .into_iter()
+        .zip(&unterminated)
+        .map(|(input, unterminated)| {
+            let input = ProgressReader::new(input, &tracker);
+            let terminator = options.record_separator.terminator();
+            BufReader::new(TerminatedReader::new(input, terminator, unterminated))
+        })
+        .collect();
+    let options = prepare_inputs(&mut inputs, &mut output, options)?;
+    let excluded = Cell::new(0);
+    let mut readers: Vec<_> = inputs
+        .into_iter()
+        .map(|input| record_reader(vec![input], &options, &excluded).with_progress(&tracker))
+        .collect();
+
+    let mut heads = BinaryHeap::with_capacity(readers.len());
+    for (idx, reader) in readers.iter_mut().enumerate() {
+        if let Some(head) = next_head(reader, idx, order, &options)
+            .map_err(|e| partial_stats(e, &tracker, &mut output, &timer))?
+        {
+            heads.push(Reverse(head));
+        }
+    }
+
+    let mut stats = DeduplicationStats::default();
+    // Keys written with the sort key being merged, and the input the last
+    // record written came from
+    let mut current: Option<(Vec<u8>, Vec<Vec<u8>>)> = None;
+    let mut last_input = None;
+    while let Some(Reverse(head)) = heads.pop() {
+        stats.lines_read += 1;
+        let idx = head.input;
+        let written = match &mut current {
+            Some((sort_key, keys)) if *sort_key == head.sort_key => keys,
+            _ => &mut current.insert((head.sort_key.clone(), Vec::new())).1,
+        };
+        if written.contains(&head.key) {
+            stats.lines_removed += 1;
+        } else {
+            output.write_all(&head.record)?;
+            written.push(head.key);
+            stats.lines_written += 1;
+            last_input = Some(idx);
+        }
+
+        if let Some(next) = next_head(&mut readers[idx], idx, order, &options)
+            .map_err(|e| partial_stats(e, &tracker, &mut output, &timer))?
+        {
+            if next.sort_key < head.sort_key {
+                return Err(Error::InvalidArgument(format!(
+                    "Input {} isn't sorted at record {}",
+                    idx + 1,
+                    readers[idx].records_read()
+                )));
+            }
+            heads.push(Reverse(next));
+        }
+    }
+    output.finish(last_input.is_some_and(|idx| unterminated[idx].get()))?;
+    output.flush()?;
+
+    stats.unique_lines = stats.lines_written;
+    stats.lines_read += excluded.get();
+    stats.lines_removed += excluded.get();
+    stats.bytes_read = tracker.bytes_read();
+    stats.bytes_written = output.get_ref().bytes_written();
+    stats.elapsed = timer.elapsed();
+    Ok(stats)
+}
+
+/// The next record of one of the merged inputs. Heads order by sort key,
+/// then by input, so the earliest input wins ties.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Head {
+    sort_key: Vec<u8>,
+    input: usize,
+    key: Vec<u8>,
+    record: Vec<u8>,
+}
+
+/// Read the next record of input `input` from `reader`
+fn next_head<R: std::io::BufRead>(
+    reader: &mut RecordReader<'_, R>,
+    input: usize,
+    order: SortOrder,
+    options: &DeduplicationOptions,
+) -> Result<Option<Head>> {
+    let mut record = Vec::new();
+    if reader.read_record(&mut record)? == 0 {
+        return Ok(None);
+    }
+    let key = make_key(options.record_separator.strip(&record), options)
+        .map_err(|e| reader.at_line(e))?;
+    Ok(Some(Head {
+        sort_key: sort_key(&key, 0, order),
+        input,
+        key,
+        record,
+    }))
+}
+
+fn validate(options: &DeduplicationOptions) -> Result<()> {
+    if options.mode != DeduplicationMode::KeepFirst
+        || options.approximate.is_some()
+        || options.fuzzy.is_some()
+        || options.window.is_some()
+        || options.use_disk
+        || options.memory_limit.is_some()
+        || options.adjacent
+        || options.group.is_some()
+    {
+        return Err(Error::InvalidArgument(
+            "Set operations compare exact keys in KeepFirst mode; they support neither other modes nor approximate, fuzzy, windowed, disk-backed, adjacent or grouped deduplication"
+                .to_string(),
+        ));
+    }
+    Ok(())
+}
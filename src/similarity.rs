@@ -0,0 +1,395 @@
+//! Shingle signatures for similar-text deduplication
+//!
+//! Keys are split into words and summarized by the set of their word
+//! shingles (runs of `shingle_size` consecutive words). Two signature
+//! schemes estimate how alike two shingle sets are:
+//!
+//! - **MinHash** estimates their Jaccard similarity, the share of shingles
+//!   they have in common. Candidates are found by locality-sensitive hashing
+//!   of signature bands, so a lookup doesn't compare against every kept key.
+//! - **SimHash** folds the shingles into a 64-bit fingerprint where similar
+//!   sets differ in few bits. It is smaller and faster than MinHash, but
+//!   only discriminates well at high thresholds (0.9 and up).
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// Signature scheme used to compare keys for `DeduplicationMode::Similar`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignatureKind {
+    /// Estimate the Jaccard similarity of shingle sets (default)
+    #[default]
+    MinHash,
+    /// Compare 64-bit fingerprints by the share of equal bits
+    SimHash,
+}
+
+/// How similar two keys must be to count as duplicates in
+/// `DeduplicationMode::Similar`: above 0 and at most 1
+///
+/// ```
+/// use uniqr::SimilarityThreshold;
+///
+/// assert_eq!(SimilarityThreshold::new(0.8).unwrap().get(), 0.8);
+/// assert!(SimilarityThreshold::new(1.5).is_err());
+/// assert!(SimilarityThreshold::new(f64::NAN).is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct SimilarityThreshold(f64);
+
+impl SimilarityThreshold {
+    /// A threshold of `value`, which must be above 0 and at most 1
+    pub fn new(value: f64) -> crate::Result<Self> {
+        if value > 0.0 && value <= 1.0 {
+            Ok(Self(value))
+        } else {
+            Err(crate::Error::InvalidArgument(format!(
+                "Similarity threshold must be above 0 and at most 1, got {}",
+                value
+            )))
+        }
+    }
+
+    /// The threshold as a number
+    pub fn get(self) -> f64 {
+        self.0
+    }
+}
+
+// `new` rejects NaN, so every threshold equals itself
+impl Eq for SimilarityThreshold {}
+
+/// Settings for `DeduplicationMode::Similar`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimilarityOptions {
+    pub signature: SignatureKind,
+    /// Number of consecutive words in each shingle; keys with fewer words
+    /// are one shingle
+    pub shingle_size: usize,
+}
+
+impl Default for SimilarityOptions {
+    fn default() -> Self {
+        Self {
+            signature: SignatureKind::MinHash,
+            shingle_size: 2,
+        }
+    }
+}
+
+/// Estimated similarity of two keys, from 0 (nothing in common) to 1
+/// (the same shingles), as `DeduplicationMode::Similar` compares them
+///
+/// ```
+/// use uniqr::similarity::{SimilarityOptions, similarity};
+///
+/// let options = SimilarityOptions::default();
+/// assert_eq!(similarity(b"a b c", b"a b c", &options), 1.0);
+/// assert!(similarity(b"a b c", b"x y z", &options) < 0.1);
+/// ```
+pub fn similarity(a: &[u8], b: &[u8], options: &SimilarityOptions) -> f64 {
+    match (signature(a, options), signature(b, options)) {
+        (Signature::MinHash(a), Signature::MinHash(b)) => minhash_similarity(&a, &b),
+        (Signature::SimHash(a), Signature::SimHash(b)) => simhash_similarity(a, b),
+        _ => unreachable!("both signatures use the same scheme"),
+    }
+}
+
+/// Number of hash functions in a MinHash signature
+const PERMUTATIONS: usize = 128;
+
+/// Most likely a pair of keys exactly at the threshold is missed by MinHash
+/// candidate lookup; pairs further above it are missed far less often
+const MISS_RATE: f64 = 0.01;
+
+/// SimHash fingerprints are split into blocks for lookup only while each
+/// block keeps at least this many bits; beyond that every kept key is scanned
+const MIN_BLOCK_BITS: usize = 4;
+
+/// The signature of one key
+pub(crate) enum Signature {
+    MinHash(Vec<u32>),
+    SimHash(u64),
+}
+
+/// Compute the signature of `key` for the scheme in `options`
+pub(crate) fn signature(key: &[u8], options: &SimilarityOptions) -> Signature {
+    let shingles = shingle_hashes(key, options.shingle_size);
+    match options.signature {
+        SignatureKind::MinHash => {
+            let mut signature = vec![u32::MAX; PERMUTATIONS];
+            for shingle in shingles {
+                for (i, min) in signature.iter_mut().enumerate() {
+                    *min = (*min).min(permute(shingle, i));
+                }
+            }
+            Signature::MinHash(signature)
+        }
+        SignatureKind::SimHash => {
+            let mut weights = [0i64; 64];
+            for shingle in shingles {
+                for (bit, weight) in weights.iter_mut().enumerate() {
+                    *weight += if shingle >> bit & 1 == 1 { 1 } else { -1 };
+                }
+            }
+            let fingerprint = weights
+                .iter()
+                .enumerate()
+                .filter(|&(_, &weight)| weight > 0)
+                .fold(0u64, |fingerprint, (bit, _)| fingerprint | 1 << bit);
+            Signature::SimHash(fingerprint)
+        }
+    }
+}
+
+/// Hash of each shingle of `key`: every run of `size` consecutive words,
+/// or all of its words if it has fewer
+fn shingle_hashes(key: &[u8], size: usize) -> Vec<u64> {
+    let words: Vec<&[u8]> = key
+        .split(u8::is_ascii_whitespace)
+        .filter(|word| !word.is_empty())
+        .collect();
+    if words.is_empty() {
+        return Vec::new();
+    }
+    words
+        .windows(size.clamp(1, words.len()))
+        .map(|shingle| {
+            let mut hasher = DefaultHasher::new();
+            shingle.hash(&mut hasher);
+            hasher.finish()
+        })
+        .collect()
+}
+
+/// The `i`th of the MinHash hash functions applied to a shingle hash
+fn permute(shingle: u64, i: usize) -> u32 {
+    (mix(shingle ^ (i as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15)) >> 32) as u32
+}
+
+/// SplitMix64 finalizer: spreads every input bit over the whole output
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+fn minhash_similarity(a: &[u32], b: &[u32]) -> f64 {
+    let equal = a.iter().zip(b).filter(|(x, y)| x == y).count();
+    equal as f64 / PERMUTATIONS as f64
+}
+
+fn simhash_similarity(a: u64, b: u64) -> f64 {
+    1.0 - f64::from((a ^ b).count_ones()) / 64.0
+}
+
+/// Kept keys indexed by signature, for finding one at least `threshold`
+/// similar to a new key without comparing against every kept key
+pub(crate) struct SimilarityIndex {
+    options: SimilarityOptions,
+    threshold: f64,
+    keys: Vec<Vec<u8>>,
+    lookup: Lookup,
+}
+
+enum Lookup {
+    /// MinHash signatures stored back to back, and for each band of `rows`
+    /// signature values, the keys with each band hash
+    MinHash {
+        signatures: Vec<u32>,
+        rows: usize,
+        bands: Vec<HashMap<u64, Vec<usize>>>,
+    },
+    /// SimHash fingerprints, and for each bit block, the keys with each
+    /// block value. Keys within `max_distance` bits agree on at least one
+    /// of `max_distance + 1` blocks; with no blocks every key is scanned.
+    SimHash {
+        fingerprints: Vec<u64>,
+        max_distance: u32,
+        blocks: Vec<HashMap<u64, Vec<usize>>>,
+    },
+}
+
+impl SimilarityIndex {
+    pub(crate) fn new(options: SimilarityOptions, threshold: f64) -> Self {
+        let lookup = match options.signature {
+            SignatureKind::MinHash => {
+                // The most rows per band (fewest false candidates) that
+                // still finds a pair at the threshold in at least one band
+                let rows = (1..=32)
+                    .rev()
+                    .find(|&rows| {
+                        let bands = (PERMUTATIONS / rows) as i32;
+                        (1.0 - threshold.powi(rows as i32)).powi(bands) <= MISS_RATE
+                    })
+                    .unwrap_or(1);
+                Lookup::MinHash {
+                    signatures: Vec::new(),
+                    rows,
+                    bands: vec![HashMap::new(); PERMUTATIONS / rows],
+                }
+            }
+            SignatureKind::SimHash => {
+                let max_distance = ((1.0 - threshold) * 64.0 + 1e-9).floor() as u32;
+                let blocks = max_distance as usize + 1;
+                let blocks = if 64 / blocks >= MIN_BLOCK_BITS {
+                    blocks
+                } else {
+                    0
+                };
+                Lookup::SimHash {
+                    fingerprints: Vec::new(),
+                    max_distance,
+                    blocks: vec![HashMap::new(); blocks],
+                }
+            }
+        };
+        Self {
+            options,
+            threshold,
+            keys: Vec::new(),
+            lookup,
+        }
+    }
+
+    /// Number of keys stored
+    pub(crate) fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// The key stored as number `node`
+    pub(crate) fn key(&self, node: usize) -> &[u8] {
+        &self.keys[node]
+    }
+
+    /// Signature of `key`, for `find` and `insert`
+    pub(crate) fn signature(&self, key: &[u8]) -> Signature {
+        signature(key, &self.options)
+    }
+
+    /// A stored key at least `threshold` similar to the one with
+    /// `signature`, if any; the earliest stored of the most similar ones
+    pub(crate) fn find(&self, signature: &Signature) -> Option<usize> {
+        let mut best: Option<(f64, usize)> = None;
+        let mut consider = |node: usize, similarity: f64| {
+            if similarity >= self.threshold
+                && best.is_none_or(|(best_similarity, best_node)| {
+                    similarity > best_similarity
+                        || (similarity == best_similarity && node < best_node)
+                })
+            {
+                best = Some((similarity, node));
+            }
+        };
+        match (&self.lookup, signature) {
+            (
+                Lookup::MinHash {
+                    signatures,
+                    rows,
+                    bands,
+                },
+                Signature::MinHash(signature),
+            ) => {
+                let mut candidates: Vec<usize> = band_hashes(signature, *rows)
+                    .zip(bands)
+                    .filter_map(|(hash, band)| band.get(&hash))
+                    .flatten()
+                    .copied()
+                    .collect();
+                candidates.sort_unstable();
+                candidates.dedup();
+                for node in candidates {
+                    let stored = &signatures[node * PERMUTATIONS..(node + 1) * PERMUTATIONS];
+                    consider(node, minhash_similarity(signature, stored));
+                }
+            }
+            (
+                Lookup::SimHash {
+                    fingerprints,
+                    max_distance,
+                    blocks,
+                },
+                &Signature::SimHash(fingerprint),
+            ) => {
+                let check = |node: usize| {
+                    if (fingerprint ^ fingerprints[node]).count_ones() <= *max_distance {
+                        consider(node, simhash_similarity(fingerprint, fingerprints[node]));
+                    }
+                };
+                if blocks.is_empty() {
+                    (0..fingerprints.len()).for_each(check);
+                } else {
+                    let mut candidates: Vec<usize> = block_values(fingerprint, blocks.len())
+                        .zip(blocks)
+                        .filter_map(|(value, block)| block.get(&value))
+                        .flatten()
+                        .copied()
+                        .collect();
+                    candidates.sort_unstable();
+                    candidates.dedup();
+                    candidates.into_iter().for_each(check);
+                }
+            }
+            _ => unreachable!("signatures come from this index"),
+        }
+        best.map(|(_, node)| node)
+    }
+
+    /// Store `key` with its `signature`, returning its node number
+    pub(crate) fn insert(&mut self, key: Vec<u8>, signature: Signature) -> usize {
+        let node = self.keys.len();
+        self.keys.push(key);
+        match (&mut self.lookup, signature) {
+            (
+                Lookup::MinHash {
+                    signatures,
+                    rows,
+                    bands,
+                },
+                Signature::MinHash(signature),
+            ) => {
+                for (hash, band) in band_hashes(&signature, *rows).zip(bands.iter_mut()) {
+                    band.entry(hash).or_default().push(node);
+                }
+                signatures.extend_from_slice(&signature);
+            }
+            (
+                Lookup::SimHash {
+                    fingerprints,
+                    blocks,
+                    ..
+                },
+                Signature::SimHash(fingerprint),
+            ) => {
+                let count = blocks.len();
+                for (value, block) in block_values(fingerprint, count).zip(blocks.iter_mut()) {
+                    block.entry(value).or_default().push(node);
+                }
+                fingerprints.push(fingerprint);
+            }
+            _ => unreachable!("signatures come from this index"),
+        }
+        node
+    }
+}
+
+/// Hash of each band of `rows` consecutive MinHash values
+fn band_hashes(signature: &[u32], rows: usize) -> impl Iterator<Item = u64> + '_ {
+    signature.chunks_exact(rows).map(|band| {
+        band.iter()
+            .fold(0u64, |hash, &value| mix(hash ^ u64::from(value)))
+    })
+}
+
+/// Value of each of `count` near-equal bit blocks of `fingerprint`
+fn block_values(fingerprint: u64, count: usize) -> impl Iterator<Item = u64> {
+    (0..count).map(move |i| {
+        let (start, end) = (i * 64 / count, (i + 1) * 64 / count);
+        let width = end - start;
+        let mask = if width == 64 {
+            u64::MAX
+        } else {
+            (1 << width) - 1
+        };
+        fingerprint >> start & mask
+    })
+}
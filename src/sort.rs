@@ -0,0 +1,220 @@
+//! External merge sort of kept records for `DeduplicationOptions::sort`
+use crate::SortOrder;
+#[cfg(feature = "disk-backed")]
+use crate::error::Error;
+use crate::error::Result;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::Write;
+#[cfg(feature = "disk-backed")]
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom};
+#[cfg(feature = "disk-backed")]
+use std::path::PathBuf;
+
+/// Records are buffered in memory until they take this many bytes, then
+/// sorted and (with the 'disk-backed' feature) spilled to a temporary file
+const BUFFER_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Collects formatted records with their sort keys and writes them out in
+/// key order, records with equal keys in the order they were added.
+///
+/// With the 'disk-backed' feature, each full buffer is sorted into a run in
+/// an anonymous temporary file and the runs are merged at the end, so
+/// memory use stays bounded; without it, everything is sorted in memory.
+pub(crate) struct Sorter {
+    order: SortOrder,
+    /// Encoded sort key and formatted record, in the order added
+    buffer: Vec<(Vec<u8>, Vec<u8>)>,
+    buffered_bytes: usize,
+    #[cfg_attr(not(feature = "disk-backed"), allow(dead_code))]
+    buffer_limit: usize,
+    #[cfg(feature = "disk-backed")]
+    temp_dir: Option<PathBuf>,
+    /// Sorted runs spilled so far, oldest first
+    #[cfg(feature = "disk-backed")]
+    runs: Vec<std::fs::File>,
+}
+
+impl Sorter {
+    pub(crate) fn new(order: SortOrder, options: &crate::DeduplicationOptions) -> Self {
+        Self::with_buffer_limit(order, options, BUFFER_LIMIT)
+    }
+
+    pub(crate) fn with_buffer_limit(
+        order: SortOrder,
+        #[cfg_attr(not(feature = "disk-backed"), allow(unused_variables))]
+        options: &crate::DeduplicationOptions,
+        buffer_limit: usize,
+    ) -> Self {
+        Self {
+            order,
+            buffer: Vec::new(),
+            buffered_bytes: 0,
+            buffer_limit,
+            #[cfg(feature = "disk-backed")]
+            temp_dir: options.disk.temp_dir.clone(),
+            #[cfg(feature = "disk-backed")]
+            runs: Vec::new(),
+        }
+    }
+
+    /// Whether records are ordered by the `count` given to `push`, so
+    /// their key isn't needed
+    pub(crate) fn by_count(&self) -> bool {
+        self.order.by_count()
+    }
+
+    /// Add a formatted `record` whose comparison key is `key`, occurring
+    /// `count` times
+    pub(crate) fn push(&mut self, key: &[u8], count: u64, record: Vec<u8>) -> Result<()> {
+        let key = sort_key(key, count, self.order);
+        self.buffered_bytes += key.len() + record.len();
+        self.buffer.push((key, record));
+        #[cfg(feature = "disk-backed")]
+        if self.buffered_bytes >= self.buffer_limit {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Sort the buffer into a new run on disk
+    #[cfg(feature = "disk-backed")]
+    fn spill(&mut self) -> Result<()> {
+        let mut file = match &self.temp_dir {
+            Some(dir) => tempfile::tempfile_in(dir),
+            None => tempfile::tempfile(),
+        }
+        .map_err(Error::Spill)?;
+        log::debug!(
+            "Spilling sorted run {} of {} records ({} bytes)",
+            self.runs.len() + 1,
+            self.buffer.len(),
+            self.buffered_bytes
+        );
+        self.buffer.sort_by(|a, b| a.0.cmp(&b.0));
+        write_run(&mut file, self.buffer.drain(..)).map_err(Error::Spill)?;
+        self.runs.push(file);
+        self.buffered_bytes = 0;
+        Ok(())
+    }
+
+    /// Write every record to `output` in sorted order
+    pub(crate) fn finish<W: Write + ?Sized>(self, output: &mut W) -> Result<()> {
+        let mut buffer = self.buffer;
+        buffer.sort_by(|a, b| a.0.cmp(&b.0));
+
+        #[cfg(feature = "disk-backed")]
+        let spilled = self
+            .runs
+            .into_iter()
+            .map(|file| Run::File(BufReader::new(file)));
+        #[cfg(not(feature = "disk-backed"))]
+        let spilled = std::iter::empty();
+        // The in-memory records were added last, so they rank last on ties
+        let mut runs: Vec<Run> = spilled.chain([Run::Memory(buffer.into_iter())]).collect();
+
+        // Merge the runs, taking the earliest run's record on equal keys
+        let mut heads = BinaryHeap::new();
+        for (idx, run) in runs.iter_mut().enumerate() {
+            if let Some((key, record)) = run.next()? {
+                heads.push(Reverse((key, idx, record)));
+            }
+        }
+        while let Some(Reverse((_, idx, record))) = heads.pop() {
+            output.write_all(&record)?;
+            if let Some((key, record)) = runs[idx].next()? {
+                heads.push(Reverse((key, idx, record)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write the sorted `records` of a run to `file`, then rewind it to be
+/// read back
+#[cfg(feature = "disk-backed")]
+fn write_run(
+    file: &mut std::fs::File,
+    records: impl Iterator<Item = (Vec<u8>, Vec<u8>)>,
+) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(&mut *file);
+    for (key, record) in records {
+        for part in [&key, &record] {
+            writer.write_all(&(part.len() as u64).to_le_bytes())?;
+            writer.write_all(part)?;
+        }
+    }
+    writer.flush()?;
+    drop(writer);
+    file.seek(SeekFrom::Start(0))?;
+    Ok(())
+}
+
+/// A sorted sequence of (sort key, record) pairs
+enum Run {
+    Memory(std::vec::IntoIter<(Vec<u8>, Vec<u8>)>),
+    #[cfg(feature = "disk-backed")]
+    File(BufReader<std::fs::File>),
+}
+
+impl Run {
+    fn next(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        match self {
+            Run::Memory(records) => Ok(records.next()),
+            #[cfg(feature = "disk-backed")]
+            Run::File(reader) => {
+                let mut len = [0u8; 8];
+                match reader.read_exact(&mut len) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+                    Err(e) => return Err(Error::Spill(e)),
+                }
+                let mut key = vec![0; u64::from_le_bytes(len) as usize];
+                reader.read_exact(&mut key).map_err(Error::Spill)?;
+                reader.read_exact(&mut len).map_err(Error::Spill)?;
+                let mut record = vec![0; u64::from_le_bytes(len) as usize];
+                reader.read_exact(&mut record).map_err(Error::Spill)?;
+                Ok(Some((key, record)))
+            }
+        }
+    }
+}
+
+/// Encode `key` (or `count`) so that comparing the encodings as bytes
+/// sorts in `order`.
+///
+/// Numeric keys become a 0 tag byte and the big-endian bits of the value,
+/// adjusted so negative numbers come first; keys that aren't numbers get a
+/// 1 tag byte, so they follow every number in text order. Counts are
+/// big-endian, inverted for descending order.
+pub(crate) fn sort_key(key: &[u8], count: u64, order: SortOrder) -> Vec<u8> {
+    let number = match order {
+        SortOrder::Numeric => std::str::from_utf8(key)
+            .ok()
+            .and_then(|text| text.trim().parse::<f64>().ok())
+            .filter(|value| !value.is_nan()),
+        _ => None,
+    };
+    match (order, number) {
+        (SortOrder::Text, _) => key.to_vec(),
+        (SortOrder::CountAscending, _) => count.to_be_bytes().to_vec(),
+        (SortOrder::CountDescending, _) => (!count).to_be_bytes().to_vec(),
+        (SortOrder::Numeric, Some(value)) => {
+            // Adding 0.0 turns -0.0 into 0.0
+            let bits = (value + 0.0).to_bits();
+            let ordered = if bits >> 63 == 1 {
+                !bits
+            } else {
+                bits | 1 << 63
+            };
+            let mut encoded = vec![0];
+            encoded.extend_from_slice(&ordered.to_be_bytes());
+            encoded
+        }
+        (SortOrder::Numeric, None) => {
+            let mut encoded = vec![1];
+            encoded.extend_from_slice(key);
+            encoded
+        }
+    }
+}
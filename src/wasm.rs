@@ -0,0 +1,71 @@
+//! JavaScript bindings via wasm-bindgen (requires 'wasm' feature)
+//!
+//! Build with `wasm-pack build --no-default-features --features wasm`.
+use crate::{DeduplicationMode, DeduplicationOptions, TrimMode, deduplicate};
+use wasm_bindgen::prelude::*;
+
+/// Deduplication strategy, exported to JS as `Mode`
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    #[default]
+    KeepFirst,
+    KeepLast,
+    RemoveAll,
+}
+
+/// Options for `dedupText`; construct with `new DedupOptions()` and set fields
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DedupOptions {
+    pub mode: Mode,
+    #[wasm_bindgen(js_name = ignoreCase)]
+    pub ignore_case: bool,
+    pub count: bool,
+    #[wasm_bindgen(js_name = showRemoved)]
+    pub show_removed: bool,
+    /// Ignore leading and trailing whitespace
+    pub trim: bool,
+    /// 1-indexed whitespace-separated column to compare
+    pub column: Option<u32>,
+}
+
+#[wasm_bindgen]
+impl DedupOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl From<&DedupOptions> for DeduplicationOptions {
+    fn from(opts: &DedupOptions) -> Self {
+        Self {
+            mode: match opts.mode {
+                Mode::KeepFirst => DeduplicationMode::KeepFirst,
+                Mode::KeepLast => DeduplicationMode::KeepLast,
+                Mode::RemoveAll => DeduplicationMode::RemoveAll,
+            },
+            ignore_case: opts.ignore_case,
+            count: opts.count,
+            show_removed: opts.show_removed,
+            trim: if opts.trim {
+                TrimMode::Both
+            } else {
+                TrimMode::None
+            },
+            column: opts.column.map(|column| column as usize),
+            ..Default::default()
+        }
+    }
+}
+
+/// Deduplicate the lines of `input`, preserving order
+#[wasm_bindgen(js_name = dedupText)]
+pub fn dedup_text(input: &str, opts: &DedupOptions) -> Result<String, JsError> {
+    let mut output = Vec::new();
+    deduplicate(input.as_bytes(), &mut output, &opts.into())
+        .map_err(|e| JsError::new(&e.to_string()))?;
+    // Records are copied through whole, so valid UTF-8 input gives valid output
+    String::from_utf8(output).map_err(|e| JsError::new(&e.to_string()))
+}
@@ -0,0 +1,183 @@
+//! Seen-set of windowed deduplication, which forgets keys that drop out of
+//! the window
+use crate::WindowMode;
+use crate::error::{Error, Result};
+use crate::hash::{HashAlgorithm, KeyMap, key_map};
+use std::collections::VecDeque;
+
+/// The recent keys `mode` keeps, with the position (or, for a time window,
+/// the time in milliseconds) each was last seen at. Memory use is bounded by
+/// the window, however long the stream.
+pub(crate) struct Window {
+    mode: WindowMode,
+    /// Keys by the position they were seen at, oldest first. A key seen
+    /// again since has a stale entry here, which is skipped on eviction.
+    keys: VecDeque<(Vec<u8>, i64)>,
+    last_seen: KeyMap<Vec<u8>, i64>,
+    position: i64,
+    /// Timestamp of the record being deduplicated, and the latest one yet
+    time: i64,
+    latest: i64,
+}
+
+impl Window {
+    /// An empty window of the records, keys or time in `mode`
+    pub(crate) fn new(mode: WindowMode, hasher: HashAlgorithm) -> Result<Self> {
+        Ok(Self {
+            mode,
+            keys: VecDeque::new(),
+            last_seen: key_map(hasher)?,
+            position: 0,
+            time: 0,
+            latest: i64::MIN,
+        })
+    }
+
+    /// The part of `record` its key is built from. A time window reads the
+    /// record's timestamp off its start and returns the rest, or `None` if
+    /// it doesn't start with one.
+    pub(crate) fn key_line<'a>(&mut self, record: &'a [u8]) -> Result<Option<&'a [u8]>> {
+        let WindowMode::Time { format, .. } = &self.mode else {
+            return Ok(Some(record));
+        };
+        Ok(parse_timestamp(record, format)?.map(|(time, rest)| {
+            self.time = time;
+            rest
+        }))
+    }
+
+    /// Add the key of the next record (after `key_line`), returning `true`
+    /// if it is in the window already
+    pub(crate) fn check_and_insert(&mut self, key: Vec<u8>) -> bool {
+        let position = self.position;
+        self.position += 1;
+        match self.mode {
+            WindowMode::Lines(size) => {
+                let seen = self.insert(key, position);
+                while self
+                    .keys
+                    .front()
+                    .is_some_and(|&(_, seen_at)| position - seen_at >= size as i64)
+                {
+                    self.evict_oldest();
+                }
+                seen
+            }
+            WindowMode::Keys(max) => {
+                let seen = self.insert(key, position);
+                while self.last_seen.len() > max {
+                    self.evict_oldest();
+                }
+                // Drop stale entries before they outnumber the keys
+                if self.keys.len() > 2 * max {
+                    let last_seen = &self.last_seen;
+                    self.keys
+                        .retain(|(key, seen_at)| last_seen.get(key) == Some(seen_at));
+                }
+                seen
+            }
+            WindowMode::Time { within, .. } => {
+                let within = i64::try_from(within.as_millis()).unwrap_or(i64::MAX);
+                let time = self.time;
+                // Only the kept record starts a key's window
+                if self
+                    .last_seen
+                    .get(&key)
+                    .is_some_and(|&kept_at| time.abs_diff(kept_at) <= within as u64)
+                {
+                    return true;
+                }
+                self.insert(key, time);
+                self.latest = self.latest.max(time);
+                let latest = self.latest;
+                while self
+                    .keys
+                    .front()
+                    .is_some_and(|&(_, kept_at)| latest.saturating_sub(kept_at) > within)
+                {
+                    self.evict_oldest();
+                }
+                false
+            }
+        }
+    }
+
+    /// Record that `key` was seen at `at`, returning whether it was in the
+    /// window
+    fn insert(&mut self, key: Vec<u8>, at: i64) -> bool {
+        let seen = self.last_seen.insert(key.clone(), at).is_some();
+        self.keys.push_back((key, at));
+        seen
+    }
+
+    /// Forget the oldest entry's key, unless it was seen again since
+    fn evict_oldest(&mut self) {
+        if let Some((key, seen_at)) = self.keys.pop_front()
+            && self.last_seen.get(&key) == Some(&seen_at)
+        {
+            self.last_seen.remove(&key);
+        }
+    }
+}
+
+/// Check a strftime-style timestamp format
+#[cfg(feature = "timestamps")]
+pub(crate) fn validate_format(format: &str) -> Result<()> {
+    use chrono::format::{Item, StrftimeItems};
+
+    if format.is_empty() || StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        return Err(Error::InvalidArgument(format!(
+            "Invalid timestamp format '{}'",
+            format
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "timestamps"))]
+pub(crate) fn validate_format(_format: &str) -> Result<()> {
+    Err(Error::InvalidArgument(
+        "Time windows require the 'timestamps' feature".to_string(),
+    ))
+}
+
+/// Parse the timestamp `record` starts with, in `format`, returning it in
+/// milliseconds since the epoch along with the rest of the record. A format
+/// without a year (like syslog's `%b %d %H:%M:%S`) is read as in a leap
+/// year, one without a date as on a single day (so times wrap at midnight),
+/// and one without an offset as UTC.
+#[cfg(feature = "timestamps")]
+fn parse_timestamp<'a>(record: &'a [u8], format: &str) -> Result<Option<(i64, &'a [u8])>> {
+    use chrono::format::{Parsed, StrftimeItems, parse_and_remainder};
+
+    let text = match std::str::from_utf8(record) {
+        Ok(text) => text,
+        // Checked by from_utf8
+        Err(e) => std::str::from_utf8(&record[..e.valid_up_to()]).unwrap_or_default(),
+    };
+    let mut parsed = Parsed::new();
+    let Ok(rest) = parse_and_remainder(&mut parsed, text, StrftimeItems::new(format)) else {
+        return Ok(None);
+    };
+    if parsed.timestamp().is_none() {
+        if parsed.year().is_none() && parsed.year_mod_100().is_none() && parsed.isoyear().is_none()
+        {
+            let _ = parsed.set_year(2000);
+        }
+        if parsed.month().is_none() && parsed.day().is_none() && parsed.ordinal().is_none() {
+            let _ = parsed.set_month(1);
+            let _ = parsed.set_day(1);
+        }
+    }
+    let offset = parsed.offset().unwrap_or(0);
+    let Ok(datetime) = parsed.to_naive_datetime_with_offset(offset) else {
+        return Ok(None);
+    };
+    let time = datetime.and_utc().timestamp_millis() - i64::from(offset) * 1000;
+    Ok(Some((time, &record[text.len() - rest.len()..])))
+}
+
+#[cfg(not(feature = "timestamps"))]
+fn parse_timestamp<'a>(_record: &'a [u8], format: &str) -> Result<Option<(i64, &'a [u8])>> {
+    validate_format(format).map(|_| None)
+}
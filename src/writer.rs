@@ -0,0 +1,184 @@
+//! A `Write` adapter that drops records it has already seen
+use crate::deduplicator::{Decision, Deduplicator};
+use crate::error::{Error, Result};
+use crate::progress::Progress;
+use crate::record::RecordSeparator;
+use crate::{CountingWriter, DeduplicationMode, DeduplicationOptions, DeduplicationStats, Origin};
+use std::io::{self, Write};
+
+/// Wraps a writer and forwards only the first occurrence of each record.
+///
+/// Bytes are buffered until a record terminator arrives, so records may be
+/// split across any number of `write` calls. Each record is then decided by
+/// a `Deduplicator`. A trailing record without a
+/// terminator is only forwarded by `finish`.
+///
+/// Only `KeepFirst` deduplication with byte-string separators is supported,
+/// since later records can't be waited for; `count` and header rows are not
+/// supported.
+///
+/// ```
+/// use std::io::Write;
+/// use uniqr::{DeduplicatingWriter, DeduplicationOptions};
+///
+/// let mut writer = DeduplicatingWriter::new(Vec::new(), DeduplicationOptions::default()).unwrap();
+/// writer.write_all(b"a\nb\na").unwrap();
+/// writer.write_all(b"\nc\n").unwrap();
+/// assert_eq!(writer.finish().unwrap(), b"a\nb\nc\n");
+/// ```
+pub struct DeduplicatingWriter<W: Write> {
+    inner: CountingWriter<W>,
+    dedup: Deduplicator,
+    buffer: Vec<u8>,
+    /// Bytes written to this writer, including any incomplete record
+    bytes_read: u64,
+}
+
+impl<W: Write> DeduplicatingWriter<W> {
+    /// Wrap `inner`, deduplicating according to `options`
+    pub fn new(inner: W, options: DeduplicationOptions) -> Result<Self> {
+        if options.mode != DeduplicationMode::KeepFirst
+            || options.count
+            || options.adjacent
+            || options.group.is_some()
+            || options.sort.is_some()
+            || options.collect_removed_records
+            || options.max_line_length.is_some()
+        {
+            return Err(Error::InvalidArgument(
+                "DeduplicatingWriter only supports non-adjacent, ungrouped, unsorted KeepFirst mode without counts, removed records or a line length limit"
+                    .to_string(),
+            ));
+        }
+        if !matches!(
+            options.record_separator,
+            RecordSeparator::Byte(_) | RecordSeparator::Bytes(_)
+        ) {
+            return Err(Error::InvalidArgument(
+                "DeduplicatingWriter only supports byte and byte-string separators".to_string(),
+            ));
+        }
+        if options.column_name.is_some()
+            || options.csv.as_ref().is_some_and(|csv| csv.has_header)
+            || options.header_lines > 0
+            || options.footer_lines > 0
+        {
+            return Err(Error::InvalidArgument(
+                "DeduplicatingWriter doesn't support header or footer rows".to_string(),
+            ));
+        }
+        Ok(Self {
+            inner: CountingWriter::new(inner),
+            dedup: Deduplicator::new(options)?,
+            buffer: Vec::new(),
+            bytes_read: 0,
+        })
+    }
+
+    /// Statistics for the records processed so far; `elapsed` and the key
+    /// reports are only set by `finish_with_stats`
+    pub fn stats(&self) -> &DeduplicationStats {
+        self.dedup.stats()
+    }
+
+    /// A reference to the wrapped writer
+    pub fn get_ref(&self) -> &W {
+        self.inner.get_ref()
+    }
+
+    /// A mutable reference to the wrapped writer
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.get_mut()
+    }
+
+    /// Forward any buffered unterminated record, flush, and return the inner writer
+    pub fn finish(self) -> Result<W> {
+        self.finish_with_stats().map(|(inner, _)| inner)
+    }
+
+    /// Like `finish`, also returning the final statistics
+    pub fn finish_with_stats(mut self) -> Result<(W, DeduplicationStats)> {
+        if !self.buffer.is_empty() {
+            let mut record = std::mem::take(&mut self.buffer);
+            let options = self.dedup.options();
+            if options.ensure_trailing_newline {
+                record.extend_from_slice(options.record_separator.terminator());
+            }
+            self.process_record(&record)?;
+        }
+        self.inner.flush()?;
+        Ok((self.inner.into_inner(), self.dedup.finish()))
+    }
+
+    /// Length of the first complete record in `data`, if any
+    fn record_end(&self, data: &[u8]) -> Option<usize> {
+        match &self.dedup.options().record_separator {
+            RecordSeparator::Byte(sep) => data.iter().position(|b| b == sep).map(|idx| idx + 1),
+            RecordSeparator::Bytes(sep) if !sep.is_empty() => data
+                .windows(sep.len())
+                .position(|window| window == &sep[..])
+                .map(|idx| idx + sep.len()),
+            _ => data.iter().position(|&b| b == b'\n').map(|idx| idx + 1),
+        }
+    }
+
+    fn process_record(&mut self, mut record: &[u8]) -> Result<()> {
+        // Strip a byte order mark at the start of the input
+        let first = self.dedup.stats().lines_read == 0;
+        if let Some(bom) = crate::bom::detect(record).filter(|_| first) {
+            if self.dedup.options().emit_bom {
+                self.inner.write_all(bom)?;
+            }
+            record = &record[bom.len()..];
+        }
+        let origin = Origin::single(self.dedup.stats().lines_read);
+        let decision = self.dedup.push_line(record)?;
+        let options = self.dedup.options();
+        match decision {
+            Decision::Keep => crate::write_record(&mut self.inner, record, origin, options)?,
+            Decision::Remove if options.show_removed => {
+                write!(self.inner, "[REMOVED] ")?;
+                crate::write_annotated(&mut self.inner, record, origin, options)?;
+            }
+            Decision::Remove => {}
+        }
+        let stats = self.dedup.stats_mut();
+        stats.bytes_read = self.bytes_read;
+        stats.bytes_written = self.inner.bytes_written();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for DeduplicatingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Only the new bytes can complete a record that wasn't complete before
+        let mut search_from = self.buffer.len();
+        self.buffer.extend_from_slice(buf);
+        if let RecordSeparator::Bytes(sep) = &self.dedup.options().record_separator {
+            search_from = search_from.saturating_sub(sep.len().saturating_sub(1));
+        }
+
+        let mut start = 0;
+        while let Some(len) = self.record_end(&self.buffer[search_from.max(start)..]) {
+            let end = search_from.max(start) + len;
+            let record = self.buffer[start..end].to_vec();
+            self.process_record(&record)?;
+            start = end;
+        }
+        self.buffer.drain(..start);
+
+        self.bytes_read += buf.len() as u64;
+        self.dedup.stats_mut().bytes_read = self.bytes_read;
+        if let Some(callback) = &self.dedup.options().progress_callback {
+            callback.report(Progress {
+                bytes_read: self.bytes_read,
+                lines_read: self.dedup.stats().lines_read as u64,
+            });
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
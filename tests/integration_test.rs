@@ -1,200 +1,2306 @@
-use assert_cmd::Command;
-use predicates::prelude::*;
-use std::fs;
-use tempfile::NamedTempFile;
-
-#[test]
-fn test_basic_dedup_stdin() {
-    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
-    cmd.write_stdin("line1\nline2\nline1\nline3\n")
-        .assert()
-        .success()
-        .stdout("line1\nline2\nline3\n");
-}
-
-#[test]
-fn test_keep_last_mode() {
-    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
-    cmd.arg("--keep-last")
-        .write_stdin("a\nb\na\nc\n")
-        .assert()
-        .success()
-        .stdout("b\na\nc\n");
-}
-
-#[test]
-fn test_remove_all_mode() {
-    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
-    cmd.arg("--remove-all")
-        .write_stdin("a\nb\na\nc\n")
-        .assert()
-        .success()
-        .stdout("b\nc\n");
-}
-
-#[test]
-fn test_ignore_case() {
-    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
-    cmd.arg("--ignore-case")
-        .write_stdin("Apple\napple\nBanana\n")
-        .assert()
-        .success()
-        .stdout("Apple\nBanana\n");
-}
-
-#[test]
-fn test_count_flag() {
-    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
-    cmd.arg("--count")
-        .write_stdin("a\nb\na\na\n")
-        .assert()
-        .success()
-        .stdout(predicate::str::contains("3"))
-        .stdout(predicate::str::contains("1"));
-}
-
-#[test]
-fn test_stats_flag() {
-    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
-    cmd.arg("--stats")
-        .write_stdin("a\nb\na\n")
-        .assert()
-        .success()
-        .stderr(predicate::str::contains("Lines read:    3"))
-        .stderr(predicate::str::contains("Lines written: 2"));
-}
-
-#[test]
-fn test_file_input() {
-    let file = NamedTempFile::new().unwrap();
-    fs::write(file.path(), "x\ny\nx\n").unwrap();
-
-    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
-    cmd.arg(file.path()).assert().success().stdout("x\ny\n");
-}
-
-#[test]
-fn test_file_output() {
-    let output_file = NamedTempFile::new().unwrap();
-
-    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
-    cmd.arg("--output")
-        .arg(output_file.path())
-        .write_stdin("1\n2\n1\n")
-        .assert()
-        .success();
-
-    let contents = fs::read_to_string(output_file.path()).unwrap();
-    assert_eq!(contents, "1\n2\n");
-}
-
-#[test]
-fn test_dry_run() {
-    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
-    cmd.arg("--dry-run")
-        .arg("--stats")
-        .write_stdin("a\nb\na\n")
-        .assert()
-        .success()
-        .stdout("")
-        .stderr(predicate::str::contains("Lines written: 2"));
-}
-
-#[test]
-fn test_conflicting_modes() {
-    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
-    cmd.arg("--keep-last")
-        .arg("--remove-all")
-        .write_stdin("a\n")
-        .assert()
-        .failure()
-        .stderr(predicate::str::contains("cannot be used with"));
-}
-
-#[test]
-fn test_empty_input() {
-    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
-    cmd.write_stdin("").assert().success().stdout("");
-}
-
-#[test]
-fn test_show_removed() {
-    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
-    cmd.arg("--show-removed")
-        .write_stdin("a\nb\na\n")
-        .assert()
-        .success()
-        .stdout(predicate::str::contains("[REMOVED] a"));
-}
-
-#[test]
-fn test_single_line() {
-    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
-    cmd.write_stdin("only\n")
-        .assert()
-        .success()
-        .stdout("only\n");
-}
-
-#[test]
-fn test_all_duplicates() {
-    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
-    cmd.write_stdin("x\nx\nx\n")
-        .assert()
-        .success()
-        .stdout("x\n");
-}
-
-#[test]
-fn test_column_mode() {
-    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
-    cmd.arg("--column")
-        .arg("1")
-        .write_stdin("1\tapple\n2\tbanana\n1\torange\n")
-        .assert()
-        .success()
-        .stdout("1\tapple\n2\tbanana\n");
-}
-
-#[test]
-fn test_keep_last_count() {
-    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
-    cmd.arg("--keep-last")
-        .arg("--count")
-        .write_stdin("a\nb\na\n")
-        .assert()
-        .success()
-        .stdout(predicate::str::contains("2 a")) // 'a' appears twice, kept last. count should be 2.
-        .stdout(predicate::str::contains("1 b"));
-}
-
-#[test]
-fn test_empty_line_preservation() {
-    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
-    cmd.write_stdin("a\n\nb\n\na\n")
-        .assert()
-        .success()
-        // Deduplicated output:
-        // 1. "a" (seen)
-        // 2. "" (seen)
-        // 3. "b" (seen)
-        // 4. "" (duplicate of 2, removed)
-        // 5. "a" (duplicate of 1, removed)
-        .stdout("a\n\nb\n");
-}
-
-#[test]
-fn test_disk_backed_keep_last_count() {
-    let input_file = NamedTempFile::new().unwrap();
-    fs::write(input_file.path(), "a\nb\na\n").unwrap();
-
-    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
-    cmd.arg("--keep-last")
-        .arg("--count")
-        .arg("--use-disk")
-        .arg(input_file.path())
-        .assert()
-        .success()
-        .stdout(predicate::str::contains("2 a"))
-        .stdout(predicate::str::contains("1 b"));
-}
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::fs;
+use tempfile::NamedTempFile;
+
+#[test]
+fn test_basic_dedup_stdin() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.write_stdin("line1\nline2\nline1\nline3\n")
+        .assert()
+        .success()
+        .stdout("line1\nline2\nline3\n");
+}
+
+#[test]
+fn test_keep_last_mode() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--keep-last")
+        .write_stdin("a\nb\na\nc\n")
+        .assert()
+        .success()
+        .stdout("b\na\nc\n");
+}
+
+#[test]
+fn test_remove_all_mode() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--remove-all")
+        .write_stdin("a\nb\na\nc\n")
+        .assert()
+        .success()
+        .stdout("b\nc\n");
+}
+
+#[test]
+fn test_ignore_case() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--ignore-case")
+        .write_stdin("Apple\napple\nBanana\n")
+        .assert()
+        .success()
+        .stdout("Apple\nBanana\n");
+}
+
+#[test]
+fn test_count_flag() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--count")
+        .write_stdin("a\nb\na\na\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("3"))
+        .stdout(predicate::str::contains("1"));
+}
+
+#[test]
+fn test_count_format() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.args(["--count", "--count-format", "{count}\\t{line}"])
+        .write_stdin("a\nb\na\na\n")
+        .assert()
+        .success()
+        .stdout("3\ta\n1\tb\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.args(["--count", "--count-format", "{line} = {count:2}"])
+        .write_stdin("a\nb\na\na\n")
+        .assert()
+        .success()
+        .stdout("a =  3\nb =  1\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.args(["--count", "--count-format", "{count}"])
+        .write_stdin("a\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid --count-format"));
+}
+
+#[test]
+fn test_compat_uniq() {
+    let input = "a\na\nb\na\nc\nc\n";
+    let cases = [
+        (vec![], "a\nb\na\nc\n"),
+        (vec!["-c"], "      2 a\n      1 b\n      1 a\n      2 c\n"),
+        (vec!["-u"], "b\na\n"),
+        (vec!["-d"], "a\nc\n"),
+        (vec!["-D"], "a\na\nc\nc\n"),
+    ];
+    for (flags, expected) in cases {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+        cmd.arg("--compat-uniq")
+            .args(&flags)
+            .write_stdin(input)
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+}
+
+#[test]
+fn test_compat_uniq_operands() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("in.txt");
+    let output = dir.path().join("out.txt");
+    fs::write(&input, "x 1\ny 1\nz 2\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.args(["--compat-uniq", "-f", "1"])
+        .arg(&input)
+        .arg(&output)
+        .assert()
+        .success()
+        .stdout("");
+    assert_eq!(fs::read_to_string(&output).unwrap(), "x 1\nz 2\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.args(["--compat-uniq", "-", "-"])
+        .write_stdin("a\na\n")
+        .assert()
+        .success()
+        .stdout("a\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--compat-uniq")
+        .args([&input, &output, &input])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("extra operand"));
+}
+
+#[test]
+fn test_repeated_flag() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--repeated")
+        .write_stdin("a\nb\na\nc\nb\n")
+        .assert()
+        .success()
+        .stdout("a\nb\n");
+}
+
+#[test]
+fn test_check() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--check")
+        .write_stdin("a\nb\nc\n")
+        .assert()
+        .success()
+        .stdout("");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--check")
+        .write_stdin("a\nb\na\nA\n")
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr("Found 1 duplicate line\n");
+}
+
+#[test]
+fn test_report_removed() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.args(["--dry-run", "--report-removed"])
+        .write_stdin("a\nb\na\nb\nc\n")
+        .assert()
+        .success()
+        .stdout("")
+        .stderr("line 3 duplicates line 1\nline 4 duplicates line 2\n");
+
+    let dir = tempfile::tempdir().unwrap();
+    let first = dir.path().join("first.txt");
+    let second = dir.path().join("second.txt");
+    fs::write(&first, "x\ny\n").unwrap();
+    fs::write(&second, "y\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.args(["--dry-run", "--report-removed"])
+        .arg(&first)
+        .arg(&second)
+        .assert()
+        .success()
+        .stderr(format!(
+            "{}:1 duplicates {}:2\n",
+            second.display(),
+            first.display()
+        ));
+}
+
+#[test]
+fn test_line_numbers() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--line-numbers")
+        .write_stdin("a\nb\na\nc\n")
+        .assert()
+        .success()
+        .stdout("1:a\n2:b\n4:c\n");
+}
+
+#[test]
+fn test_follow() {
+    use std::io::{Read, Write};
+    use std::process::Stdio;
+    use std::time::Duration;
+
+    let file = NamedTempFile::new().unwrap();
+    fs::write(file.path(), "a\nb\n").unwrap();
+
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin!("uniqr"))
+        .arg("--follow")
+        .arg(file.path())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    let mut log = fs::OpenOptions::new()
+        .append(true)
+        .open(file.path())
+        .unwrap();
+    log.write_all(b"a\nc\n").unwrap();
+    std::thread::sleep(Duration::from_millis(1000));
+    child.kill().unwrap();
+
+    let mut stdout = String::new();
+    child
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut stdout)
+        .unwrap();
+    child.wait().unwrap();
+    assert_eq!(stdout, "a\nb\nc\n");
+}
+
+#[test]
+fn test_closed_stdout() {
+    use std::io::Read;
+    use std::process::Stdio;
+
+    // More output than a pipe buffers, so writes fail once the reader is gone
+    let file = NamedTempFile::new().unwrap();
+    let lines: String = (0..200_000).map(|i| format!("line {}\n", i)).collect();
+    fs::write(file.path(), lines).unwrap();
+
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin!("uniqr"))
+        .arg(file.path())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut head = [0; 7];
+    let mut stdout = child.stdout.take().unwrap();
+    stdout.read_exact(&mut head).unwrap();
+    drop(stdout);
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(&head, b"line 0\n");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_status_on_sigusr1() {
+    use std::io::Write;
+    use std::process::Stdio;
+    use std::time::Duration;
+
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin!("uniqr"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"a\nb\na\n").unwrap();
+    stdin.flush().unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    let status = std::process::Command::new("kill")
+        .args(["-USR1", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    std::thread::sleep(Duration::from_millis(200));
+    drop(stdin);
+
+    // The run carries on after the report
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"a\nb\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.starts_with("3 lines read, 2 written, 6 bytes in "),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_follow_requires_one_file() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--follow")
+        .write_stdin("a\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("exactly one input file"));
+}
+
+#[test]
+fn test_stats_flag() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--stats")
+        .write_stdin("a\nb\na\n")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Lines read:    3"))
+        .stderr(predicate::str::contains("Lines written: 2"))
+        .stderr(predicate::str::contains("Bytes read:    6"))
+        .stderr(predicate::str::contains("Bytes written: 4"))
+        .stderr(predicate::str::contains("lines/s"));
+}
+
+#[test]
+fn test_file_input() {
+    let file = NamedTempFile::new().unwrap();
+    fs::write(file.path(), "x\ny\nx\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg(file.path()).assert().success().stdout("x\ny\n");
+}
+
+#[test]
+fn test_file_output() {
+    let output_file = NamedTempFile::new().unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--output")
+        .arg(output_file.path())
+        .write_stdin("1\n2\n1\n")
+        .assert()
+        .success();
+
+    let contents = fs::read_to_string(output_file.path()).unwrap();
+    assert_eq!(contents, "1\n2\n");
+}
+
+#[test]
+fn test_dry_run() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--dry-run")
+        .arg("--stats")
+        .write_stdin("a\nb\na\n")
+        .assert()
+        .success()
+        .stdout("")
+        .stderr(predicate::str::contains("Lines written: 2"));
+}
+
+#[test]
+fn test_conflicting_modes() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--keep-last")
+        .arg("--remove-all")
+        .write_stdin("a\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[test]
+fn test_empty_input() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.write_stdin("").assert().success().stdout("");
+}
+
+#[test]
+fn test_show_removed() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--show-removed")
+        .write_stdin("a\nb\na\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("[REMOVED] a"));
+}
+
+#[test]
+fn test_single_line() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.write_stdin("only\n")
+        .assert()
+        .success()
+        .stdout("only\n");
+}
+
+#[test]
+fn test_all_duplicates() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.write_stdin("x\nx\nx\n")
+        .assert()
+        .success()
+        .stdout("x\n");
+}
+
+#[test]
+fn test_column_mode() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--column")
+        .arg("1")
+        .write_stdin("1\tapple\n2\tbanana\n1\torange\n")
+        .assert()
+        .success()
+        .stdout("1\tapple\n2\tbanana\n");
+
+    // Columns are 1-indexed
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.args(["--column", "0"])
+        .write_stdin("1\tapple\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Column is 1-indexed"));
+}
+
+#[test]
+fn test_keep_last_count() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--keep-last")
+        .arg("--count")
+        .write_stdin("a\nb\na\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2 a")) // 'a' appears twice, kept last. count should be 2.
+        .stdout(predicate::str::contains("1 b"));
+}
+
+#[test]
+fn test_empty_line_preservation() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.write_stdin("a\n\nb\n\na\n")
+        .assert()
+        .success()
+        // Deduplicated output:
+        // 1. "a" (seen)
+        // 2. "" (seen)
+        // 3. "b" (seen)
+        // 4. "" (duplicate of 2, removed)
+        // 5. "a" (duplicate of 1, removed)
+        .stdout("a\n\nb\n");
+}
+
+#[cfg(feature = "disk-backed")]
+#[test]
+fn test_state_db_across_runs() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("state.db");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--state-db")
+        .arg(&db)
+        .write_stdin("a\nb\na\n")
+        .assert()
+        .success()
+        .stdout("a\nb\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--state-db")
+        .arg(&db)
+        .write_stdin("b\nc\na\n")
+        .assert()
+        .success()
+        .stdout("c\n");
+}
+
+#[cfg(feature = "disk-backed")]
+#[test]
+fn test_checkpoint_resume() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("state.db");
+    let input = dir.path().join("input.txt");
+    let output = dir.path().join("output.txt");
+
+    // The over-long sixth line stops the first run after two checkpoints
+    fs::write(&input, "a\nb\na\nc\nd\ntoo long\ne\na\n").unwrap();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--state-db")
+        .arg(&db)
+        .arg("--checkpoint-every")
+        .arg("2")
+        .arg("--max-line-length")
+        .arg("5")
+        .arg("-o")
+        .arg(&output)
+        .arg(&input)
+        .assert()
+        .failure();
+    assert_eq!(fs::read_to_string(&output).unwrap(), "a\nb\nc\nd\n");
+
+    fs::write(&input, "a\nb\na\nc\nd\nshort\ne\na\nd\n").unwrap();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--state-db")
+        .arg(&db)
+        .arg("--resume")
+        .arg("--max-line-length")
+        .arg("5")
+        .arg("--stats")
+        .arg("-o")
+        .arg(&output)
+        .arg(&input)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Lines removed: 3"));
+    assert_eq!(
+        fs::read_to_string(&output).unwrap(),
+        "a\nb\nc\nd\nshort\ne\n"
+    );
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--state-db")
+        .arg(&db)
+        .arg("--resume")
+        .arg("-o")
+        .arg(&output)
+        .arg(&input)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no checkpoint"));
+}
+
+#[cfg(feature = "disk-backed")]
+#[test]
+fn test_disk_temp_dir() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--use-disk")
+        .arg("--temp-dir")
+        .arg(dir.path())
+        .arg("--disk-cache-mb")
+        .arg("16")
+        .arg("--disk-low-space")
+        .write_stdin("a\nb\na\n")
+        .assert()
+        .success()
+        .stdout("a\nb\n");
+
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+#[cfg(feature = "disk-backed")]
+#[test]
+fn test_disk_backend_temp_file() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--use-disk")
+        .arg("--disk-backend")
+        .arg("temp-file")
+        .arg("--count")
+        .write_stdin("a\nb\na\n")
+        .assert()
+        .success()
+        .stdout("      2 a\n      1 b\n");
+}
+
+#[cfg(feature = "disk-backed")]
+#[test]
+fn test_memory_limit() {
+    // About 2 MiB of seen keys, so the run spills to disk part way through
+    let input: String = (0..40_000)
+        .map(|i| format!("record {:08}\n", i % 30_000))
+        .collect();
+    let expected: String = (0..30_000).map(|i| format!("record {:08}\n", i)).collect();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--memory-limit-mb")
+        .arg("1")
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(expected);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--memory-limit-mb")
+        .arg("1")
+        .arg("--keep-last")
+        .write_stdin("a\n")
+        .assert()
+        .failure();
+}
+
+#[cfg(feature = "disk-backed")]
+#[test]
+fn test_disk_backed_two_pass_stdin() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--use-disk")
+        .arg("--keep-last")
+        .write_stdin("a\nb\na\nc\n")
+        .assert()
+        .success()
+        .stdout("b\na\nc\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--use-disk")
+        .arg("--remove-all")
+        .write_stdin("a\nb\na\nc\n")
+        .assert()
+        .success()
+        .stdout("b\nc\n");
+}
+
+#[cfg(feature = "disk-backed")]
+#[test]
+fn test_disk_backed_keep_last_count() {
+    let input_file = NamedTempFile::new().unwrap();
+    fs::write(input_file.path(), "a\nb\na\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--keep-last")
+        .arg("--count")
+        .arg("--use-disk")
+        .arg(input_file.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2 a"))
+        .stdout(predicate::str::contains("1 b"));
+}
+
+#[test]
+fn test_check_chars() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--check-chars")
+        .arg("3")
+        .write_stdin("abc-1\nabc-2\nabd-3\n")
+        .assert()
+        .success()
+        .stdout("abc-1\nabd-3\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.args(["-w", "0"])
+        .write_stdin("abc-1\nabc-2\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Compare width must be at least 1"));
+}
+
+#[test]
+fn test_trim() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--trim")
+        .write_stdin("a\n  a\na  \nb\n")
+        .assert()
+        .success()
+        .stdout("a\nb\n");
+
+    // The value must be attached, so a FILE after --trim stays a FILE
+    let file = NamedTempFile::new().unwrap();
+    fs::write(file.path(), "a\n  a\n a \n").unwrap();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--trim")
+        .arg(file.path())
+        .assert()
+        .success()
+        .stdout("a\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--trim=leading")
+        .write_stdin("a \n  a \na\n")
+        .assert()
+        .success()
+        .stdout("a \na\n");
+}
+
+#[test]
+fn test_collapse_spaces() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--collapse-spaces")
+        .write_stdin("a b\na   b\n\ta\tb\n")
+        .assert()
+        .success()
+        .stdout("a b\n");
+}
+
+#[cfg(feature = "unicode")]
+#[test]
+fn test_normalize() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--normalize")
+        .arg("nfc")
+        .write_stdin("caf\u{e9}\ncafe\u{301}\n")
+        .assert()
+        .success()
+        .stdout("caf\u{e9}\n");
+}
+
+#[cfg(feature = "unicode")]
+#[test]
+fn test_ignore_diacritics() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--ignore-diacritics")
+        .write_stdin("Jos\u{e9}\nJose\nJos\u{e8}\n")
+        .assert()
+        .success()
+        .stdout("Jos\u{e9}\n");
+}
+
+#[cfg(feature = "unicode")]
+#[test]
+fn test_case_fold() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--case-fold")
+        .write_stdin("stra\u{df}e\nSTRASSE\n")
+        .assert()
+        .success()
+        .stdout("stra\u{df}e\n");
+}
+
+#[test]
+fn test_ignore_case_non_utf8_error() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--ignore-case")
+        .arg("--non-utf8-case")
+        .arg("error")
+        .write_stdin(b"ABC\xff\n".to_vec())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("UTF-8 error"));
+}
+
+#[test]
+fn test_numeric() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--numeric")
+        .write_stdin("1\n01\n1.0\n2\n")
+        .assert()
+        .success()
+        .stdout("1\n2\n");
+}
+
+#[test]
+fn test_numeric_precision() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.args(["--numeric-precision", "3", "--column", "2"])
+        .write_stdin("a 3.14159\nb 3.1416\nc 3.2\nd 3.20\n")
+        .assert()
+        .success()
+        .stdout("a 3.14159\nc 3.2\n");
+}
+
+#[test]
+fn test_zero_terminated() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("-z")
+        .write_stdin("path\nwith newline\0other\0path\nwith newline\0")
+        .assert()
+        .success()
+        .stdout("path\nwith newline\0other\0");
+}
+
+#[test]
+fn test_paragraph_mode() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--paragraph")
+        .write_stdin("a\nb\n\nc\n\na\nb\n")
+        .assert()
+        .success()
+        .stdout("a\nb\n\nc\n\n");
+}
+
+#[test]
+fn test_custom_separator() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--separator")
+        .arg("\\t")
+        .write_stdin("a\tb\ta\t")
+        .assert()
+        .success()
+        .stdout("a\tb\t");
+}
+
+#[cfg(feature = "csv")]
+#[test]
+fn test_csv_key_column() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--csv")
+        .arg("--key-column")
+        .arg("email")
+        .write_stdin("name,email\n\"Doe, J\",a@x\nJane,b@x\n\"Doe, John\",a@x\n")
+        .assert()
+        .success()
+        .stdout("name,email\n\"Doe, J\",a@x\nJane,b@x\n");
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_json_key() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--json")
+        .arg("--key")
+        .arg(".user.id")
+        .write_stdin(
+            "{\"user\":{\"id\":7},\"n\":1}\n{\"user\":{\"id\":8}}\n{\"n\":2,\"user\":{\"id\":7}}\n",
+        )
+        .assert()
+        .success()
+        .stdout("{\"user\":{\"id\":7},\"n\":1}\n{\"user\":{\"id\":8}}\n");
+}
+
+#[test]
+fn test_column_name() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--column-name")
+        .arg("email")
+        .write_stdin("email\tid\na@x\t1\nb@x\t2\na@x\t3\n")
+        .assert()
+        .success()
+        .stdout("email\tid\na@x\t1\nb@x\t2\n");
+}
+
+#[test]
+fn test_multiple_files() {
+    let first = NamedTempFile::new().unwrap();
+    let second = NamedTempFile::new().unwrap();
+    fs::write(first.path(), "a\nb").unwrap();
+    fs::write(second.path(), "b\nc\na\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg(first.path())
+        .arg(second.path())
+        .assert()
+        .success()
+        .stdout("a\nb\nc\n");
+}
+
+#[test]
+fn test_annotate_source() {
+    let first = NamedTempFile::new().unwrap();
+    let second = NamedTempFile::new().unwrap();
+    fs::write(first.path(), "a\n").unwrap();
+    fs::write(second.path(), "a\nb\n").unwrap();
+
+    let expected = format!(
+        "{}:1:a\n{}:2:b\n",
+        first.path().display(),
+        second.path().display()
+    );
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--annotate-source")
+        .arg("--source-line-numbers")
+        .arg(first.path())
+        .arg(second.path())
+        .assert()
+        .success()
+        .stdout(expected);
+}
+
+#[test]
+fn test_recursive_include() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    fs::write(dir.path().join("a.log"), "x\ny\n").unwrap();
+    fs::write(dir.path().join("sub").join("b.log"), "y\nz\n").unwrap();
+    fs::write(dir.path().join("skip.txt"), "w\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--recursive")
+        .arg("--include")
+        .arg("*.log")
+        .arg(dir.path())
+        .assert()
+        .success()
+        .stdout("x\ny\nz\n");
+}
+
+#[test]
+fn test_glob_input() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("1.log"), "a\n").unwrap();
+    fs::write(dir.path().join("2.log"), "a\nb\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg(dir.path().join("*.log"))
+        .assert()
+        .success()
+        .stdout("a\nb\n");
+}
+
+#[test]
+fn test_directory_requires_recursive() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg(dir.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--recursive"));
+}
+
+#[test]
+fn test_in_place() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.txt");
+    fs::write(&path, "a\nb\na\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--in-place")
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout("");
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "a\nb\n");
+    assert!(!dir.path().join("data.txt.bak").exists());
+}
+
+#[test]
+fn test_in_place_backup() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = dir.path().join("first.txt");
+    let second = dir.path().join("second.txt");
+    fs::write(&first, "a\na\n").unwrap();
+    fs::write(&second, "a\nb\nb\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--in-place=.bak")
+        .arg(&first)
+        .arg(&second)
+        .assert()
+        .success();
+
+    // Each file is deduplicated independently
+    assert_eq!(fs::read_to_string(&first).unwrap(), "a\n");
+    assert_eq!(fs::read_to_string(&second).unwrap(), "a\nb\n");
+    assert_eq!(
+        fs::read_to_string(dir.path().join("first.txt.bak")).unwrap(),
+        "a\na\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("second.txt.bak")).unwrap(),
+        "a\nb\nb\n"
+    );
+}
+
+#[test]
+fn test_in_place_requires_file() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--in-place")
+        .write_stdin("a\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--in-place requires a file"));
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn test_gzip_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let compressed = dir.path().join("out.txt.gz");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("-o")
+        .arg(&compressed)
+        .write_stdin("a\nb\na\n")
+        .assert()
+        .success();
+
+    // Output ending in .gz is compressed...
+    let bytes = fs::read(&compressed).unwrap();
+    assert_eq!(&bytes[..2], &[0x1f, 0x8b]);
+
+    // ...and .gz inputs are decompressed, alongside plain ones
+    let plain = dir.path().join("more.txt");
+    fs::write(&plain, "b\nc\n").unwrap();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg(&compressed)
+        .arg(&plain)
+        .assert()
+        .success()
+        .stdout("a\nb\nc\n");
+
+    // --gzip forces decompression of stdin
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--gzip")
+        .write_stdin(bytes)
+        .assert()
+        .success()
+        .stdout("a\nb\n");
+}
+
+#[test]
+#[cfg(all(feature = "compress-zstd", feature = "compress-xz"))]
+fn test_zstd_and_xz_detection() {
+    let dir = tempfile::tempdir().unwrap();
+    let zst = dir.path().join("out.zst");
+    let xz = dir.path().join("out.xz");
+
+    for path in [&zst, &xz] {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+        cmd.arg("-o")
+            .arg(path)
+            .write_stdin("a\nb\na\n")
+            .assert()
+            .success();
+    }
+    assert_eq!(&fs::read(&zst).unwrap()[..4], &[0x28, 0xb5, 0x2f, 0xfd]);
+    assert_eq!(&fs::read(&xz).unwrap()[..6], b"\xfd7zXZ\0");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg(&zst).arg(&xz).assert().success().stdout("a\nb\n");
+}
+
+#[test]
+fn test_approximate() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--approximate")
+        .arg("--false-positive-rate")
+        .arg("0.0001")
+        .arg("--stats")
+        .write_stdin("a\nb\na\nc\n")
+        .assert()
+        .success()
+        .stdout("a\nb\nc\n")
+        .stderr(predicate::str::contains("Lines removed: 1"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--approximate")
+        .arg("--false-positive-rate")
+        .arg("1.5")
+        .write_stdin("a\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("between 0 and 1"));
+}
+
+#[test]
+fn test_fuzzy() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--fuzzy")
+        .arg("--max-distance")
+        .arg("1")
+        .write_stdin(
+            "user 17 logged in\nuser 18 logged in\nuser 173 logged in\nuser 940 logged in\n",
+        )
+        .assert()
+        .success()
+        .stdout("user 17 logged in\nuser 940 logged in\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--fuzzy")
+        .arg("--keep-last")
+        .write_stdin("a\n")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_similar() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--similar")
+        .arg("0.6")
+        .write_stdin(
+            "breaking: the council approved the new budget for city parks on tuesday\n\
+             breaking: the council approved a new budget for city parks on tuesday\n\
+             local team wins the regional championship after extra time\n",
+        )
+        .assert()
+        .success()
+        .stdout(
+            "breaking: the council approved the new budget for city parks on tuesday\n\
+             local team wins the regional championship after extra time\n",
+        );
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--similar")
+        .arg("0.8")
+        .arg("--keep-last")
+        .write_stdin("a\n")
+        .assert()
+        .failure();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--similar")
+        .arg("1.5")
+        .write_stdin("a\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Similarity threshold must be above 0 and at most 1",
+        ));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--signature")
+        .arg("simhash")
+        .write_stdin("a\n")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_ignore_punct() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--ignore-punct")
+        .write_stdin("Great product!\n\"Great product\"\nGreat product.\nGreat-product\n")
+        .assert()
+        .success()
+        .stdout("Great product!\nGreat-product\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--alnum-only")
+        .write_stdin("Great product!\nGreat-product\n")
+        .assert()
+        .success()
+        .stdout("Great product!\n");
+}
+
+#[test]
+fn test_only_removed() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--only-removed")
+        .write_stdin("a\nb\na\nc\nb\na\n")
+        .assert()
+        .success()
+        .stdout("a\nb\na\n");
+
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.txt");
+    let output = temp.path().join("output.txt");
+    fs::write(&input, "a\nb\na\n").unwrap();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--only-removed")
+        .arg("--keep-last")
+        .arg("-o")
+        .arg(&output)
+        .arg(&input)
+        .assert()
+        .success()
+        .stdout("");
+    assert_eq!(fs::read_to_string(&output).unwrap(), "a\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--only-removed")
+        .arg("--show-removed")
+        .write_stdin("a\n")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_group() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--group")
+        .write_stdin("a\nb\na\nc\n")
+        .assert()
+        .success()
+        .stdout("a\na\n\nb\n\nc\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--compat-uniq")
+        .arg("--group=append")
+        .write_stdin("a\na\nb\na\n")
+        .assert()
+        .success()
+        .stdout("a\na\n\nb\n\na\n\n");
+
+    // A FILE after --group is a FILE, as with GNU uniq
+    let file = NamedTempFile::new().unwrap();
+    fs::write(file.path(), "a\na\nb\n").unwrap();
+    for compat in [false, true] {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+        if compat {
+            cmd.arg("--compat-uniq");
+        }
+        cmd.arg("--group")
+            .arg(file.path())
+            .assert()
+            .success()
+            .stdout("a\na\n\nb\n");
+    }
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--group")
+        .arg("--count")
+        .write_stdin("a\n")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_sort() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--sort")
+        .write_stdin("pear\napple\npear\nfig\n")
+        .assert()
+        .success()
+        .stdout("apple\nfig\npear\n");
+
+    // A FILE after --sort is a FILE, not the ORDER
+    let file = NamedTempFile::new().unwrap();
+    fs::write(file.path(), "pear\napple\npear\n").unwrap();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--sort")
+        .arg(file.path())
+        .assert()
+        .success()
+        .stdout("apple\npear\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--sort=numeric")
+        .arg("--column")
+        .arg("2")
+        .write_stdin("a 10\nb 9\nc 100\nd 9\n")
+        .assert()
+        .success()
+        .stdout("b 9\na 10\nc 100\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--sort")
+        .arg("--group")
+        .write_stdin("a\n")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_sort_by_count() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--sort-by-count")
+        .write_stdin("GET /a\nGET /b\nGET /b\nPOST /c\nGET /b\nPOST /c\n")
+        .assert()
+        .success()
+        .stdout("      3 GET /b\n      2 POST /c\n      1 GET /a\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--sort-by-count=asc")
+        .arg("--count-format")
+        .arg("{line}\t{count}")
+        .arg("--count")
+        .write_stdin("x\ny\ny\nz\n")
+        .assert()
+        .success()
+        .stdout("x\t1\nz\t1\ny\t2\n");
+
+    // A FILE after --sort-by-count is a FILE, not the ORDER
+    let file = NamedTempFile::new().unwrap();
+    fs::write(file.path(), "a\nb\nb\n").unwrap();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--sort-by-count")
+        .arg(file.path())
+        .assert()
+        .success()
+        .stdout("      2 b\n      1 a\n");
+}
+
+#[test]
+fn test_max_line_length() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--max-line-length")
+        .arg("4")
+        .write_stdin("ok\ntoo long\n")
+        .assert()
+        .failure()
+        .stdout("ok\n")
+        .stderr("Error: Line 2 (byte 3): Line longer than 4 bytes\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--max-line-length")
+        .arg("4")
+        .arg("--long-lines")
+        .arg("truncate")
+        .write_stdin("ok\ntoo long\ntook\n")
+        .assert()
+        .success()
+        .stdout("ok\ntoo \ntook\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--max-line-length")
+        .arg("4")
+        .arg("--long-lines")
+        .arg("pass-through")
+        .write_stdin("ok\ntoo long\nok\ntoo long\n")
+        .assert()
+        .success()
+        .stdout("ok\ntoo long\ntoo long\n");
+}
+
+#[test]
+fn test_trailing_newline() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.write_stdin("b\na\nb").assert().success().stdout("b\na");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--sort")
+        .write_stdin("c\na\nb")
+        .assert()
+        .success()
+        .stdout("a\nb\nc");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--ensure-trailing-newline")
+        .write_stdin("b\na\nb")
+        .assert()
+        .success()
+        .stdout("b\na\n");
+}
+
+#[test]
+fn test_bom() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("export.txt");
+    fs::write(&input, b"\xEF\xBB\xBFid\nb\nid\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg(&input).assert().success().stdout("id\nb\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--keep-bom")
+        .arg(&input)
+        .assert()
+        .success()
+        .stdout(&b"\xEF\xBB\xBFid\nb\n"[..]);
+
+    // Without --input-encoding, UTF-16 input passes through whole
+    fs::write(&input, b"\xFF\xFEi\0d\0\n\0").unwrap();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg(&input)
+        .assert()
+        .success()
+        .stdout(&b"\xFF\xFEi\0d\0\n\0"[..]);
+}
+
+#[cfg(feature = "encodings")]
+#[test]
+fn test_encodings() {
+    let utf16 =
+        |text: &str| -> Vec<u8> { text.encode_utf16().flat_map(u16::to_le_bytes).collect() };
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("export.csv");
+    fs::write(&input, utf16("\u{feff}café\nthé\ncafé\n")).unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--input-encoding")
+        .arg("utf-16le")
+        .arg(&input)
+        .assert()
+        .success()
+        .stdout("café\nthé\n");
+
+    let output = dir.path().join("out.csv");
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--input-encoding")
+        .arg("utf-16le")
+        .arg("--output-encoding")
+        .arg("utf-16le")
+        .arg("--keep-bom")
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+    assert_eq!(fs::read(&output).unwrap(), utf16("\u{feff}café\nthé\n"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--input-encoding")
+        .arg("klingon")
+        .arg(&input)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Unknown encoding 'klingon'"));
+}
+
+#[test]
+fn test_exclude_from() {
+    let dir = tempfile::tempdir().unwrap();
+    let reference = dir.path().join("seen.txt");
+    fs::write(&reference, "id,B\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--exclude-from")
+        .arg(&reference)
+        .arg("--ignore-case")
+        .arg("--stats")
+        .write_stdin("id,a\nid,b\nid,c\nid,a\n")
+        .assert()
+        .success()
+        .stdout("id,a\nid,c\n")
+        .stderr(predicate::str::contains("Lines removed: 2"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--exclude-from")
+        .arg(dir.path().join("missing.txt"))
+        .write_stdin("a\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("missing.txt"));
+}
+
+#[test]
+fn test_set_operations() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = dir.path().join("a.txt");
+    let b = dir.path().join("b.txt");
+    fs::write(&a, "apple\nBanana\ncherry\napple\n").unwrap();
+    fs::write(&b, "banana\ndate\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--ignore-case")
+        .arg("intersect")
+        .arg(&a)
+        .arg(&b)
+        .assert()
+        .success()
+        .stdout("Banana\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("-i")
+        .arg("diff")
+        .arg(&a)
+        .arg(&b)
+        .assert()
+        .success()
+        .stdout("apple\ncherry\n");
+
+    let output = dir.path().join("union.txt");
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--stats")
+        .arg("-o")
+        .arg(&output)
+        .arg("union")
+        .arg(&a)
+        .arg(&b)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Lines removed: 1"));
+    assert_eq!(
+        fs::read_to_string(&output).unwrap(),
+        "apple\nBanana\ncherry\nbanana\ndate\n"
+    );
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("intersect")
+        .arg(&a)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("required"));
+}
+
+#[test]
+fn test_common() {
+    let dir = tempfile::tempdir().unwrap();
+    let dns = dir.path().join("dns.txt");
+    let cmdb = dir.path().join("cmdb.txt");
+    let monitoring = dir.path().join("monitoring.txt");
+    fs::write(&dns, "web2\nDB1\nweb1\ncache1\nweb2\n").unwrap();
+    fs::write(&cmdb, "web1\ndb1\nweb2\n").unwrap();
+    fs::write(&monitoring, "db1\nweb2\nweb1\ncache1\n").unwrap();
+
+    // In the order of the first file, each line once
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("-i")
+        .arg("common")
+        .arg(&dns)
+        .arg(&cmdb)
+        .arg(&monitoring)
+        .assert()
+        .success()
+        .stdout("web2\nDB1\nweb1\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("common")
+        .arg(&dns)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("required"));
+}
+
+#[test]
+fn test_subcommands() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = dir.path().join("a.txt");
+    let b = dir.path().join("b.txt");
+    fs::write(&a, "apple\nBanana\ncherry\napple\n").unwrap();
+    fs::write(&b, "banana\ndate\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("-i")
+        .arg("dedup")
+        .arg(&a)
+        .arg(&b)
+        .assert()
+        .success()
+        .stdout("apple\nBanana\ncherry\ndate\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("count")
+        .arg("--top")
+        .arg("1")
+        .arg(&a)
+        .assert()
+        .success()
+        .stdout("2\tapple\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("-i")
+        .arg("count")
+        .arg("--sort")
+        .arg("key")
+        .arg(&a)
+        .arg(&b)
+        .assert()
+        .success()
+        .stdout("2\tapple\n2\tbanana\n1\tcherry\n1\tdate\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("-i")
+        .arg("count")
+        .arg("--min")
+        .arg("2")
+        .arg(&a)
+        .arg(&b)
+        .assert()
+        .success()
+        .stdout("2\tapple\n2\tbanana\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("-i")
+        .arg("setops")
+        .arg("intersect")
+        .arg(&a)
+        .arg(&b)
+        .assert()
+        .success()
+        .stdout("Banana\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("-o")
+        .arg(dir.path().join("out.txt"))
+        .arg("count")
+        .arg(&a)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("count supports neither --output"));
+}
+
+#[test]
+fn test_subcommand_names_as_files() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("count"), "x\nx\n").unwrap();
+    fs::write(dir.path().join("diff"), "y\ny\n").unwrap();
+
+    // The name is the subcommand even where a file of that name exists
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.current_dir(dir.path())
+        .arg("count")
+        .write_stdin("stdin\n")
+        .assert()
+        .success()
+        .stdout("1\tstdin\n");
+
+    // dedup, -- or a path read such files
+    for args in [
+        &["dedup", "count", "diff"][..],
+        &["--", "count", "diff"],
+        &["./count", "diff"],
+    ] {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+        cmd.current_dir(dir.path())
+            .args(args)
+            .assert()
+            .success()
+            .stdout("x\ny\n");
+    }
+}
+
+#[test]
+fn test_merge() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = dir.path().join("a.idx");
+    let b = dir.path().join("b.idx");
+    fs::write(&a, "1\n5\n5\n20\n").unwrap();
+    fs::write(&b, "3\n5\n100\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--sort=numeric")
+        .arg("--stats")
+        .arg("merge")
+        .arg(&a)
+        .arg(&b)
+        .assert()
+        .success()
+        .stdout("1\n3\n5\n20\n100\n")
+        .stderr(predicate::str::contains("Lines removed: 2"));
+
+    // 100 sorts before 20 as text
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("merge")
+        .arg(&a)
+        .arg(&b)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Input 1 isn't sorted at record 4"));
+}
+
+#[test]
+fn test_error_position() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--ignore-case")
+        .arg("--non-utf8-case")
+        .arg("error")
+        .write_stdin(&b"ok\nnot \xff utf-8\n"[..])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Line 2 (byte 3): UTF-8 error"));
+}
+
+#[test]
+fn test_utf8_policy() {
+    let input = &b"caf\xe9\ncafe\ncaf\xe9\ncafe\n"[..];
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--utf8")
+        .arg("skip")
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(&b"caf\xe9\ncafe\ncaf\xe9\n"[..]);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--utf8")
+        .arg("strict")
+        .write_stdin(input)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Line 1 (byte 0): UTF-8 error"));
+}
+
+#[test]
+fn test_verbose() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.env_remove("RUST_LOG")
+        .arg("-vv")
+        .write_stdin("a\nb\na\n")
+        .assert()
+        .success()
+        .stdout("a\nb\n")
+        .stderr(predicate::str::contains("keep-first algorithm"))
+        .stderr(predicate::str::contains(
+            "Read 3 records (6 bytes), wrote 2, removed 1",
+        ));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.env_remove("RUST_LOG")
+        .arg("--quiet")
+        .write_stdin("a\nb\na\n")
+        .assert()
+        .success()
+        .stderr("");
+}
+
+#[test]
+fn test_config_defaults() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("uniqr")).unwrap();
+    let config = dir.path().join("uniqr").join("config.toml");
+    fs::write(&config, "ignore_case = true\nkeep_last = true\n").unwrap();
+    let uniqr = || {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+        cmd.env("XDG_CONFIG_HOME", dir.path())
+            .env_remove("UNIQR_CONFIG")
+            .env_remove("UNIQR_OPTS")
+            .write_stdin("a\nA\nb\n");
+        cmd
+    };
+
+    uniqr().assert().success().stdout("A\nb\n");
+    // A mode on the command line replaces the configured one
+    uniqr().arg("--remove-all").assert().success().stdout("b\n");
+    uniqr()
+        .env("UNIQR_OPTS", "--unique")
+        .assert()
+        .success()
+        .stdout("b\n");
+    uniqr()
+        .arg("--no-config")
+        .assert()
+        .success()
+        .stdout("a\nA\nb\n");
+
+    fs::write(&config, "ignore_cases = true\n").unwrap();
+    uniqr()
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Unknown option 'ignore_cases'"));
+    fs::write(&config, "ignore_case = \"yes\"\n").unwrap();
+    uniqr()
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is a flag"));
+}
+
+#[test]
+fn test_tee() {
+    let dir = tempfile::tempdir().unwrap();
+    let tee = dir.path().join("tee.txt");
+
+    Command::new(assert_cmd::cargo::cargo_bin!("uniqr"))
+        .arg("--tee")
+        .arg(&tee)
+        .write_stdin("a\nb\na\nc\n")
+        .assert()
+        .success()
+        .stdout("a\nb\nc\n");
+    assert_eq!(fs::read_to_string(&tee).unwrap(), "a\nb\nc\n");
+
+    // With --only-removed, both get the removed lines
+    let input = dir.path().join("input.txt");
+    fs::write(&input, "x\ny\nx\n").unwrap();
+    Command::new(assert_cmd::cargo::cargo_bin!("uniqr"))
+        .arg(&input)
+        .arg("--only-removed")
+        .arg("--tee")
+        .arg(&tee)
+        .assert()
+        .success()
+        .stdout("x\n");
+    assert_eq!(fs::read_to_string(&tee).unwrap(), "x\n");
+
+    Command::new(assert_cmd::cargo::cargo_bin!("uniqr"))
+        .arg(&input)
+        .arg("--tee")
+        .arg(&tee)
+        .arg("-o")
+        .arg(dir.path().join("out.txt"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[test]
+fn test_append() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    let output = dir.path().join("output.txt");
+    fs::write(&input, "b\nc\nb\n").unwrap();
+
+    // The last line of the output has no newline yet
+    fs::write(&output, "a\nb").unwrap();
+    Command::new(assert_cmd::cargo::cargo_bin!("uniqr"))
+        .arg(&input)
+        .arg("--append")
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(&output).unwrap(), "a\nb\nb\nc\n");
+
+    fs::write(&output, "a\nb\n").unwrap();
+    Command::new(assert_cmd::cargo::cargo_bin!("uniqr"))
+        .arg(&input)
+        .arg("--append=new")
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(&output).unwrap(), "a\nb\nc\n");
+
+    // A FILE after --append is a FILE, not the WHAT
+    fs::write(&output, "a\n").unwrap();
+    Command::new(assert_cmd::cargo::cargo_bin!("uniqr"))
+        .arg("-o")
+        .arg(&output)
+        .arg("--append")
+        .arg(&input)
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(&output).unwrap(), "a\nb\nc\n");
+
+    // A missing output is created
+    let created = dir.path().join("created.txt");
+    Command::new(assert_cmd::cargo::cargo_bin!("uniqr"))
+        .arg(&input)
+        .arg("--append=new")
+        .arg("-o")
+        .arg(&created)
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(&created).unwrap(), "b\nc\n");
+
+    Command::new(assert_cmd::cargo::cargo_bin!("uniqr"))
+        .arg(&input)
+        .arg("--append")
+        .arg("-o")
+        .arg(dir.path().join("output.txt.gz"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("uncompressed UTF-8"));
+}
+
+#[test]
+fn test_color() {
+    Command::new(assert_cmd::cargo::cargo_bin!("uniqr"))
+        .args(["--color", "always", "--count"])
+        .write_stdin("a\nb\na\n")
+        .assert()
+        .success()
+        .stdout("      \x1b[33m2\x1b[0m a\n      \x1b[33m1\x1b[0m b\n");
+
+    Command::new(assert_cmd::cargo::cargo_bin!("uniqr"))
+        .args([
+            "--color=always",
+            "--count",
+            "--count-format",
+            "{line}: {count}",
+        ])
+        .write_stdin("a\nb\na\n")
+        .assert()
+        .success()
+        .stdout("a: \x1b[33m2\x1b[0m\nb: \x1b[33m1\x1b[0m\n");
+
+    Command::new(assert_cmd::cargo::cargo_bin!("uniqr"))
+        .args(["--color=always", "--show-removed"])
+        .write_stdin("a\nb\na")
+        .assert()
+        .success()
+        .stdout("a\nb\n\x1b[31m[REMOVED]\x1b[0m a");
+
+    // Not a terminal
+    Command::new(assert_cmd::cargo::cargo_bin!("uniqr"))
+        .arg("--show-removed")
+        .write_stdin("a\na\n")
+        .assert()
+        .success()
+        .stdout("a\n[REMOVED] a\n");
+}
+
+#[test]
+fn test_summary() {
+    Command::new(assert_cmd::cargo::cargo_bin!("uniqr"))
+        .arg("--summary")
+        .write_stdin("b\na\nb\nc\nb\na\n")
+        .assert()
+        .success()
+        .stdout("b\t3\na\t2\nc\t1\n");
+
+    Command::new(assert_cmd::cargo::cargo_bin!("uniqr"))
+        .args(["--summary", "--top", "2", "--ignore-case"])
+        .write_stdin("B\na\nb\nc\nA\nc\nb\n")
+        .assert()
+        .success()
+        .stdout("b\t3\na\t2\n");
+
+    Command::new(assert_cmd::cargo::cargo_bin!("uniqr"))
+        .args(["--top", "2"])
+        .write_stdin("a\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--summary"));
+}
+
+#[test]
+fn test_window() {
+    Command::new(assert_cmd::cargo::cargo_bin!("uniqr"))
+        .args(["--window", "2"])
+        .write_stdin("a\nb\na\nc\nd\na\n")
+        .assert()
+        .success()
+        .stdout("a\nb\nc\nd\na\n");
+
+    Command::new(assert_cmd::cargo::cargo_bin!("uniqr"))
+        .args(["--max-keys", "2"])
+        .write_stdin("a\nb\na\nc\na\nb\n")
+        .assert()
+        .success()
+        .stdout("a\nb\nc\nb\n");
+
+    Command::new(assert_cmd::cargo::cargo_bin!("uniqr"))
+        .args(["--window", "0"])
+        .write_stdin("a\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("at least one line"));
+}
+
+#[cfg(feature = "timestamps")]
+#[test]
+fn test_within() {
+    Command::new(assert_cmd::cargo::cargo_bin!("uniqr"))
+        .args(["--within", "1m", "--timestamp-format", "[%H:%M:%S]"])
+        .write_stdin("[10:00:00] a\n[10:00:30] a\n[10:00:40] b\n[10:01:01] a\n")
+        .assert()
+        .success()
+        .stdout("[10:00:00] a\n[10:00:40] b\n[10:01:01] a\n");
+
+    Command::new(assert_cmd::cargo::cargo_bin!("uniqr"))
+        .args(["--within", "5 minutes", "--timestamp-format", "%s"])
+        .write_stdin("1 a\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid duration"));
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn test_preset() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.args(["--preset", "syslog"])
+        .write_stdin(
+            "Jan  2 15:04:05 host sshd[42]: Accepted key\n\
+             Jan  2 15:09:12 host sshd[77]: Accepted key\n\
+             Jan  2 15:10:00 host cron[78]: Job done\n",
+        )
+        .assert()
+        .success()
+        .stdout(
+            "Jan  2 15:04:05 host sshd[42]: Accepted key\n\
+             Jan  2 15:10:00 host cron[78]: Job done\n",
+        );
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.args(["--preset", "cef"])
+        .write_stdin("")
+        .assert()
+        .failure();
+}
+
+#[cfg(feature = "url")]
+#[test]
+fn test_preset_url() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.args(["--preset", "url"])
+        .write_stdin(
+            "https://example.com/a?x=1&y=2\n\
+             HTTPS://Example.com:443/a?y=2&x=1&utm_campaign=spring#reviews\n\
+             https://example.com/b\n",
+        )
+        .assert()
+        .success()
+        .stdout("https://example.com/a?x=1&y=2\nhttps://example.com/b\n");
+}
+
+#[test]
+fn test_print_key() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.args(["--print-key", "--column", "2", "--ignore-case"])
+        .write_stdin("1 Alice x\n2 ALICE y\n3 bob z\n")
+        .assert()
+        .success()
+        .stdout("alice\nbob\n");
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn test_passthrough_regex() {
+    let input = "# a\nx=1\n\n# a\nx=1\n\n";
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.args(["--passthrough-regex", "^(#|$)"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("# a\nx=1\n\n# a\n\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.args(["--only-match", "^#"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("# a\nx=1\n\nx=1\n\n");
+}
+
+#[test]
+fn test_empty_lines() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.args(["--empty-lines", "preserve"])
+        .write_stdin("fn a\n\nfn a\n\nfn b\n")
+        .assert()
+        .success()
+        .stdout("fn a\n\n\nfn b\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.args(["--empty-lines", "drop"])
+        .write_stdin("fn a\n\nfn a\n\nfn b\n")
+        .assert()
+        .success()
+        .stdout("fn a\nfn b\n");
+}
+
+#[test]
+fn test_header_footer() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.args(["--header", "1", "--footer", "1", "--stats"])
+        .write_stdin("name\tscore\nann\t1\nbob\t2\nann\t1\ntotal\t4\n")
+        .assert()
+        .success()
+        .stdout("name\tscore\nann\t1\nbob\t2\ntotal\t4\n")
+        .stderr(predicate::str::contains("Lines read:    3"));
+}
+
+#[test]
+fn test_audit_log() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("audit.tsv");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--audit-log")
+        .arg(&log)
+        .write_stdin("a\nb\na\n")
+        .assert()
+        .success()
+        .stdout("a\nb\n");
+    let audit = fs::read_to_string(&log).unwrap();
+    let rows: Vec<Vec<&str>> = audit.lines().map(|row| row.split('\t').collect()).collect();
+    assert_eq!(rows[0], ["line", "action", "count", "key_hash"]);
+    assert_eq!(rows[1][..3], ["1", "kept", "1"]);
+    assert_eq!(rows[2][..3], ["2", "kept", "1"]);
+    assert_eq!(rows[3][..3], ["3", "removed", "2"]);
+    // The same key always hashes the same
+    assert_eq!(rows[1][3], rows[3][3]);
+    assert_ne!(rows[1][3], rows[2][3]);
+
+    let (first, second) = (dir.path().join("a.txt"), dir.path().join("b.txt"));
+    fs::write(&first, "x\n").unwrap();
+    fs::write(&second, "x\n").unwrap();
+    let log = dir.path().join("audit.jsonl");
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--audit-log")
+        .arg(&log)
+        .args(["--audit-format", "jsonl", "--dry-run"])
+        .arg(&first)
+        .arg(&second)
+        .assert()
+        .success();
+    let audit = fs::read_to_string(&log).unwrap();
+    let lines: Vec<&str> = audit.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].contains("b.txt\",\"line\":1,\"action\":\"removed\",\"count\":2"));
+
+    // Only streaming keep-first runs can be audited
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--audit-log")
+        .arg(dir.path().join("last.tsv"))
+        .arg("--keep-last")
+        .arg(&first)
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_rate_limit() {
+    let start = std::time::Instant::now();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--rate-limit")
+        .arg("20")
+        .arg("--stats")
+        .write_stdin("a\nb\na\nc\nd\ne\n")
+        .assert()
+        .success()
+        .stdout("a\nb\nc\nd\ne\n")
+        .stderr(predicate::str::contains("Lines read:    6"));
+    // Five lines at 20 a second: the last starts 200ms after the first
+    assert!(start.elapsed() >= std::time::Duration::from_millis(200));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--rate-limit")
+        .arg("0")
+        .write_stdin("a\n")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_no_hint_for_piped_stdin() {
+    // The hint is only for a terminal
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.write_stdin("a\na\n")
+        .assert()
+        .success()
+        .stdout("a\n")
+        .stderr("");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--no-hint")
+        .write_stdin("a\n")
+        .assert()
+        .success()
+        .stdout("a\n");
+}
+
+#[test]
+fn test_ignore_trailing_space() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--ignore-trailing-space")
+        .write_stdin("a: 1\n  a: 1\na: 1 \t\n  a: 1  \n")
+        .assert()
+        .success()
+        .stdout("a: 1\n  a: 1\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--ignore-trailing-space")
+        .arg("--trim")
+        .write_stdin("a\n")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_hash_std() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--hash")
+        .arg("std")
+        .write_stdin("a\nb\na\n")
+        .assert()
+        .success()
+        .stdout("a\nb\n");
+}
+
+#[test]
+fn test_stats_json() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--stats-format")
+        .arg("json")
+        .write_stdin("a\nb\na\n")
+        .assert()
+        .success()
+        .stdout("a\nb\n")
+        .stderr(predicate::str::starts_with(
+            "{\"lines_read\":3,\"lines_written\":2,\"lines_removed\":1,\"unique_lines\":2,\"bytes_read\":6,\"bytes_written\":4,\"elapsed_seconds\":",
+        ))
+        .stderr(predicate::str::contains("\"lines_per_second\":"));
+}
+
+#[test]
+fn test_stats_output_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let stats_path = dir.path().join("stats.txt");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--stats-output")
+        .arg(&stats_path)
+        .write_stdin("a\na\n")
+        .assert()
+        .success()
+        .stderr("");
+
+    let stats = fs::read_to_string(&stats_path).unwrap();
+    assert!(stats.contains("Lines removed: 1"));
+}
+
+#[cfg(feature = "progress")]
+#[test]
+fn test_progress() {
+    let file = NamedTempFile::new().unwrap();
+    fs::write(file.path(), "a\nb\na\n").unwrap();
+
+    // The bar is only drawn on a terminal, so output is unaffected
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--progress")
+        .arg(file.path())
+        .assert()
+        .success()
+        .stdout("a\nb\n");
+}
+
+#[test]
+fn test_top_duplicates() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--top-duplicates")
+        .arg("2")
+        .write_stdin("x\ny\nx\nz\ny\nx\nw\n")
+        .assert()
+        .success()
+        .stdout("x\ny\nz\nw\n")
+        .stderr("Top duplicates:\n      3 x\n      2 y\n");
+}
+
+#[test]
+fn test_histogram() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--histogram")
+        .write_stdin("x\ny\nx\nz\ny\nx\nx\n")
+        .assert()
+        .success()
+        .stdout("x\ny\nz\n")
+        .stderr(predicate::str::contains(
+            "  Distinct lines by occurrences:\n    1          1\n    2          1\n    3-10       1\n",
+        ));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--histogram")
+        .arg("--stats-format")
+        .arg("json")
+        .write_stdin("x\ny\nx\n")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            ",\"duplicate_histogram\":{\"1\":1,\"2\":1}}\n",
+        ));
+}
+
+#[test]
+fn test_top_duplicates_output_in_place() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = dir.path().join("a.txt");
+    let second = dir.path().join("b.txt");
+    let report = dir.path().join("report.txt");
+    fs::write(&first, "x\nx\ny\n").unwrap();
+    fs::write(&second, "x\ny\ny\nx\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--in-place")
+        .arg("--top-duplicates")
+        .arg("5")
+        .arg("--top-duplicates-output")
+        .arg(&report)
+        .arg(&first)
+        .arg(&second)
+        .assert()
+        .success()
+        .stderr("");
+
+    // Counts for the same key are combined across files
+    assert_eq!(
+        fs::read_to_string(&report).unwrap(),
+        "Top duplicates:\n      4 x\n      3 y\n"
+    );
+}
+
+#[test]
+fn test_key_reports_in_place_use_combined_counts() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = dir.path().join("a.txt");
+    let second = dir.path().join("b.txt");
+    fs::write(&first, "x\nx\nx\nk\nk\n").unwrap();
+    fs::write(&second, "y\ny\ny\nk\nk\n").unwrap();
+
+    // k is second in each file but first overall, and is binned once
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--in-place")
+        .arg("--top-duplicates")
+        .arg("1")
+        .arg("--histogram")
+        .arg(&first)
+        .arg(&second)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Top duplicates:\n      4 k\n"))
+        .stderr(predicate::str::contains(
+            "  Distinct lines by occurrences:\n    3-10       3\n",
+        ));
+}
+
+#[test]
+fn test_counts_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let counts = dir.path().join("counts.tsv");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--counts-output")
+        .arg(&counts)
+        .arg("--column")
+        .arg("2")
+        .write_stdin("1 x\n2 y\n3 x\n4 z\n5 x\n")
+        .assert()
+        .success()
+        .stdout("1 x\n2 y\n4 z\n");
+
+    assert_eq!(fs::read_to_string(&counts).unwrap(), "x\t3\ny\t1\nz\t1\n");
+}
+
+#[test]
+fn test_removed_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let removed = dir.path().join("removed.txt");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--removed-output")
+        .arg(&removed)
+        .write_stdin("a\nb\na\nb\nc\n")
+        .assert()
+        .success()
+        .stdout("a\nb\nc\n");
+
+    assert_eq!(fs::read_to_string(&removed).unwrap(), "a\nb\n");
+}
+
+#[test]
+fn test_duplicates_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let (kept, dups) = (dir.path().join("kept.txt"), dir.path().join("dups.txt"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--output")
+        .arg(&kept)
+        .arg("--duplicates-output")
+        .arg(&dups)
+        .write_stdin("a\nb\na\nc\nb\n")
+        .assert()
+        .success()
+        .stdout("");
+
+    assert_eq!(fs::read_to_string(&kept).unwrap(), "a\nb\nc\n");
+    assert_eq!(fs::read_to_string(&dups).unwrap(), "a\nb\n");
+}
+
+#[test]
+fn test_removed_output_conflicts_with_show_removed() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("uniqr"));
+    cmd.arg("--removed-output")
+        .arg("removed.txt")
+        .arg("--show-removed")
+        .write_stdin("a\n")
+        .assert()
+        .failure();
+}